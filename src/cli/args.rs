@@ -0,0 +1,293 @@
+use super::CliErr;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+
+/// Describes one option of a subcommand.
+pub struct OptSpec {
+    /// The single-letter form, used as `-x`.
+    pub short: Option<char>,
+    /// The long form, used as `--name`; also the key the option is looked up by.
+    pub long: &'static str,
+    /// The name of the option's value in the help text, or `None` for a switch.
+    pub value: Option<&'static str>,
+    /// The description in the help text.
+    pub help: &'static str,
+}
+
+/// The options and operands given to a subcommand.
+#[derive(Debug, Default)]
+pub struct Matches {
+    switches_: Vec<&'static str>,
+    values_: HashMap<&'static str, OsString>,
+    operands_: Vec<OsString>,
+    expression_: Vec<OsString>,
+}
+
+impl Matches {
+    /// Returns `true` if the switch with the given long name was given.
+    pub fn has(&self, long: &str) -> bool {
+        self.switches_.contains(&long)
+    }
+
+    /// Returns the value of the option with the given long name; the last one given wins.
+    pub fn get_value(&self, long: &str) -> Option<&OsStr> {
+        self.values_.get(long).map(OsString::as_os_str)
+    }
+
+    /// Returns the value of an option as UTF-8 text.
+    pub fn get_str(&self, long: &str) -> Result<Option<&str>, CliErr> {
+        match self.get_value(long) {
+            Some(value) => value
+                .to_str()
+                .map(Some)
+                .ok_or_else(|| CliErr::Usage(format!("the value of --{} is not valid UTF-8", long))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value of an option parsed as a number.
+    pub fn get_number(&self, long: &str) -> Result<Option<u64>, CliErr> {
+        match self.get_str(long)? {
+            Some(text) => text
+                .parse()
+                .map(Some)
+                .map_err(|_| CliErr::Usage(format!("--{} expects a number, got '{}'", long, text))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the operands, in order.
+    pub fn get_operands(&self) -> &[OsString] {
+        &self.operands_
+    }
+
+    /// Returns the arguments following the operand of a subcommand that takes an expression.
+    pub fn get_expression(&self) -> &[OsString] {
+        &self.expression_
+    }
+}
+
+/// Parses the arguments of a subcommand.
+///
+/// Short switches may be grouped, as in `-fa`, and values may be attached, as in `-L3` or
+/// `--level=3`. Everything after `--` is an operand. When `expression` is set, parsing stops at
+/// the first operand after the first one, or at the first argument that looks like an option but
+/// is not one, and the rest is returned whole by `get_expression`.
+pub fn parse(specs: &[OptSpec], args: &[OsString], expression: bool) -> Result<Matches, CliErr> {
+    let mut matches = Matches::default();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        let text = match arg.to_str() {
+            Some(text) if text.len() > 1 && text.starts_with('-') => text,
+            _ => {
+                if expression && (!matches.operands_.is_empty() || starts_expression(arg)) {
+                    matches.expression_.extend(args[i - 1..].iter().cloned());
+                    return Ok(matches);
+                }
+                matches.operands_.push(arg.clone());
+                continue;
+            }
+        };
+        if text == "--" {
+            matches.operands_.extend(args[i..].iter().cloned());
+            break;
+        }
+
+        if let Some(long) = text.strip_prefix("--") {
+            let (name, attached) = match long.find('=') {
+                Some(eq) => (&long[..eq], Some(OsString::from(&long[eq + 1..]))),
+                None => (long, None),
+            };
+            let spec = match specs.iter().find(|spec| spec.long == name) {
+                Some(spec) => spec,
+                None if expression => {
+                    matches.expression_.extend(args[i - 1..].iter().cloned());
+                    return Ok(matches);
+                }
+                None => return Err(CliErr::Usage(format!("unknown option '--{}'", name))),
+            };
+            match (spec.value, attached) {
+                (Some(_), Some(value)) => {
+                    matches.values_.insert(spec.long, value);
+                }
+                (Some(_), None) => {
+                    let value = args
+                        .get(i)
+                        .ok_or_else(|| CliErr::Usage(format!("option '--{}' needs a value", name)))?;
+                    matches.values_.insert(spec.long, value.clone());
+                    i += 1;
+                }
+                (None, Some(_)) => return Err(CliErr::Usage(format!("option '--{}' takes no value", name))),
+                (None, None) => matches.switches_.push(spec.long),
+            }
+            continue;
+        }
+
+        // A group of short options such as `-fa` or `-L3`
+        let letters = &text[1..];
+        if expression && !letters.chars().all(|c| specs.iter().any(|spec| spec.short == Some(c))) {
+            matches.expression_.extend(args[i - 1..].iter().cloned());
+            return Ok(matches);
+        }
+        for (at, c) in letters.char_indices() {
+            let spec = specs
+                .iter()
+                .find(|spec| spec.short == Some(c))
+                .ok_or_else(|| CliErr::Usage(format!("unknown option '-{}'", c)))?;
+            if spec.value.is_none() {
+                matches.switches_.push(spec.long);
+                continue;
+            }
+            let rest = &letters[at + c.len_utf8()..];
+            let value = if !rest.is_empty() {
+                OsString::from(rest)
+            } else {
+                let value = args
+                    .get(i)
+                    .cloned()
+                    .ok_or_else(|| CliErr::Usage(format!("option '-{}' needs a value", c)))?;
+                i += 1;
+                value
+            };
+            matches.values_.insert(spec.long, value);
+            break;
+        }
+    }
+    Ok(matches)
+}
+
+/// Returns `true` for arguments that can only begin an expression, such as `(` and `!`.
+fn starts_expression(arg: &OsStr) -> bool {
+    arg == "(" || arg == ")" || arg == "!"
+}
+
+/// Formats the option list of a subcommand for its help text.
+pub fn describe(specs: &[OptSpec]) -> String {
+    let lines: Vec<(String, &str)> = specs
+        .iter()
+        .map(|spec| {
+            let mut names = match spec.short {
+                Some(short) => format!("-{}, --{}", short, spec.long),
+                None => format!("    --{}", spec.long),
+            };
+            if let Some(value) = spec.value {
+                names.push(' ');
+                names.push_str(value);
+            }
+            (names, spec.help)
+        })
+        .collect();
+    let width = lines.iter().map(|(names, _)| names.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    for (names, help) in lines {
+        text.push_str(&format!("  {:width$}  {}\n", names, help, width = width));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPECS: &[OptSpec] = &[
+        OptSpec { short: Some('f'), long: "files", value: None, help: "List files" },
+        OptSpec { short: Some('a'), long: "absolute", value: None, help: "Absolute paths" },
+        OptSpec { short: Some('L'), long: "level", value: Some("N"), help: "Descend N levels" },
+        OptSpec { short: None, long: "hash", value: Some("ALGO"), help: "Hash with ALGO" },
+    ];
+
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn parsed(args: &[&str], expression: bool) -> Matches {
+        parse(SPECS, &os_args(args), expression).unwrap()
+    }
+
+    fn usage_error(args: &[&str], expression: bool) -> String {
+        match parse(SPECS, &os_args(args), expression) {
+            Err(CliErr::Usage(reason)) => reason,
+            other => panic!("expected a usage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_switches_values_and_operands() {
+        let matches = parsed(&["-fa", "root", "--level", "3", "--hash=sha256"], false);
+        assert!(matches.has("files") && matches.has("absolute"));
+        assert_eq!(matches.get_number("level").unwrap(), Some(3));
+        assert_eq!(matches.get_str("hash").unwrap(), Some("sha256"));
+        assert_eq!(matches.get_operands(), &os_args(&["root"])[..]);
+        assert!(matches.get_expression().is_empty());
+
+        // Attached short values, the last value winning, and `-` as an operand
+        let matches = parsed(&["-L3", "-", "-fL", "5", "--level=7"], false);
+        assert_eq!(matches.get_number("level").unwrap(), Some(7));
+        assert_eq!(matches.get_operands(), &os_args(&["-"])[..]);
+        assert_eq!(parsed(&["-fL2"], false).get_number("level").unwrap(), Some(2));
+        assert_eq!(parsed(&["--hash="], false).get_str("hash").unwrap(), Some(""));
+
+        let matches = parsed(&["-f", "--", "-a", "--level"], false);
+        assert!(!matches.has("absolute"));
+        assert_eq!(matches.get_operands(), &os_args(&["-a", "--level"])[..]);
+        assert!(!parsed(&[], false).has("files"));
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert_eq!(usage_error(&["--bogus"], false), "unknown option '--bogus'");
+        assert_eq!(usage_error(&["-fx"], false), "unknown option '-x'");
+        assert_eq!(usage_error(&["--level"], false), "option '--level' needs a value");
+        assert_eq!(usage_error(&["-fL"], false), "option '-L' needs a value");
+        assert_eq!(usage_error(&["--files=yes"], false), "option '--files' takes no value");
+
+        let matches = parsed(&["-L", "three"], false);
+        assert!(matches!(matches.get_number("level"), Err(CliErr::Usage(reason)) if reason == "--level expects a number, got 'three'"));
+        let matches = parsed(&["-L", "-1"], false);
+        assert!(matches.get_number("level").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_values_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+        let args = vec![OsString::from("--hash"), OsString::from_vec(b"sha\xff".to_vec())];
+        let matches = parse(SPECS, &args, false).unwrap();
+        assert_eq!(matches.get_value("hash").map(|value| value.len()), Some(4));
+        assert!(matches!(matches.get_str("hash"), Err(CliErr::Usage(_))));
+    }
+
+    #[test]
+    fn splits_off_expressions() {
+        let matches = parsed(&["-a", "root", "-name", "*.rs", "-o", "-f"], true);
+        assert!(matches.has("absolute"));
+        assert_eq!(matches.get_operands(), &os_args(&["root"])[..]);
+        assert_eq!(matches.get_expression(), &os_args(&["-name", "*.rs", "-o", "-f"])[..]);
+
+        // Without a root, the expression starts at the first argument that is not an option
+        let matches = parsed(&["-f", "-type", "d"], true);
+        assert!(matches.has("files"));
+        assert!(matches.get_operands().is_empty());
+        assert_eq!(matches.get_expression(), &os_args(&["-type", "d"])[..]);
+        assert_eq!(parsed(&["(", "-empty", ")"], true).get_expression().len(), 3);
+        assert_eq!(parsed(&["!", "-empty"], true).get_expression().len(), 2);
+        assert_eq!(parsed(&["--newer", "x"], true).get_expression(), &os_args(&["--newer", "x"])[..]);
+        assert_eq!(parsed(&["root", "extra"], true).get_expression(), &os_args(&["extra"])[..]);
+    }
+
+    #[test]
+    fn describes_options_in_columns() {
+        assert_eq!(
+            describe(SPECS),
+            concat!(
+                "  -f, --files      List files\n",
+                "  -a, --absolute   Absolute paths\n",
+                "  -L, --level N    Descend N levels\n",
+                "      --hash ALGO  Hash with ALGO\n",
+            )
+        );
+        assert_eq!(describe(&[]), "");
+    }
+}
@@ -0,0 +1,515 @@
+//! The `browse` subcommand: an interactive disk-usage browser in the style of ncdu.
+//!
+//! Input is read from `/dev/tty`, switched to unbuffered, silent mode with `stty`, and the screen
+//! is drawn with ANSI escape sequences on the alternate screen, so no terminal library is needed.
+
+use super::args::Matches;
+use super::{open_root, CliErr, Command, EXIT_OK};
+use dir_indexer::{human_size, DirEntryKind, DirIndexer, DirUsage};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Stdio};
+
+pub const BROWSE: Command = Command {
+    name: "browse",
+    operands: "[ROOT]",
+    about: "Browse the directories below ROOT by size in the terminal, and delete what is not needed",
+    options: &[],
+    expression: false,
+    run: browse,
+};
+
+const BAR_WIDTH: usize = 10;
+
+const HELP: &[(&str, &str)] = &[
+    ("Up, k", "Select the previous entry"),
+    ("Down, j", "Select the next entry"),
+    ("PgUp, PgDn", "Move by one screen"),
+    ("Home, g", "Select the first entry"),
+    ("End, G", "Select the last entry"),
+    ("Right, Enter, l", "Open the selected directory"),
+    ("Left, Backspace, h", "Go to the parent directory"),
+    ("d", "Delete the selected entry, after confirmation"),
+    ("r", "Rescan the current directory"),
+    ("?", "Show this help; any key closes it"),
+    ("q", "Quit"),
+];
+
+fn browse(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let mut terminal = Terminal::open()?;
+    let mut browser = Browser::new(indexer);
+    out.write_all(b"\x1b[?1049h\x1b[?25l")?;
+    let result = browser.run(&mut terminal, out);
+    out.write_all(b"\x1b[?25h\x1b[?1049l")?;
+    out.flush()?;
+    result.map(|_| EXIT_OK)
+}
+
+/// A key, or a group of keys with the same meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Open,
+    Back,
+    Delete,
+    Refresh,
+    Help,
+    Quit,
+    Yes,
+    Other,
+}
+
+/// The controlling terminal in unbuffered, silent mode; its settings are restored on drop.
+struct Terminal {
+    tty_: File,
+    saved_: String,
+}
+
+impl Terminal {
+    fn open() -> Result<Terminal, CliErr> {
+        let no_terminal = |_| CliErr::Io(io::Error::new(io::ErrorKind::Other, "browse needs an interactive terminal"));
+        let tty = File::open("/dev/tty").map_err(no_terminal)?;
+        let saved = stty(&["-g"]).map_err(no_terminal)?;
+        // Ctrl-C arrives as a key, so quitting always restores the terminal
+        stty(&["-icanon", "-echo", "-isig", "min", "1", "time", "0"]).map_err(no_terminal)?;
+        Ok(Terminal {
+            tty_: tty,
+            saved_: saved,
+        })
+    }
+
+    /// Returns the number of rows and columns, or 24 by 80 if they are unknown.
+    fn size(&self) -> (usize, usize) {
+        let size = stty(&["size"]).unwrap_or_default();
+        let mut numbers = size.split_whitespace().filter_map(|n| n.parse::<usize>().ok());
+        match (numbers.next(), numbers.next()) {
+            (Some(rows), Some(cols)) if rows > 2 && cols > 20 => (rows, cols),
+            _ => (24, 80),
+        }
+    }
+
+    fn read_key(&mut self) -> io::Result<Key> {
+        let mut buf = [0u8; 16];
+        let len = self.tty_.read(&mut buf)?;
+        if len == 0 {
+            return Ok(Key::Quit);
+        }
+        let key = match &buf[..len] {
+            [0x1b, b'[', rest @ ..] | [0x1b, b'O', rest @ ..] => match rest {
+                [b'A', ..] => Key::Up,
+                [b'B', ..] => Key::Down,
+                [b'C', ..] => Key::Open,
+                [b'D', ..] => Key::Back,
+                [b'H', ..] | [b'1', b'~', ..] | [b'7', b'~', ..] => Key::Home,
+                [b'F', ..] | [b'4', b'~', ..] | [b'8', b'~', ..] => Key::End,
+                [b'5', b'~', ..] => Key::PageUp,
+                [b'6', b'~', ..] => Key::PageDown,
+                _ => Key::Other,
+            },
+            [byte, ..] => match byte {
+                b'k' => Key::Up,
+                b'j' => Key::Down,
+                b'g' => Key::Home,
+                b'G' => Key::End,
+                b'\r' | b'\n' | b'l' => Key::Open,
+                b'h' | 0x7f | 0x08 => Key::Back,
+                b'd' => Key::Delete,
+                b'r' => Key::Refresh,
+                b'?' => Key::Help,
+                b'q' | 0x03 => Key::Quit,
+                b'y' | b'Y' => Key::Yes,
+                _ => Key::Other,
+            },
+            [] => Key::Other,
+        };
+        Ok(key)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = stty(&[self.saved_.as_str()]);
+    }
+}
+
+/// Runs `stty` on the controlling terminal and returns what it printed.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Process::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty")?)
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, "stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// One row of the listing.
+struct Entry {
+    rl_path_: PathBuf,
+    name_: String,
+    kind_: DirEntryKind,
+    size_: u64,
+}
+
+struct Browser {
+    indexer_: DirIndexer,
+    usage_: BTreeMap<PathBuf, DirUsage>,
+    cwd_: PathBuf,
+    entries_: Vec<Entry>,
+    selected_: usize,
+    top_: usize,
+    show_help_: bool,
+    message_: String,
+}
+
+impl Browser {
+    fn new(indexer: DirIndexer) -> Browser {
+        let usage = indexer.get_tree().get_usage();
+        let mut browser = Browser {
+            indexer_: indexer,
+            usage_: usage,
+            cwd_: PathBuf::new(),
+            entries_: Vec::new(),
+            selected_: 0,
+            top_: 0,
+            show_help_: false,
+            message_: String::new(),
+        };
+        browser.load_entries(None);
+        browser
+    }
+
+    fn run(&mut self, terminal: &mut Terminal, out: &mut dyn Write) -> Result<(), CliErr> {
+        loop {
+            let (rows, cols) = terminal.size();
+            self.draw(out, rows, cols)?;
+            let key = terminal.read_key()?;
+            let page = rows.saturating_sub(2).max(1);
+            self.message_.clear();
+            if self.show_help_ && key != Key::Quit {
+                self.show_help_ = false;
+                continue;
+            }
+            match key {
+                Key::Up => self.selected_ = self.selected_.saturating_sub(1),
+                Key::Down => self.selected_ = (self.selected_ + 1).min(self.entries_.len().saturating_sub(1)),
+                Key::PageUp => self.selected_ = self.selected_.saturating_sub(page),
+                Key::PageDown => self.selected_ = (self.selected_ + page).min(self.entries_.len().saturating_sub(1)),
+                Key::Home => self.selected_ = 0,
+                Key::End => self.selected_ = self.entries_.len().saturating_sub(1),
+                Key::Open => self.open(),
+                Key::Back => self.back(),
+                Key::Delete => self.delete(terminal, out, rows, cols)?,
+                Key::Refresh => self.refresh(),
+                Key::Help => self.show_help_ = true,
+                Key::Quit => return Ok(()),
+                Key::Yes | Key::Other => {}
+            }
+        }
+    }
+
+    /// Lists the current directory, largest first, and selects `select` if it is in it.
+    fn load_entries(&mut self, select: Option<&Path>) {
+        let current = select
+            .map(Path::to_path_buf)
+            .or_else(|| self.entries_.get(self.selected_).map(|entry| entry.rl_path_.clone()));
+        self.entries_.clear();
+        if let Some(node) = self.indexer_.get_tree().get_node(&self.cwd_) {
+            for child in node.get_sorted_children() {
+                self.entries_.push(Entry {
+                    rl_path_: child.get_entry().clone(),
+                    name_: printable(&child.get_name().to_string_lossy()),
+                    kind_: child.get_kind(),
+                    size_: self.usage_.get(child.get_entry()).map_or(0, DirUsage::get_size),
+                });
+            }
+        }
+        self.entries_.sort_by(|a, b| b.size_.cmp(&a.size_).then_with(|| a.rl_path_.cmp(&b.rl_path_)));
+        self.selected_ = current
+            .and_then(|current| self.entries_.iter().position(|entry| entry.rl_path_ == current))
+            .unwrap_or_else(|| self.selected_.min(self.entries_.len().saturating_sub(1)));
+    }
+
+    fn open(&mut self) {
+        let rl_path = match self.entries_.get(self.selected_) {
+            Some(entry) if entry.kind_ == DirEntryKind::Dir => entry.rl_path_.clone(),
+            _ => return,
+        };
+        self.cwd_ = rl_path;
+        self.selected_ = 0;
+        self.top_ = 0;
+        self.entries_.clear();
+        self.load_entries(None);
+    }
+
+    fn back(&mut self) {
+        let parent = match self.cwd_.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return,
+        };
+        let previous = std::mem::replace(&mut self.cwd_, parent);
+        self.top_ = 0;
+        self.load_entries(Some(&previous));
+    }
+
+    fn delete(&mut self, terminal: &mut Terminal, out: &mut dyn Write, rows: usize, cols: usize) -> Result<(), CliErr> {
+        let (rl_path, name, kind, size) = match self.entries_.get(self.selected_) {
+            Some(entry) => (entry.rl_path_.clone(), entry.name_.clone(), entry.kind_, entry.size_),
+            None => return Ok(()),
+        };
+        self.message_ = if kind == DirEntryKind::Dir {
+            format!("Delete the directory '{}' ({}) and everything in it? [y/N]", name, human_size(size))
+        } else {
+            format!("Delete '{}' ({})? [y/N]", name, human_size(size))
+        };
+        self.draw(out, rows, cols)?;
+        if terminal.read_key()? != Key::Yes {
+            self.message_ = String::from("Nothing deleted");
+            return Ok(());
+        }
+
+        let ab_path = self.indexer_.get_root_path().join(&rl_path);
+        let removed = if kind == DirEntryKind::Dir {
+            fs::remove_dir_all(&ab_path)
+        } else {
+            fs::remove_file(&ab_path)
+        };
+        // Whatever happened on disk, even a partial removal, is read back into the index
+        let refreshed = self.indexer_.refresh_subtree(&rl_path);
+        self.message_ = match (removed, refreshed) {
+            (Ok(()), Ok(_)) => format!("Deleted '{}'", name),
+            (Err(err), _) => format!("Cannot delete '{}': {}", name, err),
+            (_, Err(err)) => format!("Cannot rescan '{}': {}", name, err),
+        };
+        self.reload();
+        Ok(())
+    }
+
+    fn refresh(&mut self) {
+        let cwd = self.cwd_.clone();
+        self.message_ = match self.indexer_.refresh_subtree(&cwd) {
+            Ok(diff) => format!("Rescanned, {} changes", diff.get_changes().len()),
+            Err(err) => format!("Cannot rescan: {}", err),
+        };
+        self.reload();
+    }
+
+    /// Recomputes sizes after the index changed, moving up if the current directory is gone.
+    fn reload(&mut self) {
+        self.usage_ = self.indexer_.get_tree().get_usage();
+        while self.indexer_.get_tree().get_node(&self.cwd_).map(|node| node.get_kind()) != Some(DirEntryKind::Dir) {
+            match self.cwd_.parent() {
+                Some(parent) => self.cwd_ = parent.to_path_buf(),
+                None => break,
+            }
+        }
+        self.load_entries(None);
+    }
+
+    fn draw(&mut self, out: &mut dyn Write, rows: usize, cols: usize) -> io::Result<()> {
+        let height = rows - 2;
+        if self.selected_ < self.top_ {
+            self.top_ = self.selected_;
+        } else if self.selected_ >= self.top_ + height {
+            self.top_ = self.selected_ + 1 - height;
+        }
+
+        let mut frame = String::new();
+        let mut location = self.indexer_.get_root_path().clone();
+        if !self.cwd_.as_os_str().is_empty() {
+            location.push(&self.cwd_);
+        }
+        let header = format!(" dir_indexer browse  {}", printable(&location.to_string_lossy()));
+        push_line(&mut frame, 1, &header, cols, true);
+
+        let total = self.usage_.get(&self.cwd_).map_or(0, DirUsage::get_size);
+        let largest = self.entries_.first().map_or(0, |entry| entry.size_);
+        for row in 0..height {
+            if self.show_help_ {
+                let line = HELP.get(row).map_or(String::new(), |(keys, help)| format!("  {:20}{}", keys, help));
+                push_line(&mut frame, row + 2, &line, cols, false);
+                continue;
+            }
+            let entry = match self.entries_.get(self.top_ + row) {
+                Some(entry) => entry,
+                None => {
+                    let line = if row == 0 && self.entries_.is_empty() { "  (empty directory)" } else { "" };
+                    push_line(&mut frame, row + 2, line, cols, false);
+                    continue;
+                }
+            };
+            let filled = if largest == 0 {
+                0
+            } else {
+                ((entry.size_ as f64 / largest as f64) * BAR_WIDTH as f64).round() as usize
+            };
+            let percent = if total == 0 { 0.0 } else { entry.size_ as f64 * 100.0 / total as f64 };
+            let suffix = match entry.kind_ {
+                DirEntryKind::Dir => "/",
+                DirEntryKind::Symlink => "@",
+                _ => "",
+            };
+            let line = format!(
+                " {:>7} [{}{}] {:>5.1}%  {}{}",
+                human_size(entry.size_),
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                percent,
+                entry.name_,
+                suffix
+            );
+            push_line(&mut frame, row + 2, &line, cols, self.top_ + row == self.selected_);
+        }
+
+        let footer = if self.message_.is_empty() {
+            format!(
+                " Total: {}  Entries: {}    ?: help  d: delete  r: rescan  q: quit",
+                human_size(total),
+                self.entries_.len()
+            )
+        } else {
+            format!(" {}", self.message_)
+        };
+        push_line(&mut frame, rows, &footer, cols, true);
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Appends screen line `row`, counted from 1, cut to `cols` characters and padded so it covers
+/// the old one.
+fn push_line(frame: &mut String, row: usize, text: &str, cols: usize, highlight: bool) {
+    frame.push_str(&format!("\x1b[{};1H", row));
+    if highlight {
+        frame.push_str("\x1b[7m");
+    }
+    let mut width = 0;
+    for c in text.chars().take(cols) {
+        frame.push(c);
+        width += 1;
+    }
+    frame.push_str(&" ".repeat(cols - width));
+    if highlight {
+        frame.push_str("\x1b[0m");
+    }
+}
+
+/// Replaces control characters, which could move the cursor, with `?`.
+fn printable(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { '?' } else { c }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_util::TempDir;
+
+    fn sample(name: &str) -> (TempDir, Browser) {
+        let dir = TempDir::new(name);
+        dir.write("big.bin", &[0; 20000]);
+        dir.write("d/small.txt", b"small");
+        dir.write("z.txt", b"");
+        let browser = Browser::new(DirIndexer::from(dir.join("")).unwrap());
+        (dir, browser)
+    }
+
+    fn names(browser: &Browser) -> Vec<&str> {
+        browser.entries_.iter().map(|entry| entry.name_.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_the_largest_entries_first_and_moves_between_directories() {
+        let (_dir, mut browser) = sample("browse-moves");
+        assert_eq!(names(&browser), vec!["big.bin", "d", "z.txt"]);
+        assert_eq!(browser.entries_[0].size_, 20000);
+
+        // Only directories can be opened
+        browser.open();
+        assert_eq!(browser.cwd_, PathBuf::new());
+
+        browser.selected_ = 1;
+        browser.open();
+        assert_eq!(browser.cwd_, PathBuf::from("d"));
+        assert_eq!(names(&browser), vec!["small.txt"]);
+
+        // Going back selects the directory that was left, and the root has no parent
+        browser.back();
+        assert_eq!(browser.cwd_, PathBuf::new());
+        assert_eq!(browser.selected_, 1);
+        browser.back();
+        assert_eq!(browser.cwd_, PathBuf::new());
+    }
+
+    #[test]
+    fn rescans_the_current_directory_and_leaves_it_when_it_is_gone() {
+        let (dir, mut browser) = sample("browse-refresh");
+        browser.selected_ = 1;
+        browser.open();
+        fs::remove_file(dir.join("d/small.txt")).unwrap();
+        browser.refresh();
+        assert_eq!(browser.message_, "Rescanned, 1 changes");
+        assert!(browser.entries_.is_empty());
+
+        fs::remove_dir(dir.join("d")).unwrap();
+        browser.refresh();
+        assert_eq!(browser.cwd_, PathBuf::new());
+        assert_eq!(names(&browser), vec!["big.bin", "z.txt"]);
+    }
+
+    #[test]
+    fn draws_a_header_the_entries_and_a_footer() {
+        let (_dir, mut browser) = sample("browse-draw");
+        let mut out = Vec::new();
+        browser.draw(&mut out, 4, 60).unwrap();
+        let frame = String::from_utf8(out).unwrap();
+        let plain = frame.replace("\x1b[7m", "").replace("\x1b[0m", "");
+        let lines: Vec<&str> = plain.split("\x1b[").skip(1).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.chars().count() == "1;1H".len() + 60));
+        assert!(lines[0].starts_with("1;1H dir_indexer browse"));
+        assert!(lines[1].starts_with("2;1H     20K [##########] "), "{:?}", lines[1]);
+        assert!(lines[1].contains("%  big.bin "));
+        assert!(lines[2].starts_with("3;1H "));
+        assert!(lines[2].contains("%  d/ "));
+        assert!(lines[3].starts_with("4;1H Total: "));
+        for row in [1, 2, 4].iter() {
+            assert!(frame.contains(&format!("\x1b[{};1H\x1b[7m", row)));
+        }
+        assert!(frame.contains("\x1b[3;1H "));
+        assert!(frame.contains("Entries: 3"));
+
+        // The selection scrolls into view
+        browser.selected_ = 2;
+        let mut out = Vec::new();
+        browser.draw(&mut out, 4, 60).unwrap();
+        assert_eq!(browser.top_, 1);
+        assert!(String::from_utf8(out).unwrap().contains("%  z.txt"));
+    }
+
+    #[test]
+    fn lines_are_cut_and_padded_to_the_screen_width() {
+        let mut frame = String::new();
+        push_line(&mut frame, 3, "héllo world", 5, false);
+        assert_eq!(frame, "\x1b[3;1Hhéllo");
+
+        let mut frame = String::new();
+        push_line(&mut frame, 1, "ab", 4, true);
+        assert_eq!(frame, "\x1b[1;1H\x1b[7mab  \x1b[0m");
+    }
+
+    #[test]
+    fn control_characters_are_not_printed() {
+        assert_eq!(printable("a\x1b[2Jb\tc\u{7f}\u{85}"), "a?[2Jb?c??");
+        assert_eq!(printable("naïve ✓"), "naïve ✓");
+    }
+}
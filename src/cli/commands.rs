@@ -0,0 +1,581 @@
+use super::args::{Matches, OptSpec};
+use super::output::{self, OutputMode, RecordWriter, Value};
+use super::{open_index, open_root, CliErr, Command, EXIT_DIFFERENT, EXIT_OK};
+use dir_indexer::{human_size, DirChange, DirColumn, DirDiffOptions, DirEntryKind, DirIndexer, DirNode, DirQuery};
+use dir_indexer::{DirTableExporter, DotOptions, DuplicateOptions, HashAlgo, MtreeOptions, TableFormat};
+use dir_indexer::{HtmlReportOptions, TreeAnnotation, TreeCharset, TreeRenderOptions};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const ABSOLUTE: OptSpec = OptSpec {
+    short: Some('a'),
+    long: "absolute",
+    value: None,
+    help: "Print absolute paths instead of paths relative to ROOT",
+};
+
+pub const LIST: Command = Command {
+    name: "list",
+    operands: "[ROOT]",
+    about: "List the files and directories below ROOT",
+    options: &[
+        OptSpec { short: Some('f'), long: "files", value: None, help: "List regular files" },
+        OptSpec { short: Some('d'), long: "dirs", value: None, help: "List directories" },
+        OptSpec { short: Some('r'), long: "relative", value: None, help: "Print paths relative to ROOT (the default)" },
+        OptSpec { short: Some('a'), long: "absolute", value: None, help: "Print absolute paths; with -r, print both" },
+        output::NULL,
+        output::JSON,
+        output::TSV,
+    ],
+    expression: false,
+    run: list,
+};
+
+pub const TREE: Command = Command {
+    name: "tree",
+    operands: "[ROOT]",
+    about: "Draw the entries below ROOT as a tree",
+    options: &[
+        OptSpec { short: Some('L'), long: "level", value: Some("N"), help: "Descend at most N levels" },
+        OptSpec { short: Some('d'), long: "dirs", value: None, help: "Show directories only" },
+        OptSpec { short: Some('c'), long: "collapse", value: None, help: "Draw chains of single directories on one line" },
+        OptSpec { short: Some('s'), long: "size", value: None, help: "Show the total size of every entry" },
+        OptSpec { short: Some('n'), long: "count", value: None, help: "Show the number of entries in every directory" },
+        OptSpec { short: None, long: "ascii", value: None, help: "Draw branches with ASCII instead of box-drawing characters" },
+    ],
+    expression: false,
+    run: tree,
+};
+
+pub const FIND: Command = Command {
+    name: "find",
+    operands: "[ROOT] [EXPRESSION]",
+    about: "List the entries matching a find-style expression, such as '-name *.rs -size +1k'",
+    options: &[ABSOLUTE, output::NULL, output::JSON, output::TSV],
+    expression: true,
+    run: find,
+};
+
+pub const DU: Command = Command {
+    name: "du",
+    operands: "[ROOT]",
+    about: "Summarize the apparent size of every directory",
+    options: &[
+        OptSpec { short: Some('d'), long: "max-depth", value: Some("N"), help: "Print directories at most N levels deep" },
+        OptSpec { short: Some('s'), long: "summarize", value: None, help: "Print the total of ROOT only" },
+        OptSpec { short: Some('a'), long: "all", value: None, help: "Print files as well as directories" },
+        OptSpec { short: Some('H'), long: "human", value: None, help: "Print sizes as 1.5K, 23M, 4.0G" },
+        output::NULL,
+        output::JSON,
+        output::TSV,
+    ],
+    expression: false,
+    run: du,
+};
+
+pub const DIFF: Command = Command {
+    name: "diff",
+    operands: "OLD NEW",
+    about: "Compare two trees or saved indexes",
+    options: &[
+        OptSpec { short: Some('R'), long: "renames", value: None, help: "Report moved entries as renames" },
+        OptSpec { short: None, long: "hash", value: Some("ALGO"), help: "Compare file contents with xxh64 or sha256" },
+        OptSpec { short: Some('s'), long: "summary", value: None, help: "Print the number of changes of each kind" },
+        output::NULL,
+        output::JSON,
+        output::TSV,
+    ],
+    expression: false,
+    run: diff,
+};
+
+pub const DUPES: Command = Command {
+    name: "dupes",
+    operands: "[ROOT]",
+    about: "List groups of files with identical contents",
+    options: &[
+        OptSpec { short: None, long: "min-size", value: Some("BYTES"), help: "Ignore files smaller than BYTES (default 1)" },
+        OptSpec { short: None, long: "hash", value: Some("ALGO"), help: "Confirm duplicates with xxh64 (default) or sha256" },
+        ABSOLUTE,
+        output::NULL,
+        output::JSON,
+        output::TSV,
+    ],
+    expression: false,
+    run: dupes,
+};
+
+pub const EXPORT: Command = Command {
+    name: "export",
+    operands: "[ROOT]",
+    about: "Write the index as csv, tsv, json, jsonl, mtree, checksums, dot, html, index or mapped",
+    options: &[
+        OptSpec { short: None, long: "format", value: Some("FORMAT"), help: "The output format (default csv)" },
+        OptSpec { short: Some('o'), long: "output", value: Some("FILE"), help: "Write to FILE instead of standard output" },
+        OptSpec {
+            short: None,
+            long: "columns",
+            value: Some("LIST"),
+            help: "Comma-separated csv/tsv columns (default relative_path,kind,size,mtime,mode)",
+        },
+        OptSpec { short: None, long: "hash", value: Some("ALGO"), help: "The hash for checksums and the hash column" },
+    ],
+    expression: false,
+    run: export,
+};
+
+fn list(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let both_kinds = !matches.has("files") && !matches.has("dirs");
+    let (files, dirs) = (both_kinds || matches.has("files"), both_kinds || matches.has("dirs"));
+    let absolute = matches.has("absolute");
+    let relative = matches.has("relative") || !absolute;
+    let root_path = indexer.get_root_path();
+    let mode = OutputMode::from(matches)?;
+
+    if relative && absolute {
+        let mut rows: Vec<(PathBuf, PathBuf)> = Vec::new();
+        if files {
+            rows.extend(indexer.get_rl2ab_file_paths_map());
+        }
+        if dirs {
+            rows.extend(indexer.get_ab2rl_dir_paths_map().into_iter().map(|(ab_path, rl_path)| (rl_path, ab_path)));
+        }
+        rows.retain(|(rl_path, _)| !rl_path.as_os_str().is_empty());
+        rows.sort();
+        let mut writer = RecordWriter::new(out, mode, &["relative_path", "absolute_path"])?;
+        for (rl_path, ab_path) in rows {
+            writer.write(&[Value::Path(&rl_path), Value::Path(&ab_path)])?;
+        }
+        return Ok(EXIT_OK);
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    match (files, dirs, absolute) {
+        (true, _, false) => paths.extend(indexer.get_relative_file_paths_set()),
+        (true, _, true) => paths.extend(indexer.get_absolute_file_paths_set()),
+        _ => {}
+    }
+    match (dirs, absolute) {
+        (true, false) => paths.extend(indexer.get_relative_dir_paths_set()),
+        (true, true) => paths.extend(indexer.get_absolute_dir_paths_set()),
+        _ => {}
+    }
+    paths.retain(|path| !path.as_os_str().is_empty() && path != root_path);
+    paths.sort();
+    let field: &'static [&'static str] = if absolute { &["absolute_path"] } else { &["relative_path"] };
+    let mut writer = RecordWriter::new(out, mode, field)?;
+    for path in paths {
+        writer.write(&[Value::Path(&path)])?;
+    }
+    Ok(EXIT_OK)
+}
+
+fn tree(matches: &Matches, mut out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let root = matches.get_operands().first().map_or_else(|| PathBuf::from("."), PathBuf::from);
+    let annotation = match (matches.has("size"), matches.has("count")) {
+        (true, true) => return Err(CliErr::Usage("--size and --count cannot be combined".to_string())),
+        (true, false) => TreeAnnotation::Size,
+        (false, true) => TreeAnnotation::ChildCount,
+        (false, false) => TreeAnnotation::None,
+    };
+    let charset = if matches.has("ascii") { TreeCharset::Ascii } else { TreeCharset::Unicode };
+    let mut options = TreeRenderOptions::new()
+        .with_root_name(&root)
+        .with_dirs_only(matches.has("dirs"))
+        .with_collapse_chains(matches.has("collapse"))
+        .with_annotation(annotation)
+        .with_charset(charset);
+    if let Some(level) = matches.get_number("level")? {
+        options = options.with_max_depth(level as usize);
+    }
+    indexer.get_tree().write_tree(&mut out, &options)?;
+    Ok(EXIT_OK)
+}
+
+fn find(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let mut expression = String::new();
+    for arg in matches.get_expression() {
+        let arg = arg
+            .to_str()
+            .ok_or_else(|| CliErr::Usage(format!("expression argument '{}' is not valid UTF-8", arg.to_string_lossy())))?;
+        if !expression.is_empty() {
+            expression.push(' ');
+        }
+        push_query_word(&mut expression, arg);
+    }
+    let query = DirQuery::parse(&expression)?;
+    let mut writer = RecordWriter::new(out, OutputMode::from(matches)?, &["path"])?;
+    for node in indexer.find(&query) {
+        writer.write(&[Value::Path(&entry_path(&indexer, node.get_entry(), matches.has("absolute")))])?;
+    }
+    Ok(EXIT_OK)
+}
+
+/// Appends a shell word to a query so that the query tokenizer reads it back as one word.
+fn push_query_word(query: &mut String, word: &str) {
+    if word == "(" || word == ")" {
+        query.push_str(word);
+        return;
+    }
+    for c in word.chars() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | '\\' | '\'' | '"') {
+            query.push('\\');
+        }
+        query.push(c);
+    }
+}
+
+fn du(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let max_depth = if matches.has("summarize") {
+        0
+    } else {
+        matches.get_number("max-depth")?.unwrap_or(u64::MAX)
+    };
+    let usage = indexer.get_tree().get_usage();
+    let mut rows: Vec<(u64, &Path)> = Vec::new();
+    collect_du_rows(indexer.get_tree().get_root_node(), 0, max_depth, matches.has("all"), &usage, &mut rows);
+    let mut writer = RecordWriter::new(out, OutputMode::from(matches)?, &["size", "path"])?;
+    for (size, rl_path) in rows {
+        let rl_path = if rl_path.as_os_str().is_empty() { Path::new(".") } else { rl_path };
+        let human = human_size(size);
+        let size = if matches.has("human") { Value::Text(&human) } else { Value::Number(size) };
+        writer.write(&[size, Value::Path(rl_path)])?;
+    }
+    Ok(EXIT_OK)
+}
+
+/// Lists entries children first, as `du` does, with their aggregated sizes.
+fn collect_du_rows<'a>(
+    node: &'a DirNode,
+    depth: u64,
+    max_depth: u64,
+    all: bool,
+    usage: &std::collections::BTreeMap<PathBuf, dir_indexer::DirUsage>,
+    rows: &mut Vec<(u64, &'a Path)>,
+) {
+    let is_dir = node.get_kind() == DirEntryKind::Dir;
+    if is_dir {
+        for child in node.get_sorted_children() {
+            collect_du_rows(child, depth + 1, max_depth, all, usage, rows);
+        }
+    }
+    if depth <= max_depth && (is_dir || all) {
+        let size = usage.get(node.get_entry()).map_or(0, |usage| usage.get_size());
+        rows.push((size, node.get_entry()));
+    }
+}
+
+fn diff(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let (old, new) = match matches.get_operands() {
+        [old, new] => (open_index(Path::new(old))?, open_index(Path::new(new))?),
+        _ => return Err(CliErr::Usage("diff needs two operands, OLD and NEW".to_string())),
+    };
+    let mut options = DirDiffOptions::new();
+    if matches.has("renames") {
+        options = options.with_renames();
+    }
+    if let Some(algo) = matches.get_str("hash")? {
+        options = options.with_content_hash(parse_algo(algo)?);
+    }
+    let diff = old.diff_with(&new, &options);
+
+    let mode = OutputMode::from(matches)?;
+    if matches.has("summary") && mode == OutputMode::Null {
+        return Err(CliErr::Usage("--summary has no paths to print with -0".to_string()));
+    }
+    if matches.has("summary") {
+        let summary = diff.get_summary();
+        let mut writer = RecordWriter::new(out, mode, &["change", "count"])?;
+        writer.write(&[Value::Text("added"), Value::Number(summary.get_added() as u64)])?;
+        writer.write(&[Value::Text("removed"), Value::Number(summary.get_removed() as u64)])?;
+        writer.write(&[Value::Text("type_changed"), Value::Number(summary.get_type_changed() as u64)])?;
+        writer.write(&[Value::Text("modified"), Value::Number(summary.get_modified() as u64)])?;
+        writer.write(&[Value::Text("renamed"), Value::Number(summary.get_renamed() as u64)])?;
+    } else {
+        // The source of a rename comes before its destination, as in `git diff --name-status`
+        let mut writer = RecordWriter::new(out, mode, &["status", "from", "path"])?;
+        for (rl_path, change) in diff.get_changes() {
+            let (status, from) = match change {
+                DirChange::Added(_) => ("A", Value::Missing),
+                DirChange::Removed(_) => ("D", Value::Missing),
+                DirChange::TypeChanged(_, _) => ("T", Value::Missing),
+                DirChange::Modified(_) => ("M", Value::Missing),
+                DirChange::Renamed(_, from) => ("R", Value::Path(from)),
+            };
+            writer.write(&[Value::Text(status), from, Value::Path(rl_path)])?;
+        }
+    }
+    Ok(if diff.is_empty() { EXIT_OK } else { EXIT_DIFFERENT })
+}
+
+fn dupes(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let mut options = DuplicateOptions::new();
+    if let Some(min_size) = matches.get_number("min-size")? {
+        options = options.with_min_size(min_size);
+    }
+    if let Some(algo) = matches.get_str("hash")? {
+        options = options.with_hash_algo(parse_algo(algo)?);
+    }
+    let mode = OutputMode::from(matches)?;
+    let mut writer = RecordWriter::new(out, mode, &["group", "size", "path"])?;
+    for (i, group) in indexer.find_duplicates(&options).iter().enumerate() {
+        // Plain output separates groups with a blank line; JSON and TSV number them
+        if mode == OutputMode::Lines && i > 0 {
+            writer.get_out().write_all(b"\n")?;
+        }
+        for rl_path in group.get_paths() {
+            let path = entry_path(&indexer, rl_path, matches.has("absolute"));
+            if mode == OutputMode::Lines {
+                writer.write(&[Value::Path(&path)])?;
+            } else {
+                writer.write(&[Value::Number(i as u64 + 1), Value::Number(group.get_size()), Value::Path(&path)])?;
+            }
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+fn export(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let indexer = open_root(matches)?;
+    let format = matches.get_str("format")?.unwrap_or("csv");
+    let output = matches.get_value("output").map(PathBuf::from);
+    let algo = matches.get_str("hash")?.map(parse_algo).transpose()?;
+
+    match format {
+        "index" | "mapped" => {
+            let output = output.ok_or_else(|| CliErr::Usage(format!("the {} format needs --output FILE", format)))?;
+            if format == "index" {
+                indexer.save(&output)?;
+            } else {
+                indexer.save_mapped(&output)?;
+            }
+            return Ok(EXIT_OK);
+        }
+        "csv" | "tsv" | "json" | "jsonl" | "mtree" | "checksums" | "dot" | "html" => {}
+        _ => return Err(CliErr::Usage(format!("unknown export format '{}'", format))),
+    }
+
+    let mut file_out;
+    let mut out: &mut dyn Write = match &output {
+        Some(output) => {
+            file_out = BufWriter::new(fs::File::create(output)?);
+            &mut file_out
+        }
+        None => out,
+    };
+    match format {
+        "csv" | "tsv" => {
+            let columns = parse_columns(matches.get_str("columns")?.unwrap_or("relative_path,kind,size,mtime,mode"))?;
+            let table_format = if format == "csv" { TableFormat::Csv } else { TableFormat::Tsv };
+            let mut exporter = DirTableExporter::new(columns).with_format(table_format);
+            if let Some(algo) = algo {
+                exporter = exporter.with_hash_algo(algo);
+            }
+            exporter.write(&indexer, &mut out)?;
+        }
+        "json" => writeln!(out, "{}", indexer.get_tree().to_json())?,
+        "jsonl" => out.write_all(indexer.get_tree().to_json_lines().as_bytes())?,
+        "mtree" => indexer.write_mtree(&mut out, &MtreeOptions::new())?,
+        "dot" => indexer.get_tree().write_dot(&mut out, &DotOptions::new())?,
+        "html" => indexer.write_html_report(&mut out, &HtmlReportOptions::new())?,
+        _ => indexer.write_checksums(&mut out, algo.unwrap_or(HashAlgo::Sha256))?,
+    }
+    out.flush()?;
+    Ok(EXIT_OK)
+}
+
+fn parse_algo(name: &str) -> Result<HashAlgo, CliErr> {
+    [HashAlgo::Xxh64, HashAlgo::Sha256]
+        .iter()
+        .copied()
+        .find(|algo| algo.as_str() == name)
+        .ok_or_else(|| CliErr::Usage(format!("unknown hash algorithm '{}'; use xxh64 or sha256", name)))
+}
+
+fn parse_columns(list: &str) -> Result<Vec<DirColumn>, CliErr> {
+    list.split(',')
+        .map(|name| DirColumn::from(name.trim()).ok_or_else(|| CliErr::Usage(format!("unknown column '{}'", name))))
+        .collect()
+}
+
+/// Returns a path of the index, relative to its root or absolute.
+fn entry_path(indexer: &DirIndexer, rl_path: &Path, absolute: bool) -> PathBuf {
+    if absolute {
+        indexer.get_root_path().join(rl_path)
+    } else {
+        rl_path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_util::{output_of, run_command, TempDir};
+    use dir_indexer::DirIndexerErr;
+
+    /// `a/one.txt` (10 bytes), `a/b/two.txt` (20 bytes), `c.txt` (5 bytes) and an empty `d`.
+    fn sample() -> TempDir {
+        let dir = TempDir::new("commands");
+        dir.write("a/one.txt", b"0123456789");
+        dir.write("a/b/two.txt", &[b'x'; 20]);
+        dir.write("c.txt", b"hello");
+        fs::create_dir(dir.join("d")).unwrap();
+        dir
+    }
+
+    fn usage_error(command: &Command, args: &[&str]) -> String {
+        match run_command(command, args) {
+            Err(CliErr::Usage(reason)) => reason,
+            other => panic!("expected a usage error, got {:?}", other.map(|(status, _)| status)),
+        }
+    }
+
+    #[test]
+    fn list_prints_sorted_paths() {
+        let dir = sample();
+        let root = dir.get_arg();
+        assert_eq!(output_of(&LIST, &[root]), "a\na/b\na/b/two.txt\na/one.txt\nc.txt\nd\n");
+        assert_eq!(output_of(&LIST, &["-f", root]), "a/b/two.txt\na/one.txt\nc.txt\n");
+        assert_eq!(output_of(&LIST, &["--dirs", root]), "a\na/b\nd\n");
+        assert_eq!(output_of(&LIST, &["-da", root]), format!("{0}/a\n{0}/a/b\n{0}/d\n", root));
+        assert_eq!(output_of(&LIST, &["-fra", root]).lines().next().unwrap(), format!("a/b/two.txt\t{}/a/b/two.txt", root));
+        assert_eq!(usage_error(&LIST, &[root, "extra"]), "unexpected argument 'extra'");
+        assert!(matches!(run_command(&LIST, &[&format!("{}/missing", root)]), Err(CliErr::Index(_))));
+    }
+
+    #[test]
+    fn find_matches_expressions() {
+        let dir = sample();
+        dir.write("we ird 'q'.txt", b"");
+        let root = dir.get_arg();
+        assert_eq!(output_of(&FIND, &[root, "-type", "d"]), "a\na/b\nd\n");
+        assert_eq!(output_of(&FIND, &[root, "-name", "*.txt", "-size", "+6c"]), "a/b/two.txt\na/one.txt\n");
+        assert_eq!(output_of(&FIND, &[root, "(", "-empty", "-o", "-name", "c*", ")", "-type", "f"]), "c.txt\nwe ird 'q'.txt\n");
+        assert_eq!(output_of(&FIND, &[root, "-name", "we ird 'q'.txt"]), "we ird 'q'.txt\n");
+        assert_eq!(output_of(&FIND, &["-a", root, "-name", "c.txt"]), format!("{}/c.txt\n", root));
+        assert!(matches!(run_command(&FIND, &[root, "-bogus"]), Err(CliErr::Index(DirIndexerErr::QueryParseFail(_)))));
+    }
+
+    #[test]
+    fn push_query_word_quotes_what_the_tokenizer_splits_on() {
+        let mut query = String::new();
+        push_query_word(&mut query, "a (b) \"c\\d\"");
+        assert_eq!(query, "a\\ \\(b\\)\\ \\\"c\\\\d\\\"");
+        let mut query = String::new();
+        push_query_word(&mut query, "(");
+        assert_eq!(query, "(");
+    }
+
+    #[test]
+    fn du_lists_directories_children_first() {
+        let dir = sample();
+        let root = dir.get_arg();
+        assert_eq!(output_of(&DU, &[root]), "20\ta/b\n30\ta\n0\td\n35\t.\n");
+        assert_eq!(output_of(&DU, &["-s", root]), "35\t.\n");
+        assert_eq!(output_of(&DU, &["--max-depth", "1", root]), "30\ta\n0\td\n35\t.\n");
+        assert_eq!(
+            output_of(&DU, &["-a", "-d", "1", root]),
+            "30\ta\n5\tc.txt\n0\td\n35\t.\n"
+        );
+        assert_eq!(
+            output_of(&DU, &["-a", root]),
+            "20\ta/b/two.txt\n20\ta/b\n10\ta/one.txt\n30\ta\n5\tc.txt\n0\td\n35\t.\n"
+        );
+        dir.write("big", &vec![0; 1536]);
+        assert_eq!(output_of(&DU, &["-sH", root]), "1.5K\t.\n");
+        assert_eq!(usage_error(&DU, &["-d", "x", root]), "--max-depth expects a number, got 'x'");
+    }
+
+    #[test]
+    fn diff_reports_changes_and_exit_status() {
+        let dir = sample();
+        let root = dir.get_arg();
+        assert_eq!(run_command(&DIFF, &[root, root]).unwrap(), (EXIT_OK, Vec::new()));
+
+        // Compare a saved index of the tree against the tree after some changes
+        let out = TempDir::new("commands-diff");
+        let saved = out.join("old.idx");
+        let old = saved.to_str().unwrap();
+        output_of(&EXPORT, &["--format", "index", "-o", old, root]);
+        let (old, new) = (old, root);
+        fs::rename(dir.join("a/b"), dir.join("moved")).unwrap();
+        // Added before the removal, so that the new file cannot reuse the inode of the old one
+        dir.write("d/e.txt", b"");
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        let (status, out) = run_command(&DIFF, &[old, new]).unwrap();
+        assert_eq!(status, EXIT_DIFFERENT);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "D\ta/b\nD\ta/b/two.txt\nD\tc.txt\nA\td/e.txt\nA\tmoved\nA\tmoved/two.txt\n"
+        );
+        assert_eq!(
+            output_of(&DIFF, &["-R", old, new]),
+            "D\tc.txt\nA\td/e.txt\nR\ta/b\tmoved\n"
+        );
+        assert_eq!(
+            output_of(&DIFF, &["-Rs", old, new]),
+            "added\t1\nremoved\t1\ntype_changed\t0\nmodified\t0\nrenamed\t1\n"
+        );
+        assert_eq!(usage_error(&DIFF, &["-s0", old, new]), "--summary has no paths to print with -0");
+        assert_eq!(usage_error(&DIFF, &[old]), "diff needs two operands, OLD and NEW");
+        assert_eq!(
+            usage_error(&DIFF, &["--hash", "md5", old, new]),
+            "unknown hash algorithm 'md5'; use xxh64 or sha256"
+        );
+    }
+
+    #[test]
+    fn dupes_groups_identical_files() {
+        let dir = TempDir::new("commands-dupes");
+        dir.write("x1", b"same");
+        dir.write("x2", b"same");
+        dir.write("y1", b"other!");
+        dir.write("sub/y2", b"other!");
+        dir.write("lone", b"alone");
+        let root = dir.get_arg();
+        assert_eq!(output_of(&DUPES, &[root]), "sub/y2\ny1\n\nx1\nx2\n");
+        assert_eq!(output_of(&DUPES, &["--min-size", "5", "--hash", "sha256", root]), "sub/y2\ny1\n");
+        assert_eq!(output_of(&DUPES, &["-a", "--min-size", "5", root]), format!("{0}/sub/y2\n{0}/y1\n", root));
+    }
+
+    #[test]
+    fn export_writes_every_format() {
+        let dir = sample();
+        let root = dir.get_arg();
+        let out = TempDir::new("commands-export");
+        assert_eq!(
+            output_of(&EXPORT, &["--columns", "relative_path", root]),
+            "relative_path\r\na\r\na/b\r\na/b/two.txt\r\na/one.txt\r\nc.txt\r\nd\r\n"
+        );
+        assert!(output_of(&EXPORT, &["--format", "tsv", root]).starts_with("relative_path\tkind\tsize\tmtime\tmode\n"));
+        assert!(output_of(&EXPORT, &["--format", "json", root]).starts_with("[{\"type\":\"directory\",\"name\":\".\""));
+        assert_eq!(output_of(&EXPORT, &["--format", "jsonl", root]).lines().count(), 7);
+        assert!(output_of(&EXPORT, &["--format", "checksums", root]).contains("  c.txt\n"));
+        assert!(output_of(&EXPORT, &["--format", "mtree", root]).starts_with("#mtree"));
+
+        // Saved indexes are opened in place of a directory
+        let saved = out.join("saved.idx");
+        let saved = saved.to_str().unwrap();
+        assert_eq!(output_of(&EXPORT, &["--format", "index", "-o", saved, root]), "");
+        assert_eq!(output_of(&LIST, &[saved]), output_of(&LIST, &[root]));
+        let csv = out.join("out.csv");
+        output_of(&EXPORT, &["-o", csv.to_str().unwrap(), root]);
+        assert_eq!(fs::read_to_string(&csv).unwrap(), output_of(&EXPORT, &[root]));
+
+        assert_eq!(usage_error(&EXPORT, &["--format", "xml", root]), "unknown export format 'xml'");
+        assert_eq!(usage_error(&EXPORT, &["--format", "mapped", root]), "the mapped format needs --output FILE");
+        assert_eq!(usage_error(&EXPORT, &["--columns", "relative_path,bogus", root]), "unknown column 'bogus'");
+    }
+
+    #[test]
+    fn tree_rejects_conflicting_annotations() {
+        let dir = sample();
+        assert_eq!(usage_error(&TREE, &["-sn", dir.get_arg()]), "--size and --count cannot be combined");
+    }
+}
@@ -0,0 +1,44 @@
+//! The `daemon` subcommand: serve indexes to other tools over a Unix socket.
+
+use super::args::{Matches, OptSpec};
+use super::{open_index, CliErr, Command, EXIT_OK};
+use dir_indexer::{default_socket_path, DaemonOptions, IndexDaemon};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DAEMON: Command = Command {
+    name: "daemon",
+    operands: "[ROOT]...",
+    about: "Keep the indexes of the ROOTs in memory and answer queries about them over a Unix socket",
+    options: &[
+        OptSpec { short: Some('S'), long: "socket", value: Some("PATH"), help: "Listen on PATH (default $XDG_RUNTIME_DIR/dir_indexer.sock)" },
+        OptSpec { short: Some('i'), long: "interval", value: Some("SECONDS"), help: "Rescan every root this often; 0 turns rescans off (default 60)" },
+    ],
+    expression: false,
+    run: daemon,
+};
+
+fn daemon(matches: &Matches, out: &mut dyn Write) -> Result<i32, CliErr> {
+    let mut indexers = Vec::new();
+    for root in matches.get_operands() {
+        indexers.push(open_index(Path::new(root))?);
+    }
+    if indexers.is_empty() {
+        indexers.push(open_index(Path::new("."))?);
+    }
+    let mut options = DaemonOptions::new();
+    if let Some(interval) = matches.get_number("interval")? {
+        options = options.with_refresh_interval(Duration::from_secs(interval));
+    }
+    let socket_path = matches.get_value("socket").map_or_else(default_socket_path, PathBuf::from);
+
+    let daemon = IndexDaemon::from(indexers, &options);
+    for root_path in daemon.get_roots() {
+        writeln!(out, "serving {}", root_path.display())?;
+    }
+    writeln!(out, "listening on {}", socket_path.display())?;
+    out.flush()?;
+    daemon.serve(&socket_path)?;
+    Ok(EXIT_OK)
+}
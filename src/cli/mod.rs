@@ -0,0 +1,234 @@
+//! The `dir_indexer` command-line interface.
+
+mod args;
+mod browse;
+mod commands;
+#[cfg(unix)]
+mod daemon;
+mod output;
+#[cfg(test)]
+mod test_util;
+
+use args::{Matches, OptSpec};
+use dir_indexer::{DirIndexer, DirIndexerErr};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The command succeeded; for `diff`, the trees are the same.
+pub const EXIT_OK: i32 = 0;
+
+/// `diff` found differences.
+pub const EXIT_DIFFERENT: i32 = 1;
+
+/// The command line is invalid.
+pub const EXIT_USAGE: i32 = 2;
+
+/// Indexing, reading or writing failed.
+pub const EXIT_FAILURE: i32 = 3;
+
+/// The ways a command can fail.
+#[derive(Debug)]
+pub enum CliErr {
+    /// The command line is invalid; holds a description of the problem.
+    Usage(String),
+    /// The library reported an error.
+    Index(DirIndexerErr),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl From<DirIndexerErr> for CliErr {
+    fn from(err: DirIndexerErr) -> CliErr {
+        CliErr::Index(err)
+    }
+}
+
+impl From<io::Error> for CliErr {
+    fn from(err: io::Error) -> CliErr {
+        CliErr::Io(err)
+    }
+}
+
+impl fmt::Display for CliErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliErr::Usage(reason) => write!(f, "{}", reason),
+            CliErr::Index(err) => write!(f, "{}", err),
+            CliErr::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Describes one subcommand.
+pub struct Command {
+    pub name: &'static str,
+    /// The operands in the usage line, such as `[ROOT]`.
+    pub operands: &'static str,
+    /// One line describing the subcommand.
+    pub about: &'static str,
+    pub options: &'static [OptSpec],
+    /// Set when the arguments after the first operand form an expression, as for `find`.
+    pub expression: bool,
+    pub run: fn(&Matches, &mut dyn Write) -> Result<i32, CliErr>,
+}
+
+const COMMANDS: &[Command] = &[
+    commands::LIST,
+    commands::TREE,
+    commands::FIND,
+    commands::DU,
+    commands::DIFF,
+    commands::DUPES,
+    commands::EXPORT,
+    browse::BROWSE,
+    #[cfg(unix)]
+    daemon::DAEMON,
+];
+
+/// Runs the command line, without the program name, and returns the exit status.
+pub fn run(args: &[OsString]) -> i32 {
+    let (name, rest) = match args.split_first() {
+        Some((name, rest)) => (name, rest),
+        None => {
+            eprint!("{}", usage());
+            return EXIT_USAGE;
+        }
+    };
+    if name == "-h" || name == "--help" || name == "help" {
+        return match rest.first() {
+            Some(topic) => match find_command(topic) {
+                Some(command) => print_help(&command_usage(command)),
+                None => fail(&CliErr::Usage(format!("unknown command '{}'", topic.to_string_lossy()))),
+            },
+            None => print_help(&usage()),
+        };
+    }
+    if name == "-V" || name == "--version" {
+        return print_help(&format!("dir_indexer {}\n", env!("CARGO_PKG_VERSION")));
+    }
+    let command = match find_command(name) {
+        Some(command) => command,
+        None => return fail(&CliErr::Usage(format!("unknown command '{}'", name.to_string_lossy()))),
+    };
+    if rest.iter().take_while(|arg| *arg != "--").any(|arg| arg == "-h" || arg == "--help") {
+        return print_help(&command_usage(command));
+    }
+
+    let result = args::parse(command.options, rest, command.expression).and_then(|matches| {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let status = (command.run)(&matches, &mut out)?;
+        out.flush()?;
+        Ok(status)
+    });
+    match result {
+        Ok(status) => status,
+        // The reader went away, as with `| head`; there is nobody left to tell
+        Err(CliErr::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => EXIT_OK,
+        Err(err) => fail(&err),
+    }
+}
+
+fn find_command(name: &OsStr) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| name == command.name)
+}
+
+fn print_help(text: &str) -> i32 {
+    print!("{}", text);
+    EXIT_OK
+}
+
+/// Reports an error on stderr and returns the matching exit status.
+fn fail(err: &CliErr) -> i32 {
+    eprintln!("dir_indexer: {}", err);
+    match err {
+        CliErr::Usage(_) | CliErr::Index(DirIndexerErr::QueryParseFail(_)) => {
+            eprintln!("Try 'dir_indexer --help' for more information.");
+            EXIT_USAGE
+        }
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn usage() -> String {
+    let width = COMMANDS.iter().map(|command| command.name.len()).max().unwrap_or(0);
+    let mut text = String::from("Usage: dir_indexer COMMAND [OPTIONS] [ROOT]\n\nIndex a directory tree and query it.\n\nCommands:\n");
+    for command in COMMANDS {
+        text.push_str(&format!("  {:width$}  {}\n", command.name, command.about, width = width));
+    }
+    text.push_str(concat!(
+        "\n",
+        "ROOT is a directory to scan, or an index file saved with 'export --format index'. It\n",
+        "defaults to the current directory.\n",
+        "\n",
+        "Run 'dir_indexer help COMMAND' for the options of a command.\n",
+        "\n",
+        "Exit status: 0 on success, 1 when 'diff' finds differences, 2 for an invalid command\n",
+        "line, 3 when indexing, reading or writing fails.\n",
+    ));
+    text
+}
+
+fn command_usage(command: &Command) -> String {
+    format!(
+        "Usage: dir_indexer {} [OPTIONS] {}\n\n{}\n\nOptions:\n{}",
+        command.name,
+        command.operands,
+        command.about,
+        args::describe(command.options)
+    )
+}
+
+/// Indexes a directory, or loads a saved index if `path` is a regular file.
+pub fn open_index(path: &Path) -> Result<DirIndexer, CliErr> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => Ok(DirIndexer::load(path)?),
+        _ => Ok(DirIndexer::from(path.to_path_buf())?),
+    }
+}
+
+/// Opens the index named by the only operand, or the current directory if there is none.
+pub fn open_root(matches: &Matches) -> Result<DirIndexer, CliErr> {
+    match matches.get_operands() {
+        [] => open_index(Path::new(".")),
+        [root] => open_index(Path::new(root)),
+        [_, extra, ..] => Err(CliErr::Usage(format!("unexpected argument '{}'", extra.to_string_lossy()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_of(args: &[&str]) -> i32 {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        run(&args)
+    }
+
+    #[test]
+    fn exit_statuses_follow_the_outcome() {
+        assert_eq!(status_of(&[]), EXIT_USAGE);
+        assert_eq!(status_of(&["--help"]), EXIT_OK);
+        assert_eq!(status_of(&["help", "du"]), EXIT_OK);
+        assert_eq!(status_of(&["help", "bogus"]), EXIT_USAGE);
+        assert_eq!(status_of(&["-V"]), EXIT_OK);
+        assert_eq!(status_of(&["bogus"]), EXIT_USAGE);
+        assert_eq!(status_of(&["list", "--bogus"]), EXIT_USAGE);
+        assert_eq!(status_of(&["list", "--bogus", "--help"]), EXIT_OK);
+        assert_eq!(status_of(&["find", ".", "-bogus"]), EXIT_USAGE);
+        assert_eq!(status_of(&["list", "/nonexistent/dir_indexer/root"]), EXIT_FAILURE);
+        assert_eq!(status_of(&["diff", "src", "src"]), EXIT_OK);
+    }
+
+    #[test]
+    fn every_command_has_help() {
+        for command in COMMANDS {
+            let help = command_usage(command);
+            assert!(help.starts_with(&format!("Usage: dir_indexer {} [OPTIONS] ", command.name)));
+            assert!(usage().contains(command.about));
+        }
+    }
+}
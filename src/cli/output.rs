@@ -0,0 +1,338 @@
+//! Output modes shared by the listing subcommands.
+//!
+//! A listing is a sequence of records with fixed, named fields. The modes write them as:
+//!
+//! * lines (the default): fields separated by tabs, records by newlines, paths as raw bytes;
+//!   fields without a value are left out
+//! * `-0`: only the path fields, each terminated by a NUL byte and written unescaped, as
+//!   `find -print0` does, so that every item handed to `xargs -0` is a path; the other fields
+//!   and fields without a value are left out
+//! * `--tsv`: a header row of field names, then one row per record; tabs, newlines, carriage
+//!   returns and backslashes inside fields are backslash-escaped, other bytes are written as is
+//! * `--json`: one JSON object per record. A path that is not valid UTF-8 is written lossily and
+//!   its exact bytes follow in an extra `<field>_bytes` array of numbers
+
+use super::args::{Matches, OptSpec};
+use super::CliErr;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const NULL: OptSpec = OptSpec {
+    short: Some('0'),
+    long: "null",
+    value: None,
+    help: "Print only the paths, each terminated by a NUL byte, for xargs -0",
+};
+
+pub const JSON: OptSpec = OptSpec {
+    short: None,
+    long: "json",
+    value: None,
+    help: "Print one JSON object per line",
+};
+
+pub const TSV: OptSpec = OptSpec {
+    short: None,
+    long: "tsv",
+    value: None,
+    help: "Print tab-separated rows after a header row",
+};
+
+/// How records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Lines,
+    Null,
+    JsonLines,
+    Tsv,
+}
+
+impl OutputMode {
+    /// Picks the mode requested on the command line; at most one may be given.
+    pub fn from(matches: &Matches) -> Result<OutputMode, CliErr> {
+        let requested: Vec<OutputMode> = [
+            ("null", OutputMode::Null),
+            ("json", OutputMode::JsonLines),
+            ("tsv", OutputMode::Tsv),
+        ]
+        .iter()
+        .filter(|(long, _)| matches.has(long))
+        .map(|(_, mode)| *mode)
+        .collect();
+        match requested.as_slice() {
+            [] => Ok(OutputMode::Lines),
+            [mode] => Ok(*mode),
+            _ => Err(CliErr::Usage("-0, --json and --tsv cannot be combined".to_string())),
+        }
+    }
+}
+
+/// The value of one field of a record.
+#[derive(Debug, Clone, Copy)]
+pub enum Value<'a> {
+    Path(&'a Path),
+    Text(&'a str),
+    Number(u64),
+    /// No value; left out in lines and with `-0`, empty in TSV, `null` in JSON.
+    ///
+    /// Only `Path` values are written with `-0`.
+    Missing,
+}
+
+/// Writes records with the fields named at construction.
+pub struct RecordWriter<'a> {
+    out_: &'a mut dyn Write,
+    mode_: OutputMode,
+    fields_: &'static [&'static str],
+}
+
+impl<'a> RecordWriter<'a> {
+    /// Creates a writer, writing the header row right away in TSV mode.
+    pub fn new(out: &'a mut dyn Write, mode: OutputMode, fields: &'static [&'static str]) -> io::Result<RecordWriter<'a>> {
+        if mode == OutputMode::Tsv {
+            out.write_all(fields.join("\t").as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        Ok(RecordWriter {
+            out_: out,
+            mode_: mode,
+            fields_: fields,
+        })
+    }
+
+    /// Gives access to the output, as for a separator between groups of records.
+    pub fn get_out(&mut self) -> &mut dyn Write {
+        &mut *self.out_
+    }
+
+    /// Writes one record; `values` are in the order of the field names.
+    pub fn write(&mut self, values: &[Value]) -> io::Result<()> {
+        let mut line: Vec<u8> = Vec::new();
+        match self.mode_ {
+            OutputMode::Lines => {
+                let fields: Vec<Vec<u8>> = values
+                    .iter()
+                    .filter(|value| !matches!(value, Value::Missing))
+                    .map(value_bytes)
+                    .collect();
+                line = fields.join(&b'\t');
+                line.push(b'\n');
+            }
+            OutputMode::Tsv => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        line.push(b'\t');
+                    }
+                    push_tsv_field(&mut line, &value_bytes(value));
+                }
+                line.push(b'\n');
+            }
+            OutputMode::Null => {
+                for value in values {
+                    if let Value::Path(path) = value {
+                        line.extend_from_slice(&path_bytes(path));
+                        line.push(0);
+                    }
+                }
+            }
+            OutputMode::JsonLines => {
+                let mut object = String::from("{");
+                for (i, (name, value)) in self.fields_.iter().zip(values).enumerate() {
+                    if i > 0 {
+                        object.push(',');
+                    }
+                    push_json_string(&mut object, name);
+                    object.push(':');
+                    match value {
+                        Value::Path(path) => match path.to_str() {
+                            Some(text) => push_json_string(&mut object, text),
+                            None => {
+                                push_json_string(&mut object, &path.to_string_lossy());
+                                object.push(',');
+                                push_json_string(&mut object, &format!("{}_bytes", name));
+                                object.push(':');
+                                let bytes: Vec<String> = path_bytes(path).iter().map(u8::to_string).collect();
+                                let _ = write!(object, "[{}]", bytes.join(","));
+                            }
+                        },
+                        Value::Text(text) => push_json_string(&mut object, text),
+                        Value::Number(number) => {
+                            let _ = write!(object, "{}", number);
+                        }
+                        Value::Missing => object.push_str("null"),
+                    }
+                }
+                object.push_str("}\n");
+                line = object.into_bytes();
+            }
+        }
+        self.out_.write_all(&line)
+    }
+}
+
+fn value_bytes(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Path(path) => path_bytes(path),
+        Value::Text(text) => text.as_bytes().to_vec(),
+        Value::Number(number) => number.to_string().into_bytes(),
+        Value::Missing => Vec::new(),
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+/// Appends a TSV field, backslash-escaping characters that would break the row structure.
+fn push_tsv_field(line: &mut Vec<u8>, field: &[u8]) {
+    for byte in field {
+        match byte {
+            b'\t' => line.extend_from_slice(b"\\t"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            b'\r' => line.extend_from_slice(b"\\r"),
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            _ => line.push(*byte),
+        }
+    }
+}
+
+/// Appends `s` as a quoted JSON string.
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::commands::{DIFF, DUPES, EXPORT, LIST};
+    use crate::cli::test_util::{output_of, run_command, TempDir};
+    use std::ffi::OsString;
+
+    const FIELDS: &[&str] = &["status", "from", "path"];
+
+    fn written(mode: OutputMode, records: &[[Value; 3]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut writer = RecordWriter::new(&mut out, mode, FIELDS).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        out
+    }
+
+    fn mode_of(args: &[&str]) -> Result<OutputMode, CliErr> {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        OutputMode::from(&crate::cli::args::parse(&[NULL, JSON, TSV], &args, false)?)
+    }
+
+    #[test]
+    fn picks_one_mode() {
+        assert_eq!(mode_of(&[]).unwrap(), OutputMode::Lines);
+        assert_eq!(mode_of(&["-0"]).unwrap(), OutputMode::Null);
+        assert_eq!(mode_of(&["--json"]).unwrap(), OutputMode::JsonLines);
+        assert_eq!(mode_of(&["--tsv", "--tsv"]).unwrap(), OutputMode::Tsv);
+        assert!(matches!(mode_of(&["-0", "--json"]), Err(CliErr::Usage(reason)) if reason == "-0, --json and --tsv cannot be combined"));
+    }
+
+    #[test]
+    fn writes_records_in_every_mode() {
+        let records = [
+            [Value::Text("A"), Value::Missing, Value::Path(Path::new("new file"))],
+            [Value::Text("R"), Value::Path(Path::new("a\tb")), Value::Path(Path::new("c\\d\ne"))],
+            [Value::Number(7), Value::Text("quote\"\u{1}"), Value::Path(Path::new("x\ry"))],
+        ];
+        assert_eq!(written(OutputMode::Lines, &records), b"A\tnew file\nR\ta\tb\tc\\d\ne\n7\tquote\"\x01\tx\ry\n");
+        assert_eq!(written(OutputMode::Null, &records), b"new file\0a\tb\0c\\d\ne\0x\ry\0");
+        assert_eq!(
+            String::from_utf8(written(OutputMode::Tsv, &records)).unwrap(),
+            "status\tfrom\tpath\nA\t\tnew file\nR\ta\\tb\tc\\\\d\\ne\n7\tquote\"\u{1}\tx\\ry\n"
+        );
+        assert_eq!(
+            String::from_utf8(written(OutputMode::JsonLines, &records)).unwrap(),
+            concat!(
+                "{\"status\":\"A\",\"from\":null,\"path\":\"new file\"}\n",
+                "{\"status\":\"R\",\"from\":\"a\\tb\",\"path\":\"c\\\\d\\ne\"}\n",
+                "{\"status\":7,\"from\":\"quote\\\"\\u0001\",\"path\":\"x\\ry\"}\n",
+            )
+        );
+        assert_eq!(written(OutputMode::Tsv, &[]), b"status\tfrom\tpath\n");
+        assert!(written(OutputMode::JsonLines, &[]).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_bytes_of_paths_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+        let records = [[Value::Text("A"), Value::Missing, Value::Path(path)]];
+        assert_eq!(written(OutputMode::Lines, &records), b"A\tcaf\xe9\n");
+        assert_eq!(written(OutputMode::Null, &records), b"caf\xe9\0");
+        assert_eq!(
+            String::from_utf8(written(OutputMode::JsonLines, &records)).unwrap(),
+            "{\"status\":\"A\",\"from\":null,\"path\":\"caf\u{fffd}\",\"path_bytes\":[99,97,102,233]}\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_the_contents_of_directories_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = TempDir::new("output-raw-dir");
+        dir.write(Path::new(std::ffi::OsStr::from_bytes(b"caf\xe9/inner.txt")), b"");
+        let (status, out) = run_command(&LIST, &["-0", dir.get_arg()]).unwrap();
+        assert_eq!(status, 0);
+        assert_eq!(out, b"caf\xe9\0caf\xe9/inner.txt\0".to_vec());
+        let (_, out) = run_command(&LIST, &["--json", "-f", dir.get_arg()]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"relative_path\":\"caf\u{fffd}/inner.txt\",\"relative_path_bytes\":[99,97,102,233,47,105,110,110,101,114,46,116,120,116]}\n"
+        );
+    }
+
+    #[test]
+    fn listing_commands_honour_the_mode() {
+        let dir = TempDir::new("output");
+        dir.write("a/x", b"same");
+        dir.write("b\tc", b"same");
+        let root = dir.get_arg();
+        assert_eq!(output_of(&LIST, &["-f0", root]), "a/x\0b\tc\0");
+        assert_eq!(output_of(&LIST, &["-f", "--tsv", root]), "relative_path\na/x\nb\\tc\n");
+        assert_eq!(
+            output_of(&LIST, &["-fra", "--json", root]).lines().next().unwrap(),
+            format!("{{\"relative_path\":\"a/x\",\"absolute_path\":\"{}/a/x\"}}", root)
+        );
+        assert_eq!(output_of(&DUPES, &["--tsv", root]), "group\tsize\tpath\n1\t4\ta/x\n1\t4\tb\\tc\n");
+        assert_eq!(output_of(&DUPES, &["-0", root]), "a/x\0b\tc\0");
+        assert!(matches!(run_command(&LIST, &["-0", "--tsv", root]), Err(CliErr::Usage(_))));
+
+        // Renames print both paths; statuses are left out
+        let saved = TempDir::new("output-diff");
+        let old = saved.join("old.idx");
+        let old = old.to_str().unwrap();
+        output_of(&EXPORT, &["--format", "index", "-o", old, root]);
+        std::fs::rename(dir.join("a"), dir.join("z")).unwrap();
+        assert_eq!(output_of(&DIFF, &["-R0", old, root]), "a\0z\0");
+        assert_eq!(output_of(&DIFF, &["-R", "--tsv", old, root]), "status\tfrom\tpath\nR\ta\tz\n");
+    }
+}
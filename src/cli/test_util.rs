@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests of the subcommands.
+
+use super::{args, CliErr, Command};
+use std::ffi::OsString;
+
+#[path = "../dir_indexer/test_util.rs"]
+mod temp_dir;
+
+pub(crate) use self::temp_dir::TempDir;
+
+/// Parses `args` for a subcommand and runs it, returning its exit status and output.
+pub fn run_command(command: &Command, args: &[&str]) -> Result<(i32, Vec<u8>), CliErr> {
+    let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+    let matches = args::parse(command.options, &args, command.expression)?;
+    let mut out = Vec::new();
+    let status = (command.run)(&matches, &mut out)?;
+    Ok((status, out))
+}
+
+/// Runs a subcommand that must succeed and returns its output as text.
+pub fn output_of(command: &Command, args: &[&str]) -> String {
+    let (_, out) = run_command(command, args).unwrap();
+    String::from_utf8(out).unwrap()
+}
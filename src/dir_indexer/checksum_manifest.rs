@@ -0,0 +1,341 @@
+//! Generation and verification of checksum manifests in the format of coreutils `sha256sum`.
+//!
+//! Each line holds a hex digest, two spaces and a path relative to the indexed root. Names holding
+//! a backslash, line feed or carriage return are escaped and the line is prefixed with a backslash,
+//! as coreutils does. The verifier also accepts the binary-mode `*` marker and the tagged
+//! `SHA256 (path) = digest` form.
+
+use super::{DirEntryKind, DirIndexer, DirIndexerErr, HashAlgo};
+use super::codec;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Represents the outcome of checking one manifest line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// The file exists and its digest matches.
+    Ok,
+    /// The file exists but its digest differs or it could not be read.
+    Failed,
+    /// The file is not present in the index.
+    Missing,
+}
+
+impl ChecksumStatus {
+    /// Returns the status as printed by `sha256sum --check`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumStatus::Ok => "OK",
+            ChecksumStatus::Failed => "FAILED",
+            ChecksumStatus::Missing => "MISSING",
+        }
+    }
+}
+
+/// The result of verifying an index against a checksum manifest.
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumReport {
+    results_: Vec<(PathBuf, ChecksumStatus)>,
+    untracked_: Vec<PathBuf>,
+}
+
+impl ChecksumReport {
+    /// Returns the status of every manifest line, in manifest order.
+    pub fn get_results(&self) -> &[(PathBuf, ChecksumStatus)] {
+        &self.results_
+    }
+
+    /// Returns the paths whose status is `status`, in manifest order.
+    pub fn get_with_status(&self, status: ChecksumStatus) -> Vec<&PathBuf> {
+        self.results_
+            .iter()
+            .filter(|(_, s)| *s == status)
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Returns the sorted relative paths of indexed files absent from the manifest.
+    pub fn get_untracked(&self) -> &[PathBuf] {
+        &self.untracked_
+    }
+
+    /// Returns `true` if every listed file matched and no file is untracked.
+    pub fn is_ok(&self) -> bool {
+        self.untracked_.is_empty() && self.results_.iter().all(|(_, s)| *s == ChecksumStatus::Ok)
+    }
+}
+
+/// Writes one manifest line per regular file of the index, sorted by relative path.
+///
+/// Files that cannot be read are skipped.
+pub(crate) fn write_checksums<W: Write>(indexer: &DirIndexer, out: &mut W, algo: HashAlgo) -> io::Result<()> {
+    let root_path = indexer.get_root_path();
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        if node.get_kind() != DirEntryKind::File {
+            continue;
+        }
+        let hash = match node.hash_contents(root_path, algo) {
+            Ok(hash) => hash,
+            Err(_) => continue,
+        };
+        let name = codec::path_to_bytes(&rl_path);
+        let mut line: Vec<u8> = Vec::with_capacity(name.len() + 80);
+        let escaped = name.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r'));
+        if escaped {
+            line.push(b'\\');
+        }
+        line.extend_from_slice(hash.to_hex().as_bytes());
+        line.extend_from_slice(b"  ");
+        for byte in name {
+            match byte {
+                b'\\' if escaped => line.extend_from_slice(b"\\\\"),
+                b'\n' => line.extend_from_slice(b"\\n"),
+                b'\r' => line.extend_from_slice(b"\\r"),
+                _ => line.push(*byte),
+            }
+        }
+        line.push(b'\n');
+        out.write_all(&line)?;
+    }
+    out.flush()
+}
+
+/// Verifies the regular files of the index against a checksum manifest.
+pub(crate) fn verify_checksums(indexer: &DirIndexer, manifest: &[u8], algo: HashAlgo) -> Result<ChecksumReport, DirIndexerErr> {
+    let nodes = indexer.get_tree().get_rl2node_map();
+    let root_path = indexer.get_root_path();
+    let mut report = ChecksumReport::default();
+    let mut listed: BTreeSet<PathBuf> = BTreeSet::new();
+
+    for (line_no, line) in manifest.split(|b| *b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() || line[0] == b'#' {
+            continue;
+        }
+        let (digest, rl_path) = parse_line(line, algo)
+            .ok_or_else(|| DirIndexerErr::ManifestParseFail(format!("line {}: improperly formatted checksum line", line_no + 1)))?;
+        let status = match nodes.get(&rl_path) {
+            Some(node) if node.get_kind() == DirEntryKind::File => {
+                match node.hash_contents(root_path, algo) {
+                    Ok(hash) if hash.to_hex().eq_ignore_ascii_case(&digest) => ChecksumStatus::Ok,
+                    _ => ChecksumStatus::Failed,
+                }
+            }
+            _ => ChecksumStatus::Missing,
+        };
+        listed.insert(rl_path.clone());
+        report.results_.push((rl_path, status));
+    }
+    for (rl_path, node) in nodes {
+        if node.get_kind() == DirEntryKind::File && !listed.contains(&rl_path) {
+            report.untracked_.push(rl_path);
+        }
+    }
+    Ok(report)
+}
+
+/// Parses a manifest line into its hex digest and normalized relative path.
+fn parse_line(line: &[u8], algo: HashAlgo) -> Option<(String, PathBuf)> {
+    let (escaped, line) = match line.strip_prefix(b"\\") {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (digest, name) = match parse_tagged(line, algo) {
+        Some(parsed) => parsed,
+        None => {
+            let digest_end = line.iter().position(|b| *b == b' ')?;
+            let name = match &line[digest_end..] {
+                [b' ', b' ', name @ ..] | [b' ', b'*', name @ ..] => name,
+                _ => return None,
+            };
+            (&line[..digest_end], name)
+        }
+    };
+    if digest.len() != algo.digest_len() * 2 || !digest.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let name = if escaped { unescape(name)? } else { name.to_vec() };
+    let rl_path = codec::normalize_relative(&codec::bytes_to_path(&name)?)?;
+    if rl_path.as_os_str().is_empty() {
+        return None;
+    }
+    Some((String::from_utf8(digest.to_vec()).ok()?, rl_path))
+}
+
+/// Splits a tagged line, `TAG (name) = digest`, into its digest and name.
+fn parse_tagged(line: &[u8], algo: HashAlgo) -> Option<(&[u8], &[u8])> {
+    let tag = algo.as_str().to_ascii_uppercase();
+    let rest = line.strip_prefix(tag.as_bytes())?.strip_prefix(b" (")?;
+    let split = rest.windows(4).rposition(|w| w == b") = ")?;
+    Some((&rest[split + 4..], &rest[..split]))
+}
+
+fn unescape(name: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut bytes = name.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte != b'\\' {
+            out.push(*byte);
+            continue;
+        }
+        out.push(match bytes.next()? {
+            b'\\' => b'\\',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::{hash_bytes, HashCache};
+    use std::fs;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn manifest(indexer: &DirIndexer, algo: HashAlgo) -> Vec<u8> {
+        let mut out = Vec::new();
+        indexer.write_checksums(&mut out, algo).unwrap();
+        out
+    }
+
+    #[test]
+    fn manifests_match_sha256sum() {
+        let dir = TempDir::new("checksum-format");
+        dir.write("abc.txt", b"abc");
+        dir.write("sub/empty", b"");
+        fs::create_dir(dir.join("no-files")).unwrap();
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let text = String::from_utf8(manifest(&indexer, HashAlgo::Sha256)).unwrap();
+        assert_eq!(
+            text,
+            format!(
+                "{}  abc.txt\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  sub/empty\n",
+                ABC_SHA256
+            )
+        );
+    }
+
+    #[test]
+    fn stored_hashes_of_the_same_algorithm_are_not_read_again() {
+        let dir = TempDir::new("checksum-stored-hash");
+        dir.write("abc.txt", b"abc");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        fs::remove_file(dir.join("abc.txt")).unwrap();
+
+        let text = format!("{}  abc.txt\n", ABC_SHA256);
+        assert_eq!(manifest(&indexer, HashAlgo::Sha256), text.as_bytes());
+        assert!(indexer.verify_checksums(text.as_bytes(), HashAlgo::Sha256).unwrap().is_ok());
+        assert!(manifest(&indexer, HashAlgo::Xxh64).is_empty());
+    }
+
+    #[test]
+    fn awkward_names_are_escaped_and_read_back() {
+        let dir = TempDir::new("checksum-escape");
+        dir.write("back\\slash", b"1");
+        dir.write("line\nfeed", b"2");
+        dir.write("carriage\rreturn", b"3");
+        dir.write("plain name  with spaces", b"4");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let bytes = manifest(&indexer, HashAlgo::Xxh64);
+        let lines: Vec<&[u8]> = bytes.split(|b| *b == b'\n').filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().any(|line| line.starts_with(b"\\") && line.ends_with(b"  back\\\\slash")));
+        assert!(lines.iter().any(|line| line.starts_with(b"\\") && line.ends_with(b"  line\\nfeed")));
+        assert!(lines.iter().any(|line| line.starts_with(b"\\") && line.ends_with(b"  carriage\\rreturn")));
+        assert!(lines.iter().any(|line| !line.starts_with(b"\\") && line.ends_with(b"  plain name  with spaces")));
+
+        let report = indexer.verify_checksums(&bytes, HashAlgo::Xxh64).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.get_with_status(ChecksumStatus::Ok).len(), 4);
+    }
+
+    #[test]
+    fn verification_reports_each_status() {
+        let dir = TempDir::new("checksum-verify");
+        dir.write("abc.txt", b"abc");
+        dir.write("changed", b"old");
+        dir.write("untracked", b"u");
+        fs::create_dir(dir.join("sub")).unwrap();
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let changed = hash_bytes(HashAlgo::Sha256, b"old").to_hex();
+        let text = format!(
+            "{}  abc.txt\n{}  ./changed\n{}  gone\n{}  sub\n",
+            ABC_SHA256.to_ascii_uppercase(),
+            changed,
+            ABC_SHA256,
+            ABC_SHA256
+        );
+        dir.write("changed", b"new");
+
+        let report = indexer.verify_checksums(text.as_bytes(), HashAlgo::Sha256).unwrap();
+        assert!(!report.is_ok());
+        let expected = [
+            (PathBuf::from("abc.txt"), ChecksumStatus::Ok),
+            (PathBuf::from("changed"), ChecksumStatus::Failed),
+            (PathBuf::from("gone"), ChecksumStatus::Missing),
+            (PathBuf::from("sub"), ChecksumStatus::Missing),
+        ];
+        assert_eq!(report.get_results(), &expected[..]);
+        assert_eq!(report.get_with_status(ChecksumStatus::Missing), vec![&expected[2].0, &expected[3].0]);
+        assert_eq!(report.get_untracked(), &[PathBuf::from("untracked")]);
+    }
+
+    #[test]
+    fn binary_and_tagged_lines_are_accepted() {
+        let dir = TempDir::new("checksum-forms");
+        dir.write("abc.txt", b"abc");
+        dir.write("odd (name) = x", b"abc");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let text = format!(
+            "# comment\r\n{} *abc.txt\r\n\nSHA256 (odd (name) = x) = {}\n",
+            ABC_SHA256, ABC_SHA256
+        );
+        let report = indexer.verify_checksums(text.as_bytes(), HashAlgo::Sha256).unwrap();
+        assert!(report.is_ok(), "{:?}", report.get_results());
+        assert_eq!(report.get_results().len(), 2);
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let dir = TempDir::new("checksum-malformed");
+        dir.write("abc.txt", b"abc");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let short = &ABC_SHA256[..63];
+        let cases = vec![
+            format!("{} abc.txt", ABC_SHA256),
+            format!("{}  abc.txt", short),
+            format!("{}z  abc.txt", short),
+            format!("{}", ABC_SHA256),
+            format!("{}  ", ABC_SHA256),
+            format!("{}  .", ABC_SHA256),
+            format!("{}  ../abc.txt", ABC_SHA256),
+            format!("{}  /etc/passwd", ABC_SHA256),
+            format!("\\{}  bad\\escape", ABC_SHA256),
+            format!("\\{}  dangling\\", ABC_SHA256),
+            format!("SHA256 (abc.txt) {}", ABC_SHA256),
+            format!("XXH64 (abc.txt) = {}", ABC_SHA256),
+        ];
+        for case in cases.iter() {
+            let text = format!("{}  abc.txt\n{}\n", ABC_SHA256, case);
+            match indexer.verify_checksums(text.as_bytes(), HashAlgo::Sha256) {
+                Err(DirIndexerErr::ManifestParseFail(reason)) => assert_eq!(reason, "line 2: improperly formatted checksum line"),
+                other => panic!("accepted {:?}: {:?}", case, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn names_unescape_strictly() {
+        assert_eq!(unescape(b"a\\\\b\\nc\\rd"), Some(b"a\\b\nc\rd".to_vec()));
+        assert_eq!(unescape(b"plain"), Some(b"plain".to_vec()));
+        assert_eq!(unescape(b"bad\\t"), None);
+        assert_eq!(unescape(b"end\\"), None);
+    }
+}
@@ -0,0 +1,238 @@
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+/// Appends primitive values to a byte buffer using LEB128 varints.
+#[derive(Debug, Default)]
+pub(crate) struct ByteWriter {
+    buf_: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub(crate) fn new() -> ByteWriter {
+        ByteWriter::default()
+    }
+
+    pub(crate) fn put_raw(&mut self, bytes: &[u8]) {
+        self.buf_.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn put_u8(&mut self, value: u8) {
+        self.buf_.push(value);
+    }
+
+    pub(crate) fn put_u32_le(&mut self, value: u32) {
+        self.buf_.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn put_u64_le(&mut self, value: u64) {
+        self.buf_.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn put_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf_.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf_.push(value as u8);
+    }
+
+    /// Writes a signed value using zigzag encoding.
+    pub(crate) fn put_varint_signed(&mut self, value: i64) {
+        self.put_varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub(crate) fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put_varint(bytes.len() as u64);
+        self.buf_.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.buf_
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        self.buf_
+    }
+}
+
+/// Reads primitive values back from a byte slice, failing instead of panicking on truncated or
+/// malformed input.
+#[derive(Debug)]
+pub(crate) struct ByteReader<'a> {
+    data_: &'a [u8],
+    pos_: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn from(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data_: data, pos_: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data_.len() - self.pos_
+    }
+
+    pub(crate) fn get_raw(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.remaining() {
+            return None;
+        }
+        let bytes = &self.data_[self.pos_..self.pos_ + len];
+        self.pos_ += len;
+        Some(bytes)
+    }
+
+    pub(crate) fn get_u8(&mut self) -> Option<u8> {
+        self.get_raw(1).map(|b| b[0])
+    }
+
+    pub(crate) fn get_u32_le(&mut self) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.get_raw(4)?);
+        Some(u32::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn get_u64_le(&mut self) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.get_raw(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn get_varint(&mut self) -> Option<u64> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.get_u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn get_varint_signed(&mut self) -> Option<i64> {
+        let raw = self.get_varint()?;
+        Some(((raw >> 1) as i64) ^ -((raw & 1) as i64))
+    }
+
+    /// Reads a varint that must fit in a `usize` and not exceed `max`.
+    pub(crate) fn get_len(&mut self, max: usize) -> Option<usize> {
+        let value = self.get_varint()?;
+        if value > max as u64 {
+            return None;
+        }
+        Some(value as usize)
+    }
+
+    pub(crate) fn get_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.get_len(self.remaining())?;
+        self.get_raw(len)
+    }
+}
+
+/// Returns the raw bytes of a path; non-UTF-8 names are preserved on Unix.
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> &[u8] {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes()
+}
+
+/// Returns the raw bytes of a path.
+#[cfg(not(unix))]
+pub(crate) fn path_to_bytes(path: &Path) -> &[u8] {
+    path.to_str().unwrap_or("").as_bytes()
+}
+
+/// Rebuilds a path from the bytes produced by `path_to_bytes`.
+#[cfg(unix)]
+pub(crate) fn bytes_to_path(bytes: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// Rebuilds a path from the bytes produced by `path_to_bytes`.
+#[cfg(not(unix))]
+pub(crate) fn bytes_to_path(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(|s| PathBuf::from(OsStr::new(s)))
+}
+
+/// Normalizes a path read from a manifest, such as `./a/b`, to a relative path like `a/b`.
+///
+/// Returns `None` for absolute paths and paths containing `..`; `.` normalizes to the empty path.
+pub(crate) fn normalize_relative(path: &Path) -> Option<PathBuf> {
+    let mut rl_path = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => rl_path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(rl_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX - 1, u64::MAX];
+        let signed = [0, 1, -1, 63, -64, 64, i64::MIN, i64::MAX];
+        let mut writer = ByteWriter::new();
+        for value in values {
+            writer.put_varint(value);
+        }
+        for value in signed {
+            writer.put_varint_signed(value);
+        }
+        writer.put_bytes(b"tail");
+        let bytes = writer.into_vec();
+
+        let mut reader = ByteReader::from(&bytes);
+        for value in values {
+            assert_eq!(reader.get_varint(), Some(value));
+        }
+        for value in signed {
+            assert_eq!(reader.get_varint_signed(), Some(value));
+        }
+        assert_eq!(reader.get_bytes(), Some(&b"tail"[..]));
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(reader.get_u8(), None);
+    }
+
+    #[test]
+    fn small_values_take_one_byte() {
+        let mut writer = ByteWriter::new();
+        writer.put_varint(127);
+        writer.put_varint_signed(-64);
+        assert_eq!(writer.as_slice(), &[0x7f, 0x7f]);
+    }
+
+    #[test]
+    fn malformed_input_fails_instead_of_panicking() {
+        assert_eq!(ByteReader::from(&[0x80, 0x80]).get_varint(), None);
+        assert_eq!(ByteReader::from(&[0xff; 11]).get_varint(), None);
+        assert_eq!(ByteReader::from(&[1, 2, 3]).get_u32_le(), None);
+        assert_eq!(ByteReader::from(&[5, b'a']).get_bytes(), None);
+        assert_eq!(ByteReader::from(&[10]).get_len(9), None);
+        assert_eq!(ByteReader::from(&[9]).get_len(9), Some(9));
+        let mut reader = ByteReader::from(&[1, 2]);
+        assert_eq!(reader.get_raw(3), None);
+        assert_eq!(reader.get_raw(2), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn relative_paths_are_normalized() {
+        assert_eq!(normalize_relative(Path::new("./a/./b")), Some(PathBuf::from("a/b")));
+        assert_eq!(normalize_relative(Path::new(".")), Some(PathBuf::new()));
+        assert_eq!(normalize_relative(Path::new("a/../b")), None);
+        assert_eq!(normalize_relative(Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn raw_path_bytes_round_trip() {
+        let bytes = b"dir/\xfe\xffname";
+        let path = bytes_to_path(bytes).unwrap();
+        assert_eq!(path_to_bytes(&path), bytes);
+    }
+}
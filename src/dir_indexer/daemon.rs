@@ -0,0 +1,487 @@
+//! A daemon holding indexes in memory and answering queries about them over a Unix socket.
+//!
+//! Every connection is served on its own thread. Each root is rescanned periodically on a thread
+//! of its own and swapped in whole once the scan is done, so queries never wait for a scan and
+//! always see one complete index. The protocol is described in `daemon_protocol`.
+
+use super::{DirIndexer, DirIndexerErr, FuzzyIndex, FuzzyOptions};
+use super::codec;
+use super::daemon_protocol::{self, parse_number, MAX_REQUEST_LEN};
+use super::index_file;
+use super::index_source;
+#[cfg(test)]
+use super::test_util::TempDir;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::Duration;
+
+/// Options for `IndexDaemon`.
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    refresh_interval_: Duration,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> DaemonOptions {
+        DaemonOptions {
+            refresh_interval_: Duration::from_secs(60),
+        }
+    }
+}
+
+impl DaemonOptions {
+    /// Creates options rescanning every root once a minute.
+    pub fn new() -> DaemonOptions {
+        DaemonOptions::default()
+    }
+
+    /// Sets the time between the end of one scan of a root and the start of the next. A zero
+    /// interval turns periodic rescans off; roots are then only rescanned on request.
+    pub fn with_refresh_interval(mut self, refresh_interval: Duration) -> DaemonOptions {
+        self.refresh_interval_ = refresh_interval;
+        self
+    }
+
+    /// Returns the time between scans of a root.
+    pub fn get_refresh_interval(&self) -> Duration {
+        self.refresh_interval_
+    }
+}
+
+/// A root as the daemon serves it: its index and the fuzzy index built from it.
+#[derive(Debug)]
+struct ServedRoot {
+    indexer_: DirIndexer,
+    fuzzy_: FuzzyIndex,
+    /// Held while the root is rescanned, and handed on to the index that replaces this one.
+    refresh_lock_: Arc<Mutex<()>>,
+}
+
+type Roots = RwLock<BTreeMap<PathBuf, ServedRoot>>;
+
+/// Serves one or more indexes to `DaemonClient`s over a Unix socket, keeping them up to date.
+#[derive(Debug)]
+pub struct IndexDaemon {
+    roots_: Arc<Roots>,
+    options_: DaemonOptions,
+}
+
+impl IndexDaemon {
+    /// Creates a daemon serving the given indexes, each under the canonical absolute path of its
+    /// root, which is what `IndexSource::open` asks for.
+    ///
+    /// # Arguments
+    ///
+    /// * `indexers` - The indexes to serve; of several with the same root, the last one is kept.
+    /// * `options` - The rescan interval.
+    ///
+    /// # Returns
+    ///
+    /// An `IndexDaemon` that does nothing until `serve` is called.
+    pub fn from(indexers: Vec<DirIndexer>, options: &DaemonOptions) -> IndexDaemon {
+        let roots = indexers
+            .into_iter()
+            .map(|indexer| {
+                // A root reached through a symlink is served under the path it resolves to
+                let root_path = fs::canonicalize(indexer.get_root_path()).unwrap_or_else(|_| indexer.get_root_path().clone());
+                (root_path, ServedRoot::from(indexer, Arc::new(Mutex::new(()))))
+            })
+            .collect();
+        IndexDaemon {
+            roots_: Arc::new(RwLock::new(roots)),
+            options_: options.clone(),
+        }
+    }
+
+    /// Returns the absolute paths of the served roots, sorted.
+    pub fn get_roots(&self) -> Vec<PathBuf> {
+        read(&self.roots_).keys().cloned().collect()
+    }
+
+    /// Listens on a socket and answers requests until the process ends.
+    ///
+    /// A socket file left behind by a daemon that is no longer running is replaced. The socket
+    /// is made accessible to its owner only.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_path` - The path to listen on, usually `default_socket_path()`.
+    ///
+    /// # Returns
+    ///
+    /// `DirIndexerErr::DaemonIoFail` if the socket cannot be created or another daemon is
+    /// listening on it; otherwise this does not return.
+    pub fn serve(&self, socket_path: &Path) -> Result<(), DirIndexerErr> {
+        let listener = bind(socket_path)?;
+        if self.options_.refresh_interval_ > Duration::from_secs(0) {
+            for root_path in self.get_roots() {
+                let roots = Arc::clone(&self.roots_);
+                let interval = self.options_.refresh_interval_;
+                thread::spawn(move || loop {
+                    thread::sleep(interval);
+                    // A root that cannot be scanned right now keeps its last index
+                    let _ = refresh_root(&roots, &root_path);
+                });
+            }
+        }
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // Failed accepts, such as running out of file descriptors, only cost that connection
+                Err(_) => continue,
+            };
+            let roots = Arc::clone(&self.roots_);
+            thread::spawn(move || serve_connection(&roots, stream));
+        }
+        Ok(())
+    }
+}
+
+impl ServedRoot {
+    fn from(indexer: DirIndexer, refresh_lock: Arc<Mutex<()>>) -> ServedRoot {
+        let fuzzy = FuzzyIndex::from(&indexer);
+        ServedRoot {
+            indexer_: indexer,
+            fuzzy_: fuzzy,
+            refresh_lock_: refresh_lock,
+        }
+    }
+}
+
+/// The rows of an answer, already encoded.
+struct Rows {
+    count_: usize,
+    bytes_: Vec<u8>,
+}
+
+impl Rows {
+    fn new() -> Rows {
+        Rows { count_: 0, bytes_: Vec::new() }
+    }
+
+    fn push(&mut self, fields: &[&[u8]]) {
+        self.count_ += 1;
+        daemon_protocol::push_line(&mut self.bytes_, fields);
+    }
+}
+
+fn read(roots: &Roots) -> RwLockReadGuard<'_, BTreeMap<PathBuf, ServedRoot>> {
+    roots.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn bind(socket_path: &Path) -> Result<UnixListener, DirIndexerErr> {
+    let io_fail = || DirIndexerErr::DaemonIoFail(socket_path.to_path_buf());
+    if let Ok(meta) = fs::symlink_metadata(socket_path) {
+        // Only a socket nobody answers on is a leftover that may be replaced
+        if !meta.file_type().is_socket() || UnixStream::connect(socket_path).is_ok() {
+            return Err(io_fail());
+        }
+        fs::remove_file(socket_path).map_err(|_| io_fail())?;
+    }
+    let listener = UnixListener::bind(socket_path).map_err(|_| io_fail())?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600)).map_err(|_| io_fail())?;
+    Ok(listener)
+}
+
+/// Rescans a root and swaps the result in.
+///
+/// Rescans of one root take turns, so each starts from the index the previous one left and none
+/// can replace a newer index with an older scan. Queries are answered meanwhile from the current
+/// index, and content hashes of unchanged files carry over to the new one.
+fn refresh_root(roots: &Roots, root_path: &Path) -> Result<(), DirIndexerErr> {
+    let missing = || not_served(codec::path_to_bytes(root_path));
+    let refresh_lock = Arc::clone(&read(roots).get(root_path).ok_or_else(missing)?.refresh_lock_);
+    let _turn = refresh_lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut indexer = read(roots).get(root_path).ok_or_else(missing)?.indexer_.clone();
+    indexer.refresh()?;
+    let served = ServedRoot::from(indexer, Arc::clone(&refresh_lock));
+    let mut roots = roots.write().unwrap_or_else(PoisonError::into_inner);
+    roots.insert(root_path.to_path_buf(), served);
+    Ok(())
+}
+
+/// Answers the requests of one client until it disconnects or sends an overlong request.
+fn serve_connection(roots: &Roots, stream: UnixStream) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = Vec::new();
+        match reader.by_ref().take(MAX_REQUEST_LEN + 1).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let mut answer = Vec::new();
+        if line.pop() != Some(b'\n') {
+            if line.len() as u64 >= MAX_REQUEST_LEN {
+                push_error(&mut answer, &DirIndexerErr::DaemonRequestFail(String::from("request too long")));
+                let _ = writer.write_all(&answer);
+            }
+            return;
+        }
+        match answer_request(roots, &line) {
+            Ok(rows) => {
+                daemon_protocol::push_line(&mut answer, &[b"OK", rows.count_.to_string().as_bytes()]);
+                answer.extend_from_slice(&rows.bytes_);
+            }
+            Err(err) => push_error(&mut answer, &err),
+        }
+        if writer.write_all(&answer).is_err() {
+            return;
+        }
+    }
+}
+
+fn push_error(answer: &mut Vec<u8>, err: &DirIndexerErr) {
+    let (kind, message): (&[u8], String) = match err {
+        DirIndexerErr::QueryParseFail(reason) => (b"query", reason.clone()),
+        DirIndexerErr::DaemonRequestFail(reason) => (b"request", reason.clone()),
+        err => (b"request", err.to_string()),
+    };
+    daemon_protocol::push_line(answer, &[b"ERR", kind, message.as_bytes()]);
+}
+
+fn request_fail(reason: String) -> DirIndexerErr {
+    DirIndexerErr::DaemonRequestFail(reason)
+}
+
+fn answer_request(roots: &Roots, line: &[u8]) -> Result<Rows, DirIndexerErr> {
+    let fields = daemon_protocol::split_line(line).ok_or_else(|| request_fail(String::from("malformed request")))?;
+    let mut rows = Rows::new();
+    match fields.as_slice() {
+        [command] if command == b"ROOTS" => {
+            for root_path in read(roots).keys() {
+                rows.push(&[codec::path_to_bytes(root_path)]);
+            }
+        }
+        [command, root] if command == b"STATS" => {
+            let roots = read(roots);
+            let stats = index_source::index_stats(&served_root(&roots, root)?.indexer_);
+            let (secs, nanos) = index_file::time_to_parts(stats.get_scan_time());
+            let numbers = [
+                stats.get_file_count().to_string(),
+                stats.get_dir_count().to_string(),
+                stats.get_entry_count().to_string(),
+                stats.get_size().to_string(),
+                secs.to_string(),
+                nanos.to_string(),
+                stats.get_scan_error_count().to_string(),
+            ];
+            let fields: Vec<&[u8]> = numbers.iter().map(|number| number.as_bytes()).collect();
+            rows.push(&fields);
+        }
+        [command, root, kind] if command == b"LIST" => {
+            let kind = daemon_protocol::kind_from_str(kind).ok_or_else(|| request_fail(String::from("unknown kind")))?;
+            let roots = read(roots);
+            for rl_path in index_source::list_paths(&served_root(&roots, root)?.indexer_, kind) {
+                rows.push(&[codec::path_to_bytes(&rl_path)]);
+            }
+        }
+        [command, root, rl_path] if command == b"LOOKUP" => {
+            let rl_path = codec::bytes_to_path(rl_path).ok_or_else(|| request_fail(String::from("invalid path")))?;
+            let roots = read(roots);
+            if let Some(entry) = index_source::lookup_entry(&served_root(&roots, root)?.indexer_, &rl_path) {
+                let meta: Vec<String> = entry.get_meta().to_fields().iter().map(u64::to_string).collect();
+                let mut fields: Vec<&[u8]> = vec![entry.get_kind().as_str().as_bytes()];
+                fields.extend(meta.iter().map(|field| field.as_bytes()));
+                fields.push(entry.get_link_target().map_or(&[][..], |target| codec::path_to_bytes(target)));
+                rows.push(&fields);
+            }
+        }
+        [command, root, query] if command == b"FIND" => {
+            let query = utf8_field(query, "query")?;
+            let roots = read(roots);
+            for rl_path in index_source::find_paths(&served_root(&roots, root)?.indexer_, query)? {
+                rows.push(&[codec::path_to_bytes(&rl_path)]);
+            }
+        }
+        [command, root, limit, kind, case, query] if command == b"FUZZY" => {
+            let limit: usize = parse_number(limit).ok_or_else(|| request_fail(String::from("invalid limit")))?;
+            let roots = read(roots);
+            let fuzzy = &served_root(&roots, root)?.fuzzy_;
+            // No search returns more matches than there are entries, so a larger limit changes nothing
+            let mut options = FuzzyOptions::new().with_limit(limit.min(fuzzy.len()));
+            match daemon_protocol::kind_from_str(kind) {
+                Some(Some(kind)) => options = options.with_kind(kind),
+                Some(None) => {}
+                None => return Err(request_fail(String::from("unknown kind"))),
+            }
+            match case.as_slice() {
+                b"exact" => options = options.with_case_sensitive(),
+                b"smart" => {}
+                _ => return Err(request_fail(String::from("unknown case mode"))),
+            }
+            let query = utf8_field(query, "query")?;
+            for found in fuzzy.search(query, &options) {
+                let positions: Vec<String> = found.get_positions().iter().map(usize::to_string).collect();
+                let score = found.get_score().to_string();
+                rows.push(&[score.as_bytes(), positions.join(",").as_bytes(), codec::path_to_bytes(found.get_path())]);
+            }
+        }
+        [command, root] if command == b"REFRESH" => {
+            refresh_root(roots, &codec::bytes_to_path(root).ok_or_else(|| not_served(root))?)?;
+        }
+        [command, ..] => {
+            let command = String::from_utf8_lossy(command);
+            return Err(request_fail(match command.as_ref() {
+                "ROOTS" | "STATS" | "LIST" | "LOOKUP" | "FIND" | "FUZZY" | "REFRESH" => format!("wrong number of fields for {}", command),
+                _ => format!("unknown request '{}'", command),
+            }));
+        }
+        [] => return Err(request_fail(String::from("empty request"))),
+    }
+    Ok(rows)
+}
+
+fn served_root<'a>(roots: &'a BTreeMap<PathBuf, ServedRoot>, root: &[u8]) -> Result<&'a ServedRoot, DirIndexerErr> {
+    codec::bytes_to_path(root).and_then(|root_path| roots.get(&root_path)).ok_or_else(|| not_served(root))
+}
+
+fn not_served(root: &[u8]) -> DirIndexerErr {
+    request_fail(format!("'{}' is not a served root", String::from_utf8_lossy(root)))
+}
+
+fn utf8_field<'a>(field: &'a [u8], name: &str) -> Result<&'a str, DirIndexerErr> {
+    std::str::from_utf8(field).map_err(|_| request_fail(format!("the {} is not valid UTF-8", name)))
+}
+
+/// Serves `root` on a socket in `run_dir` from a thread of its own, and returns the socket.
+#[cfg(test)]
+pub(crate) fn start_daemon(root: &Path, run_dir: &TempDir) -> PathBuf {
+    let socket_path = run_dir.join("sock");
+    let daemon = IndexDaemon::from(vec![DirIndexer::from(root.to_path_buf()).unwrap()], &DaemonOptions::new().with_refresh_interval(Duration::from_secs(0)));
+    let serve_path = socket_path.clone();
+    thread::spawn(move || daemon.serve(&serve_path));
+    let started = std::time::Instant::now();
+    while UnixStream::connect(&socket_path).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(10), "the daemon did not start");
+        thread::sleep(Duration::from_millis(10));
+    }
+    socket_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::IndexSource;
+    use std::os::unix::fs::symlink;
+
+    /// Sends one raw request line and returns the status line of the answer and its rows.
+    fn send(socket_path: &Path, request: &[u8]) -> Vec<String> {
+        let mut stream = UnixStream::connect(socket_path).unwrap();
+        stream.write_all(request).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        let mut lines = vec![status.trim_end_matches('\n').to_string()];
+        let count = lines[0].strip_prefix("OK\t").map_or(0, |count| count.parse().unwrap());
+        for _ in 0..count {
+            let mut row = String::new();
+            reader.read_line(&mut row).unwrap();
+            lines.push(row.trim_end_matches('\n').to_string());
+        }
+        lines
+    }
+
+    fn request(fields: &[&[u8]]) -> Vec<u8> {
+        let mut line = Vec::new();
+        daemon_protocol::push_line(&mut line, fields);
+        line
+    }
+
+    #[test]
+    fn deeply_nested_find_is_rejected_without_stopping_the_daemon() {
+        let root = TempDir::new("daemon-nested");
+        root.write("a.txt", b"a");
+        let run_dir = TempDir::new("daemon-nested-run");
+        let socket_path = start_daemon(root.get_path(), &run_dir);
+
+        let query = "(".repeat(20_000);
+        let answer = send(&socket_path, &request(&[b"FIND", codec::path_to_bytes(root.get_path()), query.as_bytes()]));
+        assert!(answer[0].starts_with("ERR\tquery\t"), "{:?}", answer);
+        let negations = "! ".repeat(200_000);
+        let answer = send(&socket_path, &request(&[b"FIND", codec::path_to_bytes(root.get_path()), negations.as_bytes()]));
+        assert!(answer[0].starts_with("ERR\tquery\t"), "{:?}", answer);
+
+        let answer = send(&socket_path, &request(&[b"FIND", codec::path_to_bytes(root.get_path()), b"-name '*.txt'"]));
+        assert_eq!(answer, vec!["OK\t1", "a.txt"]);
+    }
+
+    #[test]
+    fn huge_fuzzy_limits_are_served() {
+        let root = TempDir::new("daemon-fuzzy");
+        root.write("src/main.rs", b"");
+        root.write("src/lib.rs", b"");
+        let run_dir = TempDir::new("daemon-fuzzy-run");
+        let socket_path = start_daemon(root.get_path(), &run_dir);
+
+        for limit in ["1000000000000", &usize::MAX.to_string()] {
+            let answer = send(&socket_path, &request(&[b"FUZZY", codec::path_to_bytes(root.get_path()), limit.as_bytes(), b"file", b"smart", b"rs"]));
+            assert_eq!(answer.len(), 3, "{:?}", answer);
+            assert_eq!(answer[0], "OK\t2");
+        }
+        let answer = send(&socket_path, &request(&[b"FUZZY", codec::path_to_bytes(root.get_path()), b"-1", b"file", b"smart", b"rs"]));
+        assert!(answer[0].starts_with("ERR\trequest\t"), "{:?}", answer);
+    }
+
+    #[test]
+    fn refreshes_take_turns_and_keep_content_hashes() {
+        let root = TempDir::new("daemon-refresh");
+        root.write("kept.txt", b"kept");
+        root.set_mtime("kept.txt", 1_000_000_000);
+        let mut indexer = DirIndexer::from(root.get_path().clone()).unwrap();
+        indexer.compute_hashes(&mut crate::dir_indexer::HashCache::new(crate::dir_indexer::HashAlgo::Sha256), 1);
+        let daemon = IndexDaemon::from(vec![indexer], &DaemonOptions::new());
+        let root_path = root.get_path().clone();
+
+        // A rescan waits for the one in progress, then starts from the index it left
+        let roots = Arc::clone(&daemon.roots_);
+        let refresh_lock = Arc::clone(&read(&roots)[&root_path].refresh_lock_);
+        let turn = refresh_lock.lock().unwrap();
+        let waiting = {
+            let roots = Arc::clone(&roots);
+            let root_path = root_path.clone();
+            thread::spawn(move || refresh_root(&roots, &root_path))
+        };
+        thread::sleep(Duration::from_millis(50));
+        root.write("added.txt", b"added");
+        drop(turn);
+        waiting.join().unwrap().unwrap();
+
+        let served = read(&roots);
+        let indexer = &served[&root_path].indexer_;
+        assert!(indexer.get_tree().get_node(Path::new("added.txt")).is_some());
+        assert!(indexer.get_content_hash(Path::new("kept.txt")).is_some());
+        assert_eq!(served[&root_path].fuzzy_.len(), 2);
+        drop(served);
+
+        let missing = root.join("missing");
+        assert!(matches!(refresh_root(&roots, &missing), Err(DirIndexerErr::DaemonRequestFail(_))));
+    }
+
+    #[test]
+    fn roots_are_served_under_their_canonical_path() {
+        let dir = TempDir::new("daemon-symlink");
+        dir.write("real/a.txt", b"a");
+        symlink(dir.join("real"), dir.join("link")).unwrap();
+        let run_dir = TempDir::new("daemon-symlink-run");
+        let socket_path = start_daemon(&dir.join("link"), &run_dir);
+
+        let mut source = IndexSource::open(&dir.join("link"), &socket_path).unwrap();
+        assert!(source.is_daemon());
+        assert_eq!(source.get_root_path(), &dir.join("real"));
+        source.refresh().unwrap();
+        assert!(source.is_daemon());
+        let answer = send(&socket_path, b"ROOTS\n");
+        assert_eq!(answer, vec!["OK\t1".to_string(), dir.join("real").to_string_lossy().into_owned()]);
+    }
+}
@@ -0,0 +1,299 @@
+use super::{DirEntryKind, DirIndexerErr, DirMeta, FuzzyMatch, FuzzyOptions, IndexEntry, IndexStats};
+use super::codec;
+use super::daemon_protocol::{self, parse_number};
+use super::index_file;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a client waits for the daemon to accept a request or send the next line of an answer
+/// before giving up on it.
+pub const DEFAULT_DAEMON_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to an `IndexDaemon`, over which any number of queries can be sent in turn.
+///
+/// Every query names the root it is about, which must be one of the roots the daemon serves.
+/// Use `IndexSource` to fall back to a local scan when no daemon is running.
+///
+/// A daemon that stops answering for longer than the timeout is treated as gone: the query fails
+/// with `DirIndexerErr::DaemonIoFail` and so does every later one on the same connection.
+#[derive(Debug)]
+pub struct DaemonClient {
+    socket_path_: PathBuf,
+    reader_: BufReader<UnixStream>,
+    writer_: UnixStream,
+}
+
+impl DaemonClient {
+    /// Connects to the daemon listening on a socket.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket_path` - The socket of the daemon, usually `default_socket_path()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DaemonClient`, waiting at most `DEFAULT_DAEMON_TIMEOUT` for
+    /// each answer, or `DirIndexerErr::DaemonIoFail` if no daemon accepts the connection or the
+    /// socket is not one the current user's daemon can have made: a socket owned by that user and
+    /// writable by nobody else.
+    pub fn connect(socket_path: &Path) -> Result<DaemonClient, DirIndexerErr> {
+        let io_fail = |_| DirIndexerErr::DaemonIoFail(socket_path.to_path_buf());
+        // The default socket may sit in a shared temporary directory, where anyone could have
+        // created it first and would then be trusted with every answer
+        if !is_own_socket(socket_path) {
+            return Err(DirIndexerErr::DaemonIoFail(socket_path.to_path_buf()));
+        }
+        let stream = UnixStream::connect(socket_path).map_err(io_fail)?;
+        let writer = stream.try_clone().map_err(io_fail)?;
+        let mut client = DaemonClient {
+            socket_path_: socket_path.to_path_buf(),
+            reader_: BufReader::new(stream),
+            writer_: writer,
+        };
+        client.set_timeout(DEFAULT_DAEMON_TIMEOUT)?;
+        Ok(client)
+    }
+
+    /// Sets how long to wait for the daemon to accept a request or send the next line of an
+    /// answer. A `refresh` of a large root may need more than the default.
+    ///
+    /// # Returns
+    ///
+    /// `DirIndexerErr::DaemonIoFail` if `timeout` is zero or the socket rejects it.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), DirIndexerErr> {
+        let result = self.reader_.get_ref().set_read_timeout(Some(timeout)).and_then(|_| self.writer_.set_write_timeout(Some(timeout)));
+        result.map_err(|_| self.io_fail())
+    }
+
+    /// Returns the path of the socket the client is connected to.
+    pub fn get_socket_path(&self) -> &PathBuf {
+        &self.socket_path_
+    }
+
+    /// Returns the absolute paths of the roots the daemon serves.
+    pub fn get_roots(&mut self) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        self.request(&[b"ROOTS"])?.iter().map(|row| path_field(row, 0)).collect()
+    }
+
+    /// Returns totals over the index of a served root.
+    pub fn get_stats(&mut self, root_path: &Path) -> Result<IndexStats, DirIndexerErr> {
+        let rows = self.request(&[b"STATS", codec::path_to_bytes(root_path)])?;
+        let row = match rows.as_slice() {
+            [row] if row.len() == 7 => row,
+            _ => return Err(malformed()),
+        };
+        let number = |index: usize| parse_number::<u64>(&row[index]).ok_or_else(malformed);
+        let secs = parse_number::<i64>(&row[4]).ok_or_else(malformed)?;
+        let scan_time = index_file::time_from_parts(secs, number(5)?).ok_or_else(malformed)?;
+        Ok(IndexStats::new([number(0)?, number(1)?, number(2)?, number(3)?], scan_time, number(6)?))
+    }
+
+    /// Lists the relative paths of the entries of one kind, or of every entry but the root if
+    /// `kind` is `None`, sorted.
+    pub fn list(&mut self, root_path: &Path, kind: Option<DirEntryKind>) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        let kind = daemon_protocol::kind_to_str(kind).as_bytes();
+        self.request(&[b"LIST", codec::path_to_bytes(root_path), kind])?
+            .iter()
+            .map(|row| path_field(row, 0))
+            .collect()
+    }
+
+    /// Looks up one entry by its relative path; an empty path refers to the root.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the entry, or `None` if it is not indexed.
+    pub fn lookup(&mut self, root_path: &Path, rl_path: &Path) -> Result<Option<IndexEntry>, DirIndexerErr> {
+        let rl_path = match codec::normalize_relative(rl_path) {
+            Some(rl_path) => rl_path,
+            None => return Ok(None),
+        };
+        let rows = self.request(&[b"LOOKUP", codec::path_to_bytes(root_path), codec::path_to_bytes(&rl_path)])?;
+        let row = match rows.as_slice() {
+            [] => return Ok(None),
+            [row] if row.len() == 11 => row,
+            _ => return Err(malformed()),
+        };
+        let kind = match daemon_protocol::kind_from_str(&row[0]) {
+            Some(Some(kind)) => kind,
+            _ => return Err(malformed()),
+        };
+        let mut fields = [0u64; 9];
+        for (value, field) in fields.iter_mut().zip(&row[1..10]) {
+            *value = parse_number(field).ok_or_else(malformed)?;
+        }
+        let meta = DirMeta::from_fields(&fields).ok_or_else(malformed)?;
+        let link_target = if row[10].is_empty() { None } else { Some(path_field(row, 10)?) };
+        Ok(Some(IndexEntry::new(rl_path, kind, meta, link_target)))
+    }
+
+    /// Finds the entries matching a query in the syntax of `DirQuery::parse`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the relative paths of the matches, sorted, or
+    /// `DirIndexerErr::QueryParseFail` if the daemon cannot parse the query.
+    pub fn find(&mut self, root_path: &Path, query: &str) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        self.request(&[b"FIND", codec::path_to_bytes(root_path), query.as_bytes()])?
+            .iter()
+            .map(|row| path_field(row, 0))
+            .collect()
+    }
+
+    /// Finds the entries whose relative paths best match a fuzzy query, as
+    /// `DirIndexer::fuzzy_find` does. The thread count of `options` is not sent.
+    pub fn fuzzy_find(&mut self, root_path: &Path, query: &str, options: &FuzzyOptions) -> Result<Vec<FuzzyMatch>, DirIndexerErr> {
+        let limit = options.get_limit().to_string();
+        let kind = daemon_protocol::kind_to_str(options.get_kind());
+        let case: &[u8] = if options.get_case_sensitive() { b"exact" } else { b"smart" };
+        let rows = self.request(&[b"FUZZY", codec::path_to_bytes(root_path), limit.as_bytes(), kind.as_bytes(), case, query.as_bytes()])?;
+        let mut matches = Vec::new();
+        for row in rows.iter() {
+            if row.len() != 3 {
+                return Err(malformed());
+            }
+            let score = parse_number(&row[0]).ok_or_else(malformed)?;
+            let mut positions = Vec::new();
+            for position in row[1].split(|&byte| byte == b',').filter(|position| !position.is_empty()) {
+                positions.push(parse_number(position).ok_or_else(malformed)?);
+            }
+            matches.push(FuzzyMatch::new(path_field(row, 2)?, score, positions));
+        }
+        Ok(matches)
+    }
+
+    /// Asks the daemon to rescan a served root now, and waits until it has.
+    pub fn refresh(&mut self, root_path: &Path) -> Result<(), DirIndexerErr> {
+        self.request(&[b"REFRESH", codec::path_to_bytes(root_path)]).map(|_| ())
+    }
+
+    /// Sends one request and reads the rows of the answer.
+    fn request(&mut self, fields: &[&[u8]]) -> Result<Vec<Vec<Vec<u8>>>, DirIndexerErr> {
+        let mut line = Vec::new();
+        daemon_protocol::push_line(&mut line, fields);
+        if self.writer_.write_all(&line).is_err() {
+            return Err(self.io_fail());
+        }
+
+        let status = self.read_row()?;
+        match status.first().map(Vec::as_slice) {
+            Some(b"OK") if status.len() == 2 => {
+                let count: usize = parse_number(&status[1]).ok_or_else(malformed)?;
+                (0..count).map(|_| self.read_row()).collect()
+            }
+            Some(b"ERR") if status.len() == 3 => {
+                let message = String::from_utf8_lossy(&status[2]).into_owned();
+                match status[1].as_slice() {
+                    b"query" => Err(DirIndexerErr::QueryParseFail(message)),
+                    _ => Err(DirIndexerErr::DaemonRequestFail(message)),
+                }
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    /// Reads one line of the answer and splits it into fields.
+    fn read_row(&mut self) -> Result<Vec<Vec<u8>>, DirIndexerErr> {
+        let mut line = Vec::new();
+        match self.reader_.read_until(b'\n', &mut line) {
+            Ok(_) if line.last() == Some(&b'\n') => {}
+            // The daemon went away, possibly in the middle of a line
+            _ => return Err(self.io_fail()),
+        }
+        line.pop();
+        daemon_protocol::split_line(&line).ok_or_else(malformed)
+    }
+
+    /// Returns the error for a daemon that cannot be used any more, and closes the connection so
+    /// that the rest of an answer arriving late is not read as the answer to another request.
+    fn io_fail(&self) -> DirIndexerErr {
+        let _ = self.writer_.shutdown(Shutdown::Both);
+        DirIndexerErr::DaemonIoFail(self.socket_path_.clone())
+    }
+}
+
+/// Returns `true` if `socket_path` is a socket, not a link to one, owned by the effective user
+/// and not writable by its group or others.
+fn is_own_socket(socket_path: &Path) -> bool {
+    extern "C" {
+        fn geteuid() -> u32;
+    }
+    // SAFETY: geteuid takes no arguments, cannot fail and touches no memory of ours.
+    let uid = unsafe { geteuid() };
+    match fs::symlink_metadata(socket_path) {
+        Ok(meta) => meta.file_type().is_socket() && meta.uid() == uid && meta.mode() & 0o022 == 0,
+        Err(_) => false,
+    }
+}
+
+fn path_field(row: &[Vec<u8>], index: usize) -> Result<PathBuf, DirIndexerErr> {
+    row.get(index).and_then(|field| codec::bytes_to_path(field)).ok_or_else(malformed)
+}
+
+fn malformed() -> DirIndexerErr {
+    DirIndexerErr::DaemonRequestFail(String::from("malformed answer from the daemon"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn a_daemon_that_stops_answering_times_out() {
+        let run_dir = TempDir::new("client-timeout");
+        let socket_path = run_dir.join("sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        // Accept the connection and read the request, but never answer it
+        let silent = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = Vec::new();
+            let _ = BufReader::new(&stream).read_until(b'\n', &mut line);
+            stream
+        });
+
+        let mut client = DaemonClient::connect(&socket_path).unwrap();
+        client.set_timeout(Duration::from_millis(100)).unwrap();
+        match client.get_roots() {
+            Err(DirIndexerErr::DaemonIoFail(path)) => assert_eq!(path, socket_path),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        // The connection is not reused once a request has timed out
+        assert!(matches!(client.get_roots(), Err(DirIndexerErr::DaemonIoFail(_))));
+        drop(silent.join());
+    }
+
+    #[test]
+    fn sockets_others_could_have_made_are_not_trusted() {
+        let run_dir = TempDir::new("client-owner");
+        let socket_path = run_dir.join("sock");
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        fs::set_permissions(&socket_path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(DaemonClient::connect(&socket_path).is_ok());
+
+        for mode in [0o620, 0o602, 0o666].iter() {
+            fs::set_permissions(&socket_path, fs::Permissions::from_mode(*mode)).unwrap();
+            assert!(matches!(DaemonClient::connect(&socket_path), Err(DirIndexerErr::DaemonIoFail(_))), "{:o}", mode);
+        }
+        fs::set_permissions(&socket_path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        // A link to a trusted socket is not trusted itself
+        symlink(&socket_path, run_dir.join("link")).unwrap();
+        assert!(matches!(DaemonClient::connect(&run_dir.join("link")), Err(DirIndexerErr::DaemonIoFail(_))));
+
+        // Only root can hand a socket to another user, which is what an attacker's socket looks like
+        if fs::metadata(run_dir.get_path()).unwrap().uid() == 0 {
+            let status = std::process::Command::new("chown").arg("12345").arg(&socket_path).status().unwrap();
+            assert!(status.success());
+            assert!(matches!(DaemonClient::connect(&socket_path), Err(DirIndexerErr::DaemonIoFail(_))));
+        }
+    }
+}
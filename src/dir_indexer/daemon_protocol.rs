@@ -0,0 +1,178 @@
+//! The line protocol spoken between `IndexDaemon` and `DaemonClient` over a Unix socket.
+//!
+//! A connection carries any number of requests, answered in order. A request is one line of
+//! fields separated by tabs, the first naming the command. The answer is either `OK` and a row
+//! count on one line, followed by that many rows, or `ERR`, `query` for an invalid `DirQuery` or
+//! `request` for anything else, and a message on one line. Rows are tab-separated fields as well.
+//! Inside a field a tab, line feed, carriage return or backslash is written as `\t`, `\n`, `\r`
+//! or `\\`; everything else, paths included, is sent as raw bytes.
+//!
+//! | Request | Rows |
+//! |---|---|
+//! | `ROOTS` | one per served root: its canonical absolute path |
+//! | `STATS ROOT` | one: file count, directory count, entry count, total size, scan time as Unix seconds and nanoseconds, scan error count |
+//! | `LIST ROOT KIND` | one per entry of the kind, sorted: its relative path |
+//! | `LOOKUP ROOT PATH` | none if the entry is not indexed, else one: kind, the nine `DirMeta` fields, symlink target |
+//! | `FIND ROOT QUERY` | one per match of the `DirQuery`, sorted: its relative path |
+//! | `FUZZY ROOT LIMIT KIND CASE QUERY` | one per match, best first: score, comma-separated positions, relative path |
+//! | `REFRESH ROOT` | none; answered once ROOT has been rescanned |
+//!
+//! `ROOT` is the path of a served root as `ROOTS` lists it, `KIND` is `all`, `file`, `dir`,
+//! `symlink` or `other`, and `CASE` is `smart` or `exact`. A `LIMIT` larger than the number of
+//! entries is treated as that number. The `DirMeta` fields are size, mtime, mtime nanoseconds,
+//! mode, uid, gid, device, inode and link count, with a negative mtime sent as its two's
+//! complement. A missing symlink target is an empty field.
+
+use super::DirEntryKind;
+use std::env;
+use std::path::PathBuf;
+
+/// The longest request line a daemon accepts.
+pub(crate) const MAX_REQUEST_LEN: u64 = 1024 * 1024;
+
+/// Returns the socket path used when none is given: `dir_indexer.sock` in `$XDG_RUNTIME_DIR`, or
+/// `dir_indexer-$USER.sock` in the temporary directory if that is not set. Since anyone can create
+/// that one first, `DaemonClient::connect` only trusts a socket owned by the current user.
+pub fn default_socket_path() -> PathBuf {
+    match (env::var_os("XDG_RUNTIME_DIR"), env::var("USER")) {
+        (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir).join("dir_indexer.sock"),
+        (_, Ok(user)) if !user.is_empty() => env::temp_dir().join(format!("dir_indexer-{}.sock", user)),
+        _ => env::temp_dir().join("dir_indexer.sock"),
+    }
+}
+
+/// Appends one line made of `fields` to `out`, escaping each field.
+pub(crate) fn push_line(out: &mut Vec<u8>, fields: &[&[u8]]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(b'\t');
+        }
+        for &byte in field.iter() {
+            match byte {
+                b'\t' => out.extend_from_slice(b"\\t"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                byte => out.push(byte),
+            }
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Splits a line, without its line feed, into unescaped fields.
+///
+/// Returns `None` if the line holds an unknown escape or ends with a lone backslash.
+pub(crate) fn split_line(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut fields = vec![Vec::new()];
+    let mut bytes = line.iter();
+    while let Some(&byte) = bytes.next() {
+        let field = fields.last_mut()?;
+        match byte {
+            b'\t' => fields.push(Vec::new()),
+            b'\\' => match bytes.next()? {
+                b't' => field.push(b'\t'),
+                b'n' => field.push(b'\n'),
+                b'r' => field.push(b'\r'),
+                b'\\' => field.push(b'\\'),
+                _ => return None,
+            },
+            byte => field.push(byte),
+        }
+    }
+    Some(fields)
+}
+
+/// Returns the protocol name of a kind filter; `None` stands for every kind.
+pub(crate) fn kind_to_str(kind: Option<DirEntryKind>) -> &'static str {
+    kind.map_or("all", |kind| kind.as_str())
+}
+
+/// Parses a kind filter written by `kind_to_str`.
+///
+/// Returns `None` if `name` is not a kind, and `Some(None)` for `all`.
+pub(crate) fn kind_from_str(name: &[u8]) -> Option<Option<DirEntryKind>> {
+    match name {
+        b"all" => Some(None),
+        b"file" => Some(Some(DirEntryKind::File)),
+        b"dir" => Some(Some(DirEntryKind::Dir)),
+        b"symlink" => Some(Some(DirEntryKind::Symlink)),
+        b"other" => Some(Some(DirEntryKind::Other)),
+        _ => None,
+    }
+}
+
+/// Parses a decimal field.
+pub(crate) fn parse_number<T: std::str::FromStr>(field: &[u8]) -> Option<T> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_escaped_on_one_line() {
+        let mut out = Vec::new();
+        push_line(&mut out, &[b"LOOKUP", b"/r\too\nt", b"a\\b\rc"]);
+        assert_eq!(out, b"LOOKUP\t/r\\too\\nt\ta\\\\b\\rc\n".to_vec());
+        assert_eq!(split_line(&out[..out.len() - 1]), Some(vec![b"LOOKUP".to_vec(), b"/r\too\nt".to_vec(), b"a\\b\rc".to_vec()]));
+    }
+
+    #[test]
+    fn any_fields_round_trip() {
+        let alphabet = [b'a', b'\t', b'\n', b'\r', b'\\', b't', b'n', 0, 0xff];
+        let mut seed = 7u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        for _ in 0..500 {
+            let count = 1 + next(4);
+            let fields: Vec<Vec<u8>> = (0..count)
+                .map(|_| {
+                    let len = next(6);
+                    (0..len).map(|_| alphabet[next(alphabet.len())]).collect()
+                })
+                .collect();
+            let borrowed: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+            let mut line = Vec::new();
+            push_line(&mut line, &borrowed);
+            assert_eq!(line.iter().filter(|&&byte| byte == b'\n').count(), 1);
+            assert_eq!(line.last(), Some(&b'\n'));
+            assert_eq!(split_line(&line[..line.len() - 1]), Some(fields));
+        }
+    }
+
+    #[test]
+    fn bad_escapes_are_rejected() {
+        assert_eq!(split_line(b""), Some(vec![Vec::new()]));
+        assert_eq!(split_line(b"\t"), Some(vec![Vec::new(), Vec::new()]));
+        for line in [&b"a\\x"[..], b"a\\", b"\\0\tb", b"a\\\\\\"].iter() {
+            assert_eq!(split_line(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn kinds_round_trip() {
+        let kinds = [None, Some(DirEntryKind::File), Some(DirEntryKind::Dir), Some(DirEntryKind::Symlink), Some(DirEntryKind::Other)];
+        for kind in kinds.iter() {
+            assert_eq!(kind_from_str(kind_to_str(*kind).as_bytes()), Some(*kind));
+        }
+        assert_eq!(kind_to_str(None), "all");
+        for name in [&b""[..], b"File", b"files", b"directory"].iter() {
+            assert_eq!(kind_from_str(name), None);
+        }
+    }
+
+    #[test]
+    fn numbers_are_decimal() {
+        assert_eq!(parse_number::<u64>(b"18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_number::<i64>(b"-3"), Some(-3));
+        assert_eq!(parse_number::<u64>(b"-3"), None);
+        assert_eq!(parse_number::<u64>(b"18446744073709551616"), None);
+        assert_eq!(parse_number::<u64>(b""), None);
+        assert_eq!(parse_number::<u64>(b" 1"), None);
+        assert_eq!(parse_number::<u64>(b"\xff"), None);
+    }
+}
@@ -0,0 +1,458 @@
+use super::{DirIndexer, DirMeta, DuplicateGroup};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Size of the buffers used when comparing two files byte by byte.
+const COMPARE_BLOCK: usize = 64 * 1024;
+
+/// How many temporary link names are tried before a copy is given up on.
+const TMP_NAME_ATTEMPTS: u32 = 100;
+
+/// Options for `DirIndexer::dedupe_hardlinks`.
+#[derive(Debug, Clone, Default)]
+pub struct DedupeOptions {
+    dry_run_: bool,
+    allow_ownership_mismatch_: bool,
+}
+
+impl DedupeOptions {
+    /// Creates options that replace duplicates with hard links.
+    pub fn new() -> DedupeOptions {
+        DedupeOptions::default()
+    }
+
+    /// Performs every check and reports what would be linked, without changing any file.
+    pub fn with_dry_run(mut self) -> DedupeOptions {
+        self.dry_run_ = true;
+        self
+    }
+
+    /// Links copies even if their permission bits, owner or group differ from the canonical
+    /// file's. The replaced copy then takes on those of the canonical file.
+    pub fn with_allow_ownership_mismatch(mut self) -> DedupeOptions {
+        self.allow_ownership_mismatch_ = true;
+        self
+    }
+
+    /// Returns `true` if no file will be changed.
+    pub fn get_dry_run(&self) -> bool {
+        self.dry_run_
+    }
+
+    /// Returns `true` if copies with other permissions, owner or group may be linked.
+    pub fn get_allow_ownership_mismatch(&self) -> bool {
+        self.allow_ownership_mismatch_
+    }
+}
+
+/// Represents why a duplicate was not replaced with a hard link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeSkipReason {
+    /// No other copy of the group is on the same filesystem.
+    CrossDevice,
+    /// The file is already a hard link to the canonical copy.
+    AlreadyLinked,
+    /// The file's size or modification time changed since it was indexed.
+    Changed,
+    /// A byte-by-byte comparison found the contents to differ.
+    ContentsDiffer,
+    /// The file's permission bits, owner or group differ from the canonical file's, and
+    /// `DedupeOptions::with_allow_ownership_mismatch` was not given.
+    OwnershipDiffers,
+    /// A directory on the file's path below the root is a symbolic link, so the file may lie
+    /// outside the tree being deduplicated.
+    OutsideRoot,
+    /// A file could not be read, or the link could not be created.
+    IoFail,
+}
+
+/// The result of `DirIndexer::dedupe_hardlinks`.
+#[derive(Debug, Clone, Default)]
+pub struct DedupeReport {
+    linked_: Vec<(PathBuf, PathBuf)>,
+    skipped_: Vec<(PathBuf, DedupeSkipReason)>,
+    bytes_saved_: u64,
+    dry_run_: bool,
+}
+
+impl DedupeReport {
+    /// Returns the `(canonical, replaced)` relative path pairs, where `replaced` is now, or in a
+    /// dry run would become, a hard link to `canonical`.
+    pub fn get_linked(&self) -> &[(PathBuf, PathBuf)] {
+        &self.linked_
+    }
+
+    /// Returns the relative paths left untouched, with the reason.
+    pub fn get_skipped(&self) -> &[(PathBuf, DedupeSkipReason)] {
+        &self.skipped_
+    }
+
+    /// Returns the bytes freed, or that a dry run would free. A replaced file that had other hard
+    /// links frees nothing, since its data is still referenced.
+    pub fn get_bytes_saved(&self) -> u64 {
+        self.bytes_saved_
+    }
+
+    /// Returns `true` if the report comes from a dry run.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run_
+    }
+}
+
+/// Replaces the copies in each group with hard links to one canonical file per filesystem.
+pub(crate) fn dedupe_hardlinks(indexer: &DirIndexer, groups: &[DuplicateGroup], options: &DedupeOptions) -> DedupeReport {
+    let root_path = indexer.get_root_path();
+    let tree = indexer.get_tree();
+    let mut report = DedupeReport {
+        dry_run_: options.dry_run_,
+        ..DedupeReport::default()
+    };
+    for group in groups {
+        // Links cannot cross filesystems, so each device gets its own canonical copy
+        let mut by_device: BTreeMap<u64, Vec<(&PathBuf, DirMeta)>> = BTreeMap::new();
+        for rl_path in group.get_paths() {
+            if through_symlink(root_path, rl_path) {
+                report.skipped_.push((rl_path.clone(), DedupeSkipReason::OutsideRoot));
+                continue;
+            }
+            match tree.get_node(rl_path) {
+                Some(node) => by_device.entry(node.get_meta().get_dev()).or_default().push((rl_path, *node.get_meta())),
+                None => report.skipped_.push((rl_path.clone(), DedupeSkipReason::Changed)),
+            }
+        }
+        for (_, mut copies) in by_device {
+            if copies.len() < 2 {
+                report.skipped_.extend(copies.into_iter().map(|(rl_path, _)| (rl_path.clone(), DedupeSkipReason::CrossDevice)));
+                continue;
+            }
+            // Prefer the copy that already has the most links, then the first path
+            copies.sort_by(|a, b| b.1.get_nlink().cmp(&a.1.get_nlink()).then_with(|| a.0.cmp(b.0)));
+            let (canonical, canonical_meta) = copies[0];
+            let canonical_path = root_path.join(canonical);
+            for (rl_path, indexed_meta) in &copies[1..] {
+                let target_path = root_path.join(rl_path);
+                match link_copy(&canonical_path, &canonical_meta, &target_path, indexed_meta, options) {
+                    Ok(saved) => {
+                        report.bytes_saved_ += saved;
+                        report.linked_.push((canonical.clone(), (*rl_path).clone()));
+                    }
+                    Err(reason) => report.skipped_.push(((*rl_path).clone(), reason)),
+                }
+            }
+        }
+    }
+    report
+}
+
+/// Checks one copy against the canonical file and, unless a dry run, replaces it with a hard link.
+///
+/// # Returns
+///
+/// The bytes freed by the replacement, or the reason it was not made.
+fn link_copy(canonical: &Path, canonical_meta: &DirMeta, target: &Path, target_meta: &DirMeta, options: &DedupeOptions) -> Result<u64, DedupeSkipReason> {
+    let current_canonical = current_meta(canonical)?;
+    let current_target = current_meta(target)?;
+    if current_canonical.get_dev() != current_target.get_dev() {
+        return Err(DedupeSkipReason::CrossDevice);
+    }
+    if current_canonical.get_ino() == current_target.get_ino() {
+        return Err(DedupeSkipReason::AlreadyLinked);
+    }
+    if !unchanged(canonical_meta, &current_canonical) || !unchanged(target_meta, &current_target) {
+        return Err(DedupeSkipReason::Changed);
+    }
+    if !options.allow_ownership_mismatch_ && !same_ownership(&current_canonical, &current_target) {
+        return Err(DedupeSkipReason::OwnershipDiffers);
+    }
+    match same_contents(canonical, target) {
+        Ok(true) => {}
+        Ok(false) => return Err(DedupeSkipReason::ContentsDiffer),
+        Err(_) => return Err(DedupeSkipReason::IoFail),
+    }
+    let saved = if current_target.get_nlink() <= 1 { current_target.get_size() } else { 0 };
+    if options.dry_run_ {
+        return Ok(saved);
+    }
+
+    // Link under a temporary name next to the target, then rename it over the target in one step
+    let tmp_path = link_to_tmp(canonical, target).map_err(|_| DedupeSkipReason::IoFail)?;
+    if fs::rename(&tmp_path, target).is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(DedupeSkipReason::IoFail);
+    }
+    Ok(saved)
+}
+
+/// Creates a hard link to `canonical` under a new name next to `target`.
+///
+/// Linking fails rather than replacing an existing file, so a name already taken is never touched
+/// and the next one is tried instead.
+///
+/// # Returns
+///
+/// A `Result` containing the path of the new link.
+fn link_to_tmp(canonical: &Path, target: &Path) -> io::Result<PathBuf> {
+    let file_name = target.file_name().unwrap_or_default();
+    for attempt in 0..TMP_NAME_ATTEMPTS {
+        let mut tmp_name = file_name.to_os_string();
+        tmp_name.push(format!(".lnk{}.{}", std::process::id(), attempt));
+        let tmp_path = target.with_file_name(tmp_name);
+        match fs::hard_link(canonical, &tmp_path) {
+            Ok(()) => return Ok(tmp_path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free temporary name"))
+}
+
+/// Returns `true` if a directory between `root_path` and the entry at `rl_path` is a symbolic
+/// link, or can no longer be examined, as it is now on disk.
+fn through_symlink(root_path: &Path, rl_path: &Path) -> bool {
+    rl_path.ancestors().skip(1).filter(|parent| !parent.as_os_str().is_empty()).any(|parent| {
+        fs::symlink_metadata(root_path.join(parent)).map_or(true, |meta| meta.file_type().is_symlink())
+    })
+}
+
+fn current_meta(path: &Path) -> Result<DirMeta, DedupeSkipReason> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_file() => Ok(DirMeta::from(&meta)),
+        Ok(_) => Err(DedupeSkipReason::Changed),
+        Err(_) => Err(DedupeSkipReason::IoFail),
+    }
+}
+
+fn unchanged(indexed: &DirMeta, current: &DirMeta) -> bool {
+    indexed.get_size() == current.get_size() && indexed.get_mtime_ns() == current.get_mtime_ns()
+}
+
+/// Returns `true` if two files have the same permission bits, owner and group.
+fn same_ownership(a: &DirMeta, b: &DirMeta) -> bool {
+    a.get_mode() == b.get_mode() && a.get_uid() == b.get_uid() && a.get_gid() == b.get_gid()
+}
+
+/// Compares two files byte by byte.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut file_a = File::open(a)?;
+    let mut file_b = File::open(b)?;
+    let mut buf_a = vec![0u8; COMPARE_BLOCK];
+    let mut buf_b = vec![0u8; COMPARE_BLOCK];
+    loop {
+        let len_a = read_full(&mut file_a, &mut buf_a)?;
+        let len_b = read_full(&mut file_b, &mut buf_b)?;
+        if len_a != len_b || buf_a[..len_a] != buf_b[..len_b] {
+            return Ok(false);
+        }
+        if len_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads until `buf` is full or the end of the file is reached.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::DuplicateOptions;
+    use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+    fn ino(dir: &TempDir, rl_path: &str) -> u64 {
+        fs::metadata(dir.join(rl_path)).unwrap().ino()
+    }
+
+    fn indexed(dir: &TempDir) -> (DirIndexer, Vec<DuplicateGroup>) {
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let groups = indexer.find_duplicates(&DuplicateOptions::new());
+        (indexer, groups)
+    }
+
+    fn names(dir: &TempDir) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir.get_path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn copies_become_hard_links() {
+        let dir = TempDir::new("dedupe-link");
+        dir.write("a", b"same contents");
+        dir.write("b", b"same contents");
+        dir.write("c", b"same contents");
+        let (indexer, groups) = indexed(&dir);
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert!(!report.is_dry_run());
+        assert_eq!(report.get_linked(), &[(PathBuf::from("a"), PathBuf::from("b")), (PathBuf::from("a"), PathBuf::from("c"))]);
+        assert!(report.get_skipped().is_empty());
+        assert_eq!(report.get_bytes_saved(), 26);
+        assert_eq!(ino(&dir, "a"), ino(&dir, "b"));
+        assert_eq!(ino(&dir, "a"), ino(&dir, "c"));
+        assert_eq!(fs::read(dir.join("c")).unwrap(), b"same contents");
+        assert_eq!(names(&dir), vec!["a", "b", "c"]);
+
+        // The stale groups now point at files that are already linked
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert!(report.get_linked().is_empty());
+        assert_eq!(report.get_skipped(), &[(PathBuf::from("b"), DedupeSkipReason::AlreadyLinked), (PathBuf::from("c"), DedupeSkipReason::AlreadyLinked)]);
+    }
+
+    #[test]
+    fn dry_runs_change_nothing() {
+        let dir = TempDir::new("dedupe-dry");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        let (indexer, groups) = indexed(&dir);
+        let before = (ino(&dir, "a"), ino(&dir, "b"));
+
+        let options = DedupeOptions::new().with_dry_run();
+        assert!(options.get_dry_run());
+        let report = indexer.dedupe_hardlinks(&groups, &options);
+        assert!(report.is_dry_run());
+        assert_eq!(report.get_linked(), &[(PathBuf::from("a"), PathBuf::from("b"))]);
+        assert_eq!(report.get_bytes_saved(), 4);
+        assert_eq!((ino(&dir, "a"), ino(&dir, "b")), before);
+        assert_eq!(names(&dir), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn the_most_linked_copy_is_kept_and_shared_data_saves_nothing() {
+        let dir = TempDir::new("dedupe-canonical");
+        dir.write("z", b"same");
+        fs::hard_link(dir.join("z"), dir.join("z2")).unwrap();
+        dir.write("a", b"same");
+        dir.write("m", b"same");
+        fs::hard_link(dir.join("m"), dir.join("m2")).unwrap();
+        fs::hard_link(dir.join("m"), dir.join("m3")).unwrap();
+        let (indexer, groups) = indexed(&dir);
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        let canonical: Vec<&PathBuf> = report.get_linked().iter().map(|(canonical, _)| canonical).collect();
+        assert!(canonical.iter().all(|path| *path == &PathBuf::from("m")));
+        assert_eq!(report.get_linked().len(), 2);
+        // Only `a` held the last link to its data; `z` is still reachable through `z2`
+        assert_eq!(report.get_bytes_saved(), 4);
+        assert_eq!(ino(&dir, "a"), ino(&dir, "m"));
+        assert_eq!(ino(&dir, "z"), ino(&dir, "m"));
+        assert_ne!(ino(&dir, "z2"), ino(&dir, "m"));
+    }
+
+    #[test]
+    fn changed_files_are_skipped() {
+        let dir = TempDir::new("dedupe-changed");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        dir.write("c", b"same");
+        dir.write("d", b"same");
+        for name in ["a", "b", "c", "d"].iter() {
+            dir.set_mtime(name, 1_000_000_000);
+        }
+        let (indexer, groups) = indexed(&dir);
+        dir.write("b", b"longer");
+        // Same size and timestamp, different bytes: only a full comparison notices
+        dir.write("c", b"diff");
+        dir.set_mtime("c", 1_000_000_000);
+        fs::remove_file(dir.join("d")).unwrap();
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert!(report.get_linked().is_empty());
+        assert_eq!(
+            report.get_skipped(),
+            &[
+                (PathBuf::from("b"), DedupeSkipReason::Changed),
+                (PathBuf::from("c"), DedupeSkipReason::ContentsDiffer),
+                (PathBuf::from("d"), DedupeSkipReason::IoFail),
+            ]
+        );
+        assert_eq!(fs::read(dir.join("c")).unwrap(), b"diff");
+    }
+
+    #[test]
+    fn ownership_mismatches_need_consent() {
+        let dir = TempDir::new("dedupe-ownership");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        fs::set_permissions(dir.join("a"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(dir.join("b"), fs::Permissions::from_mode(0o600)).unwrap();
+        let (indexer, groups) = indexed(&dir);
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert_eq!(report.get_skipped(), &[(PathBuf::from("b"), DedupeSkipReason::OwnershipDiffers)]);
+        assert_ne!(ino(&dir, "a"), ino(&dir, "b"));
+
+        let options = DedupeOptions::new().with_allow_ownership_mismatch();
+        assert!(options.get_allow_ownership_mismatch());
+        let report = indexer.dedupe_hardlinks(&groups, &options);
+        assert_eq!(report.get_linked().len(), 1);
+        assert_eq!(fs::metadata(dir.join("b")).unwrap().permissions().mode() & 0o777, 0o644);
+    }
+
+    #[test]
+    fn copies_reached_through_symlinked_directories_are_left_alone() {
+        let outside = TempDir::new("dedupe-outside");
+        outside.write("x", b"same");
+        let dir = TempDir::new("dedupe-symlinked-dir");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        symlink(outside.get_path(), dir.join("vendor")).unwrap();
+        let (indexer, groups) = indexed(&dir);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].get_paths().len(), 3);
+        let before = ino(&outside, "x");
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert_eq!(report.get_linked(), &[(PathBuf::from("a"), PathBuf::from("b"))]);
+        assert_eq!(report.get_skipped(), &[(PathBuf::from("vendor/x"), DedupeSkipReason::OutsideRoot)]);
+        assert_eq!(ino(&outside, "x"), before);
+        assert_ne!(ino(&outside, "x"), ino(&dir, "a"));
+    }
+
+    #[test]
+    fn existing_files_are_never_used_as_temporary_names() {
+        let dir = TempDir::new("dedupe-tmp-name");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        let (indexer, groups) = indexed(&dir);
+        let taken = format!("b.lnk{}.0", std::process::id());
+        dir.write(&taken, b"user data");
+
+        let report = indexer.dedupe_hardlinks(&groups, &DedupeOptions::new());
+        assert_eq!(report.get_linked().len(), 1);
+        assert_eq!(ino(&dir, "a"), ino(&dir, "b"));
+        assert_eq!(fs::read(dir.join(&taken)).unwrap(), b"user data");
+        assert_eq!(names(&dir), vec!["a".to_string(), "b".to_string(), taken]);
+    }
+
+    #[test]
+    fn contents_are_compared_block_by_block() {
+        let dir = TempDir::new("dedupe-compare");
+        let mut data = vec![5u8; COMPARE_BLOCK * 2 + 17];
+        dir.write("a", &data);
+        dir.write("b", &data);
+        *data.last_mut().unwrap() = 6;
+        dir.write("c", &data);
+        assert!(same_contents(&dir.join("a"), &dir.join("b")).unwrap());
+        assert!(!same_contents(&dir.join("a"), &dir.join("c")).unwrap());
+        dir.write("short", &data[..COMPARE_BLOCK]);
+        assert!(!same_contents(&dir.join("a"), &dir.join("short")).unwrap());
+        assert!(same_contents(&dir.join("a"), &dir.join("missing")).is_err());
+    }
+}
@@ -0,0 +1,716 @@
+use super::{ContentHash, DirEntryKind, DirIndexer, DirMeta, DirNode, HashAlgo};
+use super::hasher;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Represents how a single entry differs between two indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirChange {
+    /// The entry only exists in the newer index.
+    Added(DirEntryKind),
+    /// The entry only exists in the older index.
+    Removed(DirEntryKind),
+    /// The entry exists in both indexes but its kind changed (old kind, new kind).
+    TypeChanged(DirEntryKind, DirEntryKind),
+    /// The entry exists in both indexes with the same kind but different content.
+    Modified(DirEntryKind),
+    /// The entry was moved here from another relative path (the old path) in the older index.
+    Renamed(DirEntryKind, PathBuf),
+}
+
+/// Options controlling how two indexes are compared.
+#[derive(Debug, Clone, Default)]
+pub struct DirDiffOptions {
+    content_hash_: Option<HashAlgo>,
+    detect_renames_: bool,
+}
+
+impl DirDiffOptions {
+    /// Creates the default options, which decide modifications by size and modification time.
+    pub fn new() -> DirDiffOptions {
+        DirDiffOptions::default()
+    }
+
+    /// Decides modifications of files with equal sizes by hashing their content instead of
+    /// comparing modification times.
+    ///
+    /// A file's hash comes from the hash stored on its node when one was computed with `algo`,
+    /// and otherwise from the file on disk, but only while the file still has the recorded size,
+    /// modification time and inode. A file whose content is known neither way, such as one in an
+    /// index loaded from disk after the tree changed, falls back to the modification time.
+    ///
+    /// # Arguments
+    ///
+    /// * `algo` - The algorithm used to hash the files of both trees.
+    pub fn with_content_hash(mut self, algo: HashAlgo) -> DirDiffOptions {
+        self.content_hash_ = Some(algo);
+        self
+    }
+
+    /// Pairs removed and added entries into renames.
+    ///
+    /// When both indexes live on the same filesystem, entries are first paired by inode. The
+    /// remaining files are paired by content hash, and directories when all their descendants were
+    /// paired. A moved directory is reported as one rename, not one rename per descendant.
+    pub fn with_renames(mut self) -> DirDiffOptions {
+        self.detect_renames_ = true;
+        self
+    }
+
+    /// Returns the content hash algorithm, if content comparison is enabled.
+    pub fn get_content_hash(&self) -> Option<HashAlgo> {
+        self.content_hash_
+    }
+
+    /// Returns `true` if rename detection is enabled.
+    pub fn get_detect_renames(&self) -> bool {
+        self.detect_renames_
+    }
+}
+
+/// Holds the number of changes of each category in a `DirDiff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DirDiffSummary {
+    added_: usize,
+    removed_: usize,
+    type_changed_: usize,
+    modified_: usize,
+    renamed_: usize,
+}
+
+impl DirDiffSummary {
+    /// Returns the number of added entries.
+    pub fn get_added(&self) -> usize {
+        self.added_
+    }
+
+    /// Returns the number of removed entries.
+    pub fn get_removed(&self) -> usize {
+        self.removed_
+    }
+
+    /// Returns the number of entries whose kind changed.
+    pub fn get_type_changed(&self) -> usize {
+        self.type_changed_
+    }
+
+    /// Returns the number of modified entries.
+    pub fn get_modified(&self) -> usize {
+        self.modified_
+    }
+
+    /// Returns the number of renamed entries.
+    pub fn get_renamed(&self) -> usize {
+        self.renamed_
+    }
+
+    /// Returns the total number of changes.
+    pub fn get_total(&self) -> usize {
+        self.added_ + self.removed_ + self.type_changed_ + self.modified_ + self.renamed_
+    }
+}
+
+impl fmt::Display for DirDiffSummary {
+    /// Formats the summary as a single line of counts.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} type changed, {} modified, {} renamed",
+            self.added_, self.removed_, self.type_changed_, self.modified_, self.renamed_
+        )
+    }
+}
+
+/// The `DirDiff` struct represents the differences between two directory indexes.
+///
+/// Changes are keyed by relative path, so the two indexes may have different roots.
+#[derive(Debug, Clone, Default)]
+pub struct DirDiff {
+    changes_: BTreeMap<PathBuf, DirChange>,
+}
+
+impl DirDiff {
+    /// Compares two indexes.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The index taken before the changes.
+    /// * `new` - The index taken after the changes.
+    /// * `options` - Options controlling how modifications are detected.
+    ///
+    /// # Returns
+    ///
+    /// A `DirDiff` describing how to get from `old` to `new`.
+    pub fn from(old: &DirIndexer, new: &DirIndexer, options: &DirDiffOptions) -> DirDiff {
+        let old_nodes = old.get_tree().get_rl2node_map();
+        let new_nodes = new.get_tree().get_rl2node_map();
+        let mut diff = DirDiff {
+            changes_: compare(old, &old_nodes, new, &new_nodes, options),
+        };
+        if options.detect_renames_ {
+            diff.detect_renames(old, &old_nodes, new, &new_nodes, options);
+        }
+        diff
+    }
+
+    /// Compares two versions of one subtree of `indexer` by metadata; `None` stands for a
+    /// subtree that does not exist.
+    pub(crate) fn from_subtrees(indexer: &DirIndexer, old: Option<&DirNode>, new: Option<&DirNode>) -> DirDiff {
+        let mut old_nodes = BTreeMap::new();
+        let mut new_nodes = BTreeMap::new();
+        if let Some(old) = old {
+            old.map_rl2node(&mut old_nodes);
+        }
+        if let Some(new) = new {
+            new.map_rl2node(&mut new_nodes);
+        }
+        DirDiff {
+            changes_: compare(indexer, &old_nodes, indexer, &new_nodes, &DirDiffOptions::new()),
+        }
+    }
+
+    /// Returns every change, keyed and sorted by relative path.
+    pub fn get_changes(&self) -> &BTreeMap<PathBuf, DirChange> {
+        &self.changes_
+    }
+
+    /// Returns the sorted relative paths of the added entries.
+    pub fn get_added(&self) -> Vec<&PathBuf> {
+        self.filter_paths(|c| matches!(c, DirChange::Added(_)))
+    }
+
+    /// Returns the sorted relative paths of the removed entries.
+    pub fn get_removed(&self) -> Vec<&PathBuf> {
+        self.filter_paths(|c| matches!(c, DirChange::Removed(_)))
+    }
+
+    /// Returns the sorted relative paths of the entries whose kind changed.
+    pub fn get_type_changed(&self) -> Vec<&PathBuf> {
+        self.filter_paths(|c| matches!(c, DirChange::TypeChanged(_, _)))
+    }
+
+    /// Returns the sorted relative paths of the modified entries.
+    pub fn get_modified(&self) -> Vec<&PathBuf> {
+        self.filter_paths(|c| matches!(c, DirChange::Modified(_)))
+    }
+
+    /// Returns the renamed entries as sorted `(old path, new path)` pairs.
+    pub fn get_renamed(&self) -> Vec<(&PathBuf, &PathBuf)> {
+        self.changes_
+            .iter()
+            .filter_map(|(rl_path, change)| match change {
+                DirChange::Renamed(_, from) => Some((from, rl_path)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the number of changes of each category.
+    pub fn get_summary(&self) -> DirDiffSummary {
+        let mut summary = DirDiffSummary::default();
+        for change in self.changes_.values() {
+            match change {
+                DirChange::Added(_) => summary.added_ += 1,
+                DirChange::Removed(_) => summary.removed_ += 1,
+                DirChange::TypeChanged(_, _) => summary.type_changed_ += 1,
+                DirChange::Modified(_) => summary.modified_ += 1,
+                DirChange::Renamed(_, _) => summary.renamed_ += 1,
+            }
+        }
+        summary
+    }
+
+    /// Returns `true` if the two indexes were identical.
+    pub fn is_empty(&self) -> bool {
+        self.changes_.is_empty()
+    }
+
+    /// Pairs removed entries with added entries and replaces them with renames.
+    fn detect_renames(
+        &mut self,
+        old: &DirIndexer,
+        old_nodes: &BTreeMap<PathBuf, &DirNode>,
+        new: &DirIndexer,
+        new_nodes: &BTreeMap<PathBuf, &DirNode>,
+        options: &DirDiffOptions,
+    ) {
+        let removed: Vec<&DirNode> = self.get_removed().iter().map(|p| old_nodes[*p]).collect();
+        let added: Vec<&DirNode> = self.get_added().iter().map(|p| new_nodes[*p]).collect();
+        if removed.is_empty() || added.is_empty() {
+            return;
+        }
+
+        // Pairs keyed by new path, with the old path as value
+        let same_fs = old.get_tree().get_root_node().get_meta().get_dev()
+            == new.get_tree().get_root_node().get_meta().get_dev();
+        let mut pairs = if same_fs {
+            pair_by_inode(&removed, &added)
+        } else {
+            BTreeMap::new()
+        };
+
+        // Whatever the inodes could not explain is paired by content
+        let paired_from: HashSet<&PathBuf> = pairs.values().collect();
+        let removed: Vec<&DirNode> = removed.into_iter().filter(|n| !paired_from.contains(n.get_entry())).collect();
+        let added: Vec<&DirNode> = added.into_iter().filter(|n| !pairs.contains_key(n.get_entry())).collect();
+        let algo = options.content_hash_.unwrap_or(HashAlgo::Xxh64);
+        let content_pairs = pair_files_by_content(old, &removed, new, &added, algo);
+        pairs.extend(content_pairs);
+        pair_dirs_by_descendants(&removed, &added, &mut pairs);
+
+        for (to, from) in &pairs {
+            self.changes_.remove(from);
+            self.changes_.remove(to);
+            let collapsed = to.ancestors().skip(1).any(|ancestor| {
+                pairs.get(ancestor).map_or(false, |ancestor_from| {
+                    to.strip_prefix(ancestor)
+                        .map(|suffix| ancestor_from.join(suffix) == *from)
+                        .unwrap_or(false)
+                })
+            });
+            let old_node = old_nodes[from];
+            let new_node = new_nodes[to];
+            if !collapsed {
+                self.changes_.insert(to.clone(), DirChange::Renamed(new_node.get_kind(), from.clone()));
+            } else if old_node.get_kind() != new_node.get_kind() {
+                self.changes_.insert(
+                    to.clone(),
+                    DirChange::TypeChanged(old_node.get_kind(), new_node.get_kind()),
+                );
+            } else if is_modified(old, old_node, new, new_node, options) {
+                self.changes_.insert(to.clone(), DirChange::Modified(new_node.get_kind()));
+            }
+        }
+    }
+
+    fn filter_paths<F: Fn(&DirChange) -> bool>(&self, pred: F) -> Vec<&PathBuf> {
+        self.changes_
+            .iter()
+            .filter(|(_, change)| pred(change))
+            .map(|(rl_path, _)| rl_path)
+            .collect()
+    }
+}
+
+/// Lists the entries added, removed, retyped or modified between two sets of nodes keyed by
+/// relative path; the root is never reported.
+fn compare(
+    old: &DirIndexer,
+    old_nodes: &BTreeMap<PathBuf, &DirNode>,
+    new: &DirIndexer,
+    new_nodes: &BTreeMap<PathBuf, &DirNode>,
+    options: &DirDiffOptions,
+) -> BTreeMap<PathBuf, DirChange> {
+    let root = PathBuf::from("");
+    let mut changes: BTreeMap<PathBuf, DirChange> = BTreeMap::new();
+
+    for (rl_path, old_node) in old_nodes {
+        if *rl_path == root {
+            continue;
+        }
+        match new_nodes.get(rl_path) {
+            None => {
+                changes.insert(rl_path.clone(), DirChange::Removed(old_node.get_kind()));
+            }
+            Some(new_node) => {
+                if old_node.get_kind() != new_node.get_kind() {
+                    changes.insert(
+                        rl_path.clone(),
+                        DirChange::TypeChanged(old_node.get_kind(), new_node.get_kind()),
+                    );
+                } else if is_modified(old, old_node, new, new_node, options) {
+                    changes.insert(rl_path.clone(), DirChange::Modified(new_node.get_kind()));
+                }
+            }
+        }
+    }
+    for (rl_path, new_node) in new_nodes {
+        if *rl_path != root && !old_nodes.contains_key(rl_path) {
+            changes.insert(rl_path.clone(), DirChange::Added(new_node.get_kind()));
+        }
+    }
+    changes
+}
+
+/// Decides whether two entries of the same kind differ.
+///
+/// Directories are never reported as modified; their changes show up as changes of their children.
+/// When content hashing is enabled, files whose content is unknown are compared by modification
+/// time.
+fn is_modified(
+    old: &DirIndexer,
+    old_node: &DirNode,
+    new: &DirIndexer,
+    new_node: &DirNode,
+    options: &DirDiffOptions,
+) -> bool {
+    let old_meta = old_node.get_meta();
+    let new_meta = new_node.get_meta();
+    match old_node.get_kind() {
+        DirEntryKind::Dir => false,
+        DirEntryKind::Symlink => old_node.get_link_target() != new_node.get_link_target(),
+        DirEntryKind::File => {
+            if old_meta.get_size() != new_meta.get_size() {
+                return true;
+            }
+            match options.content_hash_ {
+                Some(algo) => match (hash_entry(algo, old, old_node), hash_entry(algo, new, new_node)) {
+                    (Some(a), Some(b)) => a != b,
+                    _ => old_meta.get_mtime_ns() != new_meta.get_mtime_ns(),
+                },
+                None => old_meta.get_mtime_ns() != new_meta.get_mtime_ns(),
+            }
+        }
+        DirEntryKind::Other => {
+            old_meta.get_size() != new_meta.get_size() || old_meta.get_mtime_ns() != new_meta.get_mtime_ns()
+        }
+    }
+}
+
+/// Returns the content hash of a file of `indexer`: the stored one if it was computed with `algo`,
+/// or else the hash of the file on disk if it still is the version that was indexed.
+fn hash_entry(algo: HashAlgo, indexer: &DirIndexer, node: &DirNode) -> Option<ContentHash> {
+    if let Some(hash) = node.get_content_hash().filter(|hash| hash.get_algo() == algo) {
+        return Some(hash.clone());
+    }
+    let path = indexer.get_root_path().join(node.get_entry());
+    let meta = node.get_meta();
+    match fs::symlink_metadata(&path).map(|disk| DirMeta::from(&disk)) {
+        Ok(disk) if disk.get_size() == meta.get_size() && disk.get_mtime_ns() == meta.get_mtime_ns() && disk.get_ino() == meta.get_ino() => {
+            hasher::hash_file(algo, &path).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Pairs removed and added entries that share a device, an inode and a kind.
+fn pair_by_inode(removed: &[&DirNode], added: &[&DirNode]) -> BTreeMap<PathBuf, PathBuf> {
+    let mut by_inode: HashMap<(u64, u64, DirEntryKind), &DirNode> = HashMap::new();
+    for node in removed {
+        let meta = node.get_meta();
+        by_inode.insert((meta.get_dev(), meta.get_ino(), node.get_kind()), node);
+    }
+    let mut pairs: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for node in added {
+        let meta = node.get_meta();
+        if let Some(old_node) = by_inode.remove(&(meta.get_dev(), meta.get_ino(), node.get_kind())) {
+            pairs.insert(node.get_entry().clone(), old_node.get_entry().clone());
+        }
+    }
+    pairs
+}
+
+/// Pairs removed and added regular files with identical content.
+///
+/// Only files of equal size are compared. Among several candidates, one with the same file name is
+/// preferred. Files whose content is unknown, as `hash_entry` decides, are left unpaired.
+fn pair_files_by_content(
+    old: &DirIndexer,
+    removed: &[&DirNode],
+    new: &DirIndexer,
+    added: &[&DirNode],
+    algo: HashAlgo,
+) -> BTreeMap<PathBuf, PathBuf> {
+    let mut by_size: HashMap<u64, Vec<&DirNode>> = HashMap::new();
+    for node in removed.iter().filter(|n| n.get_kind() == DirEntryKind::File) {
+        by_size.entry(node.get_meta().get_size()).or_default().push(node);
+    }
+
+    let mut old_hashes: HashMap<&Path, Option<ContentHash>> = HashMap::new();
+    let mut used: HashSet<&Path> = HashSet::new();
+    let mut pairs: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for node in added.iter().filter(|n| n.get_kind() == DirEntryKind::File) {
+        let candidates = match by_size.get(&node.get_meta().get_size()) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+        let new_hash = match hash_entry(algo, new, node) {
+            Some(hash) => hash,
+            None => continue,
+        };
+        let mut ordered: Vec<&&DirNode> = candidates.iter().collect();
+        ordered.sort_by_key(|c| c.get_name() != node.get_name());
+        for candidate in ordered {
+            let old_path = candidate.get_entry().as_path();
+            if used.contains(old_path) {
+                continue;
+            }
+            let old_hash = old_hashes
+                .entry(old_path)
+                .or_insert_with(|| hash_entry(algo, old, candidate));
+            if old_hash.as_ref() == Some(&new_hash) {
+                used.insert(old_path);
+                pairs.insert(node.get_entry().clone(), old_path.to_path_buf());
+                break;
+            }
+        }
+    }
+    pairs
+}
+
+/// Pairs removed and added directories whose descendants all moved together.
+///
+/// Two directories match when they have the same non-empty set of descendant paths and every
+/// descendant file was already paired with its counterpart.
+fn pair_dirs_by_descendants(removed: &[&DirNode], added: &[&DirNode], pairs: &mut BTreeMap<PathBuf, PathBuf>) {
+    let removed_dirs: Vec<(&DirNode, BTreeSet<(PathBuf, DirEntryKind)>)> = removed
+        .iter()
+        .filter(|n| n.get_kind() == DirEntryKind::Dir)
+        .map(|n| (*n, descendant_set(n)))
+        .filter(|(_, set)| !set.is_empty())
+        .collect();
+    let mut used: HashSet<&Path> = HashSet::new();
+
+    // Deepest directories first, so nested moves are paired before their parents
+    let mut added_dirs: Vec<&DirNode> = added.iter().filter(|n| n.get_kind() == DirEntryKind::Dir).copied().collect();
+    added_dirs.sort_by_key(|n| std::cmp::Reverse(n.get_entry().components().count()));
+    for node in added_dirs {
+        let set = descendant_set(node);
+        if set.is_empty() {
+            continue;
+        }
+        let to = node.get_entry();
+        let found = removed_dirs.iter().find(|(old_node, old_set)| {
+            let from = old_node.get_entry();
+            !used.contains(from.as_path())
+                && *old_set == set
+                && set.iter().all(|(suffix, kind)| {
+                    *kind != DirEntryKind::File || pairs.get(&to.join(suffix)) == Some(&from.join(suffix))
+                })
+        });
+        if let Some((old_node, old_set)) = found {
+            let from = old_node.get_entry();
+            used.insert(from.as_path());
+            pairs.insert(to.clone(), from.clone());
+            for (suffix, _) in old_set {
+                pairs.insert(to.join(suffix), from.join(suffix));
+            }
+        }
+    }
+}
+
+/// Collects the paths and kinds of all descendants of a node, relative to that node.
+fn descendant_set(node: &DirNode) -> BTreeSet<(PathBuf, DirEntryKind)> {
+    let mut map = BTreeMap::new();
+    node.map_rl2node(&mut map);
+    map.values()
+        .filter(|n| n.get_entry() != node.get_entry())
+        .filter_map(|n| {
+            n.get_entry()
+                .strip_prefix(node.get_entry())
+                .ok()
+                .map(|suffix| (suffix.to_path_buf(), n.get_kind()))
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::HashCache;
+    use std::os::unix::fs::symlink;
+
+    fn index(dir: &TempDir) -> DirIndexer {
+        DirIndexer::from(dir.get_path().clone()).unwrap()
+    }
+
+    fn path(rl_path: &str) -> PathBuf {
+        PathBuf::from(rl_path)
+    }
+
+    #[test]
+    fn identical_trees_have_no_changes() {
+        let dir = TempDir::new("diff-same");
+        dir.write("a.txt", b"a");
+        dir.write("sub/b.txt", b"b");
+        let diff = index(&dir).diff(&index(&dir));
+        assert!(diff.is_empty());
+        assert_eq!(diff.get_summary().get_total(), 0);
+    }
+
+    #[test]
+    fn reports_each_kind_of_change() {
+        let dir = TempDir::new("diff-kinds");
+        dir.write("kept.txt", b"kept");
+        dir.write("removed.txt", b"removed");
+        dir.write("grown.txt", b"short");
+        dir.write("retyped", b"file first");
+        symlink("kept.txt", dir.join("link")).unwrap();
+        let old = index(&dir);
+
+        fs::remove_file(dir.join("removed.txt")).unwrap();
+        dir.write("added/new.txt", b"new");
+        dir.write("grown.txt", b"much longer now");
+        fs::remove_file(dir.join("retyped")).unwrap();
+        fs::create_dir(dir.join("retyped")).unwrap();
+        fs::remove_file(dir.join("link")).unwrap();
+        symlink("grown.txt", dir.join("link")).unwrap();
+        let diff = old.diff(&index(&dir));
+
+        let changes = diff.get_changes();
+        assert_eq!(changes.get(&path("removed.txt")), Some(&DirChange::Removed(DirEntryKind::File)));
+        assert_eq!(changes.get(&path("added")), Some(&DirChange::Added(DirEntryKind::Dir)));
+        assert_eq!(changes.get(&path("added/new.txt")), Some(&DirChange::Added(DirEntryKind::File)));
+        assert_eq!(changes.get(&path("grown.txt")), Some(&DirChange::Modified(DirEntryKind::File)));
+        assert_eq!(changes.get(&path("retyped")), Some(&DirChange::TypeChanged(DirEntryKind::File, DirEntryKind::Dir)));
+        assert_eq!(changes.get(&path("link")), Some(&DirChange::Modified(DirEntryKind::Symlink)));
+        assert_eq!(changes.get(&path("kept.txt")), None);
+        assert_eq!(changes.len(), 6);
+
+        let summary = diff.get_summary();
+        assert_eq!((summary.get_added(), summary.get_removed(), summary.get_type_changed(), summary.get_modified()), (2, 1, 1, 2));
+        assert_eq!(summary.to_string(), "2 added, 1 removed, 1 type changed, 2 modified, 0 renamed");
+        assert_eq!(diff.get_added(), vec![&path("added"), &path("added/new.txt")]);
+    }
+
+    #[test]
+    fn directories_are_never_modified() {
+        let dir = TempDir::new("diff-dirs");
+        dir.write("sub/a.txt", b"a");
+        let old = index(&dir);
+        dir.write("sub/b.txt", b"b");
+        let diff = old.diff(&index(&dir));
+        assert_eq!(diff.get_changes().keys().collect::<Vec<_>>(), vec![&path("sub/b.txt")]);
+    }
+
+    #[test]
+    fn content_mode_ignores_copies_with_other_times() {
+        let left = TempDir::new("diff-copy-left");
+        let right = TempDir::new("diff-copy-right");
+        left.write("a.txt", b"same");
+        right.write("a.txt", b"same");
+        left.set_mtime("a.txt", 1_000_000_000);
+        left.write("b.txt", b"left");
+        right.write("b.txt", b"rght");
+        left.set_mtime("b.txt", 1_000_000_000);
+        right.set_mtime("b.txt", 1_000_000_000);
+
+        let options = DirDiffOptions::new().with_content_hash(HashAlgo::Xxh64);
+        assert_eq!(index(&left).diff_with(&index(&right), &options).get_modified(), vec![&path("b.txt")]);
+        assert_eq!(index(&left).diff(&index(&right)).get_modified(), vec![&path("a.txt")]);
+    }
+
+    #[test]
+    fn content_mode_ignores_touched_files_with_the_same_content() {
+        let dir = TempDir::new("diff-touch");
+        dir.write("a.txt", b"same");
+        let mut old = index(&dir);
+        old.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        dir.write("a.txt", b"same");
+        let new = index(&dir);
+
+        let options = DirDiffOptions::new().with_content_hash(HashAlgo::Sha256);
+        assert!(old.diff_with(&new, &options).is_empty());
+    }
+
+    #[test]
+    fn content_mode_uses_the_stored_hashes_of_the_old_tree() {
+        let dir = TempDir::new("diff-stored");
+        dir.write("a.txt", b"aaaa");
+        let mut old = index(&dir);
+        old.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        dir.write("a.txt", b"bbbb");
+        let new = index(&dir);
+
+        let options = DirDiffOptions::new().with_content_hash(HashAlgo::Sha256);
+        assert_eq!(old.diff_with(&new, &options).get_modified(), vec![&path("a.txt")]);
+    }
+
+    #[test]
+    fn content_mode_falls_back_to_metadata_without_old_contents() {
+        let dir = TempDir::new("diff-saved");
+        dir.write("a.txt", b"aaaa");
+        let index_dir = TempDir::new("diff-saved-index");
+        let index_path = index_dir.join("tree.idx");
+        index(&dir).save(&index_path).unwrap();
+        dir.write("a.txt", b"bbbb");
+        let old = DirIndexer::load(&index_path).unwrap();
+
+        // Hashing the current file for the old side would wrongly report no change
+        let options = DirDiffOptions::new().with_content_hash(HashAlgo::Xxh64);
+        assert_eq!(old.diff_with(&index(&dir), &options).get_modified(), vec![&path("a.txt")]);
+    }
+
+    #[test]
+    fn trees_with_different_roots_compare_by_relative_path() {
+        let left = TempDir::new("diff-left");
+        let right = TempDir::new("diff-right");
+        left.write("a.txt", b"a");
+        right.write("b.txt", b"b");
+        let diff = index(&left).diff(&index(&right));
+        assert_eq!(diff.get_removed(), vec![&path("a.txt")]);
+        assert_eq!(diff.get_added(), vec![&path("b.txt")]);
+    }
+
+    #[test]
+    fn renamed_files_are_paired_by_inode() {
+        let dir = TempDir::new("diff-rename");
+        dir.write("old.txt", b"contents");
+        let old = index(&dir);
+        fs::rename(dir.join("old.txt"), dir.join("new.txt")).unwrap();
+        let new = index(&dir);
+
+        assert_eq!(old.diff(&new).get_changes().len(), 2);
+        let diff = old.diff_with(&new, &DirDiffOptions::new().with_renames());
+        assert_eq!(diff.get_renamed(), vec![(&path("old.txt"), &path("new.txt"))]);
+        assert_eq!(diff.get_changes().get(&path("new.txt")), Some(&DirChange::Renamed(DirEntryKind::File, path("old.txt"))));
+        assert_eq!(diff.get_summary().get_renamed(), 1);
+        assert_eq!(diff.get_changes().len(), 1);
+    }
+
+    #[test]
+    fn a_moved_directory_is_one_rename() {
+        let dir = TempDir::new("diff-move-dir");
+        dir.write("from/a.txt", b"a");
+        dir.write("from/deep/b.txt", b"b");
+        let old = index(&dir);
+        fs::create_dir(dir.join("parent")).unwrap();
+        fs::rename(dir.join("from"), dir.join("parent/to")).unwrap();
+        dir.write("parent/to/a.txt", b"changed");
+
+        let diff = old.diff_with(&index(&dir), &DirDiffOptions::new().with_renames());
+        let changes = diff.get_changes();
+        assert_eq!(changes.get(&path("parent/to")), Some(&DirChange::Renamed(DirEntryKind::Dir, path("from"))));
+        assert_eq!(changes.get(&path("parent")), Some(&DirChange::Added(DirEntryKind::Dir)));
+        // Inside the moved directory only real changes remain
+        assert_eq!(changes.get(&path("parent/to/a.txt")), Some(&DirChange::Modified(DirEntryKind::File)));
+        assert_eq!(changes.get(&path("parent/to/deep/b.txt")), None);
+        assert_eq!(changes.len(), 3);
+    }
+
+    #[test]
+    fn copies_are_paired_by_content_preferring_the_same_name() {
+        let left = TempDir::new("diff-copy-left");
+        let right = TempDir::new("diff-copy-right");
+        left.write("one/same.txt", b"identical");
+        left.write("two/other.txt", b"identical");
+        left.write("gone.txt", b"no counterpart");
+        right.write("moved/same.txt", b"identical");
+        right.write("moved/unrelated.txt", b"different bytes");
+
+        let diff = index(&left).diff_with(&index(&right), &DirDiffOptions::new().with_renames());
+        assert_eq!(diff.get_renamed(), vec![(&path("one/same.txt"), &path("moved/same.txt"))]);
+        assert!(diff.get_removed().contains(&&path("two/other.txt")));
+        assert!(diff.get_removed().contains(&&path("gone.txt")));
+        assert!(diff.get_added().contains(&&path("moved/unrelated.txt")));
+    }
+
+    #[test]
+    fn whole_copied_directories_are_paired() {
+        let left = TempDir::new("diff-copy-dir-left");
+        let right = TempDir::new("diff-copy-dir-right");
+        left.write("src/a.txt", b"a");
+        left.write("src/b.txt", b"bb");
+        right.write("lib/a.txt", b"a");
+        right.write("lib/b.txt", b"bb");
+
+        // Copies have new modification times, so only a content comparison sees them unchanged
+        let options = DirDiffOptions::new().with_renames().with_content_hash(HashAlgo::Xxh64);
+        let diff = index(&left).diff_with(&index(&right), &options);
+        assert_eq!(diff.get_renamed(), vec![(&path("src"), &path("lib"))]);
+        assert_eq!(diff.get_changes().len(), 1);
+    }
+}
@@ -1,15 +1,34 @@
-use super::{DirTree, DirIndexerErr};
+use super::{DirColumn, DirDiff, DirDiffOptions, DirTableExporter, DirTree, DirIndexerErr, TableFormat};
+use super::{ChecksumReport, ContentHash, HashAlgo, HashCache, HashStats, MtreeOptions, MtreeReport};
+use super::{DuplicateDirGroup, DuplicateGroup, DuplicateOptions, MerkleOptions, MerkleTree};
+use super::{DedupeOptions, DedupeReport, DirNode, DirQuery, FuzzyIndex, FuzzyMatch, FuzzyOptions};
+use super::{ContentMatch, DirEntryKind, HtmlReportOptions, ScanError, TrigramIndex, TrigramStats};
+use super::codec;
+use super::dedupe;
+use super::checksum_manifest;
+use super::duplicates;
+use super::hash_cache;
+use super::html_report;
+use super::index_file;
+use super::mapped_index;
+use super::merkle;
+use super::mtree;
+use super::trigram_index;
 use std::fs;
-use std::path::PathBuf;
-use std::collections::{HashSet, HashMap};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashSet, HashMap};
+use std::time::SystemTime;
 
 /// The `DirIndexer` struct represents an indexer for a directory.
 ///
 /// It provides functionality to index files within a directory and retrieve information about them.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirIndexer {
     root_path_: PathBuf,
     root_tree_: DirTree,
+    scan_time_: SystemTime,
+    scan_errors_: Vec<ScanError>,
 }
 
 impl DirIndexer {
@@ -22,28 +41,84 @@ impl DirIndexer {
     /// # Returns
     ///
     /// An `Result` containing the `DirIndexer` instance if the root path exists and is a directory,
-    /// or an `Err` variant of `DirIndexerErr` otherwise.
+    /// or an `Err` variant of `DirIndexerErr` otherwise, such as `LackPermission` if the root
+    /// directory cannot be read.
     pub fn from(root_path: PathBuf) -> Result<DirIndexer, DirIndexerErr> {
         if root_path.exists() && root_path.is_dir() {
             let mut ab_path = root_path.clone();
             if root_path.is_relative() {
-                let con_result = fs::canonicalize(root_path.clone());
-                if con_result.is_err() {
-                    return Err(DirIndexerErr::CanonicalizeFail(root_path));
-                } else {
-                    ab_path = con_result.unwrap();
+                match fs::canonicalize(root_path.clone()) {
+                    Ok(con_result) => ab_path = con_result,
+                    Err(_) => return Err(DirIndexerErr::CanonicalizeFail(root_path)),
                 }
             }
-            let dir_tree = DirTree::from(&ab_path);
+            let scan_time = SystemTime::now();
+            let mut scan_errors = Vec::new();
+            let dir_tree = DirTree::scan(&ab_path, &mut scan_errors)?;
+            scan_errors.sort_by(|a, b| a.get_path().cmp(b.get_path()));
             Ok(DirIndexer {
                 root_path_: ab_path,
                 root_tree_: dir_tree,
+                scan_time_: scan_time,
+                scan_errors_: scan_errors,
             })
         } else {
             Err(DirIndexerErr::NotDirNorExist(root_path))
         }
     }
 
+    /// Creates a `DirIndexer` from already known parts, as when loading a saved index.
+    pub(crate) fn from_parts(root_path: PathBuf, root_tree: DirTree, scan_time: SystemTime) -> DirIndexer {
+        DirIndexer {
+            root_path_: root_path,
+            root_tree_: root_tree,
+            scan_time_: scan_time,
+            scan_errors_: Vec::new(),
+        }
+    }
+
+    /// Loads an index previously written by `save`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_path` - The path of the index file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the loaded `DirIndexer`, or an `Err` variant of `DirIndexerErr` if the file
+    /// cannot be read, was written by an incompatible format version, or is corrupt.
+    pub fn load(index_path: &Path) -> Result<DirIndexer, DirIndexerErr> {
+        index_file::load_index(index_path)
+    }
+
+    /// Saves the index to a compact binary file, so that it can be loaded without rescanning.
+    ///
+    /// The file is written to a temporary path first and then renamed over `index_path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_path` - The path of the index file.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or `DirIndexerErr::IndexIoFail` if the file cannot be written.
+    pub fn save(&self, index_path: &Path) -> Result<(), DirIndexerErr> {
+        index_file::save_index(self, index_path)
+    }
+
+    /// Saves the index in a layout that `MappedIndex::open` can query in place from a memory map.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_path` - The path of the mapped index file.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or `DirIndexerErr::IndexIoFail` if the file cannot be written.
+    pub fn save_mapped(&self, index_path: &Path) -> Result<(), DirIndexerErr> {
+        mapped_index::save_mapped_index(self, index_path)
+    }
+
     /// Retrieves a set of relative file paths within the indexed directory and its subdirectories.
     ///
     /// # Returns
@@ -115,5 +190,568 @@ impl DirIndexer {
     pub fn get_ab2rl_dir_paths_map(&self) -> HashMap<PathBuf, PathBuf> {
         self.root_tree_.get_ab2rl_dir_paths(&self.root_path_)
     }
+
+    /// Returns the absolute root path of the indexed directory.
+    pub fn get_root_path(&self) -> &PathBuf {
+        &self.root_path_
+    }
+
+    /// Returns the time at which the directory was scanned.
+    pub fn get_scan_time(&self) -> SystemTime {
+        self.scan_time_
+    }
+
+    /// Returns the entries that were left out of the index during the scan, sorted by path.
+    ///
+    /// These are directories that could not be read, with everything below them, dangling
+    /// symbolic links and entries that are neither files nor directories, such as sockets. An
+    /// index loaded from a file has no scan errors.
+    pub fn get_scan_errors(&self) -> &[ScanError] {
+        &self.scan_errors_
+    }
+
+    /// Returns the directory tree built for the indexed directory.
+    pub fn get_tree(&self) -> &DirTree {
+        &self.root_tree_
+    }
+
+    /// Compares this index, taken as the older snapshot, with another one.
+    ///
+    /// Modifications of files are decided by size and modification time. The two indexes may have
+    /// different roots, since entries are matched by relative path.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The newer snapshot.
+    ///
+    /// # Returns
+    ///
+    /// A `DirDiff` with the added, removed, type-changed and modified entries.
+    pub fn diff(&self, other: &DirIndexer) -> DirDiff {
+        DirDiff::from(self, other, &DirDiffOptions::default())
+    }
+
+    /// Compares this index, taken as the older snapshot, with another one using the given options.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The newer snapshot.
+    /// * `options` - Options controlling how modifications are detected.
+    ///
+    /// # Returns
+    ///
+    /// A `DirDiff` with the added, removed, type-changed and modified entries.
+    pub fn diff_with(&self, other: &DirIndexer, options: &DirDiffOptions) -> DirDiff {
+        DirDiff::from(self, other, options)
+    }
+
+    /// Writes one CSV row per indexed entry with the chosen columns, preceded by a header row.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The destination of the table.
+    /// * `columns` - The columns to write, in order.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the I/O error raised by `out`.
+    pub fn write_csv<W: Write>(&self, out: &mut W, columns: &[DirColumn]) -> io::Result<()> {
+        DirTableExporter::new(columns.to_vec()).write(self, out)
+    }
+
+    /// Writes one TSV row per indexed entry with the chosen columns, preceded by a header row.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The destination of the table.
+    /// * `columns` - The columns to write, in order.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the I/O error raised by `out`.
+    pub fn write_tsv<W: Write>(&self, out: &mut W, columns: &[DirColumn]) -> io::Result<()> {
+        DirTableExporter::new(columns.to_vec())
+            .with_format(TableFormat::Tsv)
+            .write(self, out)
+    }
+
+    /// Writes an mtree(5) manifest of the index with the keywords selected in `options`.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The destination of the manifest.
+    /// * `options` - The keywords to write.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the I/O error raised by `out`.
+    pub fn write_mtree<W: Write>(&self, out: &mut W, options: &MtreeOptions) -> io::Result<()> {
+        mtree::write_mtree(self, out, options)
+    }
+
+    /// Writes a disk-usage report of the index as a single HTML page.
+    ///
+    /// The page shows a treemap, a collapsible tree with aggregated sizes, the largest files and
+    /// directories, the space taken by each file extension and the scan errors. It needs no
+    /// network access or other files, so it can be mailed or archived as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The writer receiving the page.
+    /// * `options` - The title, the number of largest entries and the depth limit.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` reporting whether writing succeeded.
+    pub fn write_html_report<W: Write>(&self, out: &mut W, options: &HtmlReportOptions) -> io::Result<()> {
+        html_report::write_html_report(self, out, options)
+    }
+
+    /// Verifies the index against an mtree(5) manifest.
+    ///
+    /// Only the keywords selected in `options` are compared; entries flagged `optional` in the
+    /// manifest are not reported when missing, and the contents of `ignore` directories are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The text of the manifest.
+    /// * `options` - The keywords to check.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `MtreeReport` of missing, extra and mismatched entries, or a
+    /// `DirIndexerErr::ManifestParseFail` if the manifest is malformed.
+    pub fn verify_mtree(&self, spec: &str, options: &MtreeOptions) -> Result<MtreeReport, DirIndexerErr> {
+        mtree::verify_mtree(self, spec, options)
+    }
+
+    /// Writes a checksum manifest in the format of coreutils `sha256sum`, one line per regular
+    /// file with its path relative to the root. Files that cannot be read are left out. Hashes
+    /// stored by `compute_hashes` with the same algorithm are used instead of reading the files.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The destination of the manifest.
+    /// * `algo` - The hash algorithm; `HashAlgo::Xxh64` is much faster but not cryptographic.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the I/O error raised by `out`.
+    pub fn write_checksums<W: Write>(&self, out: &mut W, algo: HashAlgo) -> io::Result<()> {
+        checksum_manifest::write_checksums(self, out, algo)
+    }
+
+    /// Verifies the regular files of the index against a checksum manifest. Hashes stored by
+    /// `compute_hashes` with the same algorithm are used instead of reading the files.
+    ///
+    /// # Arguments
+    ///
+    /// * `manifest` - The raw bytes of the manifest; names need not be valid UTF-8.
+    /// * `algo` - The hash algorithm the manifest was written with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `ChecksumReport` with the status of each listed file and the
+    /// files absent from the manifest, or a `DirIndexerErr::ManifestParseFail` if a line is malformed.
+    pub fn verify_checksums(&self, manifest: &[u8], algo: HashAlgo) -> Result<ChecksumReport, DirIndexerErr> {
+        checksum_manifest::verify_checksums(self, manifest, algo)
+    }
+
+    /// Computes the content hash of every regular file and stores it on the file's node.
+    ///
+    /// A hash is taken from `cache` when the file's device, inode, size and modification time are
+    /// unchanged, and recomputed otherwise. Afterwards `cache` holds exactly the files of this
+    /// index and can be saved next to it with `HashCache::save`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - The cache to reuse and refresh; its algorithm decides the hash algorithm.
+    /// * `threads` - The number of threads reading files; `0` and `1` both hash on the caller's thread.
+    ///
+    /// # Returns
+    ///
+    /// The `HashStats` counting hashed, reused and unreadable files.
+    pub fn compute_hashes(&mut self, cache: &mut HashCache, threads: usize) -> HashStats {
+        let (hashes, stats) = hash_cache::compute_hashes(self, cache, threads);
+        self.root_tree_.set_content_hashes(hashes);
+        stats
+    }
+
+    /// Returns the content hash of an indexed file, once `compute_hashes` has run.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The path of the file relative to the root.
+    pub fn get_content_hash(&self, rl_path: &Path) -> Option<&ContentHash> {
+        self.root_tree_.get_node(rl_path).and_then(|node| node.get_content_hash())
+    }
+
+    /// Finds groups of regular files with identical contents.
+    ///
+    /// Candidates are grouped by size, then by a hash of their first and last 4 KiB, and only
+    /// then hashed in full. Hard links to one inode count as a single file.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The minimum size, glob filters and hash algorithm.
+    ///
+    /// # Returns
+    ///
+    /// The duplicate groups, sorted by wasted bytes, largest first.
+    pub fn find_duplicates(&self, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+        duplicates::find_duplicates(self, options)
+    }
+
+    /// Finds directories whose subtrees are identical: the same names, kinds and file contents.
+    ///
+    /// Only the topmost duplicated directories are reported, not the matching directories nested
+    /// inside them. Directories without regular files are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The hash algorithm used for the structural hashes.
+    ///
+    /// # Returns
+    ///
+    /// The duplicate directory groups, sorted by wasted bytes, largest first.
+    pub fn find_duplicate_dirs(&self, options: &MerkleOptions) -> Vec<DuplicateDirGroup> {
+        merkle::find_duplicate_dirs(self, options)
+    }
+
+    /// Computes the structural hash of every entry, for fingerprinting and comparing trees.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The hash algorithm and whether mode bits and mtimes feed into the hashes.
+    ///
+    /// # Returns
+    ///
+    /// The `MerkleTree`, whose root hash fingerprints the whole index.
+    pub fn get_merkle_tree(&self, options: &MerkleOptions) -> MerkleTree {
+        MerkleTree::from(self, options)
+    }
+
+    /// Computes the structural hash of a single subtree, without hashing the rest of the index.
+    ///
+    /// The hash does not depend on where the subtree sits, so equal subtrees at different paths,
+    /// or in different indexes, hash alike.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The path of the subtree relative to the root; an empty path is the whole tree.
+    /// * `options` - The hash algorithm and whether mode bits and mtimes feed into the hash.
+    ///
+    /// # Returns
+    ///
+    /// The hash, or `None` if `rl_path` is not indexed.
+    pub fn get_merkle_hash(&self, rl_path: &Path, options: &MerkleOptions) -> Option<ContentHash> {
+        let node = self.root_tree_.get_node(rl_path)?;
+        Some(MerkleTree::from_node(&self.root_path_, node, options).get_root_hash().clone())
+    }
+
+    /// Replaces duplicate files with hard links to a single canonical copy.
+    ///
+    /// Within each group, copies on the same filesystem are linked to the one that already has the
+    /// most links. Right before linking, each copy is checked to be unchanged since indexing and
+    /// compared byte by byte with the canonical file, then a link is created under a temporary
+    /// name and renamed over the copy. Links are never made across filesystems, nor to files
+    /// reached through a symbolically linked directory, which may lie outside the root. The index
+    /// itself is not updated and should be rescanned afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `groups` - The duplicate groups, as returned by `find_duplicates`.
+    /// * `options` - Whether to only report what would be done.
+    ///
+    /// # Returns
+    ///
+    /// The `DedupeReport` of linked and skipped files and the bytes saved.
+    pub fn dedupe_hardlinks(&self, groups: &[DuplicateGroup], options: &DedupeOptions) -> DedupeReport {
+        dedupe::dedupe_hardlinks(self, groups, options)
+    }
+
+    /// Finds the indexed entries matching a query, without touching the disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, parsed with `DirQuery::parse` or built from its constructors.
+    ///
+    /// # Returns
+    ///
+    /// The matching nodes sorted by relative path. The root itself is never included.
+    pub fn find(&self, query: &DirQuery) -> Vec<&DirNode> {
+        self.root_tree_
+            .get_rl2node_map()
+            .into_iter()
+            .filter(|(rl_path, node)| !rl_path.as_os_str().is_empty() && query.matches(node))
+            .map(|(_, node)| node)
+            .collect()
+    }
+
+    /// Finds the entries whose relative paths best match a fuzzy query, as `fzf` does.
+    ///
+    /// This packs the paths into a `FuzzyIndex` on every call; build one with `FuzzyIndex::from`
+    /// to run many queries against the same index.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The bytes to look for, in order but not necessarily adjacent.
+    /// * `options` - The number of results, kind filter and case sensitivity.
+    ///
+    /// # Returns
+    ///
+    /// The best matches with their scores and matched byte positions, best first.
+    pub fn fuzzy_find(&self, query: &str, options: &FuzzyOptions) -> Vec<FuzzyMatch> {
+        FuzzyIndex::from(self).search(query, options)
+    }
+
+    /// Rescans the root directory and replaces the index with the result.
+    ///
+    /// Content hashes are kept for files whose size, modification time and inode are unchanged.
+    /// The returned diff can be passed to structures built from the old index, such as
+    /// `PathTrie::apply_diff`, to update them without a rebuild.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirDiff` from the old index to the new one, or a `DirIndexerErr`
+    /// if the root is no longer a directory, in which case the index is left unchanged.
+    pub fn refresh(&mut self) -> Result<DirDiff, DirIndexerErr> {
+        let fresh = DirIndexer::from(self.root_path_.clone())?;
+        let diff = DirDiff::from(self, &fresh, &DirDiffOptions::new());
+        let hashes = kept_hashes(&self.root_tree_.get_rl2node_map(), &fresh.root_tree_.get_rl2node_map());
+        *self = fresh;
+        self.root_tree_.set_content_hashes(hashes);
+        Ok(diff)
+    }
+
+    /// Rescans one entry and everything below it, leaving the rest of the index as it is.
+    ///
+    /// The entry is removed from the index if it no longer exists and added if it is new.
+    /// Content hashes and scan errors below the entry are updated as `refresh` would; the
+    /// metadata of its parent directory is left as it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The relative path of the entry; an empty path rescans the whole root.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirDiff` of the changes below the entry, or
+    /// `DirIndexerErr::NotDirNorExist` if the parent of the entry is not an indexed directory.
+    pub fn refresh_subtree(&mut self, rl_path: &Path) -> Result<DirDiff, DirIndexerErr> {
+        let rl_path = match codec::normalize_relative(rl_path) {
+            Some(rl_path) => rl_path,
+            None => return Err(DirIndexerErr::NotDirNorExist(self.root_path_.join(rl_path))),
+        };
+        let parent = match rl_path.parent() {
+            Some(parent) => parent,
+            None => return self.refresh(),
+        };
+        match self.root_tree_.get_node(parent) {
+            Some(node) if node.get_kind() == DirEntryKind::Dir => {}
+            _ => return Err(DirIndexerErr::NotDirNorExist(self.root_path_.join(parent))),
+        }
+
+        // An entry that is gone is simply dropped; one that cannot be indexed is a scan error
+        let mut errors = Vec::new();
+        let mut fresh = if fs::symlink_metadata(self.root_path_.join(&rl_path)).is_ok() {
+            DirNode::scan(&self.root_path_, &rl_path, &mut errors).ok()
+        } else {
+            None
+        };
+        let old = self.root_tree_.get_node(&rl_path);
+        let diff = DirDiff::from_subtrees(self, old, fresh.as_ref());
+        if let (Some(old), Some(fresh)) = (old, fresh.as_mut()) {
+            let mut old_nodes = BTreeMap::new();
+            let mut fresh_nodes = BTreeMap::new();
+            old.map_rl2node(&mut old_nodes);
+            fresh.map_rl2node(&mut fresh_nodes);
+            let mut hashes = kept_hashes(&old_nodes, &fresh_nodes);
+            fresh.take_content_hashes(&mut hashes);
+        }
+        self.root_tree_.replace_node(&rl_path, fresh);
+        self.scan_errors_.retain(|error| !error.get_path().starts_with(&rl_path));
+        self.scan_errors_.extend(errors);
+        self.scan_errors_.sort_by(|a, b| a.get_path().cmp(b.get_path()));
+        Ok(diff)
+    }
+
+    /// Brings a trigram index of file contents up to date with the regular files of this index.
+    ///
+    /// Files whose device, inode, size and modification time are unchanged keep their trigrams;
+    /// the others are read again. Binary files, recognized by a NUL byte in their first 8 KiB,
+    /// and files over 64 MiB are recorded but not indexed. Afterwards `index` holds exactly the
+    /// files of this index and can be saved next to it with `TrigramIndex::save`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The trigram index to reuse and refresh.
+    ///
+    /// # Returns
+    ///
+    /// The `TrigramStats` counting indexed, reused, skipped, removed and unreadable files.
+    pub fn update_trigram_index(&self, index: &mut TrigramIndex) -> TrigramStats {
+        trigram_index::update(self, index)
+    }
+
+    /// Searches the contents of the regular files for lines matching a regular expression.
+    ///
+    /// The trigram index narrows the search to files that can hold a match; only those are read.
+    /// Files missing from the trigram index or changed since it was updated are always read, files
+    /// over 64 MiB are read a line at a time, and binary files are never searched.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - A trigram index, ideally updated with `update_trigram_index`.
+    /// * `pattern` - A regular expression in the syntax of `DirQuery::regex`, matched against
+    ///   each line; `(?i)` makes it case-insensitive.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the matching lines ordered by path and line number, or
+    /// `DirIndexerErr::QueryParseFail` if the pattern is invalid.
+    pub fn grep(&self, index: &TrigramIndex, pattern: &str) -> Result<Vec<ContentMatch>, DirIndexerErr> {
+        trigram_index::search(self, index, pattern)
+    }
 }
 
+/// Returns the content hashes of `old` nodes that can be kept for the `new` nodes at the same
+/// path, because their kind, size, modification time and inode are unchanged.
+fn kept_hashes(old: &BTreeMap<PathBuf, &DirNode>, new: &BTreeMap<PathBuf, &DirNode>) -> HashMap<PathBuf, ContentHash> {
+    let mut hashes = HashMap::new();
+    for (rl_path, node) in old {
+        let (hash, new_node) = match (node.get_content_hash(), new.get(rl_path)) {
+            (Some(hash), Some(new_node)) => (hash, new_node),
+            _ => continue,
+        };
+        let (old_meta, new_meta) = (node.get_meta(), new_node.get_meta());
+        if new_node.get_kind() == node.get_kind()
+            && old_meta.get_size() == new_meta.get_size()
+            && old_meta.get_mtime_ns() == new_meta.get_mtime_ns()
+            && old_meta.get_ino() == new_meta.get_ino()
+        {
+            hashes.insert(rl_path.clone(), hash.clone());
+        }
+    }
+    hashes
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::DirChange;
+    use std::os::unix::fs::symlink;
+
+    fn sample(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("sub/keep.txt", b"keep");
+        dir.write("sub/change.txt", b"old");
+        dir.write("sub/gone/inner.txt", b"inner");
+        dir.write("other.txt", b"other");
+        symlink("nowhere", dir.join("dangling")).unwrap();
+        dir
+    }
+
+    fn change_sub(dir: &TempDir) {
+        dir.write("sub/new/added.txt", b"added");
+        dir.write("sub/change.txt", b"new contents");
+        fs::remove_dir_all(dir.join("sub/gone")).unwrap();
+        symlink("nowhere", dir.join("sub/dangling")).unwrap();
+        dir.write("outside.txt", b"not rescanned");
+    }
+
+    fn under<'a>(diff: &'a DirDiff, rl_path: &Path) -> Vec<(&'a PathBuf, &'a DirChange)> {
+        diff.get_changes().iter().filter(|(path, _)| path.starts_with(rl_path)).collect()
+    }
+
+    #[test]
+    fn refresh_subtree_matches_a_full_rescan_below_the_entry() {
+        let dir = sample("refresh-subtree");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let before = DirIndexer::from(dir.get_path().clone()).unwrap();
+        change_sub(&dir);
+        let fresh = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let diff = indexer.refresh_subtree(Path::new("sub")).unwrap();
+        let sub = Path::new("sub");
+        assert_eq!(indexer.get_tree().get_node(sub), fresh.get_tree().get_node(sub));
+        assert_eq!(diff.get_changes().iter().collect::<Vec<_>>(), under(&before.diff(&fresh), sub));
+        assert!(!diff.get_changes().is_empty());
+
+        // Nothing outside the entry is rescanned, not even the metadata of the root
+        let root = Path::new("");
+        assert_eq!(indexer.get_tree().get_node(root).unwrap().get_meta(), before.get_tree().get_node(root).unwrap().get_meta());
+        assert_eq!(indexer.get_tree().get_node(Path::new("other.txt")), before.get_tree().get_node(Path::new("other.txt")));
+        assert!(indexer.get_tree().get_node(Path::new("outside.txt")).is_none());
+
+        let errors: Vec<&Path> = indexer.get_scan_errors().iter().map(|error| error.get_path().as_path()).collect();
+        assert_eq!(errors, vec![Path::new("dangling"), Path::new("sub/dangling")]);
+    }
+
+    #[test]
+    fn refresh_subtree_adds_and_drops_entries() {
+        let dir = sample("refresh-subtree-entries");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        dir.write("sub/fresh.txt", b"fresh");
+        fs::remove_dir_all(dir.join("sub/gone")).unwrap();
+
+        let diff = indexer.refresh_subtree(Path::new("sub/fresh.txt")).unwrap();
+        let changes: Vec<(&Path, &DirChange)> = diff.get_changes().iter().map(|(path, change)| (path.as_path(), change)).collect();
+        assert_eq!(changes, vec![(Path::new("sub/fresh.txt"), &DirChange::Added(DirEntryKind::File))]);
+        assert!(indexer.get_tree().get_node(Path::new("sub/fresh.txt")).is_some());
+
+        let diff = indexer.refresh_subtree(Path::new("sub/gone")).unwrap();
+        assert_eq!(diff.get_removed(), vec![Path::new("sub/gone"), Path::new("sub/gone/inner.txt")]);
+        assert!(indexer.get_tree().get_node(Path::new("sub/gone")).is_none());
+        assert!(indexer.get_tree().get_node(Path::new("sub")).unwrap().get_children().all(|child| child.get_name() != "gone"));
+
+        // An entry that neither existed nor exists changes nothing
+        let diff = indexer.refresh_subtree(Path::new("sub/never")).unwrap();
+        assert!(diff.get_changes().is_empty());
+    }
+
+    #[test]
+    fn refresh_subtree_of_the_root_is_a_full_refresh() {
+        let dir = sample("refresh-subtree-root");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let before = DirIndexer::from(dir.get_path().clone()).unwrap();
+        change_sub(&dir);
+        let fresh = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let diff = indexer.refresh_subtree(Path::new("")).unwrap();
+        assert_eq!(diff.get_changes(), before.diff(&fresh).get_changes());
+        assert_eq!(indexer.get_tree().get_root_node(), fresh.get_tree().get_root_node());
+        assert_eq!(indexer.get_scan_errors(), fresh.get_scan_errors());
+    }
+
+    #[test]
+    fn refresh_subtree_rejects_paths_outside_the_index() {
+        let dir = sample("refresh-subtree-bad");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        for rl_path in ["missing/x", "other.txt/x", "../x", "/etc"].iter() {
+            let result = indexer.refresh_subtree(Path::new(rl_path));
+            assert!(matches!(result, Err(DirIndexerErr::NotDirNorExist(_))), "{}", rl_path);
+        }
+        assert_eq!(indexer.get_tree().get_root_node(), DirIndexer::from(dir.get_path().clone()).unwrap().get_tree().get_root_node());
+    }
+
+    #[test]
+    fn refresh_subtree_keeps_hashes_of_unchanged_files() {
+        let dir = sample("refresh-subtree-hashes");
+        for rl_path in ["sub/keep.txt", "sub/change.txt", "other.txt"].iter() {
+            dir.set_mtime(rl_path, 1_000_000_000);
+        }
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        let keep = indexer.get_content_hash(Path::new("sub/keep.txt")).cloned();
+        assert!(keep.is_some());
+        assert!(indexer.get_content_hash(Path::new("sub/change.txt")).is_some());
+
+        dir.write("sub/change.txt", b"new contents");
+        indexer.refresh_subtree(Path::new("sub")).unwrap();
+        assert_eq!(indexer.get_content_hash(Path::new("sub/keep.txt")).cloned(), keep);
+        assert!(indexer.get_content_hash(Path::new("sub/change.txt")).is_none());
+        assert!(indexer.get_content_hash(Path::new("other.txt")).is_some());
+    }
+}
@@ -17,6 +17,23 @@ pub enum DirIndexerErr {
     ToStrFail,
     /// Failed to construct the directory tree.
     TreeConstructFailed,
+    /// Failed to read or write an index file.
+    IndexIoFail(PathBuf),
+    /// The index file was written by an incompatible format version.
+    IncompatibleIndexVersion(u32),
+    /// The index file is truncated, corrupt or not an index file at all.
+    CorruptIndex(PathBuf),
+    /// Failed to parse JSON input; holds a description of the syntax error.
+    JsonParseFail(String),
+    /// Failed to parse a manifest; holds the line number and a description of the problem.
+    ManifestParseFail(String),
+    /// Failed to parse a query; holds a description of the problem.
+    QueryParseFail(String),
+    /// Failed to create, connect to or talk over a daemon socket, or another daemon is already
+    /// listening on it.
+    DaemonIoFail(PathBuf),
+    /// The daemon rejected a request or sent an unreadable answer; holds a description.
+    DaemonRequestFail(String),
 }
 
 impl fmt::Display for DirIndexerErr {
@@ -24,13 +41,13 @@ impl fmt::Display for DirIndexerErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DirIndexerErr::CanonicalizeFail(path) => {
-                write!(f, "{} unable to use fs::canonicalize()\n Reasons:\n Path does not exist", path.to_str().unwrap())
+                write!(f, "{} unable to use fs::canonicalize()\n Reasons:\n Path does not exist", path.display())
             }
             DirIndexerErr::NotDirNorExist(path) => {
-                write!(f, "{} path does not exist", path.to_str().unwrap())
+                write!(f, "{} path does not exist", path.display())
             }
             DirIndexerErr::LackPermission(path) => {
-                write!(f, "{} User Lack Permission Over the Directory", path.to_str().unwrap())
+                write!(f, "{} User Lack Permission Over the Directory", path.display())
             }
             DirIndexerErr::NotFileAndDir => {
                 write!(f, "The provided path is neither a file nor a directory")
@@ -41,6 +58,30 @@ impl fmt::Display for DirIndexerErr {
             DirIndexerErr::TreeConstructFailed => {
                 write!(f, "Failed to construct directory tree")
             }
+            DirIndexerErr::IndexIoFail(path) => {
+                write!(f, "{} unable to read or write index file", path.display())
+            }
+            DirIndexerErr::IncompatibleIndexVersion(version) => {
+                write!(f, "Index file format version {} is not supported", version)
+            }
+            DirIndexerErr::CorruptIndex(path) => {
+                write!(f, "{} index file is corrupt", path.display())
+            }
+            DirIndexerErr::JsonParseFail(reason) => {
+                write!(f, "Failed to parse JSON: {}", reason)
+            }
+            DirIndexerErr::ManifestParseFail(reason) => {
+                write!(f, "Failed to parse manifest: {}", reason)
+            }
+            DirIndexerErr::QueryParseFail(reason) => {
+                write!(f, "Failed to parse query: {}", reason)
+            }
+            DirIndexerErr::DaemonIoFail(path) => {
+                write!(f, "{} unable to use daemon socket", path.display())
+            }
+            DirIndexerErr::DaemonRequestFail(reason) => {
+                write!(f, "Daemon request failed: {}", reason)
+            }
         }
     }
 }
@@ -0,0 +1,212 @@
+use std::fs;
+
+/// Represents the kind of an entry in a directory tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DirEntryKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link.
+    Symlink,
+    /// Anything else (sockets, fifos, device nodes).
+    Other,
+}
+
+impl DirEntryKind {
+    /// Determines the kind of an entry from its file type.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_type` - The file type obtained from `fs::symlink_metadata`.
+    ///
+    /// # Returns
+    ///
+    /// The matching `DirEntryKind`.
+    pub fn from(file_type: fs::FileType) -> DirEntryKind {
+        if file_type.is_symlink() {
+            DirEntryKind::Symlink
+        } else if file_type.is_dir() {
+            DirEntryKind::Dir
+        } else if file_type.is_file() {
+            DirEntryKind::File
+        } else {
+            DirEntryKind::Other
+        }
+    }
+
+    /// Returns a short lowercase name of the kind.
+    ///
+    /// # Returns
+    ///
+    /// One of `"file"`, `"dir"`, `"symlink"` or `"other"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DirEntryKind::File => "file",
+            DirEntryKind::Dir => "dir",
+            DirEntryKind::Symlink => "symlink",
+            DirEntryKind::Other => "other",
+        }
+    }
+}
+
+/// Holds the metadata recorded for an entry at indexing time.
+///
+/// The metadata is taken from `fs::symlink_metadata`, so symbolic links describe the link itself
+/// rather than its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DirMeta {
+    size_: u64,
+    mtime_: i64,
+    mtime_nsec_: u32,
+    mode_: u32,
+    uid_: u32,
+    gid_: u32,
+    dev_: u64,
+    ino_: u64,
+    nlink_: u64,
+}
+
+impl DirMeta {
+    /// Creates a new `DirMeta` from the metadata of an entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `meta` - The metadata of the entry.
+    ///
+    /// # Returns
+    ///
+    /// A `DirMeta` instance.
+    #[cfg(unix)]
+    pub fn from(meta: &fs::Metadata) -> DirMeta {
+        use std::os::unix::fs::MetadataExt;
+        DirMeta {
+            size_: meta.size(),
+            mtime_: meta.mtime(),
+            mtime_nsec_: meta.mtime_nsec() as u32,
+            mode_: meta.mode(),
+            uid_: meta.uid(),
+            gid_: meta.gid(),
+            dev_: meta.dev(),
+            ino_: meta.ino(),
+            nlink_: meta.nlink(),
+        }
+    }
+
+    /// Creates a new `DirMeta` from the metadata of an entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `meta` - The metadata of the entry.
+    ///
+    /// # Returns
+    ///
+    /// A `DirMeta` instance.
+    #[cfg(not(unix))]
+    pub fn from(meta: &fs::Metadata) -> DirMeta {
+        use std::time::UNIX_EPOCH;
+        let (mtime, mtime_nsec) = match meta.modified().map(|t| t.duration_since(UNIX_EPOCH)) {
+            Ok(Ok(d)) => (d.as_secs() as i64, d.subsec_nanos()),
+            _ => (0, 0),
+        };
+        let mode = if meta.permissions().readonly() { 0o444 } else { 0o644 };
+        DirMeta {
+            size_: meta.len(),
+            mtime_: mtime,
+            mtime_nsec_: mtime_nsec,
+            mode_: mode,
+            nlink_: 1,
+            ..DirMeta::default()
+        }
+    }
+
+    /// Returns the size in bytes.
+    pub fn get_size(&self) -> u64 {
+        self.size_
+    }
+
+    /// Returns the modification time in seconds since the Unix epoch.
+    pub fn get_mtime(&self) -> i64 {
+        self.mtime_
+    }
+
+    /// Returns the nanosecond part of the modification time.
+    pub fn get_mtime_nsec(&self) -> u32 {
+        self.mtime_nsec_
+    }
+
+    /// Returns the modification time in nanoseconds since the Unix epoch.
+    pub fn get_mtime_ns(&self) -> i128 {
+        self.mtime_ as i128 * 1_000_000_000 + self.mtime_nsec_ as i128
+    }
+
+    /// Returns the raw mode bits, including the file type bits.
+    pub fn get_mode(&self) -> u32 {
+        self.mode_
+    }
+
+    /// Returns the permission bits (`mode & 0o7777`).
+    pub fn get_permissions(&self) -> u32 {
+        self.mode_ & 0o7777
+    }
+
+    /// Returns the owner user id.
+    pub fn get_uid(&self) -> u32 {
+        self.uid_
+    }
+
+    /// Returns the owner group id.
+    pub fn get_gid(&self) -> u32 {
+        self.gid_
+    }
+
+    /// Returns the id of the device containing the entry.
+    pub fn get_dev(&self) -> u64 {
+        self.dev_
+    }
+
+    /// Returns the inode number.
+    pub fn get_ino(&self) -> u64 {
+        self.ino_
+    }
+
+    /// Returns the number of hard links.
+    pub fn get_nlink(&self) -> u64 {
+        self.nlink_
+    }
+
+    /// Returns the metadata as a fixed list of fields, in declaration order, for serialization.
+    pub(crate) fn to_fields(self) -> [u64; 9] {
+        [
+            self.size_,
+            self.mtime_ as u64,
+            self.mtime_nsec_ as u64,
+            self.mode_ as u64,
+            self.uid_ as u64,
+            self.gid_ as u64,
+            self.dev_,
+            self.ino_,
+            self.nlink_,
+        ]
+    }
+
+    /// Rebuilds the metadata from the fields produced by `to_fields`.
+    ///
+    /// Returns `None` if a field does not fit its type.
+    pub(crate) fn from_fields(fields: &[u64; 9]) -> Option<DirMeta> {
+        if fields[2] >= 1_000_000_000 || fields[3] > u32::MAX as u64 || fields[4] > u32::MAX as u64 || fields[5] > u32::MAX as u64 {
+            return None;
+        }
+        Some(DirMeta {
+            size_: fields[0],
+            mtime_: fields[1] as i64,
+            mtime_nsec_: fields[2] as u32,
+            mode_: fields[3] as u32,
+            uid_: fields[4] as u32,
+            gid_: fields[5] as u32,
+            dev_: fields[6],
+            ino_: fields[7],
+            nlink_: fields[8],
+        })
+    }
+}
@@ -1,14 +1,22 @@
 use std::fs;
-use std::path::PathBuf;
-use std::collections::{HashSet, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashSet, HashMap};
 use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 
-use super::DirIndexerErr;
+use super::{ContentHash, DirEntryKind, DirIndexerErr, DirMeta, HashAlgo, ScanError, TreeRenderOptions};
+use super::hasher;
+use super::tree_render;
 
 /// Represents a directory node in a directory tree.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DirNode {
     entry_: PathBuf,
+    kind_: DirEntryKind,
+    meta_: DirMeta,
+    link_target_: Option<PathBuf>,
+    content_hash_: Option<ContentHash>,
     child_entry_: HashSet<DirNode>,
 }
 
@@ -25,48 +33,97 @@ impl DirNode {
     /// A `Result` containing the constructed `DirNode` if successful,
     /// or a `DirIndexerErr` if there was an error.
     pub fn from(root_path: &PathBuf, rl_path: &PathBuf) -> Result<DirNode, DirIndexerErr> {
+        DirNode::scan(root_path, rl_path, &mut Vec::new())
+    }
+
+    /// Builds a node like `from`, adding every entry left out of the tree to `errors`.
+    pub(crate) fn scan(root_path: &PathBuf, rl_path: &PathBuf, errors: &mut Vec<ScanError>) -> Result<DirNode, DirIndexerErr> {
         let joined_path = root_path.join(rl_path);
         
         // Check if the joined path is a valid file or directory
         if !joined_path.is_file() && !joined_path.is_dir() {
+            let reason = match fs::symlink_metadata(&joined_path) {
+                Ok(meta) if meta.file_type().is_symlink() => String::from("dangling symbolic link"),
+                Ok(_) => String::from("not a regular file or directory"),
+                Err(err) => err.to_string(),
+            };
+            errors.push(ScanError::new(rl_path.to_path_buf(), reason));
             return Err(DirIndexerErr::NotFileAndDir);
         }
 
-        let mut ab_path_entry: PathBuf = PathBuf::new();
-        let to_str_res = joined_path.to_str();
+        // Attempt to obtain the canonicalized absolute path, whether or not it is valid UTF-8
+        let ab_path_entry = fs::canonicalize(&joined_path).unwrap_or_default();
 
-        if let Some(to_str) = to_str_res {
-            // Attempt to obtain the canonicalized absolute path
-            if let Ok(con_result) = fs::canonicalize(to_str) {
-                ab_path_entry = con_result;
+        // Record the entry's own metadata, without following symbolic links
+        let (kind, meta) = match fs::symlink_metadata(&joined_path) {
+            Ok(meta) => (DirEntryKind::from(meta.file_type()), DirMeta::from(&meta)),
+            Err(err) => {
+                errors.push(ScanError::new(rl_path.to_path_buf(), err.to_string()));
+                return Err(DirIndexerErr::NotFileAndDir);
             }
-        }
+        };
+        let link_target = if kind == DirEntryKind::Symlink {
+            fs::read_link(&joined_path).ok()
+        } else {
+            None
+        };
 
         let mut dir_node = DirNode {
             entry_: rl_path.to_path_buf(),
+            kind_: kind,
+            meta_: meta,
+            link_target_: link_target,
+            content_hash_: None,
             child_entry_: HashSet::new(),
         };
 
         if ab_path_entry.is_dir() {
-            if let Ok(entries) = ab_path_entry.read_dir() {
-                for entry in entries {
-                    if let Ok(entry) = entry {
+            match ab_path_entry.read_dir() {
+                Ok(entries) => {
+                    for entry in entries {
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(err) => {
+                                errors.push(ScanError::new(rl_path.to_path_buf(), err.to_string()));
+                                continue;
+                            }
+                        };
                         let entry_name = entry.file_name();
                         let new_entry = rl_path.join(entry_name);
-                        let child_entry = DirNode::from(root_path, &new_entry);
+                        let child_entry = DirNode::scan(root_path, &new_entry, errors);
 
                         if let Ok(a) = child_entry {
                             dir_node.child_entry_.insert(a);
                         }
                     }
                 }
-            } else {
-                return Err(DirIndexerErr::LackPermission(ab_path_entry));
+                Err(err) => {
+                    errors.push(ScanError::new(rl_path.to_path_buf(), err.to_string()));
+                    return Err(DirIndexerErr::LackPermission(ab_path_entry));
+                }
             }
         }
         Ok(dir_node)
     }
 
+    /// Constructs a `DirNode` from already known parts, as when loading a saved index.
+    pub(crate) fn from_parts(
+        entry: PathBuf,
+        kind: DirEntryKind,
+        meta: DirMeta,
+        link_target: Option<PathBuf>,
+        children: HashSet<DirNode>,
+    ) -> DirNode {
+        DirNode {
+            entry_: entry,
+            kind_: kind,
+            meta_: meta,
+            link_target_: link_target,
+            content_hash_: None,
+            child_entry_: children,
+        }
+    }
+
     /// Adds the relative file paths of the current node and its children to a set.
     ///
     /// # Arguments
@@ -244,6 +301,148 @@ impl DirNode {
         let ab_path_entry = fs::canonicalize(joined_path.to_str().unwrap()).unwrap();
         ab_path_entry
     }
+
+    /// Returns the relative path of the current node.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the relative path; the root node has an empty path.
+    pub fn get_entry(&self) -> &PathBuf {
+        &self.entry_
+    }
+
+    /// Returns the file name of the current node.
+    ///
+    /// # Returns
+    ///
+    /// The last component of the relative path, or an empty string for the root node.
+    pub fn get_name(&self) -> &OsStr {
+        self.entry_.file_name().unwrap_or_else(|| OsStr::new(""))
+    }
+
+    /// Returns the kind of the current node.
+    pub fn get_kind(&self) -> DirEntryKind {
+        self.kind_
+    }
+
+    /// Returns the metadata recorded for the current node.
+    pub fn get_meta(&self) -> &DirMeta {
+        &self.meta_
+    }
+
+    /// Returns the target of the current node if it is a symbolic link.
+    pub fn get_link_target(&self) -> Option<&PathBuf> {
+        self.link_target_.as_ref()
+    }
+
+    /// Returns the content hash of the current node, if one has been computed.
+    ///
+    /// Hashes are only computed for regular files, by `DirIndexer::compute_hashes`.
+    pub fn get_content_hash(&self) -> Option<&ContentHash> {
+        self.content_hash_.as_ref()
+    }
+
+    /// Returns the stored content hash if it was computed with `algo`, or else reads the file
+    /// below `root_path` and hashes it now.
+    pub(crate) fn hash_contents(&self, root_path: &Path, algo: HashAlgo) -> io::Result<ContentHash> {
+        match self.get_content_hash() {
+            Some(hash) if hash.get_algo() == algo => Ok(hash.clone()),
+            _ => hasher::hash_file(algo, &root_path.join(&self.entry_)),
+        }
+    }
+
+    /// Moves the hashes of the current node and its descendants out of `hashes`, keyed by
+    /// relative path. Nodes without an entry keep no hash.
+    pub(crate) fn take_content_hashes(&mut self, hashes: &mut HashMap<PathBuf, ContentHash>) {
+        self.content_hash_ = hashes.remove(&self.entry_);
+        if self.child_entry_.is_empty() {
+            return;
+        }
+        // Children are keyed by path only, so they can be taken out and put back unchanged
+        let children: Vec<DirNode> = self.child_entry_.drain().collect();
+        for mut child in children {
+            child.take_content_hashes(hashes);
+            self.child_entry_.insert(child);
+        }
+    }
+
+    /// Replaces the descendant at `rl_path` with `node`, or removes it if `node` is `None`. The
+    /// parent of `rl_path` must be the current node or one of its descendants.
+    pub(crate) fn replace_descendant(&mut self, rl_path: &Path, node: Option<DirNode>) {
+        let (matching, rest): (Vec<DirNode>, Vec<DirNode>) =
+            self.child_entry_.drain().partition(|child| rl_path.starts_with(&child.entry_));
+        self.child_entry_.extend(rest);
+        match matching.into_iter().next() {
+            Some(child) if child.entry_.as_path() != rl_path => {
+                let mut child = child;
+                child.replace_descendant(rl_path, node);
+                self.child_entry_.insert(child);
+            }
+            // Either the entry itself, which is dropped, or a new entry of the current node
+            _ => {
+                if let Some(node) = node {
+                    self.child_entry_.insert(node);
+                }
+            }
+        }
+    }
+
+    /// Returns the children of the current node, in no particular order.
+    pub fn get_children(&self) -> impl Iterator<Item = &DirNode> {
+        self.child_entry_.iter()
+    }
+
+    /// Returns the children of the current node sorted by their relative path.
+    pub fn get_sorted_children(&self) -> Vec<&DirNode> {
+        let mut children: Vec<&DirNode> = self.child_entry_.iter().collect();
+        children.sort_by(|a, b| a.entry_.cmp(&b.entry_));
+        children
+    }
+
+    /// Looks up a descendant of the current node by its relative path.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The relative path of the descendant, from the tree root.
+    ///
+    /// # Returns
+    ///
+    /// The matching node, or `None` if there is no such entry.
+    pub fn get_descendant(&self, rl_path: &Path) -> Option<&DirNode> {
+        if self.entry_.as_path() == rl_path {
+            return Some(self);
+        }
+        self.child_entry_
+            .iter()
+            .find(|child| rl_path.starts_with(&child.entry_))
+            .and_then(|child| child.get_descendant(rl_path))
+    }
+
+    /// Adds the current node and all its descendants to a map keyed by relative path.
+    ///
+    /// # Arguments
+    ///
+    /// * `map` - A mutable reference to the mapping of relative paths to nodes.
+    pub fn map_rl2node<'a>(&'a self, map: &mut BTreeMap<PathBuf, &'a DirNode>) {
+        map.insert(self.entry_.clone(), self);
+        for child in &self.child_entry_ {
+            child.map_rl2node(map);
+        }
+    }
+
+    /// Draws the current node and the entries below it in the style of the `tree` command.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The writer receiving the drawing.
+    /// * `options` - The depth limit, characters, annotations and other rendering options.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` reporting whether writing succeeded.
+    pub fn write_tree<W: Write>(&self, out: &mut W, options: &TreeRenderOptions) -> io::Result<()> {
+        tree_render::write_tree(self, out, options)
+    }
 }
 
 impl Eq for DirNode {}
@@ -1,11 +1,15 @@
-use super::DirNode;
-use std::path::PathBuf;
-use std::collections::{HashSet, HashMap};
+use super::{ContentHash, DirIndexerErr, DirNode, DirUsage, DotOptions, ScanError, TreeRenderOptions};
+use super::dot_export;
+use super::dir_usage;
+use super::tree_json;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashSet, HashMap};
 
 /// The `DirTree` struct represents a tree structure of a directory.
 ///
 /// It provides operations to retrieve file paths and mappings between file paths within the directory tree.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirTree {
     root_node_: DirNode,
 }
@@ -19,11 +23,22 @@ impl DirTree {
     ///
     /// # Returns
     ///
-    /// A `DirTree` instance.
-    pub fn from(ab_path: &PathBuf) -> DirTree {
+    /// A `Result` containing the `DirTree`, or `DirIndexerErr::LackPermission` if the root
+    /// directory cannot be read.
+    pub fn from(ab_path: &PathBuf) -> Result<DirTree, DirIndexerErr> {
+        DirTree::scan(ab_path, &mut Vec::new())
+    }
+
+    /// Builds a tree like `from`, adding every entry left out of it to `errors`.
+    pub(crate) fn scan(ab_path: &PathBuf, errors: &mut Vec<ScanError>) -> Result<DirTree, DirIndexerErr> {
         let relative_start = PathBuf::from("");
-        let node = DirNode::from(ab_path, &relative_start).unwrap();
-        DirTree { root_node_: node }
+        let node = DirNode::scan(ab_path, &relative_start, errors)?;
+        Ok(DirTree { root_node_: node })
+    }
+
+    /// Creates a `DirTree` around an already built root node.
+    pub(crate) fn from_root_node(root_node: DirNode) -> DirTree {
+        DirTree { root_node_: root_node }
     }
 
     /// Retrieves a set of relative file paths within the directory tree.
@@ -37,7 +52,7 @@ impl DirTree {
     /// A `HashSet` containing the relative file paths as `PathBuf` values.
     pub fn get_relative_file_paths(&self, root_path: &PathBuf) -> HashSet<PathBuf> {
         let mut path_set: HashSet<PathBuf> = HashSet::new();
-        self.root_node_.add_rl_file_path(&root_path, &mut path_set);
+        self.root_node_.add_rl_file_path(root_path, &mut path_set);
         path_set
     }
 
@@ -52,7 +67,7 @@ impl DirTree {
     /// A `HashSet` containing the relative directory paths as `PathBuf` values.
     pub fn get_relative_dir_paths(&self, root_path: &PathBuf) -> HashSet<PathBuf> {
         let mut path_set: HashSet<PathBuf> = HashSet::new();
-        self.root_node_.add_rl_dir_path(&root_path, &mut path_set);
+        self.root_node_.add_rl_dir_path(root_path, &mut path_set);
         path_set
     }
 
@@ -145,5 +160,144 @@ impl DirTree {
         self.root_node_.map_ab2rl_dir_path(root_path, &mut path_map);
         path_map
     }
-}
 
+    /// Returns the root node of the directory tree.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the root `DirNode`, whose relative path is empty.
+    pub fn get_root_node(&self) -> &DirNode {
+        &self.root_node_
+    }
+
+    /// Stores computed content hashes, keyed by relative path, on the nodes of the tree.
+    pub(crate) fn set_content_hashes(&mut self, mut hashes: HashMap<PathBuf, ContentHash>) {
+        self.root_node_.take_content_hashes(&mut hashes);
+    }
+
+    /// Replaces the node at `rl_path`, whose parent must be in the tree, or removes it if `node`
+    /// is `None`.
+    pub(crate) fn replace_node(&mut self, rl_path: &Path, node: Option<DirNode>) {
+        self.root_node_.replace_descendant(rl_path, node);
+    }
+
+    /// Looks up a node by its relative path.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The relative path of the entry; an empty path refers to the root.
+    ///
+    /// # Returns
+    ///
+    /// The matching `DirNode`, or `None` if the entry is not indexed.
+    pub fn get_node(&self, rl_path: &Path) -> Option<&DirNode> {
+        self.root_node_.get_descendant(rl_path)
+    }
+
+    /// Retrieves a mapping between relative paths and nodes within the directory tree.
+    ///
+    /// # Returns
+    ///
+    /// A `BTreeMap` containing every node, including the root, keyed and sorted by relative path.
+    pub fn get_rl2node_map(&self) -> BTreeMap<PathBuf, &DirNode> {
+        let mut node_map: BTreeMap<PathBuf, &DirNode> = BTreeMap::new();
+        self.root_node_.map_rl2node(&mut node_map);
+        node_map
+    }
+
+    /// Aggregates sizes and entry counts over every subtree.
+    ///
+    /// # Returns
+    ///
+    /// A `BTreeMap` holding the `DirUsage` of every entry, including the root, keyed and sorted by
+    /// relative path.
+    pub fn get_usage(&self) -> BTreeMap<PathBuf, DirUsage> {
+        let mut usage_map: BTreeMap<PathBuf, DirUsage> = BTreeMap::new();
+        dir_usage::add_usage(&self.root_node_, &mut usage_map);
+        usage_map
+    }
+
+    /// Draws the directory tree in the style of the `tree` command.
+    ///
+    /// Every entry is drawn on its own line below its parent, with connectors such as `├── `, and
+    /// the drawing ends with the number of directories and files shown. Use
+    /// `get_node(..).write_tree(..)` to draw a subtree.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The writer receiving the drawing.
+    /// * `options` - The depth limit, characters, annotations and other rendering options.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` reporting whether writing succeeded.
+    pub fn write_tree<W: Write>(&self, out: &mut W, options: &TreeRenderOptions) -> io::Result<()> {
+        self.root_node_.write_tree(out, options)
+    }
+
+    /// Exports the directory tree as a Graphviz DOT graph, ready for `dot -Tsvg`.
+    ///
+    /// Every shown entry becomes a node linked to its parent; directories are drawn as folders and
+    /// other entries as notes.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The writer receiving the graph.
+    /// * `options` - The depth limit, labels, clustering and other options.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` reporting whether writing succeeded.
+    pub fn write_dot<W: Write>(&self, out: &mut W, options: &DotOptions) -> io::Result<()> {
+        dot_export::write_dot(self, out, options)
+    }
+
+    /// Exports the directory tree as nested JSON compatible with `tree -J`.
+    ///
+    /// Every entry carries its `type`, `name`, metadata and, for directories, its `contents`. The
+    /// root is named `"."` and the output ends with a `report` object holding the counts.
+    ///
+    /// # Returns
+    ///
+    /// The JSON document as a `String`.
+    pub fn to_json(&self) -> String {
+        tree_json::tree_to_json(self)
+    }
+
+    /// Exports the directory tree as newline-delimited JSON, one record per entry.
+    ///
+    /// # Returns
+    ///
+    /// One JSON object per line, each holding the relative `path` of the entry, sorted by path.
+    pub fn to_json_lines(&self) -> String {
+        tree_json::tree_to_json_lines(self)
+    }
+
+    /// Rebuilds a directory tree from the nested JSON produced by `to_json` or by `tree -J`.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - The JSON document.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirTree`, `DirIndexerErr::JsonParseFail` for malformed JSON, or
+    /// `DirIndexerErr::TreeConstructFailed` if the document does not describe a valid tree.
+    pub fn from_json(json: &str) -> Result<DirTree, DirIndexerErr> {
+        tree_json::tree_from_json(json)
+    }
+
+    /// Rebuilds a directory tree from the newline-delimited JSON produced by `to_json_lines`.
+    ///
+    /// # Arguments
+    ///
+    /// * `json_lines` - One JSON record per line.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirTree`, `DirIndexerErr::JsonParseFail` for malformed JSON, or
+    /// `DirIndexerErr::TreeConstructFailed` if the records do not describe a valid tree.
+    pub fn from_json_lines(json_lines: &str) -> Result<DirTree, DirIndexerErr> {
+        tree_json::tree_from_json_lines(json_lines)
+    }
+}
@@ -0,0 +1,148 @@
+use super::{DirEntryKind, DirNode};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The `DirUsage` struct holds the aggregated size and entry counts of an entry and everything
+/// below it.
+///
+/// Sizes are apparent sizes: the sum of the sizes of regular files, symlinks and other
+/// non-directory entries. Directories add no size of their own, and a file with several hard
+/// links is counted at every path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirUsage {
+    size_: u64,
+    files_: u64,
+    dirs_: u64,
+    entries_: u64,
+}
+
+impl DirUsage {
+    /// Returns the total size in bytes.
+    pub fn get_size(&self) -> u64 {
+        self.size_
+    }
+
+    /// Returns the number of regular files, including the entry itself if it is one.
+    pub fn get_file_count(&self) -> u64 {
+        self.files_
+    }
+
+    /// Returns the number of directories below the entry.
+    pub fn get_dir_count(&self) -> u64 {
+        self.dirs_
+    }
+
+    /// Returns the number of entries of any kind below the entry.
+    pub fn get_entry_count(&self) -> u64 {
+        self.entries_
+    }
+}
+
+/// Formats a size in bytes the way `du -h` does, as in `512`, `1.5K` or `23M`.
+///
+/// # Arguments
+///
+/// * `size` - The size in bytes.
+///
+/// # Returns
+///
+/// The size in powers of 1024, with one decimal below 10 of a unit.
+pub fn human_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    // Compare against the rounded value, so that 1023.9K is shown as 1.0M rather than 1024K
+    while value >= 1023.5 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 9.95 {
+        format!("{:.1}{}", value, UNITS[unit])
+    } else {
+        format!("{:.0}{}", value, UNITS[unit])
+    }
+}
+
+/// Computes the usage of `node` and of every entry below it, adding them to `map` keyed by
+/// relative path.
+pub(crate) fn add_usage(node: &DirNode, map: &mut BTreeMap<PathBuf, DirUsage>) -> DirUsage {
+    let mut usage = DirUsage::default();
+    match node.get_kind() {
+        DirEntryKind::Dir => {
+            for child in node.get_children() {
+                let child_usage = add_usage(child, map);
+                usage.size_ += child_usage.size_;
+                usage.files_ += child_usage.files_;
+                usage.dirs_ += child_usage.dirs_ + (child.get_kind() == DirEntryKind::Dir) as u64;
+                usage.entries_ += child_usage.entries_ + 1;
+            }
+        }
+        DirEntryKind::File => {
+            usage.size_ = node.get_meta().get_size();
+            usage.files_ = 1;
+        }
+        _ => usage.size_ = node.get_meta().get_size(),
+    }
+    map.insert(node.get_entry().clone(), usage);
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::DirTree;
+
+    #[test]
+    fn formats_sizes_like_du() {
+        let cases = [
+            (0, "0"),
+            (1023, "1023"),
+            (1024, "1.0K"),
+            (1536, "1.5K"),
+            (10_188, "9.9K"),
+            (10_239, "10K"),
+            (1024 * 1000, "1000K"),
+            (1024 * 1024 - 1, "1.0M"),
+            (1024 * 1024 - 600, "1023K"),
+            (5 << 30, "5.0G"),
+            (3 << 40, "3.0T"),
+            (7 << 50, "7.0P"),
+            (u64::MAX, "16E"),
+        ];
+        for (size, expected) in cases.iter() {
+            assert_eq!(human_size(*size), *expected, "{}", size);
+        }
+    }
+
+    #[test]
+    fn aggregates_sizes_and_counts() {
+        let lines = [
+            "{\"path\":\"\",\"type\":\"directory\",\"mode\":\"0755\",\"size\":4096}",
+            "{\"path\":\"src\",\"type\":\"directory\",\"mode\":\"0755\",\"size\":4096}",
+            "{\"path\":\"src/a.rs\",\"type\":\"file\",\"mode\":\"0644\",\"size\":100}",
+            "{\"path\":\"src/deep/b.rs\",\"type\":\"file\",\"mode\":\"0644\",\"size\":20}",
+            "{\"path\":\"link\",\"type\":\"link\",\"target\":\"src\",\"mode\":\"0777\",\"size\":3}",
+            "{\"path\":\"pipe\",\"type\":\"fifo\",\"mode\":\"0600\"}",
+            "{\"path\":\"empty\",\"type\":\"directory\",\"mode\":\"0755\",\"size\":4096}",
+        ];
+        let tree = DirTree::from_json_lines(&lines.join("\n")).unwrap();
+        let usage = tree.get_usage();
+        assert_eq!(usage.len(), 8);
+
+        let root = usage[&PathBuf::from("")];
+        assert_eq!(
+            (root.get_size(), root.get_file_count(), root.get_dir_count(), root.get_entry_count()),
+            (123, 2, 3, 7)
+        );
+        let src = usage[&PathBuf::from("src")];
+        assert_eq!((src.get_size(), src.get_file_count(), src.get_dir_count(), src.get_entry_count()), (120, 2, 1, 3));
+        assert_eq!(usage[&PathBuf::from("empty")], DirUsage::default());
+        let file = usage[&PathBuf::from("src/a.rs")];
+        assert_eq!((file.get_size(), file.get_file_count(), file.get_entry_count()), (100, 1, 0));
+        let link = usage[&PathBuf::from("link")];
+        assert_eq!((link.get_size(), link.get_file_count()), (3, 0));
+    }
+}
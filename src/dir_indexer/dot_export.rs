@@ -0,0 +1,360 @@
+//! Graphviz DOT export of a `DirTree`.
+//!
+//! Every shown entry becomes a node named `n0`, `n1`, ... in depth-first order, with the root as
+//! `n0`, and every parent is linked to its children. Directories are drawn as folders, files as
+//! notes and symlinks with a dashed outline. Names that are not valid UTF-8 are exported lossily.
+
+use super::{DirEntryKind, DirNode, DirTree, DirUsage};
+use super::dir_usage;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The information written below the name of each node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotLabel {
+    /// The name only.
+    Name,
+    /// The aggregated size of the entry, in the form `1.5K`.
+    Size,
+    /// For directories, the number of regular files anywhere below them.
+    FileCount,
+}
+
+/// Options controlling what a DOT graph shows.
+#[derive(Debug, Clone)]
+pub struct DotOptions {
+    max_depth_: Option<usize>,
+    dirs_only_: bool,
+    label_: DotLabel,
+    cluster_top_level_: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> DotOptions {
+        DotOptions {
+            max_depth_: None,
+            dirs_only_: false,
+            label_: DotLabel::Name,
+            cluster_top_level_: false,
+        }
+    }
+}
+
+impl DotOptions {
+    /// Creates options showing every entry, labelled with its name.
+    pub fn new() -> DotOptions {
+        DotOptions::default()
+    }
+
+    /// Leaves out entries more than `max_depth` levels below the root.
+    pub fn with_max_depth(mut self, max_depth: usize) -> DotOptions {
+        self.max_depth_ = Some(max_depth);
+        self
+    }
+
+    /// Leaves out everything but directories.
+    pub fn with_dirs_only(mut self, dirs_only: bool) -> DotOptions {
+        self.dirs_only_ = dirs_only;
+        self
+    }
+
+    /// Sets the information written below each name. Sizes and counts always cover the whole
+    /// subtree, including entries left out of the graph.
+    pub fn with_label(mut self, label: DotLabel) -> DotOptions {
+        self.label_ = label;
+        self
+    }
+
+    /// Draws each directory at the top level, with everything below it, inside its own box.
+    pub fn with_cluster_top_level(mut self, cluster_top_level: bool) -> DotOptions {
+        self.cluster_top_level_ = cluster_top_level;
+        self
+    }
+
+    /// Returns the depth limit, if any.
+    pub fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth_
+    }
+
+    /// Returns `true` if only directories are shown.
+    pub fn get_dirs_only(&self) -> bool {
+        self.dirs_only_
+    }
+
+    /// Returns the information written below each name.
+    pub fn get_label(&self) -> DotLabel {
+        self.label_
+    }
+
+    /// Returns `true` if top-level directories are drawn as clusters.
+    pub fn get_cluster_top_level(&self) -> bool {
+        self.cluster_top_level_
+    }
+}
+
+/// A node of the graph, with the cluster it is drawn in, if any.
+struct GraphNode<'a> {
+    node_: &'a DirNode,
+    cluster_: Option<usize>,
+}
+
+/// The shown entries, indexed by node id, the parent-child links between them and the id of the
+/// top-level directory of each cluster.
+struct Graph<'a> {
+    nodes_: Vec<GraphNode<'a>>,
+    edges_: Vec<(usize, usize)>,
+    clusters_: Vec<usize>,
+}
+
+/// Writes `tree` as a DOT `digraph`.
+pub(crate) fn write_dot<W: Write>(tree: &DirTree, out: &mut W, options: &DotOptions) -> io::Result<()> {
+    let mut usage = BTreeMap::new();
+    if options.label_ != DotLabel::Name {
+        dir_usage::add_usage(tree.get_root_node(), &mut usage);
+    }
+
+    let mut graph = Graph {
+        nodes_: vec![GraphNode {
+            node_: tree.get_root_node(),
+            cluster_: None,
+        }],
+        edges_: Vec::new(),
+        clusters_: Vec::new(),
+    };
+    graph.collect(tree.get_root_node(), 0, 1, None, options);
+    let Graph { nodes_: nodes, edges_: edges, clusters_: clusters } = graph;
+
+    out.write_all(b"digraph \"dir_indexer\" {\n")?;
+    out.write_all(b"  rankdir=LR;\n")?;
+    out.write_all(b"  node [shape=folder, fontname=\"Helvetica\"];\n")?;
+    for (id, graph_node) in nodes.iter().enumerate() {
+        if graph_node.cluster_.is_none() {
+            write_node(out, id, graph_node.node_, &usage, options, "  ")?;
+        }
+    }
+    for (cluster, &top_id) in clusters.iter().enumerate() {
+        writeln!(out, "  subgraph cluster_{} {{", cluster)?;
+        writeln!(out, "    label={};", quote(&nodes[top_id].node_.get_entry().to_string_lossy()))?;
+        out.write_all(b"    style=rounded;\n")?;
+        for (id, graph_node) in nodes.iter().enumerate() {
+            if graph_node.cluster_ == Some(cluster) {
+                write_node(out, id, graph_node.node_, &usage, options, "    ")?;
+            }
+        }
+        out.write_all(b"  }\n")?;
+    }
+    for (parent, child) in edges {
+        writeln!(out, "  n{} -> n{};", parent, child)?;
+    }
+    out.write_all(b"}\n")
+}
+
+impl<'a> Graph<'a> {
+    /// Adds the shown children of `node`, which has id `id` and is `depth` levels below the root,
+    /// and everything below them.
+    fn collect(&mut self, node: &'a DirNode, id: usize, depth: usize, cluster: Option<usize>, options: &DotOptions) {
+        if options.max_depth_.map_or(false, |max_depth| depth > max_depth) {
+            return;
+        }
+        for child in node.get_sorted_children() {
+            let is_dir = child.get_kind() == DirEntryKind::Dir;
+            if options.dirs_only_ && !is_dir {
+                continue;
+            }
+            let child_id = self.nodes_.len();
+            let child_cluster = if depth == 1 && is_dir && options.cluster_top_level_ {
+                self.clusters_.push(child_id);
+                Some(self.clusters_.len() - 1)
+            } else {
+                cluster
+            };
+            self.nodes_.push(GraphNode {
+                node_: child,
+                cluster_: child_cluster,
+            });
+            self.edges_.push((id, child_id));
+            if is_dir {
+                self.collect(child, child_id, depth + 1, child_cluster, options);
+            }
+        }
+    }
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    id: usize,
+    node: &DirNode,
+    usage: &BTreeMap<PathBuf, DirUsage>,
+    options: &DotOptions,
+    indent: &str,
+) -> io::Result<()> {
+    let mut label = if node.get_entry().as_os_str().is_empty() {
+        String::from(".")
+    } else if node.get_kind() == DirEntryKind::Dir {
+        format!("{}/", node.get_name().to_string_lossy())
+    } else {
+        node.get_name().to_string_lossy().into_owned()
+    };
+    let node_usage = usage.get(node.get_entry()).copied().unwrap_or_default();
+    match options.label_ {
+        DotLabel::Name => {}
+        DotLabel::Size => {
+            label.push('\n');
+            label.push_str(&dir_usage::human_size(node_usage.get_size()));
+        }
+        DotLabel::FileCount if node.get_kind() == DirEntryKind::Dir => {
+            let count = node_usage.get_file_count();
+            label.push_str(&format!("\n{} {}", count, if count == 1 { "file" } else { "files" }));
+        }
+        DotLabel::FileCount => {}
+    }
+    let attributes = match node.get_kind() {
+        DirEntryKind::Dir => "",
+        DirEntryKind::Symlink => ", shape=note, style=dashed",
+        _ => ", shape=note",
+    };
+    writeln!(out, "{}n{} [label={}{}];", indent, id, quote(&label), attributes)
+}
+
+/// Quotes `s` as a DOT string; line breaks become `\n`, which Graphviz centres.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => {}
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> DirTree {
+        let lines = [
+            "{\"path\":\"\",\"type\":\"directory\",\"mode\":\"0755\"}",
+            "{\"path\":\"a/x.txt\",\"type\":\"file\",\"mode\":\"0644\",\"size\":1536}",
+            "{\"path\":\"a/y/z.txt\",\"type\":\"file\",\"mode\":\"0644\",\"size\":5}",
+            "{\"path\":\"b\",\"type\":\"directory\",\"mode\":\"0755\"}",
+            "{\"path\":\"l\",\"type\":\"link\",\"target\":\"a\",\"mode\":\"0777\"}",
+            "{\"path\":\"q\\\"\\\\\",\"type\":\"file\",\"mode\":\"0644\",\"size\":2}",
+        ];
+        DirTree::from_json_lines(&lines.join("\n")).unwrap()
+    }
+
+    fn dot(options: &DotOptions) -> String {
+        let mut out = Vec::new();
+        sample_tree().write_dot(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Returns the node and edge lines, without the header and the closing brace.
+    fn body(dot: &str) -> Vec<&str> {
+        dot.lines().skip(3).take_while(|line| *line != "}").collect()
+    }
+
+    #[test]
+    fn writes_every_entry_and_link() {
+        assert_eq!(
+            dot(&DotOptions::new()),
+            concat!(
+                "digraph \"dir_indexer\" {\n",
+                "  rankdir=LR;\n",
+                "  node [shape=folder, fontname=\"Helvetica\"];\n",
+                "  n0 [label=\".\"];\n",
+                "  n1 [label=\"a/\"];\n",
+                "  n2 [label=\"x.txt\", shape=note];\n",
+                "  n3 [label=\"y/\"];\n",
+                "  n4 [label=\"z.txt\", shape=note];\n",
+                "  n5 [label=\"b/\"];\n",
+                "  n6 [label=\"l\", shape=note, style=dashed];\n",
+                "  n7 [label=\"q\\\"\\\\\", shape=note];\n",
+                "  n0 -> n1;\n",
+                "  n1 -> n2;\n",
+                "  n1 -> n3;\n",
+                "  n3 -> n4;\n",
+                "  n0 -> n5;\n",
+                "  n0 -> n6;\n",
+                "  n0 -> n7;\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn limits_depth_and_kinds() {
+        let shallow = dot(&DotOptions::new().with_max_depth(1));
+        assert_eq!(body(&shallow).iter().filter(|line| line.contains("[label=")).count(), 5);
+        assert!(shallow.contains("  n4 [label=\"q\\\"\\\\\", shape=note];\n  n0 -> n1;\n  n0 -> n2;\n"));
+        assert_eq!(body(&dot(&DotOptions::new().with_max_depth(0))), vec!["  n0 [label=\".\"];"]);
+        assert_eq!(
+            body(&dot(&DotOptions::new().with_dirs_only(true))),
+            vec![
+                "  n0 [label=\".\"];",
+                "  n1 [label=\"a/\"];",
+                "  n2 [label=\"y/\"];",
+                "  n3 [label=\"b/\"];",
+                "  n0 -> n1;",
+                "  n1 -> n2;",
+                "  n0 -> n3;",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_sizes_and_file_counts_of_whole_subtrees() {
+        let sizes = dot(&DotOptions::new().with_label(DotLabel::Size).with_max_depth(1));
+        assert!(sizes.contains("  n0 [label=\".\\n1.5K\"];\n  n1 [label=\"a/\\n1.5K\"];\n"));
+        assert!(sizes.contains("  n3 [label=\"l\\n0\", shape=note, style=dashed];\n"));
+        let counts = dot(&DotOptions::new().with_label(DotLabel::FileCount).with_dirs_only(true));
+        assert!(counts.contains("  n0 [label=\".\\n3 files\"];\n  n1 [label=\"a/\\n2 files\"];\n  n2 [label=\"y/\\n1 file\"];\n"));
+        assert!(counts.contains("  n3 [label=\"b/\\n0 files\"];\n"));
+    }
+
+    #[test]
+    fn clusters_top_level_directories() {
+        let clustered = dot(&DotOptions::new().with_cluster_top_level(true));
+        assert!(clustered.contains(concat!(
+            "  n0 [label=\".\"];\n",
+            "  n6 [label=\"l\", shape=note, style=dashed];\n",
+            "  n7 [label=\"q\\\"\\\\\", shape=note];\n",
+            "  subgraph cluster_0 {\n",
+            "    label=\"a\";\n",
+            "    style=rounded;\n",
+            "    n1 [label=\"a/\"];\n",
+            "    n2 [label=\"x.txt\", shape=note];\n",
+            "    n3 [label=\"y/\"];\n",
+            "    n4 [label=\"z.txt\", shape=note];\n",
+            "  }\n",
+            "  subgraph cluster_1 {\n",
+            "    label=\"b\";\n",
+            "    style=rounded;\n",
+            "    n5 [label=\"b/\"];\n",
+            "  }\n",
+            "  n0 -> n1;\n",
+        )));
+        // Clustering changes the layout only, not the nodes or links
+        let mut plain: Vec<String> = body(&dot(&DotOptions::new())).iter().map(|line| line.trim().to_string()).collect();
+        let mut grouped: Vec<String> = body(&clustered)
+            .iter()
+            .map(|line| line.trim().to_string())
+            .filter(|line| line.starts_with('n'))
+            .collect();
+        plain.sort();
+        grouped.sort();
+        assert_eq!(plain, grouped);
+    }
+
+    #[test]
+    fn quotes_labels() {
+        assert_eq!(quote("a\"b\\c\r\nd"), "\"a\\\"b\\\\c\\nd\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
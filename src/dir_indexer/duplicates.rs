@@ -0,0 +1,310 @@
+use super::{ContentHash, ContentHasher, DirEntryKind, DirIndexer, DirNode, HashAlgo};
+use super::glob::GlobPattern;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Size of the blocks read from the start and the end of a file for the partial hash.
+const PARTIAL_BLOCK: u64 = 4096;
+
+/// Options for `DirIndexer::find_duplicates`.
+#[derive(Debug, Clone)]
+pub struct DuplicateOptions {
+    min_size_: u64,
+    globs_: Vec<GlobPattern>,
+    hash_algo_: HashAlgo,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> DuplicateOptions {
+        DuplicateOptions {
+            min_size_: 1,
+            globs_: Vec::new(),
+            hash_algo_: HashAlgo::Xxh64,
+        }
+    }
+}
+
+impl DuplicateOptions {
+    /// Creates options that consider every non-empty file and hash with XXH64.
+    pub fn new() -> DuplicateOptions {
+        DuplicateOptions::default()
+    }
+
+    /// Ignores files smaller than `min_size` bytes. A minimum of `0` also reports empty files.
+    pub fn with_min_size(mut self, min_size: u64) -> DuplicateOptions {
+        self.min_size_ = min_size;
+        self
+    }
+
+    /// Only considers files matching the glob. Patterns containing a `/` are matched against the
+    /// relative path, others against the file name. Several globs are alternatives.
+    pub fn with_glob(mut self, pattern: &str) -> DuplicateOptions {
+        self.globs_.push(GlobPattern::from(pattern));
+        self
+    }
+
+    /// Sets the algorithm used for the full content hash.
+    pub fn with_hash_algo(mut self, algo: HashAlgo) -> DuplicateOptions {
+        self.hash_algo_ = algo;
+        self
+    }
+
+    /// Returns the minimum file size considered.
+    pub fn get_min_size(&self) -> u64 {
+        self.min_size_
+    }
+
+    /// Returns the algorithm used for the full content hash.
+    pub fn get_hash_algo(&self) -> HashAlgo {
+        self.hash_algo_
+    }
+
+    fn accepts(&self, rl_path: &Path, node: &DirNode) -> bool {
+        node.get_kind() == DirEntryKind::File
+            && node.get_meta().get_size() >= self.min_size_
+            && (self.globs_.is_empty() || self.globs_.iter().any(|glob| glob.matches_entry(rl_path)))
+    }
+}
+
+/// A group of files with identical contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    size_: u64,
+    hash_: ContentHash,
+    paths_: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Returns the size of each file in bytes.
+    pub fn get_size(&self) -> u64 {
+        self.size_
+    }
+
+    /// Returns the content hash shared by the files.
+    pub fn get_hash(&self) -> &ContentHash {
+        &self.hash_
+    }
+
+    /// Returns the sorted relative paths of the files; there are always at least two.
+    pub fn get_paths(&self) -> &[PathBuf] {
+        &self.paths_
+    }
+
+    /// Returns the bytes that would be freed by keeping a single copy.
+    pub fn get_wasted_bytes(&self) -> u64 {
+        self.size_ * (self.paths_.len() as u64 - 1)
+    }
+}
+
+/// Finds groups of regular files with identical contents.
+///
+/// Candidates are narrowed by size, then by a hash of their first and last blocks, and only the
+/// remaining ones are hashed in full. Hashes already computed by `DirIndexer::compute_hashes` with
+/// the same algorithm are reused. Hard links to one inode count as a single file, under the first
+/// of their paths. Groups are sorted by wasted bytes, largest first.
+pub(crate) fn find_duplicates(indexer: &DirIndexer, options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    let root_path = indexer.get_root_path();
+    let mut by_size: BTreeMap<u64, Vec<(PathBuf, &DirNode)>> = BTreeMap::new();
+    let mut seen_inodes: HashSet<(u64, u64)> = HashSet::new();
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        if !options.accepts(&rl_path, node) {
+            continue;
+        }
+        let meta = node.get_meta();
+        if !seen_inodes.insert((meta.get_dev(), meta.get_ino())) {
+            continue;
+        }
+        by_size.entry(meta.get_size()).or_default().push((rl_path, node));
+    }
+
+    let mut groups = Vec::new();
+    for (size, candidates) in by_size {
+        if candidates.len() < 2 {
+            continue;
+        }
+        // Files no larger than the two partial blocks are read whole by the partial hash
+        let partial_is_full = size <= 2 * PARTIAL_BLOCK;
+        let mut by_partial: HashMap<ContentHash, Vec<(PathBuf, &DirNode)>> = HashMap::new();
+        for (rl_path, node) in candidates {
+            let partial = if partial_is_full {
+                node.hash_contents(root_path, options.hash_algo_)
+            } else {
+                partial_hash(&root_path.join(&rl_path), size)
+            };
+            if let Ok(partial) = partial {
+                by_partial.entry(partial).or_default().push((rl_path, node));
+            }
+        }
+        for (partial, candidates) in by_partial {
+            if candidates.len() < 2 {
+                continue;
+            }
+            if partial_is_full {
+                groups.push(group_from(size, partial, candidates));
+                continue;
+            }
+            let mut by_full: HashMap<ContentHash, Vec<(PathBuf, &DirNode)>> = HashMap::new();
+            for (rl_path, node) in candidates {
+                if let Ok(full) = node.hash_contents(root_path, options.hash_algo_) {
+                    by_full.entry(full).or_default().push((rl_path, node));
+                }
+            }
+            for (full, candidates) in by_full {
+                if candidates.len() >= 2 {
+                    groups.push(group_from(size, full, candidates));
+                }
+            }
+        }
+    }
+    groups.sort_by(|a, b| {
+        b.get_wasted_bytes()
+            .cmp(&a.get_wasted_bytes())
+            .then_with(|| a.paths_.cmp(&b.paths_))
+    });
+    groups
+}
+
+fn group_from(size: u64, hash: ContentHash, candidates: Vec<(PathBuf, &DirNode)>) -> DuplicateGroup {
+    let mut paths: Vec<PathBuf> = candidates.into_iter().map(|(rl_path, _)| rl_path).collect();
+    paths.sort();
+    DuplicateGroup {
+        size_: size,
+        hash_: hash,
+        paths_: paths,
+    }
+}
+
+/// Hashes the first and last `PARTIAL_BLOCK` bytes of a file larger than two blocks.
+fn partial_hash(path: &Path, size: u64) -> io::Result<ContentHash> {
+    let mut file = File::open(path)?;
+    let mut block = vec![0u8; PARTIAL_BLOCK as usize];
+    let mut hasher = ContentHasher::new(HashAlgo::Xxh64);
+    file.read_exact(&mut block)?;
+    hasher.update(&block);
+    file.seek(SeekFrom::Start(size - PARTIAL_BLOCK))?;
+    file.read_exact(&mut block)?;
+    hasher.update(&block);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::{hash_bytes, HashCache};
+    use std::fs;
+
+    fn paths(group: &DuplicateGroup) -> Vec<&str> {
+        group.get_paths().iter().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn identical_files_are_grouped_by_wasted_bytes() {
+        let dir = TempDir::new("dupes-groups");
+        dir.write("a/one", b"same");
+        dir.write("b/two", b"same");
+        dir.write("three", b"same");
+        dir.write("big1", &[7u8; 100]);
+        dir.write("big2", &[7u8; 100]);
+        dir.write("unique", b"diff");
+        dir.write("empty1", b"");
+        dir.write("empty2", b"");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicates(&DuplicateOptions::new());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(paths(&groups[0]), vec!["big1", "big2"]);
+        assert_eq!(groups[0].get_size(), 100);
+        assert_eq!(groups[0].get_wasted_bytes(), 100);
+        assert_eq!(paths(&groups[1]), vec!["a/one", "b/two", "three"]);
+        assert_eq!(groups[1].get_wasted_bytes(), 8);
+        assert_eq!(groups[1].get_hash(), &hash_bytes(HashAlgo::Xxh64, b"same"));
+
+        let groups = indexer.find_duplicates(&DuplicateOptions::new().with_min_size(0));
+        assert!(groups.iter().any(|group| paths(group) == vec!["empty1", "empty2"]));
+        let groups = indexer.find_duplicates(&DuplicateOptions::new().with_min_size(5));
+        assert_eq!(groups.len(), 1);
+
+        let options = DuplicateOptions::new().with_hash_algo(HashAlgo::Sha256);
+        assert_eq!(options.get_hash_algo(), HashAlgo::Sha256);
+        assert_eq!(indexer.find_duplicates(&options)[1].get_hash(), &hash_bytes(HashAlgo::Sha256, b"same"));
+    }
+
+    #[test]
+    fn globs_restrict_the_candidates() {
+        let dir = TempDir::new("dupes-globs");
+        dir.write("x.txt", b"same");
+        dir.write("sub/y.txt", b"same");
+        dir.write("z.bin", b"same");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicates(&DuplicateOptions::new().with_glob("*.txt"));
+        assert_eq!(paths(&groups[0]), vec!["sub/y.txt", "x.txt"]);
+        assert!(indexer.find_duplicates(&DuplicateOptions::new().with_glob("sub/*")).is_empty());
+        let groups = indexer.find_duplicates(&DuplicateOptions::new().with_glob("sub/*").with_glob("*.bin"));
+        assert_eq!(paths(&groups[0]), vec!["sub/y.txt", "z.bin"]);
+    }
+
+    #[test]
+    fn hard_links_count_once() {
+        let dir = TempDir::new("dupes-links");
+        dir.write("a", b"same");
+        fs::hard_link(dir.join("a"), dir.join("b")).unwrap();
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        assert!(indexer.find_duplicates(&DuplicateOptions::new()).is_empty());
+
+        dir.write("c", b"same");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let groups = indexer.find_duplicates(&DuplicateOptions::new());
+        assert_eq!(paths(&groups[0]), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn large_files_are_compared_in_full() {
+        let dir = TempDir::new("dupes-large");
+        let size = 3 * PARTIAL_BLOCK as usize;
+        let base = vec![1u8; size];
+        let mut middle = base.clone();
+        middle[size / 2] = 2;
+        let mut end = base.clone();
+        end[size - 1] = 2;
+        dir.write("base", &base);
+        dir.write("copy", &base);
+        dir.write("middle", &middle);
+        dir.write("end", &end);
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicates(&DuplicateOptions::new());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(paths(&groups[0]), vec!["base", "copy"]);
+        assert_eq!(groups[0].get_hash(), &hash_bytes(HashAlgo::Xxh64, &base));
+        assert_ne!(partial_hash(&dir.join("base"), size as u64).unwrap(), partial_hash(&dir.join("end"), size as u64).unwrap());
+        assert_eq!(partial_hash(&dir.join("base"), size as u64).unwrap(), partial_hash(&dir.join("middle"), size as u64).unwrap());
+    }
+
+    #[test]
+    fn stored_hashes_are_reused() {
+        let dir = TempDir::new("dupes-stored");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Xxh64), 1);
+        // The stored hashes are trusted over the disk, which has since changed
+        dir.write("b", b"diff");
+        assert_eq!(indexer.find_duplicates(&DuplicateOptions::new()).len(), 1);
+        // A different algorithm cannot use them and reads the files again
+        assert!(indexer.find_duplicates(&DuplicateOptions::new().with_hash_algo(HashAlgo::Sha256)).is_empty());
+    }
+
+    #[test]
+    fn unreadable_candidates_are_left_out() {
+        let dir = TempDir::new("dupes-unreadable");
+        dir.write("a", b"same");
+        dir.write("b", b"same");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        fs::remove_file(dir.join("b")).unwrap();
+        assert!(indexer.find_duplicates(&DuplicateOptions::new()).is_empty());
+    }
+}
@@ -0,0 +1,598 @@
+//! Fuzzy matching of relative paths, in the manner of `fzf`.
+//!
+//! A query matches a path when its bytes appear in the path in order. Among the possible
+//! alignments the best scoring one is chosen: every matched byte scores, gaps cost a little, and
+//! matches at the start of a path component, after a separator such as `_` or `.`, at a camelCase
+//! hump, or continuing a contiguous run score extra. Matches in the file name get a further bonus
+//! and long paths a small penalty.
+//!
+//! The query is lowercased and compared case-insensitively unless it contains an uppercase letter
+//! ("smart case"). Case folding covers ASCII letters only.
+
+use super::{codec, DirEntryKind, DirIndexer};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+const SCORE_MATCH: i32 = 16;
+const SCORE_GAP_START: i32 = -3;
+const SCORE_GAP_EXTENSION: i32 = -1;
+const BONUS_SLASH: i32 = 10;
+const BONUS_BOUNDARY: i32 = 8;
+const BONUS_CAMEL: i32 = 7;
+const BONUS_CONSECUTIVE: i32 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+const BONUS_FILE_NAME: i32 = 2;
+/// One point is taken off per this many bytes of path.
+const LENGTH_PENALTY_DIVISOR: i32 = 16;
+const IMPOSSIBLE: i32 = i32::MIN / 2;
+/// Searches are not split across threads into chunks smaller than this.
+const MIN_ENTRIES_PER_THREAD: usize = 16 * 1024;
+
+/// Options for `FuzzyIndex::search` and `DirIndexer::fuzzy_find`.
+#[derive(Debug, Clone)]
+pub struct FuzzyOptions {
+    limit_: usize,
+    kind_: Option<DirEntryKind>,
+    case_sensitive_: bool,
+    threads_: usize,
+}
+
+impl Default for FuzzyOptions {
+    fn default() -> FuzzyOptions {
+        FuzzyOptions {
+            limit_: 20,
+            kind_: None,
+            case_sensitive_: false,
+            threads_: 1,
+        }
+    }
+}
+
+impl FuzzyOptions {
+    /// Creates options returning the best 20 entries of any kind, with smart case, on one thread.
+    pub fn new() -> FuzzyOptions {
+        FuzzyOptions::default()
+    }
+
+    /// Sets the maximum number of results.
+    pub fn with_limit(mut self, limit: usize) -> FuzzyOptions {
+        self.limit_ = limit;
+        self
+    }
+
+    /// Only returns entries of the given kind.
+    pub fn with_kind(mut self, kind: DirEntryKind) -> FuzzyOptions {
+        self.kind_ = Some(kind);
+        self
+    }
+
+    /// Compares case-sensitively even when the query is all lowercase.
+    pub fn with_case_sensitive(mut self) -> FuzzyOptions {
+        self.case_sensitive_ = true;
+        self
+    }
+
+    /// Splits searches of large indexes across up to `threads` threads.
+    pub fn with_threads(mut self, threads: usize) -> FuzzyOptions {
+        self.threads_ = threads;
+        self
+    }
+
+    /// Returns the maximum number of results.
+    pub fn get_limit(&self) -> usize {
+        self.limit_
+    }
+
+    /// Returns the kind results are restricted to, if any.
+    pub fn get_kind(&self) -> Option<DirEntryKind> {
+        self.kind_
+    }
+
+    /// Returns `true` if matching is always case-sensitive.
+    pub fn get_case_sensitive(&self) -> bool {
+        self.case_sensitive_
+    }
+
+    /// Returns the maximum number of threads a search uses.
+    pub fn get_threads(&self) -> usize {
+        self.threads_
+    }
+}
+
+/// A path matched by a fuzzy query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    path_: PathBuf,
+    score_: i32,
+    positions_: Vec<usize>,
+}
+
+impl FuzzyMatch {
+    pub(crate) fn new(path: PathBuf, score: i32, positions: Vec<usize>) -> FuzzyMatch {
+        FuzzyMatch {
+            path_: path,
+            score_: score,
+            positions_: positions,
+        }
+    }
+
+    /// Returns the relative path.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns the score; higher is better. Scores are only comparable within one query.
+    pub fn get_score(&self) -> i32 {
+        self.score_
+    }
+
+    /// Returns the byte offsets in the path of the matched query bytes, in increasing order, for
+    /// highlighting.
+    pub fn get_positions(&self) -> &[usize] {
+        &self.positions_
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FuzzyEntry {
+    start: usize,
+    end: usize,
+    name_start: usize,
+    kind: DirEntryKind,
+}
+
+#[derive(Debug, Default)]
+struct FuzzyData {
+    bytes_: Vec<u8>,
+    /// `bytes_` with ASCII letters lowercased, for case-insensitive queries.
+    folded_: Vec<u8>,
+    entries_: Vec<FuzzyEntry>,
+}
+
+impl FuzzyData {
+    fn push(&mut self, path: &[u8], kind: DirEntryKind) {
+        let start = self.bytes_.len();
+        let name_start = start + path.iter().rposition(|b| *b == b'/').map_or(0, |i| i + 1);
+        self.bytes_.extend_from_slice(path);
+        self.folded_.extend(path.iter().map(u8::to_ascii_lowercase));
+        self.entries_.push(FuzzyEntry {
+            start,
+            end: self.bytes_.len(),
+            name_start,
+            kind,
+        });
+    }
+
+    fn candidate(&self, i: usize, case_sensitive: bool) -> Candidate<'_> {
+        let entry = &self.entries_[i];
+        let haystack = if case_sensitive { &self.bytes_ } else { &self.folded_ };
+        Candidate {
+            text: &self.bytes_[entry.start..entry.end],
+            haystack: &haystack[entry.start..entry.end],
+            name_start: entry.name_start - entry.start,
+        }
+    }
+
+    /// Scores the entries in `range` and keeps the best `options.get_limit()` as (score, entry).
+    fn top_matches(&self, range: Range<usize>, pattern: &[u8], case_sensitive: bool, options: &FuzzyOptions) -> Vec<(i32, usize)> {
+        // A min-heap of (score, entry) holding the best so far, where later entries lose ties. It
+        // never holds more than the limit, nor more than there are entries, whatever the limit.
+        let mut best: BinaryHeap<Reverse<(i32, Reverse<usize>)>> = BinaryHeap::with_capacity(options.limit_.min(range.len()));
+        let mut rows = Vec::new();
+        for i in range {
+            if options.kind_.map_or(false, |kind| kind != self.entries_[i].kind) {
+                continue;
+            }
+            let score = match self.candidate(i, case_sensitive).align(pattern, &mut rows) {
+                Some(score) => score,
+                None => continue,
+            };
+            let ranked = Reverse((score, Reverse(i)));
+            if best.len() < options.limit_ {
+                best.push(ranked);
+            } else if best.peek().map_or(false, |worst| ranked.cmp(worst) == Ordering::Less) {
+                best.pop();
+                best.push(ranked);
+            }
+        }
+        best.into_iter().map(|Reverse((score, Reverse(i)))| (score, i)).collect()
+    }
+}
+
+/// The relative paths of an index packed for repeated fuzzy searches.
+///
+/// Cloning is cheap: clones share the packed paths.
+#[derive(Debug, Clone, Default)]
+pub struct FuzzyIndex {
+    data_: Arc<FuzzyData>,
+}
+
+impl FuzzyIndex {
+    /// Collects the relative paths of every entry of an index except the root.
+    pub fn from(indexer: &DirIndexer) -> FuzzyIndex {
+        let mut data = FuzzyData::default();
+        for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+            if !rl_path.as_os_str().is_empty() {
+                data.push(codec::path_to_bytes(&rl_path), node.get_kind());
+            }
+        }
+        FuzzyIndex { data_: Arc::new(data) }
+    }
+
+    /// Returns the number of paths.
+    pub fn len(&self) -> usize {
+        self.data_.entries_.len()
+    }
+
+    /// Returns `true` if there are no paths.
+    pub fn is_empty(&self) -> bool {
+        self.data_.entries_.is_empty()
+    }
+
+    /// Finds the paths best matching a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The bytes to look for, in order. An empty query matches every path.
+    /// * `options` - The number of results, kind filter, case sensitivity and threads.
+    ///
+    /// # Returns
+    ///
+    /// Up to `options.get_limit()` matches, best first. Equal scores are ordered by path.
+    pub fn search(&self, query: &str, options: &FuzzyOptions) -> Vec<FuzzyMatch> {
+        if options.limit_ == 0 {
+            return Vec::new();
+        }
+        let case_sensitive = options.case_sensitive_ || query.bytes().any(|b| b.is_ascii_uppercase());
+        let pattern: Vec<u8> = if case_sensitive { query.bytes().collect() } else { query.bytes().map(|b| b.to_ascii_lowercase()).collect() };
+
+        let len = self.len();
+        let threads = options.threads_.max(1).min(len / MIN_ENTRIES_PER_THREAD).max(1);
+        let mut results = if threads == 1 {
+            self.data_.top_matches(0..len, &pattern, case_sensitive, options)
+        } else {
+            let chunk = (len + threads - 1) / threads;
+            let workers: Vec<thread::JoinHandle<Vec<(i32, usize)>>> = (0..threads)
+                .map(|t| {
+                    let data = Arc::clone(&self.data_);
+                    let pattern = pattern.clone();
+                    let options = options.clone();
+                    let range = t * chunk..((t + 1) * chunk).min(len);
+                    thread::spawn(move || data.top_matches(range, &pattern, case_sensitive, &options))
+                })
+                .collect();
+            let mut merged = Vec::new();
+            for (t, worker) in workers.into_iter().enumerate() {
+                match worker.join() {
+                    Ok(found) => merged.extend(found),
+                    // Should a worker fail, search its share here instead
+                    Err(_) => merged.extend(self.data_.top_matches(t * chunk..((t + 1) * chunk).min(len), &pattern, case_sensitive, options)),
+                }
+            }
+            merged
+        };
+        results.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        results.truncate(options.limit_);
+
+        let mut rows = Vec::new();
+        results
+            .into_iter()
+            .filter_map(|(score, i)| {
+                let candidate = self.data_.candidate(i, case_sensitive);
+                candidate.align(&pattern, &mut rows);
+                codec::bytes_to_path(candidate.text).map(|path| FuzzyMatch {
+                    path_: path,
+                    score_: score,
+                    positions_: candidate.positions(&rows),
+                })
+            })
+            .collect()
+    }
+}
+
+/// One path being matched. `haystack` is the path as compared with the pattern, lowercased for
+/// case-insensitive queries, and `text` the original bytes the bonuses are computed from.
+struct Candidate<'a> {
+    text: &'a [u8],
+    haystack: &'a [u8],
+    name_start: usize,
+}
+
+impl<'a> Candidate<'a> {
+    /// Scores the best alignment of `pattern`, or returns `None` if it does not match.
+    ///
+    /// Row `i` of `rows` receives, for every position where pattern byte `i` can be matched, the
+    /// best score of the pattern up to that byte ending there. Only matching positions are kept,
+    /// so the work grows with the number of occurrences rather than with the path length.
+    fn align(&self, pattern: &[u8], rows: &mut Vec<Vec<(usize, i32)>>) -> Option<i32> {
+        let penalty = self.text.len() as i32 / LENGTH_PENALTY_DIVISOR;
+        rows.resize_with(pattern.len(), Vec::new);
+        if pattern.is_empty() {
+            return Some(-penalty);
+        }
+        let (start, end) = self.window(pattern)?;
+        for (pi, pattern_byte) in pattern.iter().enumerate() {
+            let (done, rest) = rows.split_at_mut(pi);
+            let cur = &mut rest[0];
+            cur.clear();
+            if pi == 0 {
+                for pos in start..end {
+                    if self.haystack[pos] == *pattern_byte {
+                        cur.push((pos, SCORE_MATCH + self.bonus_at(pos) * BONUS_FIRST_CHAR_MULTIPLIER + self.name_bonus(pos)));
+                    }
+                }
+                continue;
+            }
+            let prev = &done[pi - 1];
+            // Best of prev score - SCORE_GAP_EXTENSION * prev position, over positions two or
+            // more bytes back; adding SCORE_GAP_EXTENSION * (pos - 2) gives the gapped score
+            let mut gap_best = IMPOSSIBLE;
+            let mut next_prev = 0;
+            let first = prev.first().map_or(end, |(pos, _)| pos + 1);
+            for pos in first..end {
+                if self.haystack[pos] != *pattern_byte {
+                    continue;
+                }
+                while next_prev < prev.len() && prev[next_prev].0 + 2 <= pos {
+                    let (k, score) = prev[next_prev];
+                    gap_best = gap_best.max(score - SCORE_GAP_EXTENSION * k as i32);
+                    next_prev += 1;
+                }
+                let bonus = self.bonus_at(pos);
+                let gapped = if gap_best > IMPOSSIBLE { gap_best + SCORE_GAP_START + SCORE_GAP_EXTENSION * (pos as i32 - 2) + bonus } else { IMPOSSIBLE };
+                let consecutive = match prev.get(next_prev) {
+                    Some((k, score)) if k + 1 == pos => score + bonus.max(BONUS_CONSECUTIVE),
+                    _ => IMPOSSIBLE,
+                };
+                let score = gapped.max(consecutive);
+                if score > IMPOSSIBLE {
+                    cur.push((pos, score + SCORE_MATCH + self.name_bonus(pos)));
+                }
+            }
+            if cur.is_empty() {
+                return None;
+            }
+        }
+        let best = rows[pattern.len() - 1].iter().map(|(_, score)| *score).max()?;
+        Some(best - penalty)
+    }
+
+    /// Returns the smallest range that can hold an alignment: from the first occurrence of the
+    /// first pattern byte to the last occurrence of the last one.
+    fn window(&self, pattern: &[u8]) -> Option<(usize, usize)> {
+        let mut pi = 0;
+        let mut start = None;
+        for (pos, b) in self.haystack.iter().enumerate() {
+            if *b == pattern[pi] {
+                if pi == 0 {
+                    start = Some(pos);
+                }
+                pi += 1;
+                if pi == pattern.len() {
+                    break;
+                }
+            }
+        }
+        if pi < pattern.len() {
+            return None;
+        }
+        let last = pattern[pattern.len() - 1];
+        let end = self.haystack.iter().rposition(|b| *b == last)? + 1;
+        Some((start?, end))
+    }
+
+    /// Walks the rows filled by `align` back from the best final score to the matched positions.
+    fn positions(&self, rows: &[Vec<(usize, i32)>]) -> Vec<usize> {
+        let last = match rows.last() {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        let mut current = match last.iter().rev().max_by_key(|(_, score)| *score) {
+            Some(entry) => *entry,
+            None => return Vec::new(),
+        };
+        let mut positions = vec![current.0];
+        for prev in rows[..rows.len() - 1].iter().rev() {
+            let (pos, score) = current;
+            let bonus = self.bonus_at(pos);
+            let own = score - SCORE_MATCH - self.name_bonus(pos);
+            let consecutive = prev.iter().find(|(k, s)| k + 1 == pos && s + bonus.max(BONUS_CONSECUTIVE) == own);
+            current = match consecutive {
+                Some(entry) => *entry,
+                None => match prev
+                    .iter()
+                    .rev()
+                    .find(|(k, s)| k + 2 <= pos && s + SCORE_GAP_START + SCORE_GAP_EXTENSION * (pos - k - 2) as i32 + bonus == own)
+                {
+                    Some(entry) => *entry,
+                    None => break,
+                },
+            };
+            positions.push(current.0);
+        }
+        positions.reverse();
+        positions
+    }
+
+    fn name_bonus(&self, pos: usize) -> i32 {
+        if pos >= self.name_start {
+            BONUS_FILE_NAME
+        } else {
+            0
+        }
+    }
+
+    /// Returns the bonus for a match at `pos`, from the byte before it.
+    fn bonus_at(&self, pos: usize) -> i32 {
+        if pos == 0 {
+            return BONUS_SLASH;
+        }
+        let prev = self.text[pos - 1];
+        let cur = self.text[pos];
+        match prev {
+            b'/' => BONUS_SLASH,
+            b'_' | b'-' | b'.' | b' ' if cur != prev => BONUS_BOUNDARY,
+            _ if prev.is_ascii_lowercase() && cur.is_ascii_uppercase() => BONUS_CAMEL,
+            _ if !prev.is_ascii_digit() && cur.is_ascii_digit() => BONUS_CAMEL,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::path::Path;
+
+    fn index_of(paths: &[&str]) -> FuzzyIndex {
+        let mut data = FuzzyData::default();
+        for path in paths {
+            let kind = if path.ends_with('/') { DirEntryKind::Dir } else { DirEntryKind::File };
+            data.push(path.trim_end_matches('/').as_bytes(), kind);
+        }
+        FuzzyIndex { data_: Arc::new(data) }
+    }
+
+    fn found(index: &FuzzyIndex, query: &str, options: &FuzzyOptions) -> Vec<String> {
+        index.search(query, options).iter().map(|m| m.get_path().to_string_lossy().into_owned()).collect()
+    }
+
+    /// Scores one alignment the way `align` defines it.
+    fn alignment_score(candidate: &Candidate<'_>, positions: &[usize]) -> i32 {
+        let mut score = 0;
+        for (i, pos) in positions.iter().enumerate() {
+            let bonus = candidate.bonus_at(*pos);
+            score += SCORE_MATCH + candidate.name_bonus(*pos);
+            score += match i {
+                0 => bonus * BONUS_FIRST_CHAR_MULTIPLIER,
+                _ if positions[i - 1] + 1 == *pos => bonus.max(BONUS_CONSECUTIVE),
+                _ => SCORE_GAP_START + SCORE_GAP_EXTENSION * (*pos - positions[i - 1] - 2) as i32 + bonus,
+            };
+        }
+        score - candidate.text.len() as i32 / LENGTH_PENALTY_DIVISOR
+    }
+
+    /// Tries every alignment of `pattern` in `haystack`.
+    fn brute_force(candidate: &Candidate<'_>, pattern: &[u8], from: usize, chosen: &mut Vec<usize>) -> Option<i32> {
+        if chosen.len() == pattern.len() {
+            return Some(alignment_score(candidate, chosen));
+        }
+        let mut best = None;
+        for pos in from..candidate.haystack.len() {
+            if candidate.haystack[pos] == pattern[chosen.len()] {
+                chosen.push(pos);
+                best = best.max(brute_force(candidate, pattern, pos + 1, chosen));
+                chosen.pop();
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn alignment_is_optimal() {
+        let paths = ["a/b_a/ab", "ab/ab/ab", "aXbA/Ba", "x/a-b.b", "bbaab/ba", "A_b/aB/ba.b"];
+        let patterns = ["a", "ab", "ba", "aba", "abb", "bab", "aa", "bb", "abab"];
+        let mut rows = Vec::new();
+        for path in paths.iter() {
+            for case_sensitive in [false, true].iter() {
+                let index = index_of(&[path]);
+                let candidate = index.data_.candidate(0, *case_sensitive);
+                for pattern in patterns.iter() {
+                    let expected = brute_force(&candidate, pattern.as_bytes(), 0, &mut Vec::new());
+                    let score = candidate.align(pattern.as_bytes(), &mut rows);
+                    assert_eq!(score, expected, "{:?} in {:?}", pattern, path);
+                    if let Some(score) = score {
+                        let positions = candidate.positions(&rows);
+                        assert_eq!(positions.len(), pattern.len());
+                        assert_eq!(alignment_score(&candidate, &positions), score, "{:?} in {:?}", pattern, path);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn better_matches_rank_first() {
+        let index = index_of(&["foo/bar.txt", "bar/foo.txt", "xfxoxo.txt", "deep/er/path/to/foo.txt"]);
+        let results = found(&index, "foo", &FuzzyOptions::new());
+        assert_eq!(results[0], "bar/foo.txt");
+        assert_eq!(results.last().unwrap(), "xfxoxo.txt");
+        assert_eq!(results.len(), 4);
+
+        let index = index_of(&["src/fuzzy_match.rs", "src/fuzzymatch.rs", "src/FuzzyMatch.rs", "src/f_u_z_z_y.rs"]);
+        let results = index.search("fm", &FuzzyOptions::new());
+        assert_eq!(results[0].get_path(), &PathBuf::from("src/fuzzy_match.rs"));
+        assert_eq!(results[0].get_positions(), &[4, 10]);
+        assert_eq!(results[1].get_path(), &PathBuf::from("src/FuzzyMatch.rs"));
+        assert_eq!(results[1].get_positions(), &[4, 9]);
+        assert!(results.windows(2).all(|pair| pair[0].get_score() >= pair[1].get_score()));
+        let plain = results.iter().position(|m| m.get_path() == Path::new("src/fuzzymatch.rs")).unwrap();
+        assert!(plain >= 2);
+    }
+
+    #[test]
+    fn ties_are_broken_by_path_order() {
+        let index = index_of(&["c/x", "a/x", "b/x"]);
+        assert_eq!(found(&index, "x", &FuzzyOptions::new()), vec!["c/x", "a/x", "b/x"]);
+        let results = index.search("x", &FuzzyOptions::new());
+        assert!(results.iter().all(|m| m.get_score() == results[0].get_score()));
+    }
+
+    #[test]
+    fn limits_and_kinds_are_applied() {
+        let index = index_of(&["alpha/", "alpha/one", "alpha/two", "beta/alpha"]);
+        assert_eq!(found(&index, "alpha", &FuzzyOptions::new().with_limit(1)).len(), 1);
+        assert!(found(&index, "alpha", &FuzzyOptions::new().with_limit(0)).is_empty());
+        assert_eq!(found(&index, "alpha", &FuzzyOptions::new().with_limit(usize::MAX)).len(), 4);
+        assert_eq!(found(&index, "alpha", &FuzzyOptions::new().with_kind(DirEntryKind::Dir)), vec!["alpha"]);
+        assert_eq!(found(&index, "", &FuzzyOptions::new()).len(), 4);
+        assert!(found(&index, "zeta", &FuzzyOptions::new()).is_empty());
+        assert!(found(&index, "ahpla", &FuzzyOptions::new()).is_empty());
+    }
+
+    #[test]
+    fn case_is_smart() {
+        let index = index_of(&["README.md", "readme.txt"]);
+        assert_eq!(found(&index, "readme", &FuzzyOptions::new()).len(), 2);
+        assert_eq!(found(&index, "README", &FuzzyOptions::new()), vec!["README.md"]);
+        assert_eq!(found(&index, "readme", &FuzzyOptions::new().with_case_sensitive()), vec!["readme.txt"]);
+        // Positions refer to the original bytes whatever the case
+        let results = index.search("rdm", &FuzzyOptions::new());
+        let readme = results.iter().find(|m| m.get_path() == Path::new("README.md")).unwrap();
+        assert_eq!(readme.get_positions(), &[0, 3, 4]);
+    }
+
+    #[test]
+    fn threaded_searches_match_single_threaded() {
+        let paths: Vec<String> = (0..3 * MIN_ENTRIES_PER_THREAD).map(|i| format!("dir{}/file_{}.rs", i % 97, i)).collect();
+        let refs: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let index = index_of(&refs);
+        for query in ["f1", "d9/f_3", "file", "zz"].iter() {
+            for limit in [1, 25, 100_000].iter() {
+                let single = index.search(query, &FuzzyOptions::new().with_limit(*limit));
+                let threaded = index.search(query, &FuzzyOptions::new().with_limit(*limit).with_threads(4));
+                assert_eq!(single, threaded, "{:?} limit {}", query, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn indexes_list_every_entry_but_the_root() {
+        let dir = TempDir::new("fuzzy-index");
+        dir.write("src/main.rs", b"");
+        dir.write("src/lib.rs", b"");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let index = FuzzyIndex::from(&indexer);
+        assert_eq!(index.len(), 3);
+        assert!(!index.is_empty());
+        assert!(FuzzyIndex::default().is_empty());
+        let results = indexer.fuzzy_find("main", &FuzzyOptions::new().with_kind(DirEntryKind::File));
+        assert_eq!(results[0].get_path(), &PathBuf::from("src/main.rs"));
+        assert_eq!(results[0].get_positions(), &[4, 5, 6, 7]);
+    }
+}
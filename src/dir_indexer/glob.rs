@@ -0,0 +1,293 @@
+//! Shell-style glob patterns over raw path bytes.
+//!
+//! Supported syntax: `?` matches one byte other than `/`, `*` matches any run of bytes without
+//! `/`, `**` matches any run of bytes and `**/` matches zero or more whole directories. Bracket
+//! classes such as `[a-z]` and `[!0-9]` (or `[^0-9]`) match one byte, and a backslash escapes the
+//! next character. An unterminated `[` is taken literally. Case-insensitive patterns fold ASCII
+//! letters only.
+
+use std::path::Path;
+
+use super::codec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    AnyByte,
+    Star,
+    DoubleStar,
+    DoubleStarSlash,
+    Class { negated: bool, ranges: Vec<(u8, u8)> },
+}
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GlobPattern {
+    tokens_: Vec<Token>,
+    has_slash_: bool,
+    case_insensitive_: bool,
+}
+
+impl GlobPattern {
+    /// Compiles a pattern.
+    pub(crate) fn from(pattern: &str) -> GlobPattern {
+        let bytes = pattern.as_bytes();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if i + 1 < bytes.len() => {
+                    tokens.push(Token::Literal(bytes[i + 1]));
+                    i += 2;
+                }
+                b'?' => {
+                    tokens.push(Token::AnyByte);
+                    i += 1;
+                }
+                b'*' if bytes.get(i + 1) == Some(&b'*') => {
+                    if bytes.get(i + 2) == Some(&b'/') {
+                        tokens.push(Token::DoubleStarSlash);
+                        i += 3;
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                        i += 2;
+                    }
+                }
+                b'*' => {
+                    tokens.push(Token::Star);
+                    i += 1;
+                }
+                b'[' => match parse_class(&bytes[i + 1..]) {
+                    Some((token, len)) => {
+                        tokens.push(token);
+                        i += 1 + len;
+                    }
+                    None => {
+                        tokens.push(Token::Literal(b'['));
+                        i += 1;
+                    }
+                },
+                byte => {
+                    tokens.push(Token::Literal(byte));
+                    i += 1;
+                }
+            }
+        }
+        GlobPattern {
+            tokens_: tokens,
+            has_slash_: pattern.contains('/'),
+            case_insensitive_: false,
+        }
+    }
+
+    /// Compiles a pattern that ignores the case of ASCII letters.
+    pub(crate) fn from_case_insensitive(pattern: &str) -> GlobPattern {
+        let mut glob = GlobPattern::from(&pattern.to_ascii_lowercase());
+        glob.case_insensitive_ = true;
+        glob
+    }
+
+    /// Matches an entry the way `.gitignore` does: patterns with a slash are matched against the
+    /// whole relative path, others against the file name only.
+    pub(crate) fn matches_entry(&self, rl_path: &Path) -> bool {
+        if self.has_slash_ {
+            self.matches(codec::path_to_bytes(rl_path))
+        } else {
+            let name = rl_path.file_name().map(Path::new).unwrap_or(rl_path);
+            self.matches(codec::path_to_bytes(name))
+        }
+    }
+
+    /// Returns `true` if the whole of `text` matches the pattern.
+    pub(crate) fn matches(&self, text: &[u8]) -> bool {
+        let folded;
+        let text = if self.case_insensitive_ {
+            folded = text.to_ascii_lowercase();
+            &folded[..]
+        } else {
+            text
+        };
+        let n = text.len();
+        // next[i] holds whether tokens t+1.. match text[i..]; filled from the last token backwards
+        let mut next: Vec<bool> = (0..=n).map(|i| i == n).collect();
+        let mut cur = vec![false; n + 1];
+        for token in self.tokens_.iter().rev() {
+            let mut dirs_then_rest = false;
+            for i in (0..=n).rev() {
+                let byte = text.get(i).copied();
+                let single = |accept: bool| accept && next[i + 1];
+                cur[i] = match token {
+                    Token::Literal(c) => byte.map_or(false, |b| single(b == *c)),
+                    Token::AnyByte => byte.map_or(false, |b| single(b != b'/')),
+                    Token::Class { negated, ranges } => byte.map_or(false, |b| {
+                        single(b != b'/' && in_class(b, ranges) != *negated)
+                    }),
+                    Token::Star => next[i] || (byte.map_or(false, |b| b != b'/') && cur[i + 1]),
+                    Token::DoubleStar => next[i] || (byte.is_some() && cur[i + 1]),
+                    Token::DoubleStarSlash => {
+                        // Either no directory at all, or any run of bytes ending in a slash
+                        dirs_then_rest = byte.is_some() && ((byte == Some(b'/') && next[i + 1]) || dirs_then_rest);
+                        next[i] || dirs_then_rest
+                    }
+                };
+            }
+            std::mem::swap(&mut cur, &mut next);
+        }
+        next[0]
+    }
+}
+
+fn in_class(byte: u8, ranges: &[(u8, u8)]) -> bool {
+    ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&byte))
+}
+
+/// Parses a bracket class following `[`.
+///
+/// # Returns
+///
+/// The class token and the number of bytes consumed including the closing `]`, or `None` if the
+/// class is not terminated.
+fn parse_class(bytes: &[u8]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(bytes.first(), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut lo = *bytes.get(i)?;
+        if lo == b']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+        if lo == b'\\' {
+            lo = *bytes.get(i + 1)?;
+            i += 1;
+        }
+        i += 1;
+        let mut hi = lo;
+        if bytes.get(i) == Some(&b'-') && bytes.get(i + 1).map_or(false, |b| *b != b']') {
+            hi = bytes[i + 1];
+            if hi == b'\\' {
+                hi = *bytes.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+        ranges.push((lo.min(hi), lo.max(hi)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        GlobPattern::from(pattern).matches(text.as_bytes())
+    }
+
+    /// A direct backtracking matcher over the same tokens, used as the reference.
+    fn reference(tokens: &[Token], text: &[u8]) -> bool {
+        let (token, rest) = match tokens.split_first() {
+            Some(split) => split,
+            None => return text.is_empty(),
+        };
+        match token {
+            Token::Literal(c) => text.first() == Some(c) && reference(rest, &text[1..]),
+            Token::AnyByte => text.first().map_or(false, |b| *b != b'/') && reference(rest, &text[1..]),
+            Token::Class { negated, ranges } => {
+                text.first().map_or(false, |b| *b != b'/' && in_class(*b, ranges) != *negated) && reference(rest, &text[1..])
+            }
+            Token::Star => {
+                let run = text.iter().take_while(|b| **b != b'/').count();
+                (0..=run).any(|len| reference(rest, &text[len..]))
+            }
+            Token::DoubleStar => (0..=text.len()).any(|len| reference(rest, &text[len..])),
+            Token::DoubleStarSlash => {
+                reference(rest, text) || (0..text.len()).any(|i| text[i] == b'/' && reference(rest, &text[i + 1..]))
+            }
+        }
+    }
+
+    #[test]
+    fn wildcards_stop_at_slashes() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "src/main.rs"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(matches("src/**.rs", "src/bin/main.rs"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "a/c"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn double_star_slash_matches_whole_directories() {
+        assert!(matches("**/x", "x"));
+        assert!(matches("**/x", "a/x"));
+        assert!(matches("**/x", "a/b/x"));
+        assert!(!matches("**/x", "ax"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/xb"));
+        assert!(matches("**", "any/thing/at/all"));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(!matches("[!a-c]x", "/x"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[c-a]", "b"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("[abc", "[abc"));
+        assert!(matches("trailing\\", "trailing\\"));
+    }
+
+    #[test]
+    fn case_folding_is_ascii_only() {
+        let glob = GlobPattern::from_case_insensitive("*.JPG");
+        assert!(glob.matches(b"photo.jpg"));
+        assert!(glob.matches(b"PHOTO.Jpg"));
+        assert!(!GlobPattern::from("*.JPG").matches(b"photo.jpg"));
+        assert!(GlobPattern::from_case_insensitive("\u{c9}*").matches("\u{c9}t\u{e9}".as_bytes()));
+        assert!(!GlobPattern::from_case_insensitive("\u{c9}*").matches("\u{e9}t\u{e9}".as_bytes()));
+    }
+
+    #[test]
+    fn entries_match_by_name_or_path() {
+        let glob = GlobPattern::from("*.txt");
+        assert!(glob.matches_entry(Path::new("deep/dir/notes.txt")));
+        let glob = GlobPattern::from("deep/*.txt");
+        assert!(!glob.matches_entry(Path::new("deep/dir/notes.txt")));
+        assert!(glob.matches_entry(Path::new("deep/notes.txt")));
+    }
+
+    #[test]
+    fn matcher_agrees_with_backtracking() {
+        let pieces = ["a", "b", "/", "?", "*", "**", "**/", "[ab]", "[!a]"];
+        let texts: Vec<Vec<u8>> = (0..3u32.pow(5))
+            .flat_map(|n| {
+                (1..=5).map(move |len| (0..len).map(|i| b"ab/"[(n / 3u32.pow(i)) as usize % 3]).collect::<Vec<u8>>())
+            })
+            .chain(std::iter::once(Vec::new()))
+            .collect();
+        for n in 0..pieces.len().pow(3) {
+            let pattern: String = (0..3).map(|i| pieces[n / pieces.len().pow(i) % pieces.len()]).collect();
+            let glob = GlobPattern::from(&pattern);
+            for text in &texts {
+                assert_eq!(glob.matches(text), reference(&glob.tokens_, text), "{:?} on {:?}", pattern, String::from_utf8_lossy(text));
+            }
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! Persistent cache of file content hashes, stored next to a saved index.
+//!
+//! A cached hash is reused only while the file's device, inode, size and modification time are
+//! unchanged. Layout, all integers as LEB128 varints unless noted:
+//!
+//! * magic `DIRIDXH\n` (8 bytes) and format version (`u32`, little endian)
+//! * hash algorithm (one byte), entry count
+//! * every entry: device, inode, size, zigzag mtime seconds, mtime nanoseconds, raw digest
+//! * XXH64 of everything above (`u64`, little endian)
+
+use super::{ContentHash, DirEntryKind, DirIndexer, DirIndexerErr, DirMeta, HashAlgo};
+use super::codec::{ByteReader, ByteWriter};
+use super::hasher;
+use super::index_file;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Magic bytes at the start of every hash cache file.
+const CACHE_MAGIC: &[u8; 8] = b"DIRIDXH\n";
+
+/// Current version of the hash cache format.
+const CACHE_VERSION: u32 = 1;
+
+/// Files modified this close to the scan time cannot be told apart from a later write within the
+/// timestamp granularity of the filesystem, so what is derived from their contents must not be
+/// trusted on the next run. The hash cache leaves them out; the trigram index re-reads them.
+pub(crate) const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Identifies one version of a file's contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FileKey {
+    pub(crate) dev: u64,
+    pub(crate) ino: u64,
+    pub(crate) size: u64,
+    pub(crate) mtime: i64,
+    pub(crate) mtime_nsec: u32,
+}
+
+impl FileKey {
+    pub(crate) fn from(meta: &DirMeta) -> FileKey {
+        FileKey {
+            dev: meta.get_dev(),
+            ino: meta.get_ino(),
+            size: meta.get_size(),
+            mtime: meta.get_mtime(),
+            mtime_nsec: meta.get_mtime_nsec(),
+        }
+    }
+
+    /// Reads a key written by `write`: device, inode, size, zigzag mtime seconds and mtime
+    /// nanoseconds as varints.
+    pub(crate) fn read(reader: &mut ByteReader<'_>) -> Option<FileKey> {
+        Some(FileKey {
+            dev: reader.get_varint()?,
+            ino: reader.get_varint()?,
+            size: reader.get_varint()?,
+            mtime: reader.get_varint_signed()?,
+            mtime_nsec: reader.get_varint().filter(|n| *n < 1_000_000_000)? as u32,
+        })
+    }
+
+    /// Appends the key to `writer`.
+    pub(crate) fn write(&self, writer: &mut ByteWriter) {
+        writer.put_varint(self.dev);
+        writer.put_varint(self.ino);
+        writer.put_varint(self.size);
+        writer.put_varint_signed(self.mtime);
+        writer.put_varint(self.mtime_nsec as u64);
+    }
+}
+
+/// The `HashCache` struct holds content hashes keyed by device, inode, size and modification time.
+#[derive(Debug, Clone)]
+pub struct HashCache {
+    algo_: HashAlgo,
+    entries_: HashMap<FileKey, Vec<u8>>,
+}
+
+impl HashCache {
+    /// Creates an empty cache for the given algorithm.
+    pub fn new(algo: HashAlgo) -> HashCache {
+        HashCache {
+            algo_: algo,
+            entries_: HashMap::new(),
+        }
+    }
+
+    /// Returns the conventional location of the hash cache of a saved index: the index path
+    /// with `.hashes` appended.
+    pub fn path_for_index(index_path: &Path) -> PathBuf {
+        let mut cache_path = index_path.as_os_str().to_os_string();
+        cache_path.push(".hashes");
+        PathBuf::from(cache_path)
+    }
+
+    /// Loads a cache written by `save`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_path` - The path of the cache file.
+    /// * `algo` - The algorithm the cache is wanted for.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the loaded cache. A missing file, or one written for a different
+    /// algorithm, yields an empty cache. A `DirIndexerErr::IndexIoFail`, `IncompatibleIndexVersion`
+    /// or `CorruptIndex` is returned if the file cannot be read or decoded.
+    pub fn load(cache_path: &Path, algo: HashAlgo) -> Result<HashCache, DirIndexerErr> {
+        let bytes = match fs::read(cache_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashCache::new(algo)),
+            Err(_) => return Err(DirIndexerErr::IndexIoFail(cache_path.to_path_buf())),
+        };
+        let corrupt = || DirIndexerErr::CorruptIndex(cache_path.to_path_buf());
+        if bytes.len() < CACHE_MAGIC.len() + 12 || &bytes[..CACHE_MAGIC.len()] != CACHE_MAGIC {
+            return Err(corrupt());
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 8);
+        let mut reader = ByteReader::from(&body[CACHE_MAGIC.len()..]);
+        let version = reader.get_u32_le().ok_or_else(corrupt)?;
+        if version != CACHE_VERSION {
+            return Err(DirIndexerErr::IncompatibleIndexVersion(version));
+        }
+        if ByteReader::from(sum).get_u64_le() != Some(index_file::checksum(body)) {
+            return Err(corrupt());
+        }
+        let cached_algo = algo_from_u8(reader.get_u8().ok_or_else(corrupt)?).ok_or_else(corrupt)?;
+        if cached_algo != algo {
+            return Ok(HashCache::new(algo));
+        }
+        let digest_len = algo.digest_len();
+        let count = reader.get_len(reader.remaining() / (5 + digest_len)).ok_or_else(corrupt)?;
+        let mut cache = HashCache::new(algo);
+        for _ in 0..count {
+            let key = FileKey::read(&mut reader).ok_or_else(corrupt)?;
+            let digest = reader.get_raw(digest_len).ok_or_else(corrupt)?;
+            cache.entries_.insert(key, digest.to_vec());
+        }
+        if reader.remaining() != 0 {
+            return Err(corrupt());
+        }
+        Ok(cache)
+    }
+
+    /// Writes the cache to `cache_path` through a temporary file.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or `DirIndexerErr::IndexIoFail` if the file cannot be written.
+    pub fn save(&self, cache_path: &Path) -> Result<(), DirIndexerErr> {
+        let mut writer = ByteWriter::new();
+        writer.put_raw(CACHE_MAGIC);
+        writer.put_u32_le(CACHE_VERSION);
+        writer.put_u8(algo_to_u8(self.algo_));
+        writer.put_varint(self.entries_.len() as u64);
+        for (key, digest) in &self.entries_ {
+            key.write(&mut writer);
+            writer.put_raw(digest);
+        }
+        let sum = index_file::checksum(writer.as_slice());
+        writer.put_u64_le(sum);
+        index_file::write_atomically(cache_path, &writer.into_vec())
+    }
+
+    /// Returns the algorithm of the cached hashes.
+    pub fn get_algo(&self) -> HashAlgo {
+        self.algo_
+    }
+
+    /// Returns the number of cached hashes.
+    pub fn len(&self) -> usize {
+        self.entries_.len()
+    }
+
+    /// Returns `true` if the cache holds no hashes.
+    pub fn is_empty(&self) -> bool {
+        self.entries_.is_empty()
+    }
+}
+
+/// Counts of how the hashes of a `DirIndexer::compute_hashes` run were obtained.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashStats {
+    hashed_: usize,
+    reused_: usize,
+    failed_: usize,
+}
+
+impl HashStats {
+    /// Returns the number of files whose contents were read and hashed.
+    pub fn get_hashed(&self) -> usize {
+        self.hashed_
+    }
+
+    /// Returns the number of files whose hash was taken from the cache.
+    pub fn get_reused(&self) -> usize {
+        self.reused_
+    }
+
+    /// Returns the number of files that could not be read.
+    pub fn get_failed(&self) -> usize {
+        self.failed_
+    }
+}
+
+/// Hashes every regular file of the index, reusing and refreshing the cache. The cache is left
+/// holding exactly the files of the index.
+///
+/// # Returns
+///
+/// The hashes keyed by relative path, and how they were obtained.
+pub(crate) fn compute_hashes(indexer: &DirIndexer, cache: &mut HashCache, threads: usize) -> (HashMap<PathBuf, ContentHash>, HashStats) {
+    let algo = cache.algo_;
+    let racy_since = indexer.get_scan_time().checked_sub(RACY_WINDOW);
+    let mut stats = HashStats::default();
+    let mut hashes: HashMap<PathBuf, ContentHash> = HashMap::new();
+    let mut fresh_entries: HashMap<FileKey, Vec<u8>> = HashMap::new();
+    let mut pending: Vec<(PathBuf, FileKey, bool)> = Vec::new();
+
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        if node.get_kind() != DirEntryKind::File {
+            continue;
+        }
+        let meta = node.get_meta();
+        let key = FileKey::from(meta);
+        if let Some(digest) = cache.entries_.get(&key) {
+            hashes.insert(rl_path, ContentHash::from(algo, digest.clone()));
+            fresh_entries.insert(key, digest.clone());
+            stats.reused_ += 1;
+            continue;
+        }
+        let cacheable = match racy_since {
+            Some(since) => index_file::time_from_parts(meta.get_mtime(), meta.get_mtime_nsec() as u64)
+                .map_or(false, |mtime| mtime < since),
+            None => false,
+        };
+        pending.push((rl_path, key, cacheable));
+    }
+
+    let paths: Vec<PathBuf> = pending.iter().map(|(rl_path, _, _)| rl_path.clone()).collect();
+    let results = hash_in_parallel(indexer.get_root_path(), paths, algo, threads);
+    for ((rl_path, key, cacheable), result) in pending.into_iter().zip(results) {
+        match result {
+            Ok((hash, read_key)) => {
+                // A file changed since the scan is hashed as it is now, which the key of the
+                // indexed version must not be made to stand for
+                if cacheable && read_key == key {
+                    fresh_entries.insert(key, hash.get_digest().to_vec());
+                }
+                hashes.insert(rl_path, hash);
+                stats.hashed_ += 1;
+            }
+            Err(_) => stats.failed_ += 1,
+        }
+    }
+    cache.entries_ = fresh_entries;
+    (hashes, stats)
+}
+
+/// Hashes files below `root_path` on up to `threads` worker threads.
+///
+/// # Returns
+///
+/// The hash and the key of the contents read for each path, in the order of `paths`.
+fn hash_in_parallel(root_path: &Path, paths: Vec<PathBuf>, algo: HashAlgo, threads: usize) -> Vec<io::Result<(ContentHash, FileKey)>> {
+    let threads = threads.max(1).min(paths.len());
+    if threads <= 1 {
+        return paths
+            .iter()
+            .map(|rl_path| hash_with_key(algo, &root_path.join(rl_path)))
+            .collect();
+    }
+
+    let count = paths.len();
+    let queue = Arc::new(Mutex::new(paths.into_iter().enumerate().collect::<Vec<_>>()));
+    let root_path = Arc::new(root_path.to_path_buf());
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<thread::JoinHandle<()>> = (0..threads)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let root_path = Arc::clone(&root_path);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let next = match queue.lock() {
+                    Ok(mut queue) => queue.pop(),
+                    Err(_) => None,
+                };
+                let (i, rl_path) = match next {
+                    Some(job) => job,
+                    None => break,
+                };
+                if sender.send((i, hash_with_key(algo, &root_path.join(&rl_path)))).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut results: Vec<Option<io::Result<(ContentHash, FileKey)>>> = (0..count).map(|_| None).collect();
+    for (i, result) in receiver.iter() {
+        results[i] = Some(result);
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    // A worker that died leaves its job without a result; report it as an I/O failure
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "hashing thread failed"))))
+        .collect()
+}
+
+/// Hashes a file and returns the key of the opened file as it was once read, so that a write
+/// since the scan, or during the read, can be told apart from the indexed version.
+fn hash_with_key(algo: HashAlgo, path: &Path) -> io::Result<(ContentHash, FileKey)> {
+    let mut file = File::open(path)?;
+    let hash = hasher::hash_reader(algo, &mut file)?;
+    let meta = file.metadata()?;
+    Ok((hash, FileKey::from(&DirMeta::from(&meta))))
+}
+
+fn algo_to_u8(algo: HashAlgo) -> u8 {
+    match algo {
+        HashAlgo::Xxh64 => 0,
+        HashAlgo::Sha256 => 1,
+    }
+}
+
+fn algo_from_u8(value: u8) -> Option<HashAlgo> {
+    match value {
+        0 => Some(HashAlgo::Xxh64),
+        1 => Some(HashAlgo::Sha256),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::hash_bytes;
+
+    /// Creates `old-*` files outside the racy window and `new` files inside it.
+    fn sample_indexer(dir: &TempDir) -> DirIndexer {
+        dir.write("old-a", b"alpha");
+        dir.write("sub/old-b", b"beta");
+        dir.write("new", b"gamma");
+        dir.set_mtime("old-a", 1_000_000_000);
+        dir.set_mtime("sub/old-b", 1_000_000_000);
+        DirIndexer::from(dir.get_path().clone()).unwrap()
+    }
+
+    #[test]
+    fn racy_files_are_hashed_but_not_cached() {
+        let dir = TempDir::new("hash-cache-racy");
+        let mut indexer = sample_indexer(&dir);
+        let mut cache = HashCache::new(HashAlgo::Xxh64);
+        let stats = indexer.compute_hashes(&mut cache, 1);
+        assert_eq!((stats.get_hashed(), stats.get_reused(), stats.get_failed()), (3, 0, 0));
+        assert_eq!(indexer.get_content_hash(Path::new("new")), Some(&hash_bytes(HashAlgo::Xxh64, b"gamma")));
+        assert_eq!(indexer.get_content_hash(Path::new("sub/old-b")), Some(&hash_bytes(HashAlgo::Xxh64, b"beta")));
+        assert_eq!(indexer.get_content_hash(Path::new("sub")), None);
+        assert_eq!(cache.len(), 2);
+
+        let stats = indexer.compute_hashes(&mut cache, 1);
+        assert_eq!((stats.get_hashed(), stats.get_reused()), (1, 2));
+    }
+
+    #[test]
+    fn changed_files_are_rehashed_and_removed_files_dropped() {
+        let dir = TempDir::new("hash-cache-changed");
+        let mut indexer = sample_indexer(&dir);
+        let mut cache = HashCache::new(HashAlgo::Sha256);
+        indexer.compute_hashes(&mut cache, 1);
+
+        dir.write("old-a", b"alpha, longer");
+        dir.set_mtime("old-a", 1_000_000_000);
+        fs::remove_file(dir.join("sub/old-b")).unwrap();
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let stats = indexer.compute_hashes(&mut cache, 1);
+        assert_eq!((stats.get_hashed(), stats.get_reused()), (2, 0));
+        assert_eq!(indexer.get_content_hash(Path::new("old-a")), Some(&hash_bytes(HashAlgo::Sha256, b"alpha, longer")));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn files_changed_since_the_scan_are_not_cached_under_their_old_key() {
+        let dir = TempDir::new("hash-cache-stale");
+        let mut indexer = sample_indexer(&dir);
+        // Same size, so only the modification time tells the versions apart
+        dir.write("old-a", b"ALPHA");
+        let mut cache = HashCache::new(HashAlgo::Sha256);
+        let stats = indexer.compute_hashes(&mut cache, 1);
+        assert_eq!(stats.get_hashed(), 3);
+        assert_eq!(cache.len(), 1);
+
+        // Putting the indexed version back, timestamp included, must not revive the newer hash
+        dir.write("old-a", b"alpha");
+        dir.set_mtime("old-a", 1_000_000_000);
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let stats = indexer.compute_hashes(&mut cache, 1);
+        assert_eq!((stats.get_hashed(), stats.get_reused()), (2, 1));
+        assert_eq!(indexer.get_content_hash(Path::new("old-a")), Some(&hash_bytes(HashAlgo::Sha256, b"alpha")));
+    }
+
+    #[test]
+    fn threaded_hashing_matches_single_threaded() {
+        let dir = TempDir::new("hash-cache-threads");
+        for i in 0..40 {
+            dir.write(format!("d{}/f{}", i % 4, i), format!("contents {}", i).as_bytes());
+        }
+        let mut single = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let mut threaded = DirIndexer::from(dir.get_path().clone()).unwrap();
+        single.compute_hashes(&mut HashCache::new(HashAlgo::Xxh64), 1);
+        let stats = threaded.compute_hashes(&mut HashCache::new(HashAlgo::Xxh64), 8);
+        assert_eq!(stats.get_hashed(), 40);
+        for i in 0..40 {
+            let rl_path = PathBuf::from(format!("d{}/f{}", i % 4, i));
+            let expected = hash_bytes(HashAlgo::Xxh64, format!("contents {}", i).as_bytes());
+            assert_eq!(single.get_content_hash(&rl_path), Some(&expected));
+            assert_eq!(threaded.get_content_hash(&rl_path), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn saved_caches_load_back() {
+        let dir = TempDir::new("hash-cache-save");
+        let mut indexer = sample_indexer(&dir);
+        let mut cache = HashCache::new(HashAlgo::Sha256);
+        indexer.compute_hashes(&mut cache, 1);
+        let cache_path = HashCache::path_for_index(&dir.join("tree.idx"));
+        assert_eq!(cache_path, dir.join("tree.idx.hashes"));
+        cache.save(&cache_path).unwrap();
+
+        let mut loaded = HashCache::load(&cache_path, HashAlgo::Sha256).unwrap();
+        assert_eq!(loaded.get_algo(), HashAlgo::Sha256);
+        assert_eq!(loaded.entries_, cache.entries_);
+        let stats = indexer.compute_hashes(&mut loaded, 1);
+        assert_eq!(stats.get_reused(), 2);
+
+        // A cache for another algorithm is of no use and loads empty
+        let other = HashCache::load(&cache_path, HashAlgo::Xxh64).unwrap();
+        assert!(other.is_empty());
+        assert_eq!(other.get_algo(), HashAlgo::Xxh64);
+        assert!(HashCache::load(&dir.join("missing"), HashAlgo::Sha256).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt_caches_are_rejected() {
+        let dir = TempDir::new("hash-cache-corrupt");
+        let mut indexer = sample_indexer(&dir);
+        let mut cache = HashCache::new(HashAlgo::Xxh64);
+        indexer.compute_hashes(&mut cache, 1);
+        let cache_path = dir.join("tree.idx.hashes");
+        cache.save(&cache_path).unwrap();
+        let bytes = fs::read(&cache_path).unwrap();
+        let bad_path = dir.join("bad.hashes");
+
+        for len in 0..bytes.len() {
+            fs::write(&bad_path, &bytes[..len]).unwrap();
+            assert!(HashCache::load(&bad_path, HashAlgo::Xxh64).is_err(), "accepted {} bytes", len);
+        }
+        for pos in 0..bytes.len() {
+            let mut data = bytes.clone();
+            data[pos] ^= 0x20;
+            fs::write(&bad_path, &data).unwrap();
+            assert!(HashCache::load(&bad_path, HashAlgo::Xxh64).is_err(), "accepted a flip at {}", pos);
+        }
+
+        let mut data = bytes.clone();
+        data[8..12].copy_from_slice(&2u32.to_le_bytes());
+        fs::write(&bad_path, &data).unwrap();
+        assert!(matches!(HashCache::load(&bad_path, HashAlgo::Xxh64), Err(DirIndexerErr::IncompatibleIndexVersion(2))));
+        assert!(matches!(HashCache::load(dir.get_path(), HashAlgo::Xxh64), Err(DirIndexerErr::IndexIoFail(_))));
+    }
+
+    #[test]
+    fn hostile_entry_counts_are_rejected() {
+        let dir = TempDir::new("hash-cache-count");
+        let bad_path = dir.join("bad.hashes");
+        let mut writer = ByteWriter::new();
+        writer.put_raw(CACHE_MAGIC);
+        writer.put_u32_le(CACHE_VERSION);
+        writer.put_u8(algo_to_u8(HashAlgo::Xxh64));
+        writer.put_varint(u64::MAX);
+        let sum = index_file::checksum(writer.as_slice());
+        writer.put_u64_le(sum);
+        fs::write(&bad_path, writer.into_vec()).unwrap();
+        assert!(matches!(HashCache::load(&bad_path, HashAlgo::Xxh64), Err(DirIndexerErr::CorruptIndex(_))));
+    }
+
+    #[test]
+    fn file_keys_round_trip() {
+        let key = FileKey {
+            dev: u64::MAX,
+            ino: 12,
+            size: 0,
+            mtime: -5,
+            mtime_nsec: 999_999_999,
+        };
+        let mut writer = ByteWriter::new();
+        key.write(&mut writer);
+        let bytes = writer.into_vec();
+        assert_eq!(FileKey::read(&mut ByteReader::from(&bytes[..])), Some(key));
+        for len in 0..bytes.len() {
+            assert_eq!(FileKey::read(&mut ByteReader::from(&bytes[..len])), None);
+        }
+    }
+}
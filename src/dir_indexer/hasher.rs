@@ -0,0 +1,408 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// Size of the buffer used when reading files for hashing.
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+/// Represents the algorithms available for content hashing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashAlgo {
+    /// XXH64 with a zero seed; fast but not cryptographic.
+    Xxh64,
+    /// SHA-256, as used by `sha256sum` and mtree's `sha256digest`.
+    Sha256,
+}
+
+impl HashAlgo {
+    /// Returns the conventional name of the algorithm.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgo::Xxh64 => "xxh64",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+
+    /// Returns the length of a digest produced by the algorithm, in bytes.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgo::Xxh64 => 8,
+            HashAlgo::Sha256 => 32,
+        }
+    }
+}
+
+/// Represents the digest of some content together with the algorithm that produced it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentHash {
+    algo_: HashAlgo,
+    digest_: Vec<u8>,
+}
+
+impl ContentHash {
+    /// Creates a `ContentHash` from a raw digest.
+    ///
+    /// # Arguments
+    ///
+    /// * `algo` - The algorithm that produced the digest.
+    /// * `digest` - The digest bytes.
+    ///
+    /// # Returns
+    ///
+    /// A `ContentHash` instance.
+    pub fn from(algo: HashAlgo, digest: Vec<u8>) -> ContentHash {
+        ContentHash {
+            algo_: algo,
+            digest_: digest,
+        }
+    }
+
+    /// Returns the algorithm that produced the digest.
+    pub fn get_algo(&self) -> HashAlgo {
+        self.algo_
+    }
+
+    /// Returns the raw digest bytes.
+    pub fn get_digest(&self) -> &[u8] {
+        &self.digest_
+    }
+
+    /// Returns the digest as a lowercase hexadecimal string.
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(self.digest_.len() * 2);
+        for byte in &self.digest_ {
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        hex
+    }
+}
+
+impl fmt::Display for ContentHash {
+    /// Formats the digest as lowercase hexadecimal.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+/// Incremental hasher over any of the supported algorithms.
+#[derive(Debug, Clone)]
+pub struct ContentHasher {
+    state_: HasherState,
+}
+
+#[derive(Debug, Clone)]
+enum HasherState {
+    Xxh64(Xxh64),
+    Sha256(Box<Sha256>),
+}
+
+impl ContentHasher {
+    /// Creates a new hasher for the given algorithm.
+    pub fn new(algo: HashAlgo) -> ContentHasher {
+        let state = match algo {
+            HashAlgo::Xxh64 => HasherState::Xxh64(Xxh64::new(0)),
+            HashAlgo::Sha256 => HasherState::Sha256(Box::new(Sha256::new())),
+        };
+        ContentHasher { state_: state }
+    }
+
+    /// Feeds more bytes into the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.state_ {
+            HasherState::Xxh64(h) => h.update(data),
+            HasherState::Sha256(h) => h.update(data),
+        }
+    }
+
+    /// Consumes the hasher and returns the digest of everything fed so far.
+    pub fn finish(self) -> ContentHash {
+        match self.state_ {
+            HasherState::Xxh64(h) => ContentHash::from(HashAlgo::Xxh64, h.digest().to_be_bytes().to_vec()),
+            HasherState::Sha256(h) => ContentHash::from(HashAlgo::Sha256, h.digest().to_vec()),
+        }
+    }
+}
+
+/// Hashes a byte slice.
+///
+/// # Arguments
+///
+/// * `algo` - The algorithm to use.
+/// * `data` - The bytes to hash.
+///
+/// # Returns
+///
+/// The `ContentHash` of the bytes.
+pub fn hash_bytes(algo: HashAlgo, data: &[u8]) -> ContentHash {
+    let mut hasher = ContentHasher::new(algo);
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Hashes everything readable from a reader.
+///
+/// # Arguments
+///
+/// * `algo` - The algorithm to use.
+/// * `reader` - The source of the bytes to hash.
+///
+/// # Returns
+///
+/// The `ContentHash` of the content, or the I/O error that interrupted reading.
+pub fn hash_reader<R: Read>(algo: HashAlgo, reader: &mut R) -> io::Result<ContentHash> {
+    let mut hasher = ContentHasher::new(algo);
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Hashes the content of a file.
+///
+/// # Arguments
+///
+/// * `algo` - The algorithm to use.
+/// * `path` - The path of the file.
+///
+/// # Returns
+///
+/// The `ContentHash` of the file content, or the I/O error that prevented reading it.
+pub fn hash_file(algo: HashAlgo, path: &Path) -> io::Result<ContentHash> {
+    let mut file = File::open(path)?;
+    hash_reader(algo, &mut file)
+}
+
+/// Streaming implementation of the XXH64 hash function.
+#[derive(Debug, Clone)]
+struct Xxh64 {
+    seed_: u64,
+    acc_: [u64; 4],
+    buf_: [u8; 32],
+    buf_len_: usize,
+    total_len_: u64,
+}
+
+impl Xxh64 {
+    fn new(seed: u64) -> Xxh64 {
+        Xxh64 {
+            seed_: seed,
+            acc_: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buf_: [0; 32],
+            buf_len_: 0,
+            total_len_: 0,
+        }
+    }
+
+    fn round(acc: u64, input: u64) -> u64 {
+        acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME64_1)
+    }
+
+    fn merge_round(acc: u64, val: u64) -> u64 {
+        (acc ^ Xxh64::round(0, val))
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4)
+    }
+
+    fn read_u64(data: &[u8]) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[..8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn read_u32(data: &[u8]) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[..4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn consume_stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc_.iter_mut().enumerate() {
+            *acc = Xxh64::round(*acc, Xxh64::read_u64(&stripe[i * 8..]));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len_ += data.len() as u64;
+        if self.buf_len_ > 0 {
+            let take = (32 - self.buf_len_).min(data.len());
+            self.buf_[self.buf_len_..self.buf_len_ + take].copy_from_slice(&data[..take]);
+            self.buf_len_ += take;
+            data = &data[take..];
+            if self.buf_len_ < 32 {
+                return;
+            }
+            let stripe = self.buf_;
+            self.consume_stripe(&stripe);
+            self.buf_len_ = 0;
+        }
+        while data.len() >= 32 {
+            self.consume_stripe(&data[..32]);
+            data = &data[32..];
+        }
+        self.buf_[..data.len()].copy_from_slice(data);
+        self.buf_len_ = data.len();
+    }
+
+    fn digest(&self) -> u64 {
+        let mut h = if self.total_len_ >= 32 {
+            let [v1, v2, v3, v4] = self.acc_;
+            let mut h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in &self.acc_ {
+                h = Xxh64::merge_round(h, *v);
+            }
+            h
+        } else {
+            self.seed_.wrapping_add(PRIME64_5)
+        };
+        h = h.wrapping_add(self.total_len_);
+
+        let mut rest = &self.buf_[..self.buf_len_];
+        while rest.len() >= 8 {
+            h ^= Xxh64::round(0, Xxh64::read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= (Xxh64::read_u32(rest) as u64).wrapping_mul(PRIME64_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            h ^= (*byte as u64).wrapping_mul(PRIME64_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^= h >> 32;
+        h
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Streaming implementation of SHA-256 (FIPS 180-4).
+#[derive(Debug, Clone)]
+struct Sha256 {
+    state_: [u32; 8],
+    buf_: [u8; 64],
+    buf_len_: usize,
+    total_len_: u64,
+}
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state_: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            buf_: [0; 64],
+            buf_len_: 0,
+            total_len_: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in w.iter_mut().take(16).enumerate() {
+            *word = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state_;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state_.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len_ = self.total_len_.wrapping_add(data.len() as u64);
+        if self.buf_len_ > 0 {
+            let take = (64 - self.buf_len_).min(data.len());
+            self.buf_[self.buf_len_..self.buf_len_ + take].copy_from_slice(&data[..take]);
+            self.buf_len_ += take;
+            data = &data[take..];
+            if self.buf_len_ < 64 {
+                return;
+            }
+            let block = self.buf_;
+            self.compress(&block);
+            self.buf_len_ = 0;
+        }
+        while data.len() >= 64 {
+            self.compress(&data[..64]);
+            data = &data[64..];
+        }
+        self.buf_[..data.len()].copy_from_slice(data);
+        self.buf_len_ = data.len();
+    }
+
+    fn digest(mut self) -> [u8; 32] {
+        let bit_len = self.total_len_.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        let pad_zeros = (119 - self.buf_len_) % 64;
+        padding.extend(std::iter::repeat(0u8).take(pad_zeros));
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+        self.update(&padding);
+        let mut out = [0u8; 32];
+        for (i, word) in self.state_.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+}
@@ -0,0 +1,646 @@
+//! A self-contained HTML disk-usage report of an index.
+//!
+//! The page holds its styles inline and uses no scripts: the tree is built from nested
+//! `<details>` elements and the treemap is an inline SVG whose rectangles show the path and size
+//! of each entry on hover. Names that are not valid UTF-8 are shown lossily.
+
+use super::{DirEntryKind, DirIndexer, DirNode, DirUsage};
+use super::dir_usage::{self, human_size};
+use super::time_format;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const TREEMAP_WIDTH: f64 = 960.0;
+const TREEMAP_HEIGHT: f64 = 600.0;
+/// Rectangles narrower or lower than this many pixels are not subdivided further.
+const TREEMAP_MIN_SIDE: f64 = 6.0;
+/// The height of the name strip at the top of a directory rectangle.
+const TREEMAP_LABEL_HEIGHT: f64 = 14.0;
+
+const STYLE: &str = "\
+body{font:14px/1.4 system-ui,sans-serif;margin:2em auto;max-width:1000px;color:#222;padding:0 1em}\
+h1{font-size:1.6em}h2{font-size:1.25em;margin-top:2em;border-bottom:1px solid #ccc}\
+table{border-collapse:collapse;width:100%}th,td{text-align:left;padding:2px 8px;border-bottom:1px solid #eee}\
+td.num,th.num{text-align:right;white-space:nowrap}td.path{word-break:break-all}\
+.bar{display:inline-block;height:.8em;background:#4a7bd0;vertical-align:middle}\
+.tree ul{list-style:none;margin:0;padding-left:1.2em}.tree summary{cursor:pointer}\
+.tree .size{display:inline-block;min-width:5em;text-align:right;margin-right:.6em;color:#555}\
+.tree .pct{display:inline-block;width:6em;margin-right:.6em;background:#eee}\
+svg text{font:11px sans-serif;pointer-events:none}\
+.summary td{border:none;padding:1px 12px 1px 0}";
+
+/// Options controlling the content of an HTML report.
+#[derive(Debug, Clone)]
+pub struct HtmlReportOptions {
+    title_: String,
+    top_n_: usize,
+    max_depth_: Option<usize>,
+}
+
+impl Default for HtmlReportOptions {
+    fn default() -> HtmlReportOptions {
+        HtmlReportOptions {
+            title_: String::from("Disk usage report"),
+            top_n_: 20,
+            max_depth_: None,
+        }
+    }
+}
+
+impl HtmlReportOptions {
+    /// Creates options listing the 20 largest files and directories with the full tree.
+    pub fn new() -> HtmlReportOptions {
+        HtmlReportOptions::default()
+    }
+
+    /// Sets the title of the page.
+    pub fn with_title(mut self, title: &str) -> HtmlReportOptions {
+        self.title_ = title.to_string();
+        self
+    }
+
+    /// Sets how many of the largest files and directories are listed.
+    pub fn with_top_n(mut self, top_n: usize) -> HtmlReportOptions {
+        self.top_n_ = top_n;
+        self
+    }
+
+    /// Limits the tree and the treemap to entries at most `max_depth` levels below the root.
+    /// Sizes still cover everything below each entry.
+    pub fn with_max_depth(mut self, max_depth: usize) -> HtmlReportOptions {
+        self.max_depth_ = Some(max_depth);
+        self
+    }
+
+    /// Returns the title of the page.
+    pub fn get_title(&self) -> &str {
+        &self.title_
+    }
+
+    /// Returns how many of the largest files and directories are listed.
+    pub fn get_top_n(&self) -> usize {
+        self.top_n_
+    }
+
+    /// Returns the depth limit of the tree and the treemap, if any.
+    pub fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth_
+    }
+}
+
+/// The files of one extension.
+#[derive(Default)]
+struct ExtensionUsage {
+    files_: u64,
+    size_: u64,
+}
+
+/// Writes the report of `indexer` as one HTML document.
+pub(crate) fn write_html_report<W: Write>(indexer: &DirIndexer, out: &mut W, options: &HtmlReportOptions) -> io::Result<()> {
+    let root = indexer.get_tree().get_root_node();
+    let mut usage = BTreeMap::new();
+    let total = dir_usage::add_usage(root, &mut usage);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape(&options.title_)));
+    html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n", STYLE));
+    html.push_str(&format!("<h1>{}</h1>\n", escape(&options.title_)));
+
+    let scan_secs = indexer
+        .get_scan_time()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    html.push_str("<table class=\"summary\">\n");
+    push_summary_row(&mut html, "Root", &escape(&indexer.get_root_path().to_string_lossy()));
+    push_summary_row(&mut html, "Scanned", &time_format::format_utc(scan_secs));
+    push_summary_row(
+        &mut html,
+        "Total size",
+        &format!("{} ({} bytes)", human_size(total.get_size()), total.get_size()),
+    );
+    push_summary_row(&mut html, "Files", &total.get_file_count().to_string());
+    push_summary_row(&mut html, "Directories", &total.get_dir_count().to_string());
+    push_summary_row(&mut html, "Scan errors", &indexer.get_scan_errors().len().to_string());
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Treemap</h2>\n");
+    push_treemap(&mut html, root, &usage, options);
+
+    html.push_str("<h2>Tree</h2>\n<div class=\"tree\">\n<ul>\n");
+    push_tree_entry(&mut html, root, 0, total.get_size(), &usage, options);
+    html.push_str("</ul>\n</div>\n");
+
+    let nodes = indexer.get_tree().get_rl2node_map();
+    let mut files: Vec<(u64, &PathBuf)> = nodes
+        .iter()
+        .filter(|(_, node)| node.get_kind() == DirEntryKind::File)
+        .map(|(rl_path, node)| (node.get_meta().get_size(), rl_path))
+        .collect();
+    files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    files.truncate(options.top_n_);
+    html.push_str("<h2>Largest files</h2>\n");
+    push_size_table(&mut html, "File", &files, total.get_size());
+
+    let mut dirs: Vec<(u64, &PathBuf)> = nodes
+        .iter()
+        .filter(|(rl_path, node)| node.get_kind() == DirEntryKind::Dir && !rl_path.as_os_str().is_empty())
+        .map(|(rl_path, _)| (usage.get(rl_path.as_path()).map_or(0, DirUsage::get_size), rl_path))
+        .collect();
+    dirs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    dirs.truncate(options.top_n_);
+    html.push_str("<h2>Largest directories</h2>\n");
+    push_size_table(&mut html, "Directory", &dirs, total.get_size());
+
+    let mut extensions: HashMap<String, ExtensionUsage> = HashMap::new();
+    for node in nodes.values().filter(|node| node.get_kind() == DirEntryKind::File) {
+        let extension = extension_of(node.get_entry());
+        let entry = extensions.entry(extension).or_default();
+        entry.files_ += 1;
+        entry.size_ += node.get_meta().get_size();
+    }
+    let mut extensions: Vec<(String, ExtensionUsage)> = extensions.into_iter().collect();
+    extensions.sort_by(|a, b| b.1.size_.cmp(&a.1.size_).then_with(|| a.0.cmp(&b.0)));
+    html.push_str("<h2>File types</h2>\n<table>\n");
+    html.push_str("<tr><th>Extension</th><th class=\"num\">Files</th><th class=\"num\">Size</th><th class=\"num\">Share</th><th></th></tr>\n");
+    for (extension, ext_usage) in &extensions {
+        let name = if extension.is_empty() { String::from("(none)") } else { format!(".{}", extension) };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\" title=\"{} bytes\">{}</td>{}</tr>\n",
+            escape(&name),
+            ext_usage.files_,
+            ext_usage.size_,
+            human_size(ext_usage.size_),
+            share_cells(ext_usage.size_, total.get_size())
+        ));
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>Scan errors</h2>\n");
+    if indexer.get_scan_errors().is_empty() {
+        html.push_str("<p>Every entry was indexed.</p>\n");
+    } else {
+        html.push_str("<table>\n<tr><th>Path</th><th>Reason</th></tr>\n");
+        for error in indexer.get_scan_errors() {
+            html.push_str(&format!(
+                "<tr><td class=\"path\">{}</td><td>{}</td></tr>\n",
+                escape(&display_path(error.get_path())),
+                escape(error.get_reason())
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    out.write_all(html.as_bytes())
+}
+
+fn push_summary_row(html: &mut String, name: &str, value: &str) {
+    html.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", name, value));
+}
+
+fn push_size_table(html: &mut String, heading: &str, rows: &[(u64, &PathBuf)], total: u64) {
+    html.push_str(&format!(
+        "<table>\n<tr><th>{}</th><th class=\"num\">Size</th><th class=\"num\">Share</th><th></th></tr>\n",
+        heading
+    ));
+    for (size, rl_path) in rows {
+        html.push_str(&format!(
+            "<tr><td class=\"path\">{}</td><td class=\"num\" title=\"{} bytes\">{}</td>{}</tr>\n",
+            escape(&display_path(rl_path)),
+            size,
+            human_size(*size),
+            share_cells(*size, total)
+        ));
+    }
+    html.push_str("</table>\n");
+}
+
+/// Returns the percentage of `total` and a bar of matching width as two table cells.
+fn share_cells(size: u64, total: u64) -> String {
+    let share = percent(size, total);
+    format!(
+        "<td class=\"num\">{:.1}%</td><td style=\"width:30%\"><span class=\"bar\" style=\"width:{:.1}%\"></span></td>",
+        share, share
+    )
+}
+
+fn percent(size: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        size as f64 * 100.0 / total as f64
+    }
+}
+
+/// Adds `node` to the tree, with its children sorted by size when it is a directory.
+fn push_tree_entry(
+    html: &mut String,
+    node: &DirNode,
+    depth: usize,
+    total: u64,
+    usage: &BTreeMap<PathBuf, DirUsage>,
+    options: &HtmlReportOptions,
+) {
+    let size = usage.get(node.get_entry()).map_or(0, DirUsage::get_size);
+    let mut name = if depth == 0 {
+        String::from(".")
+    } else {
+        node.get_name().to_string_lossy().into_owned()
+    };
+    if node.get_kind() == DirEntryKind::Dir {
+        name.push('/');
+    }
+    let line = format!(
+        "<span class=\"size\" title=\"{} bytes\">{}</span><span class=\"pct\"><span class=\"bar\" style=\"width:{:.1}%\"></span></span>{}",
+        size,
+        human_size(size),
+        percent(size, total),
+        escape(&name)
+    );
+
+    let children = sorted_by_size(node, usage);
+    let expandable = !children.is_empty() && options.max_depth_.map_or(true, |max_depth| depth < max_depth);
+    if !expandable {
+        html.push_str(&format!("<li>{}</li>\n", line));
+        return;
+    }
+    html.push_str(&format!(
+        "<li><details{}><summary>{}</summary>\n<ul>\n",
+        if depth == 0 { " open" } else { "" },
+        line
+    ));
+    for child in children {
+        push_tree_entry(html, child, depth + 1, total, usage, options);
+    }
+    html.push_str("</ul>\n</details></li>\n");
+}
+
+/// Returns the children of `node` from the largest to the smallest, ties in path order.
+fn sorted_by_size<'a>(node: &'a DirNode, usage: &BTreeMap<PathBuf, DirUsage>) -> Vec<&'a DirNode> {
+    let size_of = |node: &DirNode| usage.get(node.get_entry()).map_or(0, DirUsage::get_size);
+    let mut children = node.get_sorted_children();
+    children.sort_by_key(|child| Reverse(size_of(child)));
+    children
+}
+
+/// A rectangle of the treemap, in pixels.
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+fn push_treemap(html: &mut String, root: &DirNode, usage: &BTreeMap<PathBuf, DirUsage>, options: &HtmlReportOptions) {
+    html.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {w} {h}\" width=\"100%\" role=\"img\">\n",
+        w = TREEMAP_WIDTH,
+        h = TREEMAP_HEIGHT
+    ));
+    let area = Rect {
+        x: 0.0,
+        y: 0.0,
+        w: TREEMAP_WIDTH,
+        h: TREEMAP_HEIGHT,
+    };
+    push_treemap_children(html, root, area, 1, usage, options);
+    html.push_str("</svg>\n");
+}
+
+/// Lays out the children of `node` inside `area` and draws them, recursing into directories.
+fn push_treemap_children(
+    html: &mut String,
+    node: &DirNode,
+    area: Rect,
+    depth: usize,
+    usage: &BTreeMap<PathBuf, DirUsage>,
+    options: &HtmlReportOptions,
+) {
+    if options.max_depth_.map_or(false, |max_depth| depth > max_depth) || area.w < TREEMAP_MIN_SIDE || area.h < TREEMAP_MIN_SIDE {
+        return;
+    }
+    let children: Vec<(&DirNode, u64)> = sorted_by_size(node, usage)
+        .into_iter()
+        .map(|child| (child, usage.get(child.get_entry()).map_or(0, DirUsage::get_size)))
+        .filter(|(_, size)| *size > 0)
+        .collect();
+    let weights: Vec<f64> = children.iter().map(|(_, size)| *size as f64).collect();
+    for ((child, size), rect) in children.iter().zip(squarify(&weights, area)) {
+        if rect.w < 1.0 || rect.h < 1.0 {
+            continue;
+        }
+        let path = escape(&display_path(child.get_entry()));
+        let is_dir = child.get_kind() == DirEntryKind::Dir;
+        let fill = if is_dir {
+            String::from("#f4f4f4")
+        } else {
+            format!("hsl({},55%,68%)", hue_of(&extension_of(child.get_entry())))
+        };
+        html.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" stroke=\"#fff\"><title>{} ({})</title></rect>\n",
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            fill,
+            path,
+            human_size(*size)
+        ));
+
+        let name = child.get_name().to_string_lossy();
+        let fits_label = rect.w > 7.0 * name.chars().count().min(24) as f64 && rect.h > TREEMAP_LABEL_HEIGHT;
+        if fits_label {
+            html.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+                rect.x + 3.0,
+                rect.y + 11.0,
+                escape(&name)
+            ));
+        }
+        if is_dir {
+            let label_height = if fits_label { TREEMAP_LABEL_HEIGHT } else { 2.0 };
+            let inner = Rect {
+                x: rect.x + 2.0,
+                y: rect.y + label_height,
+                w: rect.w - 4.0,
+                h: rect.h - label_height - 2.0,
+            };
+            push_treemap_children(html, child, inner, depth + 1, usage, options);
+        }
+    }
+}
+
+/// Splits `area` into one rectangle per weight with the squarified treemap algorithm of Bruls,
+/// Huizing and van Wijk; `weights` must be positive and sorted from the largest.
+fn squarify(weights: &[f64], area: Rect) -> Vec<Rect> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 || area.w <= 0.0 || area.h <= 0.0 {
+        return Vec::new();
+    }
+    let scale = area.w * area.h / total;
+    let areas: Vec<f64> = weights.iter().map(|weight| weight * scale).collect();
+    let mut rects = Vec::with_capacity(areas.len());
+    let mut free = area;
+    let mut start = 0;
+    while start < areas.len() {
+        // Grow the row along the shorter side while that keeps its rectangles squarer
+        let side = free.w.min(free.h);
+        let mut end = start + 1;
+        let mut worst = worst_ratio(&areas[start..end], side);
+        while end < areas.len() {
+            let next = worst_ratio(&areas[start..end + 1], side);
+            if next > worst {
+                break;
+            }
+            worst = next;
+            end += 1;
+        }
+
+        let row_area: f64 = areas[start..end].iter().sum();
+        if free.w >= free.h {
+            let strip = row_area / free.h;
+            let mut y = free.y;
+            for area in &areas[start..end] {
+                let h = area / strip;
+                rects.push(Rect { x: free.x, y, w: strip, h });
+                y += h;
+            }
+            free.x += strip;
+            free.w = (free.w - strip).max(0.0);
+        } else {
+            let strip = row_area / free.w;
+            let mut x = free.x;
+            for area in &areas[start..end] {
+                let w = area / strip;
+                rects.push(Rect { x, y: free.y, w, h: strip });
+                x += w;
+            }
+            free.y += strip;
+            free.h = (free.h - strip).max(0.0);
+        }
+        start = end;
+    }
+    rects
+}
+
+/// Returns the largest aspect ratio among the rectangles of a row laid along a side of `side`.
+fn worst_ratio(row: &[f64], side: f64) -> f64 {
+    let sum: f64 = row.iter().sum();
+    let max = row.iter().cloned().fold(f64::MIN, f64::max);
+    let min = row.iter().cloned().fold(f64::MAX, f64::min);
+    let side_sq = side * side;
+    let sum_sq = sum * sum;
+    (side_sq * max / sum_sq).max(sum_sq / (side_sq * min))
+}
+
+/// Returns the lowercased extension of a file name, or an empty string if it has none.
+fn extension_of(rl_path: &Path) -> String {
+    rl_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Picks a stable hue for an extension, so files of one type share a colour.
+fn hue_of(extension: &str) -> u32 {
+    let hash = extension
+        .bytes()
+        .fold(2166136261u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(16777619));
+    hash % 360
+}
+
+fn display_path(rl_path: &Path) -> String {
+    if rl_path.as_os_str().is_empty() {
+        String::from(".")
+    } else {
+        rl_path.to_string_lossy().into_owned()
+    }
+}
+
+/// Escapes text for use in HTML content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::os::unix::fs::symlink;
+
+    fn sample() -> TempDir {
+        let dir = TempDir::new("html");
+        dir.write("src/main.rs", &[b'x'; 3000]);
+        dir.write("src/lib.RS", &[b'x'; 1000]);
+        dir.write("docs/<b>&\"notes\".md", &[b'x'; 500]);
+        dir.write("Makefile", &[b'x'; 100]);
+        dir.write("deep/er/data.bin", &[b'x'; 2000]);
+        symlink("missing", dir.join("dangling")).unwrap();
+        dir
+    }
+
+    fn report(dir: &TempDir, options: &HtmlReportOptions) -> String {
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let mut out = Vec::new();
+        indexer.write_html_report(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Returns the part of `html` from `start` up to the next `end`.
+    fn section<'a>(html: &'a str, start: &str, end: &str) -> &'a str {
+        let from = html.find(start).unwrap();
+        let to = from + html[from..].find(end).unwrap();
+        &html[from..to]
+    }
+
+    #[test]
+    fn summarizes_the_index() {
+        let dir = sample();
+        let html = report(&dir, &HtmlReportOptions::new().with_title("Usage of <x> & y"));
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"en\">\n"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+        assert!(html.contains("<title>Usage of &lt;x&gt; &amp; y</title>"));
+        assert!(html.contains("<h1>Usage of &lt;x&gt; &amp; y</h1>"));
+        assert!(html.contains("<tr><td>Total size</td><td>6.4K (6600 bytes)</td></tr>"));
+        assert!(html.contains("<tr><td>Files</td><td>5</td></tr>"));
+        assert!(html.contains("<tr><td>Directories</td><td>4</td></tr>"));
+        assert!(html.contains("<tr><td>Scan errors</td><td>1</td></tr>"));
+        assert!(html.contains(&format!("<tr><td>Root</td><td>{}</td></tr>", dir.get_path().display())));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn lists_the_largest_entries_and_file_types() {
+        let dir = sample();
+        let html = report(&dir, &HtmlReportOptions::new().with_top_n(3));
+        let files = section(&html, "<h2>Largest files</h2>", "</table>");
+        let rows: Vec<&str> = files.lines().filter(|line| line.starts_with("<tr><td")).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].starts_with("<tr><td class=\"path\">src/main.rs</td><td class=\"num\" title=\"3000 bytes\">2.9K</td><td class=\"num\">45.5%</td>"));
+        assert!(rows[1].starts_with("<tr><td class=\"path\">deep/er/data.bin</td>"));
+        assert!(rows[2].starts_with("<tr><td class=\"path\">src/lib.RS</td>"));
+
+        let dirs = section(&html, "<h2>Largest directories</h2>", "</table>");
+        let rows: Vec<&str> = dirs.lines().filter(|line| line.starts_with("<tr><td")).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].starts_with("<tr><td class=\"path\">src</td><td class=\"num\" title=\"4000 bytes\">"));
+        assert!(rows[1].starts_with("<tr><td class=\"path\">deep</td>"));
+        assert!(rows[2].starts_with("<tr><td class=\"path\">deep/er</td>"));
+
+        let types = section(&html, "<h2>File types</h2>", "</table>");
+        let names: Vec<&str> = types
+            .lines()
+            .filter_map(|line| line.strip_prefix("<tr><td>"))
+            .map(|line| &line[..line.find('<').unwrap()])
+            .collect();
+        assert_eq!(names, vec![".rs", ".bin", ".md", "(none)"]);
+        assert!(types.contains("<tr><td>.rs</td><td class=\"num\">2</td><td class=\"num\" title=\"4000 bytes\">3.9K</td>"));
+    }
+
+    #[test]
+    fn escapes_names_everywhere() {
+        let dir = sample();
+        let html = report(&dir, &HtmlReportOptions::new());
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("docs/&lt;b&gt;&amp;&quot;notes&quot;.md"));
+        assert!(html.contains("&lt;b&gt;&amp;&quot;notes&quot;.md</li>"));
+        assert_eq!(escape("<a href='x'>&</a>"), "&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn reports_scan_errors() {
+        let dir = sample();
+        let html = report(&dir, &HtmlReportOptions::new());
+        let errors = section(&html, "<h2>Scan errors</h2>", "</table>");
+        assert!(errors.contains("<tr><td class=\"path\">dangling</td><td>dangling symbolic link</td></tr>"));
+
+        std::fs::remove_file(dir.join("dangling")).unwrap();
+        let html = report(&dir, &HtmlReportOptions::new());
+        assert!(html.contains("<h2>Scan errors</h2>\n<p>Every entry was indexed.</p>\n"));
+    }
+
+    #[test]
+    fn draws_the_tree_by_size() {
+        let dir = sample();
+        let html = report(&dir, &HtmlReportOptions::new());
+        let tree = section(&html, "<div class=\"tree\">", "</div>");
+        assert!(tree.contains("<li><details open><summary><span class=\"size\" title=\"6600 bytes\">6.4K</span>"));
+        let order: Vec<usize> = ["src/</summary>", "deep/</summary>", "docs/</summary>", "Makefile</li>"]
+            .iter()
+            .map(|name| tree.find(name).unwrap())
+            .collect();
+        assert!(order.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(tree.contains("main.rs</li>"));
+
+        let html = report(&dir, &HtmlReportOptions::new().with_max_depth(1));
+        let tree = section(&html, "<div class=\"tree\">", "</div>");
+        assert!(tree.contains("src/</li>"));
+        assert!(!tree.contains("main.rs"));
+        let treemap = section(&html, "<svg", "</svg>");
+        assert!(treemap.contains("<title>src (3.9K)</title>"));
+        assert!(!treemap.contains("main.rs"));
+    }
+
+    #[test]
+    fn treemaps_show_every_nonempty_entry() {
+        let dir = sample();
+        dir.write("empty.txt", b"");
+        let html = report(&dir, &HtmlReportOptions::new());
+        let treemap = section(&html, "<svg", "</svg>");
+        for title in ["src (3.9K)", "src/main.rs (2.9K)", "deep/er/data.bin (2.0K)", "Makefile (100)"].iter() {
+            assert!(treemap.contains(&format!("<title>{}</title>", title)), "{}", title);
+        }
+        assert!(!treemap.contains("empty.txt"));
+        assert!(!treemap.contains("dangling"));
+    }
+
+    #[test]
+    fn squarified_rectangles_tile_the_area() {
+        let area = Rect { x: 10.0, y: 20.0, w: 300.0, h: 200.0 };
+        let weights = [50.0, 30.0, 30.0, 20.0, 10.0, 5.0, 1.0, 1.0];
+        let rects = squarify(&weights, area);
+        assert_eq!(rects.len(), weights.len());
+        let total: f64 = weights.iter().sum();
+        for (rect, weight) in rects.iter().zip(weights.iter()) {
+            assert!((rect.w * rect.h - weight / total * area.w * area.h).abs() < 1e-6);
+            assert!(rect.x >= area.x - 1e-9 && rect.y >= area.y - 1e-9);
+            assert!(rect.x + rect.w <= area.x + area.w + 1e-6 && rect.y + rect.h <= area.y + area.h + 1e-6);
+        }
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                let overlap_w = (a.x + a.w).min(b.x + b.w) - a.x.max(b.x);
+                let overlap_h = (a.y + a.h).min(b.y + b.h) - a.y.max(b.y);
+                assert!(overlap_w <= 1e-6 || overlap_h <= 1e-6, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        assert!(squarify(&[], area).is_empty());
+        assert!(squarify(&[1.0], Rect { x: 0.0, y: 0.0, w: 0.0, h: 5.0 }).is_empty());
+        assert!((worst_ratio(&[4.0], 2.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn colours_and_extensions_are_stable() {
+        assert_eq!(extension_of(Path::new("a/B.TXT")), "txt");
+        assert_eq!(extension_of(Path::new("Makefile")), "");
+        assert_eq!(extension_of(Path::new(".bashrc")), "");
+        assert_eq!(hue_of("rs"), hue_of("rs"));
+        assert!(hue_of("rs") < 360);
+        assert_eq!(display_path(Path::new("")), ".");
+    }
+}
@@ -0,0 +1,448 @@
+//! Compact versioned binary format for saved indexes.
+//!
+//! Layout, all integers as LEB128 varints unless noted:
+//!
+//! * magic `DIRIDX\0\n` (8 bytes) and format version (`u32`, little endian)
+//! * root path, scan time (zigzag seconds and nanoseconds since the Unix epoch), option flags
+//! * node count, then every node in pre-order: name, kind, metadata fields, link target (length
+//!   plus one, zero when absent) and child count
+//! * XXH64 of everything above (`u64`, little endian)
+
+use super::{hash_bytes, DirEntryKind, DirIndexer, DirIndexerErr, DirMeta, DirNode, DirTree, HashAlgo};
+use super::codec::{self, ByteReader, ByteWriter};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the start of every index file.
+pub(crate) const INDEX_MAGIC: &[u8; 8] = b"DIRIDX\0\n";
+
+/// Current version of the index file format.
+pub(crate) const INDEX_VERSION: u32 = 1;
+
+/// Option flag: symbolic links to directories were followed while scanning.
+pub(crate) const FLAG_FOLLOW_SYMLINKS: u64 = 1;
+
+/// Smallest possible encoded node: empty name, kind, nine metadata fields, link and child count.
+const MIN_NODE_LEN: usize = 13;
+
+/// Writes an index to `index_path` through a temporary file.
+pub(crate) fn save_index(indexer: &DirIndexer, index_path: &Path) -> Result<(), DirIndexerErr> {
+    let bytes = encode_index(indexer);
+    write_atomically(index_path, &bytes)
+}
+
+/// Reads and validates an index from `index_path`.
+pub(crate) fn load_index(index_path: &Path) -> Result<DirIndexer, DirIndexerErr> {
+    let bytes = fs::read(index_path).map_err(|_| DirIndexerErr::IndexIoFail(index_path.to_path_buf()))?;
+    decode_index(&bytes).map_err(|err| match err {
+        DecodeErr::Version(version) => DirIndexerErr::IncompatibleIndexVersion(version),
+        DecodeErr::Corrupt => DirIndexerErr::CorruptIndex(index_path.to_path_buf()),
+    })
+}
+
+/// Writes `bytes` to a sibling temporary file and renames it over `path`.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), DirIndexerErr> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".tmp{}", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+    let result = fs::write(&tmp_path, bytes).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(DirIndexerErr::IndexIoFail(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Reasons a buffer could not be decoded.
+#[derive(Debug)]
+enum DecodeErr {
+    Version(u32),
+    Corrupt,
+}
+
+fn encode_index(indexer: &DirIndexer) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+    writer.put_raw(INDEX_MAGIC);
+    writer.put_u32_le(INDEX_VERSION);
+    writer.put_bytes(codec::path_to_bytes(indexer.get_root_path()));
+    let (secs, nanos) = time_to_parts(indexer.get_scan_time());
+    writer.put_varint_signed(secs);
+    writer.put_varint(nanos as u64);
+    writer.put_varint(FLAG_FOLLOW_SYMLINKS);
+
+    let root_node = indexer.get_tree().get_root_node();
+    writer.put_varint(count_nodes(root_node) as u64);
+    let mut stack = vec![root_node];
+    while let Some(node) = stack.pop() {
+        encode_node(&mut writer, node);
+        // Reverse so that children come out of the stack in sorted order
+        let mut children = node.get_sorted_children();
+        children.reverse();
+        stack.extend(children);
+    }
+
+    let checksum = checksum(writer.as_slice());
+    writer.put_u64_le(checksum);
+    writer.into_vec()
+}
+
+fn encode_node(writer: &mut ByteWriter, node: &DirNode) {
+    writer.put_bytes(codec::path_to_bytes(Path::new(node.get_name())));
+    writer.put_u8(kind_to_u8(node.get_kind()));
+    let fields = node.get_meta().to_fields();
+    for (i, field) in fields.iter().enumerate() {
+        if i == 1 {
+            writer.put_varint_signed(*field as i64);
+        } else {
+            writer.put_varint(*field);
+        }
+    }
+    match node.get_link_target() {
+        Some(target) => {
+            let bytes = codec::path_to_bytes(target);
+            writer.put_varint(bytes.len() as u64 + 1);
+            writer.put_raw(bytes);
+        }
+        None => writer.put_varint(0),
+    }
+    writer.put_varint(node.get_children().count() as u64);
+}
+
+fn decode_index(bytes: &[u8]) -> Result<DirIndexer, DecodeErr> {
+    if bytes.len() < INDEX_MAGIC.len() + 4 + 8 || &bytes[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+        return Err(DecodeErr::Corrupt);
+    }
+    let mut reader = ByteReader::from(&bytes[INDEX_MAGIC.len()..]);
+    let version = reader.get_u32_le().ok_or(DecodeErr::Corrupt)?;
+    if version != INDEX_VERSION {
+        return Err(DecodeErr::Version(version));
+    }
+    let (body, trailer) = bytes.split_at(bytes.len() - 8);
+    let mut trailer_reader = ByteReader::from(trailer);
+    if trailer_reader.get_u64_le() != Some(checksum(body)) {
+        return Err(DecodeErr::Corrupt);
+    }
+
+    let mut reader = ByteReader::from(&body[INDEX_MAGIC.len() + 4..]);
+    let root_path = reader.get_bytes().and_then(codec::bytes_to_path).ok_or(DecodeErr::Corrupt)?;
+    if !root_path.is_absolute() {
+        return Err(DecodeErr::Corrupt);
+    }
+    let secs = reader.get_varint_signed().ok_or(DecodeErr::Corrupt)?;
+    let nanos = reader.get_varint().ok_or(DecodeErr::Corrupt)?;
+    let scan_time = time_from_parts(secs, nanos).ok_or(DecodeErr::Corrupt)?;
+    // Options this version does not know would change what the index means
+    let flags = reader.get_varint().ok_or(DecodeErr::Corrupt)?;
+    if flags & !FLAG_FOLLOW_SYMLINKS != 0 {
+        return Err(DecodeErr::Corrupt);
+    }
+
+    let node_count = reader.get_len(reader.remaining() / MIN_NODE_LEN).ok_or(DecodeErr::Corrupt)?;
+    let root_node = decode_nodes(&mut reader, node_count).ok_or(DecodeErr::Corrupt)?;
+    if reader.remaining() != 0 {
+        return Err(DecodeErr::Corrupt);
+    }
+    Ok(DirIndexer::from_parts(root_path, DirTree::from_root_node(root_node), scan_time))
+}
+
+/// A node whose children are still being decoded.
+struct PendingNode {
+    entry: PathBuf,
+    kind: DirEntryKind,
+    meta: DirMeta,
+    link_target: Option<PathBuf>,
+    remaining: usize,
+    children: HashSet<DirNode>,
+}
+
+impl PendingNode {
+    fn finish(self) -> DirNode {
+        DirNode::from_parts(self.entry, self.kind, self.meta, self.link_target, self.children)
+    }
+}
+
+/// Decodes `node_count` pre-order nodes without recursion, so deep or hostile input cannot
+/// overflow the stack.
+fn decode_nodes(reader: &mut ByteReader, node_count: usize) -> Option<DirNode> {
+    let mut stack: Vec<PendingNode> = Vec::new();
+    for index in 0..node_count {
+        let name = reader.get_bytes()?;
+        let parent_entry = match stack.last() {
+            Some(parent) => parent.entry.clone(),
+            None if index == 0 => PathBuf::new(),
+            None => return None,
+        };
+        let entry = if index == 0 {
+            if !name.is_empty() {
+                return None;
+            }
+            PathBuf::new()
+        } else {
+            parent_entry.join(valid_name(name)?)
+        };
+        let kind = kind_from_u8(reader.get_u8()?)?;
+        let mut fields = [0u64; 9];
+        for (i, field) in fields.iter_mut().enumerate() {
+            *field = if i == 1 {
+                reader.get_varint_signed()? as u64
+            } else {
+                reader.get_varint()?
+            };
+        }
+        let meta = DirMeta::from_fields(&fields)?;
+        let link_target = match reader.get_len(reader.remaining() + 1)? {
+            0 => None,
+            len => Some(codec::bytes_to_path(reader.get_raw(len - 1)?)?),
+        };
+        let child_count = reader.get_len(node_count - index - 1)?;
+
+        let mut node = PendingNode {
+            entry,
+            kind,
+            meta,
+            link_target,
+            remaining: child_count,
+            children: HashSet::new(),
+        };
+        if let Some(parent) = stack.last_mut() {
+            parent.remaining -= 1;
+        }
+        // Attach finished nodes to their parents until one still expects children
+        loop {
+            if node.remaining > 0 {
+                stack.push(node);
+                break;
+            }
+            let done = node.finish();
+            match stack.pop() {
+                Some(mut parent) => {
+                    if !parent.children.insert(done) {
+                        return None;
+                    }
+                    node = parent;
+                }
+                None => {
+                    return if index + 1 == node_count { Some(done) } else { None };
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Accepts a node name only if it is a single normal path component.
+fn valid_name(name: &[u8]) -> Option<PathBuf> {
+    let path = codec::bytes_to_path(name)?;
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(&0) => Some(path),
+        _ => None,
+    }
+}
+
+pub(crate) fn kind_to_u8(kind: DirEntryKind) -> u8 {
+    match kind {
+        DirEntryKind::File => 0,
+        DirEntryKind::Dir => 1,
+        DirEntryKind::Symlink => 2,
+        DirEntryKind::Other => 3,
+    }
+}
+
+pub(crate) fn kind_from_u8(value: u8) -> Option<DirEntryKind> {
+    match value {
+        0 => Some(DirEntryKind::File),
+        1 => Some(DirEntryKind::Dir),
+        2 => Some(DirEntryKind::Symlink),
+        3 => Some(DirEntryKind::Other),
+        _ => None,
+    }
+}
+
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    let hash = hash_bytes(HashAlgo::Xxh64, bytes);
+    let mut digest = [0u8; 8];
+    digest.copy_from_slice(hash.get_digest());
+    u64::from_be_bytes(digest)
+}
+
+pub(crate) fn count_nodes(node: &DirNode) -> usize {
+    1 + node.get_children().map(count_nodes).sum::<usize>()
+}
+
+pub(crate) fn time_to_parts(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            if d.subsec_nanos() == 0 {
+                (-(d.as_secs() as i64), 0)
+            } else {
+                (-(d.as_secs() as i64) - 1, 1_000_000_000 - d.subsec_nanos())
+            }
+        }
+    }
+}
+
+pub(crate) fn time_from_parts(secs: i64, nanos: u64) -> Option<SystemTime> {
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos as u32))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|t| t.checked_add(Duration::from_nanos(nanos)))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    fn sample_tree(dir: &TempDir) -> DirIndexer {
+        dir.write("a.txt", b"a");
+        dir.write("sub/deeper/b.bin", &[0, 1, 2]);
+        dir.write(Path::new(OsStr::from_bytes(b"not\xffutf8")), b"raw");
+        symlink("sub/deeper", dir.join("link")).unwrap();
+        symlink("missing", dir.join("dangling")).unwrap();
+        DirIndexer::from(dir.get_path().clone()).unwrap()
+    }
+
+    #[test]
+    fn saved_indexes_load_back_unchanged() {
+        let dir = TempDir::new("index-roundtrip");
+        let indexer = sample_tree(&dir);
+        let out = TempDir::new("index-roundtrip-out");
+        let index_path = out.join("tree.idx");
+        indexer.save(&index_path).unwrap();
+
+        let loaded = DirIndexer::load(&index_path).unwrap();
+        assert_eq!(loaded.get_root_path(), indexer.get_root_path());
+        assert_eq!(loaded.get_scan_time(), indexer.get_scan_time());
+        assert!(loaded.get_tree().get_root_node() == indexer.get_tree().get_root_node());
+        let link = loaded.get_tree().get_node(Path::new("link")).unwrap();
+        assert_eq!(link.get_link_target(), Some(&PathBuf::from("sub/deeper")));
+        assert!(loaded.get_tree().get_node(Path::new(OsStr::from_bytes(b"not\xffutf8"))).is_some());
+    }
+
+    #[test]
+    fn encoding_is_deterministic() {
+        let dir = TempDir::new("index-deterministic");
+        let indexer = sample_tree(&dir);
+        let bytes = encode_index(&indexer);
+        assert_eq!(bytes, encode_index(&indexer));
+        let decoded = decode_index(&bytes).unwrap();
+        assert_eq!(encode_index(&decoded), bytes);
+    }
+
+    #[test]
+    fn every_truncation_and_bit_flip_is_rejected() {
+        let dir = TempDir::new("index-corrupt");
+        let bytes = encode_index(&sample_tree(&dir));
+        for len in 0..bytes.len() {
+            assert!(decode_index(&bytes[..len]).is_err(), "truncated to {}", len);
+        }
+        for i in 0..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[i] ^= 0x10;
+            assert!(decode_index(&flipped).is_err(), "flipped byte {}", i);
+        }
+    }
+
+    #[test]
+    fn other_versions_are_reported_as_such() {
+        let dir = TempDir::new("index-version");
+        let mut bytes = encode_index(&sample_tree(&dir));
+        bytes[INDEX_MAGIC.len()..INDEX_MAGIC.len() + 4].copy_from_slice(&7u32.to_le_bytes());
+        let out = TempDir::new("index-version-out");
+        let index_path = out.write("tree.idx", &bytes);
+        assert!(matches!(DirIndexer::load(&index_path), Err(DirIndexerErr::IncompatibleIndexVersion(7))));
+        let index_path = out.write("tree.idx", b"not an index at all");
+        assert!(matches!(DirIndexer::load(&index_path), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(DirIndexer::load(&out.join("missing.idx")), Err(DirIndexerErr::IndexIoFail(_))));
+    }
+
+    /// Builds an index holding a root directory with the given children, each written as
+    /// `(name, kind, child count)` in pre-order, with a valid checksum.
+    fn crafted(node_count: u64, children: &[(&[u8], u8, u64)]) -> Vec<u8> {
+        crafted_with_flags(0, node_count, children)
+    }
+
+    /// Like `crafted`, with the given option flags.
+    fn crafted_with_flags(flags: u64, node_count: u64, children: &[(&[u8], u8, u64)]) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.put_raw(INDEX_MAGIC);
+        writer.put_u32_le(INDEX_VERSION);
+        writer.put_bytes(b"/root");
+        writer.put_varint_signed(0);
+        writer.put_varint(0);
+        writer.put_varint(flags);
+        writer.put_varint(node_count);
+        let mut put_node = |name: &[u8], kind: u8, child_count: u64| {
+            writer.put_bytes(name);
+            writer.put_u8(kind);
+            for _ in 0..9 {
+                writer.put_varint(0);
+            }
+            writer.put_varint(0);
+            writer.put_varint(child_count);
+        };
+        put_node(b"", 1, children.len() as u64);
+        for (name, kind, child_count) in children {
+            put_node(name, *kind, *child_count);
+        }
+        let sum = checksum(writer.as_slice());
+        writer.put_u64_le(sum);
+        writer.into_vec()
+    }
+
+    #[test]
+    fn unknown_option_flags_are_rejected() {
+        assert!(decode_index(&crafted_with_flags(FLAG_FOLLOW_SYMLINKS, 1, &[])).is_ok());
+        for flags in [2, 4, FLAG_FOLLOW_SYMLINKS | 8, 1 << 40].iter() {
+            assert!(matches!(decode_index(&crafted_with_flags(*flags, 1, &[])), Err(DecodeErr::Corrupt)), "flags {}", flags);
+        }
+        let out = TempDir::new("index-flags");
+        let index_path = out.write("tree.idx", &crafted_with_flags(2, 1, &[]));
+        assert!(matches!(DirIndexer::load(&index_path), Err(DirIndexerErr::CorruptIndex(_))));
+    }
+
+    #[test]
+    fn hostile_node_lists_are_rejected() {
+        assert!(decode_index(&crafted(2, &[(b"a", 0, 0)])).is_ok());
+        for name in [&b".."[..], b".", b"a/b", b"", b"/", b"a\0"] {
+            assert!(decode_index(&crafted(2, &[(name, 0, 0)])).is_err(), "{:?}", name);
+        }
+        // Duplicate names, an unknown kind, and counts that disagree with the nodes present
+        assert!(decode_index(&crafted(3, &[(b"a", 0, 0), (b"a", 0, 0)])).is_err());
+        assert!(decode_index(&crafted(2, &[(b"a", 9, 0)])).is_err());
+        assert!(decode_index(&crafted(3, &[(b"a", 0, 0)])).is_err());
+        assert!(decode_index(&crafted(1, &[(b"a", 0, 0)])).is_err());
+        assert!(decode_index(&crafted(u64::MAX, &[(b"a", 0, 0)])).is_err());
+        assert!(decode_index(&crafted(2, &[(b"a", 1, 5)])).is_err());
+    }
+
+    #[test]
+    fn times_round_trip_through_parts() {
+        for time in [
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            UNIX_EPOCH - Duration::new(1, 0),
+            UNIX_EPOCH - Duration::new(86_400, 1),
+        ] {
+            let (secs, nanos) = time_to_parts(time);
+            assert!(nanos < 1_000_000_000);
+            assert_eq!(time_from_parts(secs, nanos as u64), Some(time));
+        }
+        assert_eq!(time_to_parts(UNIX_EPOCH - Duration::new(0, 1)), (-1, 999_999_999));
+        assert_eq!(time_from_parts(0, 1_000_000_000), None);
+    }
+}
@@ -0,0 +1,391 @@
+use super::{DaemonClient, DirEntryKind, DirIndexer, DirIndexerErr, DirMeta, DirNode, DirQuery, FuzzyMatch, FuzzyOptions};
+use super::codec;
+use super::dir_usage;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// One indexed entry, as returned by `IndexSource::lookup` and `DaemonClient::lookup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    rl_path_: PathBuf,
+    kind_: DirEntryKind,
+    meta_: DirMeta,
+    link_target_: Option<PathBuf>,
+}
+
+impl IndexEntry {
+    pub(crate) fn new(rl_path: PathBuf, kind: DirEntryKind, meta: DirMeta, link_target: Option<PathBuf>) -> IndexEntry {
+        IndexEntry {
+            rl_path_: rl_path,
+            kind_: kind,
+            meta_: meta,
+            link_target_: link_target,
+        }
+    }
+
+    /// Returns the relative path of the entry; the root has an empty path.
+    pub fn get_rl_path(&self) -> &PathBuf {
+        &self.rl_path_
+    }
+
+    /// Returns the kind of the entry.
+    pub fn get_kind(&self) -> DirEntryKind {
+        self.kind_
+    }
+
+    /// Returns the metadata recorded for the entry.
+    pub fn get_meta(&self) -> &DirMeta {
+        &self.meta_
+    }
+
+    /// Returns the target of the entry if it is a symbolic link.
+    pub fn get_link_target(&self) -> Option<&PathBuf> {
+        self.link_target_.as_ref()
+    }
+}
+
+/// Totals over a whole index, as returned by `IndexSource::get_stats` and
+/// `DaemonClient::get_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexStats {
+    file_count_: u64,
+    dir_count_: u64,
+    entry_count_: u64,
+    size_: u64,
+    scan_time_: SystemTime,
+    scan_error_count_: u64,
+}
+
+impl IndexStats {
+    pub(crate) fn new(counts: [u64; 4], scan_time: SystemTime, scan_error_count: u64) -> IndexStats {
+        IndexStats {
+            file_count_: counts[0],
+            dir_count_: counts[1],
+            entry_count_: counts[2],
+            size_: counts[3],
+            scan_time_: scan_time,
+            scan_error_count_: scan_error_count,
+        }
+    }
+
+    /// Returns the number of regular files.
+    pub fn get_file_count(&self) -> u64 {
+        self.file_count_
+    }
+
+    /// Returns the number of directories below the root.
+    pub fn get_dir_count(&self) -> u64 {
+        self.dir_count_
+    }
+
+    /// Returns the number of entries of any kind below the root.
+    pub fn get_entry_count(&self) -> u64 {
+        self.entry_count_
+    }
+
+    /// Returns the total size in bytes, counted as `DirUsage` does.
+    pub fn get_size(&self) -> u64 {
+        self.size_
+    }
+
+    /// Returns the time the index was last scanned.
+    pub fn get_scan_time(&self) -> SystemTime {
+        self.scan_time_
+    }
+
+    /// Returns the number of entries the last scan had to leave out.
+    pub fn get_scan_error_count(&self) -> u64 {
+        self.scan_error_count_
+    }
+}
+
+/// An index answered by a running `IndexDaemon` when one serves the root, or by a local scan
+/// otherwise.
+///
+/// Both variants give the same answers for the same tree. Should the daemon go away, the next
+/// query scans the root locally and the source stays local from then on.
+#[derive(Debug)]
+pub enum IndexSource {
+    /// Queries go to a daemon serving the root at the given absolute path.
+    Daemon(DaemonClient, PathBuf),
+    /// Queries are answered from an index held in this process.
+    Local(DirIndexer),
+}
+
+impl IndexSource {
+    /// Connects to the daemon listening on `socket_path` if it serves `root_path`, and indexes
+    /// `root_path` locally if it does not or if no daemon is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `root_path` - The directory to index.
+    /// * `socket_path` - The socket of the daemon, usually `default_socket_path()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `IndexSource`, or the `DirIndexerErr` of the local scan if it
+    /// fails.
+    pub fn open(root_path: &Path, socket_path: &Path) -> Result<IndexSource, DirIndexerErr> {
+        let ab_path = match fs::canonicalize(root_path) {
+            Ok(ab_path) => ab_path,
+            Err(_) => return Err(DirIndexerErr::NotDirNorExist(root_path.to_path_buf())),
+        };
+        if let Ok(mut client) = DaemonClient::connect(socket_path) {
+            if client.get_roots().map_or(false, |roots| roots.contains(&ab_path)) {
+                return Ok(IndexSource::Daemon(client, ab_path));
+            }
+        }
+        Ok(IndexSource::Local(DirIndexer::from(ab_path)?))
+    }
+
+    /// Returns `true` if queries are answered by a daemon.
+    pub fn is_daemon(&self) -> bool {
+        matches!(self, IndexSource::Daemon(..))
+    }
+
+    /// Returns the absolute path of the indexed root.
+    pub fn get_root_path(&self) -> &PathBuf {
+        match self {
+            IndexSource::Daemon(_, root_path) => root_path,
+            IndexSource::Local(indexer) => indexer.get_root_path(),
+        }
+    }
+
+    /// Returns totals over the whole index.
+    pub fn get_stats(&mut self) -> Result<IndexStats, DirIndexerErr> {
+        self.query(|client, root_path| client.get_stats(root_path), |indexer| Ok(index_stats(indexer)))
+    }
+
+    /// Lists the relative paths of the entries of one kind, or of every entry but the root if
+    /// `kind` is `None`, sorted.
+    pub fn list(&mut self, kind: Option<DirEntryKind>) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        self.query(|client, root_path| client.list(root_path, kind), |indexer| Ok(list_paths(indexer, kind)))
+    }
+
+    /// Looks up one entry by its relative path; an empty path refers to the root.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the entry, or `None` if it is not indexed.
+    pub fn lookup(&mut self, rl_path: &Path) -> Result<Option<IndexEntry>, DirIndexerErr> {
+        self.query(|client, root_path| client.lookup(root_path, rl_path), |indexer| Ok(lookup_entry(indexer, rl_path)))
+    }
+
+    /// Finds the entries matching a query in the syntax of `DirQuery::parse`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the relative paths of the matches, sorted, or
+    /// `DirIndexerErr::QueryParseFail` if the query is invalid.
+    pub fn find(&mut self, query: &str) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        self.query(|client, root_path| client.find(root_path, query), |indexer| find_paths(indexer, query))
+    }
+
+    /// Finds the entries whose relative paths best match a fuzzy query, as
+    /// `DirIndexer::fuzzy_find` does. A daemon ignores the thread count of `options`.
+    pub fn fuzzy_find(&mut self, query: &str, options: &FuzzyOptions) -> Result<Vec<FuzzyMatch>, DirIndexerErr> {
+        self.query(
+            |client, root_path| client.fuzzy_find(root_path, query, options),
+            |indexer| Ok(indexer.fuzzy_find(query, options)),
+        )
+    }
+
+    /// Rescans the root now rather than waiting for the daemon to do so.
+    pub fn refresh(&mut self) -> Result<(), DirIndexerErr> {
+        match self {
+            IndexSource::Daemon(client, root_path) => match client.refresh(root_path) {
+                Err(DirIndexerErr::DaemonIoFail(_)) => {
+                    *self = IndexSource::Local(DirIndexer::from(root_path.clone())?);
+                    Ok(())
+                }
+                result => result,
+            },
+            IndexSource::Local(indexer) => indexer.refresh().map(|_| ()),
+        }
+    }
+
+    /// Answers a query with `remote` from the daemon, or with `local` from the local index. If the
+    /// daemon cannot be reached, the root is scanned and kept locally.
+    fn query<T, R, L>(&mut self, remote: R, local: L) -> Result<T, DirIndexerErr>
+    where
+        R: FnOnce(&mut DaemonClient, &Path) -> Result<T, DirIndexerErr>,
+        L: FnOnce(&DirIndexer) -> Result<T, DirIndexerErr>,
+    {
+        let root_path = match self {
+            IndexSource::Local(indexer) => return local(indexer),
+            IndexSource::Daemon(client, root_path) => match remote(client, root_path) {
+                Err(DirIndexerErr::DaemonIoFail(_)) => root_path.clone(),
+                result => return result,
+            },
+        };
+        let indexer = DirIndexer::from(root_path)?;
+        let result = local(&indexer);
+        *self = IndexSource::Local(indexer);
+        result
+    }
+}
+
+/// Computes the totals of `indexer`.
+pub(crate) fn index_stats(indexer: &DirIndexer) -> IndexStats {
+    let usage = dir_usage::add_usage(indexer.get_tree().get_root_node(), &mut BTreeMap::new());
+    let counts = [usage.get_file_count(), usage.get_dir_count(), usage.get_entry_count(), usage.get_size()];
+    IndexStats::new(counts, indexer.get_scan_time(), indexer.get_scan_errors().len() as u64)
+}
+
+/// Lists the relative paths of the entries of `indexer` of one kind, or of every kind.
+pub(crate) fn list_paths(indexer: &DirIndexer, kind: Option<DirEntryKind>) -> Vec<PathBuf> {
+    indexer
+        .get_tree()
+        .get_rl2node_map()
+        .into_iter()
+        .filter(|(rl_path, node)| !rl_path.as_os_str().is_empty() && kind.map_or(true, |kind| node.get_kind() == kind))
+        .map(|(rl_path, _)| rl_path)
+        .collect()
+}
+
+/// Looks up one entry of `indexer`; paths such as `./a` are normalized first.
+pub(crate) fn lookup_entry(indexer: &DirIndexer, rl_path: &Path) -> Option<IndexEntry> {
+    let rl_path = codec::normalize_relative(rl_path)?;
+    let node = indexer.get_tree().get_node(&rl_path)?;
+    Some(IndexEntry::new(rl_path, node.get_kind(), *node.get_meta(), node.get_link_target().cloned()))
+}
+
+/// Runs a query in the syntax of `DirQuery::parse` against `indexer`.
+pub(crate) fn find_paths(indexer: &DirIndexer, query: &str) -> Result<Vec<PathBuf>, DirIndexerErr> {
+    let query = DirQuery::parse(query)?;
+    Ok(indexer.find(&query).into_iter().map(DirNode::get_entry).cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::daemon::start_daemon;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    fn sample(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        dir.write("src/main.rs", b"fn main() {}");
+        dir.write("src/lib.rs", b"");
+        dir.write("docs/read me.txt", b"docs");
+        dir.write("tab\there.txt", b"tab");
+        symlink("src/main.rs", dir.join("main")).unwrap();
+        symlink("nowhere", dir.join("dangling")).unwrap();
+        dir
+    }
+
+    fn fuzzy_rows(source: &mut IndexSource, query: &str, options: &FuzzyOptions) -> Vec<(PathBuf, i32, Vec<usize>)> {
+        let matches = source.fuzzy_find(query, options).unwrap();
+        matches.iter().map(|m| (m.get_path().clone(), m.get_score(), m.get_positions().to_vec())).collect()
+    }
+
+    #[test]
+    fn daemon_and_local_sources_agree() {
+        let dir = sample("source-agree");
+        let run_dir = TempDir::new("source-agree-run");
+        let socket_path = start_daemon(dir.get_path(), &run_dir);
+        let mut daemon = IndexSource::open(dir.get_path(), &socket_path).unwrap();
+        let mut local = IndexSource::open(dir.get_path(), &run_dir.join("no-such-sock")).unwrap();
+        assert!(daemon.is_daemon());
+        assert!(!local.is_daemon());
+        assert_eq!(daemon.get_root_path(), local.get_root_path());
+
+        let (daemon_stats, local_stats) = (daemon.get_stats().unwrap(), local.get_stats().unwrap());
+        assert_eq!(
+            (daemon_stats.get_file_count(), daemon_stats.get_dir_count(), daemon_stats.get_entry_count(), daemon_stats.get_size()),
+            (local_stats.get_file_count(), local_stats.get_dir_count(), local_stats.get_entry_count(), local_stats.get_size())
+        );
+        assert_eq!((local_stats.get_file_count(), local_stats.get_dir_count()), (4, 2));
+        assert_eq!(daemon_stats.get_scan_error_count(), 1);
+        assert_eq!(local_stats.get_scan_error_count(), 1);
+
+        let kinds = [None, Some(DirEntryKind::File), Some(DirEntryKind::Dir), Some(DirEntryKind::Symlink), Some(DirEntryKind::Other)];
+        for kind in kinds.iter() {
+            assert_eq!(daemon.list(*kind).unwrap(), local.list(*kind).unwrap(), "{:?}", kind);
+        }
+        assert_eq!(local.list(Some(DirEntryKind::Symlink)).unwrap(), vec![PathBuf::from("main")]);
+        assert!(!local.list(None).unwrap().contains(&PathBuf::new()));
+
+        for rl_path in ["", ".", "./src/main.rs", "main", "tab\there.txt", "missing", "src/../main"].iter() {
+            let rl_path = Path::new(rl_path);
+            assert_eq!(daemon.lookup(rl_path).unwrap(), local.lookup(rl_path).unwrap(), "{:?}", rl_path);
+        }
+        let main = local.lookup(Path::new("main")).unwrap().unwrap();
+        assert_eq!(main.get_rl_path(), Path::new("main"));
+        assert_eq!(main.get_kind(), DirEntryKind::Symlink);
+        assert_eq!(main.get_link_target(), Some(&PathBuf::from("src/main.rs")));
+        assert_eq!(local.lookup(Path::new("missing")).unwrap(), None);
+
+        for query in ["-name '*.txt'", "-type d", "-size +1"].iter() {
+            assert_eq!(daemon.find(query).unwrap(), local.find(query).unwrap(), "{}", query);
+        }
+        assert_eq!(local.find("-name '*.txt'").unwrap(), vec![PathBuf::from("docs/read me.txt"), PathBuf::from("tab\there.txt")]);
+        assert!(matches!(daemon.find("-name"), Err(DirIndexerErr::QueryParseFail(_))));
+        assert!(matches!(local.find("-name"), Err(DirIndexerErr::QueryParseFail(_))));
+
+        let options = [FuzzyOptions::new(), FuzzyOptions::new().with_limit(1), FuzzyOptions::new().with_kind(DirEntryKind::File).with_case_sensitive()];
+        for options in options.iter() {
+            for query in ["rs", "M", "", "zzz"].iter() {
+                assert_eq!(fuzzy_rows(&mut daemon, query, options), fuzzy_rows(&mut local, query, options), "{}", query);
+            }
+        }
+        assert!(daemon.is_daemon());
+    }
+
+    #[test]
+    fn roots_not_served_are_indexed_locally() {
+        let dir = sample("source-other-root");
+        let other = TempDir::new("source-other-root-served");
+        let run_dir = TempDir::new("source-other-root-run");
+        let socket_path = start_daemon(other.get_path(), &run_dir);
+        let mut source = IndexSource::open(dir.get_path(), &socket_path).unwrap();
+        assert!(!source.is_daemon());
+        assert_eq!(source.list(Some(DirEntryKind::Dir)).unwrap(), vec![PathBuf::from("docs"), PathBuf::from("src")]);
+
+        let missing = dir.join("missing");
+        assert!(matches!(IndexSource::open(&missing, &socket_path), Err(DirIndexerErr::NotDirNorExist(path)) if path == missing));
+    }
+
+    #[test]
+    fn a_daemon_that_goes_away_is_replaced_by_a_local_scan() {
+        let dir = sample("source-fallback");
+        let run_dir = TempDir::new("source-fallback-run");
+        let socket_path = run_dir.join("sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        // Answer the `ROOTS` request of `open`, then hang up
+        let root = dir.get_path().clone();
+        let daemon = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = Vec::new();
+            BufReader::new(&stream).read_until(b'\n', &mut line).unwrap();
+            assert_eq!(line, b"ROOTS\n".to_vec());
+            let mut answer = b"OK\t1\n".to_vec();
+            answer.extend_from_slice(codec::path_to_bytes(&root));
+            answer.push(b'\n');
+            stream.write_all(&answer).unwrap();
+        });
+
+        let mut source = IndexSource::open(dir.get_path(), &socket_path).unwrap();
+        assert!(source.is_daemon());
+        daemon.join().unwrap();
+        assert_eq!(source.list(Some(DirEntryKind::Symlink)).unwrap(), vec![PathBuf::from("main")]);
+        assert!(!source.is_daemon());
+        assert_eq!(source.get_root_path(), dir.get_path());
+    }
+
+    #[test]
+    fn local_sources_refresh() {
+        let dir = sample("source-refresh");
+        let mut source = IndexSource::open(dir.get_path(), &dir.join("no-such-sock")).unwrap();
+        dir.write("src/new.rs", b"");
+        assert_eq!(source.lookup(Path::new("src/new.rs")).unwrap(), None);
+        source.refresh().unwrap();
+        assert_eq!(source.lookup(Path::new("src/new.rs")).unwrap().map(|entry| entry.get_kind()), Some(DirEntryKind::File));
+        assert_eq!(source.get_stats().unwrap().get_file_count(), 5);
+    }
+}
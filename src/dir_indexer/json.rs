@@ -0,0 +1,359 @@
+use std::fmt::Write;
+
+/// Maximum nesting of arrays and objects accepted by the parser.
+const MAX_DEPTH: usize = 1024;
+
+/// A parsed JSON value. Numbers keep their source text so that 64-bit integers survive intact.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub(crate) fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            JsonValue::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsonValue::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a complete JSON document.
+///
+/// # Returns
+///
+/// The parsed value, or a message describing the first syntax error and its byte offset.
+pub(crate) fn parse(text: &str) -> Result<JsonValue, String> {
+    let mut parser = Parser { text_: text.as_bytes(), pos_: 0 };
+    let value = parser.parse_value(0)?;
+    parser.skip_ws();
+    if parser.pos_ != parser.text_.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Appends `s` to `out` as a quoted JSON string.
+pub(crate) fn push_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    text_: &'a [u8],
+    pos_: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> String {
+        format!("{} at byte {}", msg, self.pos_)
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.text_.get(self.pos_) {
+            self.pos_ += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text_.get(self.pos_).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.pos_ += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<JsonValue, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.skip_ws();
+        match self.peek() {
+            Some(b'{') => self.parse_object(depth),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b't') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.parse_literal("null", JsonValue::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            _ => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn parse_literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.text_[self.pos_..].starts_with(word.as_bytes()) {
+            self.pos_ += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.pos_;
+        if self.peek() == Some(b'-') {
+            self.pos_ += 1;
+        }
+        let digits_start = self.pos_;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos_ += 1;
+        }
+        if self.pos_ == digits_start {
+            return Err(self.error("invalid number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos_ += 1;
+            while let Some(b'0'..=b'9') = self.peek() {
+                self.pos_ += 1;
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos_ += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos_ += 1;
+            }
+            while let Some(b'0'..=b'9') = self.peek() {
+                self.pos_ += 1;
+            }
+        }
+        let text = std::str::from_utf8(&self.text_[start..self.pos_]).map_err(|_| self.error("invalid number"))?;
+        Ok(JsonValue::Number(text.to_string()))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text_.get(self.pos_..self.pos_ + 4).ok_or_else(|| self.error("truncated escape"))?;
+        let digits = std::str::from_utf8(digits).map_err(|_| self.error("invalid escape"))?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.pos_ += 4;
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let byte = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.pos_ += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos_ += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.text_[self.pos_..].starts_with(b"\\u") {
+                                self.pos_ += 2;
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                0x00..=0x1f => return Err(self.error("control character in string")),
+                _ => out.push(byte),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8 in string"))
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some(b']') {
+            self.pos_ += 1;
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.parse_value(depth + 1)?);
+            self.skip_ws();
+            match self.peek() {
+                Some(b',') => self.pos_ += 1,
+                Some(b']') => {
+                    self.pos_ += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some(b'}') {
+            self.pos_ += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_ws();
+            let key = self.parse_string()?;
+            self.skip_ws();
+            self.expect(b':')?;
+            let value = self.parse_value(depth + 1)?;
+            members.push((key, value));
+            self.skip_ws();
+            match self.peek() {
+                Some(b',') => self.pos_ += 1,
+                Some(b'}') => {
+                    self.pos_ += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_parse_into_values() {
+        let value = parse(" {\"a\": [1, -2.5e3, true, false, null], \"b\": {}, \"c\": []} ").unwrap();
+        let items = value.get("a").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(items[0].as_u64(), Some(1));
+        assert_eq!(items[1], JsonValue::Number(String::from("-2.5e3")));
+        assert_eq!(items[2], JsonValue::Bool(true));
+        assert_eq!(items[3], JsonValue::Bool(false));
+        assert_eq!(items[4], JsonValue::Null);
+        assert_eq!(value.get("b"), Some(&JsonValue::Object(Vec::new())));
+        assert_eq!(value.get("c").and_then(JsonValue::as_array), Some(&Vec::new()));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn integers_keep_all_64_bits() {
+        assert_eq!(parse("18446744073709551615").unwrap().as_u64(), Some(u64::MAX));
+        assert_eq!(parse("-9223372036854775808").unwrap().as_i64(), Some(i64::MIN));
+        assert_eq!(parse("18446744073709551616").unwrap().as_u64(), None);
+        assert_eq!(parse("-1").unwrap().as_u64(), None);
+        assert_eq!(parse("\"1\"").unwrap().as_u64(), None);
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let value = parse(r#""q\" b\\ s\/ \b\f\n\r\t \u00e9 \ud83d\ude00""#).unwrap();
+        assert_eq!(value.as_str(), Some("q\" b\\ s/ \u{8}\u{c}\n\r\t \u{e9} \u{1f600}"));
+        // A lone surrogate cannot be represented and becomes the replacement character
+        assert_eq!(parse(r#""\ud800""#).unwrap().as_str(), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn pushed_strings_parse_back() {
+        let original = "tab\there \"quoted\" back\\slash nul\u{0} bell\u{7} line\nfeed é";
+        let mut out = String::new();
+        push_string(&mut out, original);
+        assert!(!out.bytes().any(|b| b < 0x20));
+        assert_eq!(parse(&out).unwrap().as_str(), Some(original));
+    }
+
+    #[test]
+    fn malformed_documents_are_rejected() {
+        let cases = [
+            "",
+            "   ",
+            "{",
+            "[1,]",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{\"a\":1,}",
+            "{1:2}",
+            "\"unterminated",
+            "\"bad \\x escape\"",
+            "\"\\u12\"",
+            "\"\\uzzzz\"",
+            "\"\\ud800\\u0041\"",
+            "\"raw\ncontrol\"",
+            "tru",
+            "nul",
+            "-",
+            "01x",
+            "[] []",
+            "+1",
+        ];
+        for case in cases.iter() {
+            assert!(parse(case).is_err(), "accepted {:?}", case);
+        }
+        assert!(parse("[1] x").unwrap_err().contains("trailing characters at byte 4"));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let ok = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&ok).is_ok());
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(parse(&deep).unwrap_err().contains("nesting too deep"));
+        let deep_objects = "{\"a\":".repeat(100_000);
+        assert!(parse(&deep_objects).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+//! Read-only index layout that is queried in place from a memory-mapped file.
+//!
+//! Layout, all integers little endian:
+//!
+//! * a 64 byte header: magic `DIRIDXM\n`, format version, flags, node count, offset of the node
+//!   table, offset and length of the string table, scan time and length of the root path
+//! * the node table: one fixed 96 byte record per node in breadth-first order, so the children of
+//!   a node are contiguous and sorted by name
+//! * the string table: the root path followed by every name and link target
+//!
+//! Opening a file only validates the header; every record access is bounds checked, so a corrupt
+//! file yields errors or empty results instead of panics.
+
+use super::{DirEntryKind, DirIndexer, DirIndexerErr, DirMeta, DirNode};
+use super::codec;
+use super::index_file;
+use super::mmap::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// Magic bytes at the start of every mapped index file.
+const MAPPED_MAGIC: &[u8; 8] = b"DIRIDXM\n";
+
+/// Current version of the mapped index file format.
+const MAPPED_VERSION: u32 = 1;
+
+const HEADER_LEN: usize = 64;
+const RECORD_LEN: usize = 96;
+
+/// Sentinel link length meaning "not a symbolic link".
+const NO_LINK: u32 = u32::MAX;
+
+/// Sentinel parent index of the root record.
+const NO_PARENT: u32 = u32::MAX;
+
+/// The `MappedIndex` struct represents a saved index that is queried directly from a memory-mapped
+/// file, without deserializing it.
+#[derive(Debug)]
+pub struct MappedIndex {
+    map_: Mmap,
+    node_count_: usize,
+    nodes_off_: usize,
+    strings_off_: usize,
+    strings_len_: usize,
+    root_path_: PathBuf,
+    scan_time_: SystemTime,
+}
+
+impl MappedIndex {
+    /// Opens a mapped index file written by `DirIndexer::save_mapped`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_path` - The path of the mapped index file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `MappedIndex`, or an `Err` variant of `DirIndexerErr` if the file
+    /// cannot be mapped, has an incompatible version or an inconsistent header.
+    pub fn open(index_path: &Path) -> Result<MappedIndex, DirIndexerErr> {
+        let io_err = || DirIndexerErr::IndexIoFail(index_path.to_path_buf());
+        let corrupt = || DirIndexerErr::CorruptIndex(index_path.to_path_buf());
+        let file = File::open(index_path).map_err(|_| io_err())?;
+        let map = Mmap::open(&file).map_err(|_| io_err())?;
+
+        let data = map.as_slice();
+        if data.len() < HEADER_LEN || &data[..8] != MAPPED_MAGIC {
+            return Err(corrupt());
+        }
+        let version = read_u32(data, 8);
+        if version != MAPPED_VERSION {
+            return Err(DirIndexerErr::IncompatibleIndexVersion(version));
+        }
+        let node_count = read_u64(data, 16) as usize;
+        let nodes_off = read_u64(data, 24) as usize;
+        let strings_off = read_u64(data, 32) as usize;
+        let strings_len = read_u64(data, 40) as usize;
+        let scan_secs = read_u64(data, 48) as i64;
+        let scan_nanos = read_u32(data, 56);
+        let root_len = read_u32(data, 60) as usize;
+
+        let nodes_end = node_count
+            .checked_mul(RECORD_LEN)
+            .and_then(|len| len.checked_add(nodes_off))
+            .ok_or_else(corrupt)?;
+        let strings_end = strings_off.checked_add(strings_len).ok_or_else(corrupt)?;
+        if node_count == 0
+            || node_count > NO_PARENT as usize
+            || nodes_off < HEADER_LEN
+            || nodes_end > data.len()
+            || strings_off < nodes_end
+            || strings_end > data.len()
+            || root_len > strings_len
+        {
+            return Err(corrupt());
+        }
+        let root_path = codec::bytes_to_path(&data[strings_off..strings_off + root_len]).ok_or_else(corrupt)?;
+        let scan_time = index_file::time_from_parts(scan_secs, scan_nanos as u64).ok_or_else(corrupt)?;
+
+        Ok(MappedIndex {
+            map_: map,
+            node_count_: node_count,
+            nodes_off_: nodes_off,
+            strings_off_: strings_off,
+            strings_len_: strings_len,
+            root_path_: root_path,
+            scan_time_: scan_time,
+        })
+    }
+
+    /// Returns the absolute root path of the indexed directory.
+    pub fn get_root_path(&self) -> &Path {
+        &self.root_path_
+    }
+
+    /// Returns the time at which the directory was scanned.
+    pub fn get_scan_time(&self) -> SystemTime {
+        self.scan_time_
+    }
+
+    /// Returns the number of entries, including the root.
+    pub fn len(&self) -> usize {
+        self.node_count_
+    }
+
+    /// Returns `true` if the index holds no entries; a valid index always holds at least its root.
+    pub fn is_empty(&self) -> bool {
+        self.node_count_ == 0
+    }
+
+    /// Returns the root entry.
+    pub fn get_root(&self) -> MappedEntry<'_> {
+        MappedEntry { index_: self, id_: 0 }
+    }
+
+    /// Looks up an entry by its relative path, descending from the root with a binary search among
+    /// the children of each directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `rl_path` - The relative path of the entry; an empty path refers to the root.
+    ///
+    /// # Returns
+    ///
+    /// The matching entry, or `None` if the path is not indexed.
+    pub fn lookup(&self, rl_path: &Path) -> Option<MappedEntry<'_>> {
+        let mut current = self.get_root();
+        for component in rl_path.components() {
+            let name = match component {
+                Component::Normal(name) => codec::path_to_bytes(Path::new(name)),
+                Component::CurDir => continue,
+                _ => return None,
+            };
+            current = current.find_child(name)?;
+        }
+        Some(current)
+    }
+
+    /// Iterates over every entry in breadth-first order, starting with the root.
+    pub fn iter(&self) -> impl Iterator<Item = MappedEntry<'_>> {
+        (0..self.node_count_ as u32).map(move |id| MappedEntry { index_: self, id_: id })
+    }
+
+    /// Iterates over the entries matching a predicate, in breadth-first order.
+    ///
+    /// # Arguments
+    ///
+    /// * `pred` - Called for every entry; only entries for which it returns `true` are yielded.
+    pub fn filter<'a, F>(&'a self, pred: F) -> impl Iterator<Item = MappedEntry<'a>> + 'a
+    where
+        F: Fn(&MappedEntry<'a>) -> bool + 'a,
+    {
+        self.iter().filter(move |entry| pred(entry))
+    }
+
+    fn record(&self, id: u32) -> &[u8] {
+        let start = self.nodes_off_ + id as usize * RECORD_LEN;
+        &self.map_.as_slice()[start..start + RECORD_LEN]
+    }
+
+    fn string(&self, offset: u64, len: u32) -> &[u8] {
+        let offset = offset as usize;
+        let len = len as usize;
+        match offset.checked_add(len) {
+            Some(end) if end <= self.strings_len_ => {
+                &self.map_.as_slice()[self.strings_off_ + offset..self.strings_off_ + end]
+            }
+            _ => &[],
+        }
+    }
+}
+
+/// A handle to one entry of a `MappedIndex`.
+#[derive(Debug, Clone, Copy)]
+pub struct MappedEntry<'a> {
+    index_: &'a MappedIndex,
+    id_: u32,
+}
+
+impl<'a> MappedEntry<'a> {
+    /// Returns the position of the entry in the breadth-first node table.
+    pub fn get_id(&self) -> u32 {
+        self.id_
+    }
+
+    /// Returns the raw bytes of the entry name; the root has an empty name.
+    pub fn get_name_bytes(&self) -> &'a [u8] {
+        let record = self.index_.record(self.id_);
+        self.index_.string(read_u64(record, 16), read_u32(record, 24))
+    }
+
+    /// Returns the entry name; the root has an empty name.
+    pub fn get_name(&self) -> PathBuf {
+        codec::bytes_to_path(self.get_name_bytes()).unwrap_or_default()
+    }
+
+    /// Returns the kind of the entry.
+    pub fn get_kind(&self) -> DirEntryKind {
+        index_file::kind_from_u8(self.index_.record(self.id_)[12]).unwrap_or(DirEntryKind::Other)
+    }
+
+    /// Returns the metadata recorded for the entry.
+    pub fn get_meta(&self) -> DirMeta {
+        let r = self.index_.record(self.id_);
+        let fields = [
+            read_u64(r, 40),
+            read_u64(r, 48),
+            read_u32(r, 56) as u64,
+            read_u32(r, 60) as u64,
+            read_u32(r, 64) as u64,
+            read_u32(r, 68) as u64,
+            read_u64(r, 72),
+            read_u64(r, 80),
+            read_u64(r, 88),
+        ];
+        DirMeta::from_fields(&fields).unwrap_or_default()
+    }
+
+    /// Returns the target of the entry if it is a symbolic link.
+    pub fn get_link_target(&self) -> Option<PathBuf> {
+        let record = self.index_.record(self.id_);
+        let len = read_u32(record, 28);
+        if len == NO_LINK {
+            return None;
+        }
+        codec::bytes_to_path(self.index_.string(read_u64(record, 32), len))
+    }
+
+    /// Returns the parent entry, or `None` for the root.
+    pub fn get_parent(&self) -> Option<MappedEntry<'a>> {
+        let parent = read_u32(self.index_.record(self.id_), 0);
+        // Breadth-first order puts parents before children; anything else is corrupt
+        if parent >= self.id_ {
+            return None;
+        }
+        Some(MappedEntry { index_: self.index_, id_: parent })
+    }
+
+    /// Returns the relative path of the entry, rebuilt from its ancestors.
+    pub fn get_relative_path(&self) -> PathBuf {
+        let mut names: Vec<&[u8]> = Vec::new();
+        let mut current = *self;
+        while let Some(parent) = current.get_parent() {
+            names.push(current.get_name_bytes());
+            current = parent;
+        }
+        let mut rl_path = PathBuf::new();
+        for name in names.iter().rev() {
+            if let Some(name) = codec::bytes_to_path(name) {
+                rl_path.push(name);
+            }
+        }
+        rl_path
+    }
+
+    /// Returns the absolute path of the entry.
+    pub fn get_absolute_path(&self) -> PathBuf {
+        self.index_.root_path_.join(self.get_relative_path())
+    }
+
+    /// Returns the children of the entry, sorted by name.
+    pub fn get_children(&self) -> impl Iterator<Item = MappedEntry<'a>> {
+        let index = self.index_;
+        let (first, count) = self.child_range();
+        (first..first + count).map(move |id| MappedEntry { index_: index, id_: id })
+    }
+
+    fn child_range(&self) -> (u32, u32) {
+        let record = self.index_.record(self.id_);
+        let first = read_u32(record, 4);
+        let count = read_u32(record, 8);
+        match first.checked_add(count) {
+            Some(end) if first > self.id_ && end as usize <= self.index_.node_count_ => (first, count),
+            _ => (0, 0),
+        }
+    }
+
+    fn find_child(&self, name: &[u8]) -> Option<MappedEntry<'a>> {
+        let (first, count) = self.child_range();
+        let (mut lo, mut hi) = (first, first + count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = MappedEntry { index_: self.index_, id_: mid };
+            match entry.get_name_bytes().cmp(name) {
+                std::cmp::Ordering::Equal => return Some(entry),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+}
+
+/// Writes `indexer` to `index_path` in the mapped layout, through a temporary file.
+pub(crate) fn save_mapped_index(indexer: &DirIndexer, index_path: &Path) -> Result<(), DirIndexerErr> {
+    let root_node = indexer.get_tree().get_root_node();
+    let node_count = index_file::count_nodes(root_node);
+    if node_count >= NO_PARENT as usize {
+        return Err(DirIndexerErr::IndexIoFail(index_path.to_path_buf()));
+    }
+
+    let mut strings: Vec<u8> = Vec::new();
+    let root_bytes = codec::path_to_bytes(indexer.get_root_path());
+    strings.extend_from_slice(root_bytes);
+
+    let mut records: Vec<u8> = Vec::with_capacity(node_count * RECORD_LEN);
+    let mut queue: VecDeque<(&DirNode, u32)> = VecDeque::new();
+    queue.push_back((root_node, NO_PARENT));
+    let mut next_id: u32 = 1;
+    let mut id: u32 = 0;
+    while let Some((node, parent)) = queue.pop_front() {
+        let mut children: Vec<&DirNode> = node.get_children().collect();
+        children.sort_by(|a, b| name_bytes(a).cmp(name_bytes(b)));
+
+        let mut record = [0u8; RECORD_LEN];
+        put_u32(&mut record, 0, parent);
+        put_u32(&mut record, 4, next_id);
+        put_u32(&mut record, 8, children.len() as u32);
+        record[12] = index_file::kind_to_u8(node.get_kind());
+        let name = name_bytes(node);
+        put_u64(&mut record, 16, strings.len() as u64);
+        put_u32(&mut record, 24, name.len() as u32);
+        strings.extend_from_slice(name);
+        match node.get_link_target() {
+            Some(target) => {
+                let bytes = codec::path_to_bytes(target);
+                put_u32(&mut record, 28, bytes.len() as u32);
+                put_u64(&mut record, 32, strings.len() as u64);
+                strings.extend_from_slice(bytes);
+            }
+            None => put_u32(&mut record, 28, NO_LINK),
+        }
+        let fields = node.get_meta().to_fields();
+        put_u64(&mut record, 40, fields[0]);
+        put_u64(&mut record, 48, fields[1]);
+        put_u32(&mut record, 56, fields[2] as u32);
+        put_u32(&mut record, 60, fields[3] as u32);
+        put_u32(&mut record, 64, fields[4] as u32);
+        put_u32(&mut record, 68, fields[5] as u32);
+        put_u64(&mut record, 72, fields[6]);
+        put_u64(&mut record, 80, fields[7]);
+        put_u64(&mut record, 88, fields[8]);
+        records.extend_from_slice(&record);
+
+        next_id += children.len() as u32;
+        for child in children {
+            queue.push_back((child, id));
+        }
+        id += 1;
+    }
+
+    let (scan_secs, scan_nanos) = index_file::time_to_parts(indexer.get_scan_time());
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(MAPPED_MAGIC);
+    put_u32(&mut header, 8, MAPPED_VERSION);
+    put_u32(&mut header, 12, index_file::FLAG_FOLLOW_SYMLINKS as u32);
+    put_u64(&mut header, 16, node_count as u64);
+    put_u64(&mut header, 24, HEADER_LEN as u64);
+    put_u64(&mut header, 32, (HEADER_LEN + records.len()) as u64);
+    put_u64(&mut header, 40, strings.len() as u64);
+    put_u64(&mut header, 48, scan_secs as u64);
+    put_u32(&mut header, 56, scan_nanos);
+    put_u32(&mut header, 60, root_bytes.len() as u32);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + records.len() + strings.len());
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&records);
+    bytes.extend_from_slice(&strings);
+    index_file::write_atomically(index_path, &bytes)
+}
+
+fn name_bytes(node: &DirNode) -> &[u8] {
+    codec::path_to_bytes(Path::new(node.get_name()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    fn sample_tree(dir: &TempDir) -> DirIndexer {
+        dir.write("a.txt", b"a");
+        dir.write("sub/deeper/b.bin", &[0, 1, 2]);
+        dir.write("sub/c", b"c");
+        dir.write("sub/B", b"B");
+        dir.write(Path::new(OsStr::from_bytes(b"not\xffutf8")), b"raw");
+        symlink("sub/deeper", dir.join("link")).unwrap();
+        DirIndexer::from(dir.get_path().clone()).unwrap()
+    }
+
+    /// Touches every accessor of every entry, so that a corrupt file is read end to end.
+    fn walk(index: &MappedIndex) {
+        for entry in index.iter() {
+            let _ = entry.get_name();
+            let _ = entry.get_kind();
+            let _ = entry.get_meta();
+            let _ = entry.get_link_target();
+            let _ = entry.get_absolute_path();
+            let rl_path = entry.get_relative_path();
+            let _ = index.lookup(&rl_path);
+            assert!(entry.get_children().count() <= index.len());
+        }
+    }
+
+    #[test]
+    fn mapped_entries_match_the_indexer() {
+        let dir = TempDir::new("mapped-roundtrip");
+        let indexer = sample_tree(&dir);
+        let out = TempDir::new("mapped-roundtrip-out");
+        let index_path = out.join("tree.idxm");
+        indexer.save_mapped(&index_path).unwrap();
+
+        let index = MappedIndex::open(&index_path).unwrap();
+        assert_eq!(index.get_root_path(), indexer.get_root_path().as_path());
+        assert_eq!(index.get_scan_time(), indexer.get_scan_time());
+        let nodes = indexer.get_tree().get_rl2node_map();
+        assert_eq!(index.len(), nodes.len());
+        assert!(!index.is_empty());
+        for (rl_path, node) in &nodes {
+            let entry = index.lookup(rl_path).unwrap();
+            assert_eq!(&entry.get_relative_path(), rl_path);
+            assert_eq!(entry.get_absolute_path(), indexer.get_root_path().join(rl_path));
+            assert_eq!(entry.get_kind(), node.get_kind());
+            assert_eq!(&entry.get_meta(), node.get_meta());
+            assert_eq!(entry.get_link_target().as_ref(), node.get_link_target());
+            let children: Vec<PathBuf> = entry.get_children().map(|child| child.get_name()).collect();
+            let expected: Vec<PathBuf> = node.get_sorted_children().iter().map(|child| PathBuf::from(child.get_name())).collect();
+            assert_eq!(children, expected);
+            for child in entry.get_children() {
+                assert_eq!(child.get_parent().unwrap().get_id(), entry.get_id());
+            }
+        }
+        assert!(index.get_root().get_parent().is_none());
+        assert!(index.get_root().get_name_bytes().is_empty());
+        assert!(index.lookup(Path::new("sub/missing")).is_none());
+        assert!(index.lookup(Path::new("../sub")).is_none());
+        assert!(index.lookup(Path::new("/sub")).is_none());
+        assert_eq!(index.lookup(Path::new("./sub/c")).unwrap().get_relative_path(), PathBuf::from("sub/c"));
+    }
+
+    #[test]
+    fn entries_are_stored_breadth_first() {
+        let dir = TempDir::new("mapped-order");
+        let indexer = sample_tree(&dir);
+        let out = TempDir::new("mapped-order-out");
+        let index_path = out.join("tree.idxm");
+        indexer.save_mapped(&index_path).unwrap();
+
+        let index = MappedIndex::open(&index_path).unwrap();
+        let depths: Vec<usize> = index.iter().map(|entry| entry.get_relative_path().components().count()).collect();
+        assert!(depths.windows(2).all(|pair| pair[0] <= pair[1]));
+        let files: Vec<PathBuf> = index
+            .filter(|entry| entry.get_kind() == DirEntryKind::File)
+            .map(|entry| entry.get_relative_path())
+            .collect();
+        let expected = indexer.get_tree().get_rl2node_map().values().filter(|node| node.get_kind() == DirEntryKind::File).count();
+        assert_eq!(files.len(), expected);
+        assert!(files.contains(&PathBuf::from("sub/deeper/b.bin")));
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let dir = TempDir::new("mapped-header");
+        let indexer = sample_tree(&dir);
+        let out = TempDir::new("mapped-header-out");
+        let index_path = out.join("tree.idxm");
+        indexer.save_mapped(&index_path).unwrap();
+        let bytes = fs::read(&index_path).unwrap();
+        let bad_path = out.join("bad.idxm");
+        let open_with = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = bytes.clone();
+            edit(&mut data);
+            fs::write(&bad_path, &data).unwrap();
+            MappedIndex::open(&bad_path)
+        };
+
+        assert!(matches!(open_with(&|data| data.truncate(HEADER_LEN - 1)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| data.clear()), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| data[0] ^= 1), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u32(data, 8, 9)), Err(DirIndexerErr::IncompatibleIndexVersion(9))));
+        assert!(matches!(open_with(&|data| put_u64(data, 16, 0)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u64(data, 16, u64::MAX)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u64(data, 24, 8)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u64(data, 32, u64::MAX)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u64(data, 40, u64::MAX)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(open_with(&|data| put_u32(data, 60, u32::MAX)), Err(DirIndexerErr::CorruptIndex(_))));
+        let len = bytes.len();
+        assert!(matches!(open_with(&|data| data.truncate(len - 1)), Err(DirIndexerErr::CorruptIndex(_))));
+        assert!(matches!(MappedIndex::open(&out.join("missing.idxm")), Err(DirIndexerErr::IndexIoFail(_))));
+    }
+
+    #[test]
+    fn corrupt_tables_never_panic() {
+        let dir = TempDir::new("mapped-tables");
+        let indexer = sample_tree(&dir);
+        let out = TempDir::new("mapped-tables-out");
+        let index_path = out.join("tree.idxm");
+        indexer.save_mapped(&index_path).unwrap();
+        let bytes = fs::read(&index_path).unwrap();
+        let bad_path = out.join("bad.idxm");
+
+        for pos in HEADER_LEN..bytes.len() {
+            for value in [0x00u8, 0x7f, 0xff] {
+                let mut data = bytes.clone();
+                data[pos] = value;
+                fs::write(&bad_path, &data).unwrap();
+                if let Ok(index) = MappedIndex::open(&bad_path) {
+                    walk(&index);
+                }
+            }
+        }
+    }
+}
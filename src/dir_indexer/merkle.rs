@@ -0,0 +1,627 @@
+//! Merkle-style structural hashing of an indexed tree.
+//!
+//! A regular file hashes to its content hash. A directory hashes its children sorted by name
+//! bytes, each as name, kind and hash, so the result does not depend on scan order. A symbolic
+//! link hashes its target; the contents of followed directory links are not included. When mode
+//! bits or modification times are selected, every entry also hashes its own, so a file then no
+//! longer hashes to its bare content hash.
+
+use super::{ContentHash, ContentHasher, DirChange, DirEntryKind, DirIndexer, DirNode, HashAlgo};
+use super::codec::{self, ByteWriter};
+use super::hasher;
+use super::index_file;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Options selecting what feeds into structural hashes.
+#[derive(Debug, Clone)]
+pub struct MerkleOptions {
+    hash_algo_: HashAlgo,
+    include_mode_: bool,
+    include_mtime_: bool,
+}
+
+impl Default for MerkleOptions {
+    fn default() -> MerkleOptions {
+        MerkleOptions {
+            hash_algo_: HashAlgo::Sha256,
+            include_mode_: false,
+            include_mtime_: false,
+        }
+    }
+}
+
+impl MerkleOptions {
+    /// Creates options hashing names, kinds and contents only, with SHA-256.
+    pub fn new() -> MerkleOptions {
+        MerkleOptions::default()
+    }
+
+    /// Sets the algorithm used for file contents and structural hashes.
+    pub fn with_hash_algo(mut self, algo: HashAlgo) -> MerkleOptions {
+        self.hash_algo_ = algo;
+        self
+    }
+
+    /// Also hashes the permission bits of every entry.
+    pub fn with_mode(mut self) -> MerkleOptions {
+        self.include_mode_ = true;
+        self
+    }
+
+    /// Also hashes the modification time of every entry.
+    pub fn with_mtime(mut self) -> MerkleOptions {
+        self.include_mtime_ = true;
+        self
+    }
+
+    /// Returns the hash algorithm.
+    pub fn get_hash_algo(&self) -> HashAlgo {
+        self.hash_algo_
+    }
+
+    /// Returns `true` if permission bits are hashed.
+    pub fn get_include_mode(&self) -> bool {
+        self.include_mode_
+    }
+
+    /// Returns `true` if modification times are hashed.
+    pub fn get_include_mtime(&self) -> bool {
+        self.include_mtime_
+    }
+
+    fn has_attributes(&self) -> bool {
+        self.include_mode_ || self.include_mtime_
+    }
+
+    fn put_attributes(&self, writer: &mut ByteWriter, node: &DirNode) {
+        let meta = node.get_meta();
+        if self.include_mode_ {
+            writer.put_varint(meta.get_permissions() as u64);
+        }
+        if self.include_mtime_ {
+            writer.put_varint_signed(meta.get_mtime());
+            writer.put_varint(meta.get_mtime_nsec() as u64);
+        }
+    }
+}
+
+/// The structural hash of one entry and a summary of its subtree.
+#[derive(Debug, Clone)]
+pub(crate) struct MerkleEntry {
+    pub(crate) hash: ContentHash,
+    pub(crate) kind: DirEntryKind,
+    pub(crate) file_count: u64,
+    pub(crate) total_size: u64,
+    /// `false` if a file in the subtree could not be read, so the hash does not reflect it.
+    pub(crate) complete: bool,
+    /// The relative paths of the children of a directory, sorted by name.
+    pub(crate) children: Vec<PathBuf>,
+}
+
+/// The `MerkleTree` struct holds the structural hash of every entry of an index.
+///
+/// Equal root hashes mean equal trees for the inputs selected in `MerkleOptions`, whatever the
+/// root paths and scan order. `explain_difference` lists the subtrees behind unequal hashes.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    root_: PathBuf,
+    entries_: BTreeMap<PathBuf, MerkleEntry>,
+}
+
+impl MerkleTree {
+    /// Hashes every entry of the index.
+    pub(crate) fn from(indexer: &DirIndexer, options: &MerkleOptions) -> MerkleTree {
+        MerkleTree::from_node(indexer.get_root_path(), indexer.get_tree().get_root_node(), options)
+    }
+
+    /// Hashes a node and its descendants.
+    pub(crate) fn from_node(root_path: &Path, node: &DirNode, options: &MerkleOptions) -> MerkleTree {
+        let mut entries = BTreeMap::new();
+        hash_node(root_path, node, options, &mut entries);
+        MerkleTree {
+            root_: node.get_entry().clone(),
+            entries_: entries,
+        }
+    }
+
+    pub(crate) fn get_entries(&self) -> &BTreeMap<PathBuf, MerkleEntry> {
+        &self.entries_
+    }
+
+    /// Returns the hash of the whole tree.
+    pub fn get_root_hash(&self) -> &ContentHash {
+        &self.entries_[&self.root_].hash
+    }
+
+    /// Returns the hash of the subtree at `rl_path`, relative to the indexed root.
+    pub fn get_hash(&self, rl_path: &Path) -> Option<&ContentHash> {
+        self.entries_.get(rl_path).map(|entry| &entry.hash)
+    }
+
+    /// Returns `true` if every file could be read, so that the hashes reflect all contents.
+    pub fn is_complete(&self) -> bool {
+        self.entries_[&self.root_].complete
+    }
+
+    /// Explains why this tree's root hash differs from `newer`'s.
+    ///
+    /// Starting at the roots, every entry whose hash differs is listed, followed by its differing
+    /// children, down to the files that changed. Entries present in only one tree are listed
+    /// without descending further.
+    ///
+    /// # Arguments
+    ///
+    /// * `newer` - The tree to compare against, hashed with the same options.
+    ///
+    /// # Returns
+    ///
+    /// The differences in pre-order, each keyed by its path relative to the roots; empty if the
+    /// root hashes are equal.
+    pub fn explain_difference(&self, newer: &MerkleTree) -> Vec<MerkleDifference> {
+        let mut differences = Vec::new();
+        // Pairs of (old path, new path) still to compare, visited depth first in name order
+        let mut pending: Vec<(PathBuf, PathBuf, PathBuf)> = vec![(PathBuf::new(), self.root_.clone(), newer.root_.clone())];
+        while let Some((rl_path, old_path, new_path)) = pending.pop() {
+            let old = &self.entries_[&old_path];
+            let new = &newer.entries_[&new_path];
+            if old.hash == new.hash {
+                continue;
+            }
+            let change = if old.kind == new.kind {
+                DirChange::Modified(new.kind)
+            } else {
+                DirChange::TypeChanged(old.kind, new.kind)
+            };
+            differences.push(MerkleDifference::from(rl_path.clone(), change, Some(old), Some(new)));
+            if old.kind != DirEntryKind::Dir || new.kind != DirEntryKind::Dir {
+                continue;
+            }
+
+            let old_children: BTreeMap<&OsStr, &PathBuf> = old.children.iter().filter_map(|p| Some((p.file_name()?, p))).collect();
+            let new_children: BTreeMap<&OsStr, &PathBuf> = new.children.iter().filter_map(|p| Some((p.file_name()?, p))).collect();
+            let names: BTreeSet<&OsStr> = old_children.keys().chain(new_children.keys()).copied().collect();
+            let mut nested = Vec::new();
+            for name in names {
+                let child_path = rl_path.join(name);
+                match (old_children.get(name), new_children.get(name)) {
+                    (Some(o), Some(n)) => nested.push((child_path, (*o).clone(), (*n).clone())),
+                    (Some(o), None) => {
+                        let entry = &self.entries_[*o];
+                        differences.push(MerkleDifference::from(child_path, DirChange::Removed(entry.kind), Some(entry), None));
+                    }
+                    (None, Some(n)) => {
+                        let entry = &newer.entries_[*n];
+                        differences.push(MerkleDifference::from(child_path, DirChange::Added(entry.kind), None, Some(entry)));
+                    }
+                    (None, None) => {}
+                }
+            }
+            pending.extend(nested.into_iter().rev());
+        }
+        differences
+    }
+}
+
+/// One entry of a `MerkleTree::explain_difference` listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleDifference {
+    path_: PathBuf,
+    change_: DirChange,
+    old_hash_: Option<ContentHash>,
+    new_hash_: Option<ContentHash>,
+}
+
+impl MerkleDifference {
+    fn from(path: PathBuf, change: DirChange, old: Option<&MerkleEntry>, new: Option<&MerkleEntry>) -> MerkleDifference {
+        MerkleDifference {
+            path_: path,
+            change_: change,
+            old_hash_: old.map(|entry| entry.hash.clone()),
+            new_hash_: new.map(|entry| entry.hash.clone()),
+        }
+    }
+
+    /// Returns the path of the entry relative to the compared roots; the roots have an empty path.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns how the entry differs; directories whose subtree changed are `Modified`.
+    pub fn get_change(&self) -> &DirChange {
+        &self.change_
+    }
+
+    /// Returns the hash in the older tree, or `None` if the entry was added.
+    pub fn get_old_hash(&self) -> Option<&ContentHash> {
+        self.old_hash_.as_ref()
+    }
+
+    /// Returns the hash in the newer tree, or `None` if the entry was removed.
+    pub fn get_new_hash(&self) -> Option<&ContentHash> {
+        self.new_hash_.as_ref()
+    }
+}
+
+fn hash_node(root_path: &Path, node: &DirNode, options: &MerkleOptions, entries: &mut BTreeMap<PathBuf, MerkleEntry>) -> MerkleEntry {
+    let algo = options.hash_algo_;
+    let kind = node.get_kind();
+    let mut writer = ByteWriter::new();
+    let entry = match kind {
+        DirEntryKind::File => {
+            let content = match node.get_content_hash() {
+                Some(hash) if hash.get_algo() == algo => Ok(hash.clone()),
+                _ => hasher::hash_file(algo, &root_path.join(node.get_entry())),
+            };
+            let complete = content.is_ok();
+            let content = content.unwrap_or_else(|_| hasher::hash_bytes(algo, b"unreadable"));
+            let hash = if options.has_attributes() {
+                writer.put_u8(b'F');
+                options.put_attributes(&mut writer, node);
+                writer.put_raw(content.get_digest());
+                hash_record(algo, writer)
+            } else {
+                content
+            };
+            MerkleEntry {
+                hash,
+                kind,
+                file_count: 1,
+                total_size: node.get_meta().get_size(),
+                complete,
+                children: Vec::new(),
+            }
+        }
+        DirEntryKind::Dir => {
+            let mut children: Vec<(&[u8], &PathBuf, MerkleEntry)> = node
+                .get_children()
+                .map(|child| {
+                    let name = codec::path_to_bytes(Path::new(child.get_name()));
+                    (name, child.get_entry(), hash_node(root_path, child, options, entries))
+                })
+                .collect();
+            children.sort_by(|a, b| a.0.cmp(b.0));
+            writer.put_u8(b'D');
+            options.put_attributes(&mut writer, node);
+            writer.put_varint(children.len() as u64);
+            let mut file_count = 0;
+            let mut total_size = 0;
+            let mut complete = true;
+            for (name, _, child) in &children {
+                writer.put_bytes(name);
+                writer.put_u8(index_file::kind_to_u8(child.kind));
+                writer.put_raw(child.hash.get_digest());
+                file_count += child.file_count;
+                total_size += child.total_size;
+                complete &= child.complete;
+            }
+            MerkleEntry {
+                hash: hash_record(algo, writer),
+                kind,
+                file_count,
+                total_size,
+                complete,
+                children: children.into_iter().map(|(_, path, _)| path.clone()).collect(),
+            }
+        }
+        DirEntryKind::Symlink | DirEntryKind::Other => {
+            if kind == DirEntryKind::Symlink {
+                writer.put_u8(b'L');
+                writer.put_bytes(node.get_link_target().map_or(&[][..], |t| codec::path_to_bytes(t)));
+            } else {
+                writer.put_u8(b'O');
+                writer.put_varint((node.get_meta().get_mode() & 0o170000) as u64);
+            }
+            options.put_attributes(&mut writer, node);
+            MerkleEntry {
+                hash: hash_record(algo, writer),
+                kind,
+                file_count: 0,
+                total_size: 0,
+                complete: true,
+                children: Vec::new(),
+            }
+        }
+    };
+    entries.insert(node.get_entry().clone(), entry.clone());
+    entry
+}
+
+fn hash_record(algo: HashAlgo, writer: ByteWriter) -> ContentHash {
+    let mut hasher = ContentHasher::new(algo);
+    hasher.update(writer.as_slice());
+    hasher.finish()
+}
+
+/// A group of directories whose subtrees are structurally identical.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateDirGroup {
+    hash_: ContentHash,
+    file_count_: u64,
+    total_size_: u64,
+    paths_: Vec<PathBuf>,
+}
+
+impl DuplicateDirGroup {
+    /// Returns the structural hash shared by the directories.
+    pub fn get_hash(&self) -> &ContentHash {
+        &self.hash_
+    }
+
+    /// Returns the number of regular files in each directory's subtree.
+    pub fn get_file_count(&self) -> u64 {
+        self.file_count_
+    }
+
+    /// Returns the total size of the regular files in each directory's subtree.
+    pub fn get_total_size(&self) -> u64 {
+        self.total_size_
+    }
+
+    /// Returns the sorted relative paths of the directories; there are always at least two.
+    pub fn get_paths(&self) -> &[PathBuf] {
+        &self.paths_
+    }
+
+    /// Returns the bytes that would be freed by keeping a single copy.
+    pub fn get_wasted_bytes(&self) -> u64 {
+        self.total_size_ * (self.paths_.len() as u64 - 1)
+    }
+}
+
+/// Finds directories with identical structural hashes, reporting only the topmost ones.
+///
+/// Directories without any regular file in their subtree, and those holding unreadable files,
+/// are left out. A group is dropped when all its directories sit inside the directories of one
+/// other group, since that group already covers it.
+pub(crate) fn find_duplicate_dirs(indexer: &DirIndexer, options: &MerkleOptions) -> Vec<DuplicateDirGroup> {
+    let tree = MerkleTree::from(indexer, options);
+    let mut by_hash: HashMap<&ContentHash, Vec<&PathBuf>> = HashMap::new();
+    for (rl_path, entry) in tree.get_entries() {
+        if entry.kind == DirEntryKind::Dir && entry.file_count > 0 && entry.complete {
+            by_hash.entry(&entry.hash).or_default().push(rl_path);
+        }
+    }
+    let candidates: Vec<Vec<&PathBuf>> = by_hash.into_values().filter(|paths| paths.len() >= 2).collect();
+    let group_of: HashMap<&Path, usize> = candidates
+        .iter()
+        .enumerate()
+        .flat_map(|(i, paths)| paths.iter().map(move |p| (p.as_path(), i)))
+        .collect();
+
+    let mut groups: Vec<DuplicateDirGroup> = candidates
+        .iter()
+        .filter(|paths| {
+            let parent_group = |p: &&PathBuf| p.parent().and_then(|parent| group_of.get(parent)).copied();
+            let first = parent_group(&paths[0]);
+            first.is_none() || !paths.iter().all(|p| parent_group(p) == first)
+        })
+        .map(|paths| {
+            let entry = &tree.get_entries()[paths[0]];
+            let mut paths: Vec<PathBuf> = paths.iter().map(|p| (*p).clone()).collect();
+            paths.sort();
+            DuplicateDirGroup {
+                hash_: entry.hash.clone(),
+                file_count_: entry.file_count,
+                total_size_: entry.total_size,
+                paths_: paths,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.get_wasted_bytes()
+            .cmp(&a.get_wasted_bytes())
+            .then_with(|| a.paths_.cmp(&b.paths_))
+    });
+    groups
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn vendored(dir: &TempDir, prefix: &str) {
+        dir.write(format!("{}/lib/mod.rs", prefix), b"pub mod inner;");
+        dir.write(format!("{}/lib/inner/code.rs", prefix), b"fn main() {}");
+        dir.write(format!("{}/lib/inner/deeper/data.bin", prefix), &[1, 2, 3]);
+    }
+
+    fn group_paths(group: &DuplicateDirGroup) -> Vec<&str> {
+        group.get_paths().iter().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn only_topmost_duplicate_directories_are_reported() {
+        let dir = TempDir::new("merkle-dupes");
+        vendored(&dir, "one");
+        vendored(&dir, "two/nested");
+        dir.write("one/other", b"not copied");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicate_dirs(&MerkleOptions::new());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(group_paths(&groups[0]), vec!["one/lib", "two/nested/lib"]);
+        assert_eq!(groups[0].get_file_count(), 3);
+        assert_eq!(groups[0].get_total_size(), 14 + 12 + 3);
+        assert_eq!(groups[0].get_wasted_bytes(), 29);
+        assert_eq!(Some(groups[0].get_hash()), indexer.get_merkle_hash(Path::new("one/lib"), &MerkleOptions::new()).as_ref());
+    }
+
+    #[test]
+    fn nested_groups_survive_when_their_parents_differ() {
+        let dir = TempDir::new("merkle-nested");
+        vendored(&dir, "a");
+        vendored(&dir, "b");
+        vendored(&dir, "c");
+        dir.write("c/extra", b"makes c differ");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicate_dirs(&MerkleOptions::new());
+        let listed: Vec<Vec<&str>> = groups.iter().map(group_paths).collect();
+        assert!(listed.contains(&vec!["a/lib", "b/lib", "c/lib"]));
+        assert!(listed.contains(&vec!["a", "b"]));
+        assert!(!listed.iter().any(|paths| paths.contains(&"a/lib/inner")));
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[test]
+    fn names_and_kinds_are_part_of_the_hash() {
+        let dir = TempDir::new("merkle-names");
+        dir.write("a/x", b"same");
+        dir.write("b/y", b"same");
+        dir.write("c/x/z", b"same");
+        dir.write("d/x/z", b"same");
+        fs::create_dir_all(dir.join("e/empty")).unwrap();
+        fs::create_dir_all(dir.join("f/empty")).unwrap();
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let groups = indexer.find_duplicate_dirs(&MerkleOptions::new());
+        let listed: Vec<Vec<&str>> = groups.iter().map(group_paths).collect();
+        // Directories without files are not worth reporting
+        assert_eq!(listed, vec![vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn unreadable_subtrees_are_not_reported() {
+        let dir = TempDir::new("merkle-unreadable");
+        vendored(&dir, "one");
+        vendored(&dir, "two");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        fs::remove_file(dir.join("one/lib/mod.rs")).unwrap();
+        fs::remove_file(dir.join("two/lib/mod.rs")).unwrap();
+
+        let groups = indexer.find_duplicate_dirs(&MerkleOptions::new());
+        let listed: Vec<Vec<&str>> = groups.iter().map(group_paths).collect();
+        assert_eq!(listed, vec![vec!["one/lib/inner", "two/lib/inner"]]);
+        assert!(!indexer.get_merkle_tree(&MerkleOptions::new()).is_complete());
+    }
+
+    fn source_tree(dir: &TempDir) {
+        dir.write("src/main.rs", b"fn main() {}");
+        dir.write("src/util/mod.rs", b"pub fn f() {}");
+        dir.write("README", b"readme");
+        symlink("README", dir.join("link")).unwrap();
+    }
+
+    #[test]
+    fn root_hashes_depend_only_on_contents() {
+        let first = TempDir::new("merkle-root-a");
+        let second = TempDir::new("merkle-root-b");
+        source_tree(&first);
+        source_tree(&second);
+        let options = MerkleOptions::new();
+        let a = DirIndexer::from(first.get_path().clone()).unwrap().get_merkle_tree(&options);
+        let b = DirIndexer::from(second.get_path().clone()).unwrap().get_merkle_tree(&options);
+        assert_eq!(a.get_root_hash(), b.get_root_hash());
+        assert!(a.is_complete());
+        assert!(a.explain_difference(&b).is_empty());
+
+        // A file hashes to its bare content hash
+        let readme = a.get_hash(Path::new("README")).unwrap();
+        assert_eq!(readme, &hasher::hash_bytes(HashAlgo::Sha256, b"readme"));
+        let xxh = MerkleOptions::new().with_hash_algo(HashAlgo::Xxh64);
+        let c = DirIndexer::from(first.get_path().clone()).unwrap().get_merkle_tree(&xxh);
+        assert_eq!(c.get_root_hash().get_algo(), HashAlgo::Xxh64);
+
+        second.write("src/util/mod.rs", b"pub fn g() {}");
+        let b = DirIndexer::from(second.get_path().clone()).unwrap().get_merkle_tree(&options);
+        assert_ne!(a.get_root_hash(), b.get_root_hash());
+        assert_eq!(a.get_hash(Path::new("README")), b.get_hash(Path::new("README")));
+        assert_ne!(a.get_hash(Path::new("src")), b.get_hash(Path::new("src")));
+    }
+
+    #[test]
+    fn subtree_hashes_match_the_full_tree() {
+        let dir = TempDir::new("merkle-subtree");
+        source_tree(&dir);
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let options = MerkleOptions::new().with_mode();
+        let tree = indexer.get_merkle_tree(&options);
+        for rl_path in ["", "src", "src/util", "src/main.rs", "link"].iter() {
+            let rl_path = Path::new(rl_path);
+            assert_eq!(indexer.get_merkle_hash(rl_path, &options).as_ref(), tree.get_hash(rl_path));
+        }
+        assert_eq!(indexer.get_merkle_hash(Path::new("missing"), &options), None);
+        assert_eq!(tree.get_hash(Path::new("missing")), None);
+    }
+
+    #[test]
+    fn selected_attributes_feed_the_hash() {
+        let first = TempDir::new("merkle-attrs-a");
+        let second = TempDir::new("merkle-attrs-b");
+        source_tree(&first);
+        source_tree(&second);
+        first.set_mtime("README", 1_000_000_000);
+        second.set_mtime("README", 1_000_000_001);
+        fs::set_permissions(second.join("src/main.rs"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(first.join("src/main.rs"), fs::Permissions::from_mode(0o644)).unwrap();
+        let a = DirIndexer::from(first.get_path().clone()).unwrap();
+        let b = DirIndexer::from(second.get_path().clone()).unwrap();
+        let root = |indexer: &DirIndexer, options: &MerkleOptions| indexer.get_merkle_hash(Path::new(""), options).unwrap();
+        let file = |indexer: &DirIndexer, options: &MerkleOptions, rl_path: &str| indexer.get_merkle_hash(Path::new(rl_path), options).unwrap();
+
+        let contents = MerkleOptions::new();
+        assert_eq!(root(&a, &contents), root(&b, &contents));
+        let mode = MerkleOptions::new().with_mode();
+        assert!(mode.get_include_mode() && !mode.get_include_mtime());
+        assert_ne!(file(&a, &mode, "src/main.rs"), file(&b, &mode, "src/main.rs"));
+        assert_eq!(file(&a, &mode, "README"), file(&b, &mode, "README"));
+        assert_ne!(file(&a, &mode, "README"), hasher::hash_bytes(HashAlgo::Sha256, b"readme"));
+        let mtime = MerkleOptions::new().with_mtime();
+        assert_ne!(file(&a, &mtime, "README"), file(&b, &mtime, "README"));
+    }
+
+    #[test]
+    fn differences_are_explained_down_to_the_changed_entries() {
+        let first = TempDir::new("merkle-explain-a");
+        let second = TempDir::new("merkle-explain-b");
+        source_tree(&first);
+        source_tree(&second);
+        second.write("src/util/mod.rs", b"changed");
+        second.write("src/new.rs", b"added");
+        fs::remove_file(second.join("README")).unwrap();
+        fs::create_dir(second.join("README")).unwrap();
+        fs::remove_file(second.join("link")).unwrap();
+        let options = MerkleOptions::new();
+        let old = DirIndexer::from(first.get_path().clone()).unwrap().get_merkle_tree(&options);
+        let new = DirIndexer::from(second.get_path().clone()).unwrap().get_merkle_tree(&options);
+
+        let differences = old.explain_difference(&new);
+        let listed: Vec<(&str, &DirChange)> = differences.iter().map(|d| (d.get_path().to_str().unwrap(), d.get_change())).collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("", &DirChange::Modified(DirEntryKind::Dir)),
+                ("link", &DirChange::Removed(DirEntryKind::Symlink)),
+                ("README", &DirChange::TypeChanged(DirEntryKind::File, DirEntryKind::Dir)),
+                ("src", &DirChange::Modified(DirEntryKind::Dir)),
+                ("src/new.rs", &DirChange::Added(DirEntryKind::File)),
+                ("src/util", &DirChange::Modified(DirEntryKind::Dir)),
+                ("src/util/mod.rs", &DirChange::Modified(DirEntryKind::File)),
+            ]
+        );
+        assert_eq!(differences[0].get_old_hash(), Some(old.get_root_hash()));
+        assert_eq!(differences[0].get_new_hash(), Some(new.get_root_hash()));
+        assert_eq!(differences[1].get_new_hash(), None);
+        assert_eq!(differences[4].get_old_hash(), None);
+        assert_eq!(differences[6].get_new_hash(), Some(&hasher::hash_bytes(HashAlgo::Sha256, b"changed")));
+    }
+
+    #[test]
+    fn subtrees_compare_against_each_other() {
+        let dir = TempDir::new("merkle-explain-sub");
+        dir.write("a/x", b"1");
+        dir.write("b/x", b"2");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let root = indexer.get_tree().get_root_node();
+        let options = MerkleOptions::new();
+        let a = MerkleTree::from_node(indexer.get_root_path(), root.get_descendant(Path::new("a")).unwrap(), &options);
+        let b = MerkleTree::from_node(indexer.get_root_path(), root.get_descendant(Path::new("b")).unwrap(), &options);
+        let listed: Vec<PathBuf> = a.explain_difference(&b).iter().map(|d| d.get_path().clone()).collect();
+        assert_eq!(listed, vec![PathBuf::new(), PathBuf::from("x")]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io;
+
+/// A read-only view of a whole file, memory-mapped where the platform allows it.
+///
+/// The mapping is shared, so several processes opening the same file share its pages through the
+/// page cache. Truncating the file while it is mapped is undefined behaviour at the OS level
+/// (`SIGBUS`), which is why index files are always replaced by renaming a new file over them.
+#[derive(Debug)]
+pub(crate) struct Mmap {
+    inner_: MmapInner,
+}
+
+impl Mmap {
+    /// Maps the whole content of `file`.
+    pub(crate) fn open(file: &File) -> io::Result<Mmap> {
+        Ok(Mmap { inner_: MmapInner::open(file)? })
+    }
+
+    /// Returns the mapped bytes.
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.inner_.as_slice()
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+use self::unix_map::MmapInner;
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+use self::read_map::MmapInner;
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod unix_map {
+    use std::fs::File;
+    use std::io;
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    const PROT_READ: c_int = 1;
+    const MAP_SHARED: c_int = 1;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct MmapInner {
+        ptr_: *const u8,
+        len_: usize,
+    }
+
+    // The mapping is read-only and never aliased mutably, so it can be shared between threads.
+    unsafe impl Send for MmapInner {}
+    unsafe impl Sync for MmapInner {}
+
+    impl MmapInner {
+        pub(crate) fn open(file: &File) -> io::Result<MmapInner> {
+            let len = file.metadata()?.len();
+            if len > usize::MAX as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "file too large to map"));
+            }
+            let len = len as usize;
+            if len == 0 {
+                // mmap rejects empty mappings
+                return Ok(MmapInner { ptr_: ptr::null(), len_: 0 });
+            }
+            // SAFETY: a fresh read-only mapping of a valid descriptor; the result is checked below.
+            let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_SHARED, file.as_raw_fd(), 0) };
+            if ptr as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(MmapInner { ptr_: ptr as *const u8, len_: len })
+        }
+
+        pub(crate) fn as_slice(&self) -> &[u8] {
+            if self.len_ == 0 {
+                return &[];
+            }
+            // SAFETY: `ptr_` points to `len_` readable bytes until `drop` unmaps them.
+            unsafe { std::slice::from_raw_parts(self.ptr_, self.len_) }
+        }
+    }
+
+    impl Drop for MmapInner {
+        fn drop(&mut self) {
+            if self.len_ != 0 {
+                // SAFETY: unmaps exactly the region mapped in `open`.
+                unsafe {
+                    munmap(self.ptr_ as *mut c_void, self.len_);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+mod read_map {
+    use std::fs::File;
+    use std::io::{self, Read};
+
+    #[derive(Debug)]
+    pub(crate) struct MmapInner {
+        data_: Vec<u8>,
+    }
+
+    impl MmapInner {
+        pub(crate) fn open(file: &File) -> io::Result<MmapInner> {
+            let mut data = Vec::new();
+            let mut file = file;
+            file.read_to_end(&mut data)?;
+            Ok(MmapInner { data_: data })
+        }
+
+        pub(crate) fn as_slice(&self) -> &[u8] {
+            &self.data_
+        }
+    }
+}
@@ -1,12 +1,82 @@
+#[allow(clippy::module_inception)]
 mod dir_indexer;
 mod dir_tree;
 mod dir_node;
+mod dir_meta;
+mod dir_diff;
+mod dir_usage;
+mod scan_error;
+mod hasher;
+mod codec;
+mod index_file;
+mod mmap;
+mod mapped_index;
+mod json;
+mod tree_json;
+mod users;
+mod time_format;
+mod table_export;
+mod mtree;
+mod checksum_manifest;
+mod hash_cache;
+mod glob;
+mod duplicates;
+mod merkle;
+mod dedupe;
+mod regex;
+mod query;
+mod fuzzy;
+mod path_trie;
+mod trigram_index;
+mod word_index;
+mod tree_render;
+mod dot_export;
+mod html_report;
+#[cfg(unix)]
+mod daemon_protocol;
+#[cfg(unix)]
+mod daemon;
+#[cfg(unix)]
+mod daemon_client;
+#[cfg(unix)]
+mod index_source;
 mod utils;
 mod dir_indexer_err;
+#[cfg(test)]
+mod test_util;
 
 pub use dir_indexer::DirIndexer;
 pub use dir_tree::DirTree;
 pub use dir_node::DirNode;
+pub use dir_meta::{DirEntryKind, DirMeta};
+pub use dir_diff::{DirChange, DirDiff, DirDiffOptions, DirDiffSummary};
+pub use dir_usage::{human_size, DirUsage};
+pub use scan_error::ScanError;
+pub use hasher::{hash_bytes, hash_file, hash_reader, ContentHash, ContentHasher, HashAlgo};
+pub use mapped_index::{MappedEntry, MappedIndex};
+pub use table_export::{DirColumn, DirTableExporter, TableFormat};
+pub use mtree::{MtreeKeyword, MtreeMismatch, MtreeOptions, MtreeReport};
+pub use checksum_manifest::{ChecksumReport, ChecksumStatus};
+pub use hash_cache::{HashCache, HashStats};
+pub use duplicates::{DuplicateGroup, DuplicateOptions};
+pub use merkle::{DuplicateDirGroup, MerkleDifference, MerkleOptions, MerkleTree};
+pub use dedupe::{DedupeOptions, DedupeReport, DedupeSkipReason};
+pub use query::{DirQuery, QueryFileType};
+pub use fuzzy::{FuzzyIndex, FuzzyMatch, FuzzyOptions};
+pub use path_trie::PathTrie;
+pub use trigram_index::{ContentMatch, TrigramIndex, TrigramStats};
+pub use word_index::{WordIndex, WordMatch, WordSearchOptions, WordSnippet};
+pub use tree_render::{TreeAnnotation, TreeCharset, TreeRenderOptions};
+pub use dot_export::{DotLabel, DotOptions};
+pub use html_report::HtmlReportOptions;
+#[cfg(unix)]
+pub use daemon_protocol::default_socket_path;
+#[cfg(unix)]
+pub use daemon::{DaemonOptions, IndexDaemon};
+#[cfg(unix)]
+pub use daemon_client::{DaemonClient, DEFAULT_DAEMON_TIMEOUT};
+#[cfg(unix)]
+pub use index_source::{IndexEntry, IndexSource, IndexStats};
 pub use dir_indexer_err::DirIndexerErr;
 
 pub use utils::get_relative_dir_paths_set;
@@ -0,0 +1,620 @@
+//! Generation and verification of mtree(5) manifests.
+//!
+//! Manifests are written in the full-path form, one line per entry starting with `./`. The
+//! verifier also understands the classic form, where entries without a slash are relative to the
+//! current directory, directories are entered and `..` leaves them, as well as `/set` and `/unset`.
+
+use super::{DirEntryKind, DirIndexer, DirIndexerErr, DirNode, HashAlgo};
+use super::codec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// Represents the mtree keywords that can be written and verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MtreeKeyword {
+    /// The entry type (`file`, `dir`, `link`, `fifo`, `socket`, `char`, `block`).
+    Type,
+    /// The permission bits in octal.
+    Mode,
+    /// The owner user id.
+    Uid,
+    /// The owner group id.
+    Gid,
+    /// The size of regular files in bytes.
+    Size,
+    /// The modification time as `seconds.nanoseconds`.
+    Time,
+    /// The target of symbolic links.
+    Link,
+    /// The SHA-256 digest of regular files, taken from the node when one is stored.
+    Sha256Digest,
+}
+
+impl MtreeKeyword {
+    /// Returns every supported keyword, in output order.
+    pub fn all() -> Vec<MtreeKeyword> {
+        vec![
+            MtreeKeyword::Type,
+            MtreeKeyword::Mode,
+            MtreeKeyword::Uid,
+            MtreeKeyword::Gid,
+            MtreeKeyword::Size,
+            MtreeKeyword::Time,
+            MtreeKeyword::Link,
+            MtreeKeyword::Sha256Digest,
+        ]
+    }
+
+    /// Returns the keyword as written in manifests.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MtreeKeyword::Type => "type",
+            MtreeKeyword::Mode => "mode",
+            MtreeKeyword::Uid => "uid",
+            MtreeKeyword::Gid => "gid",
+            MtreeKeyword::Size => "size",
+            MtreeKeyword::Time => "time",
+            MtreeKeyword::Link => "link",
+            MtreeKeyword::Sha256Digest => "sha256digest",
+        }
+    }
+
+    /// Parses a keyword name; `sha256` is accepted as an alias of `sha256digest`.
+    pub fn from(name: &str) -> Option<MtreeKeyword> {
+        match name {
+            "type" => Some(MtreeKeyword::Type),
+            "mode" => Some(MtreeKeyword::Mode),
+            "uid" => Some(MtreeKeyword::Uid),
+            "gid" => Some(MtreeKeyword::Gid),
+            "size" => Some(MtreeKeyword::Size),
+            "time" => Some(MtreeKeyword::Time),
+            "link" => Some(MtreeKeyword::Link),
+            "sha256digest" | "sha256" => Some(MtreeKeyword::Sha256Digest),
+            _ => None,
+        }
+    }
+}
+
+/// Options selecting the keywords written to, or checked against, an mtree manifest.
+#[derive(Debug, Clone)]
+pub struct MtreeOptions {
+    keywords_: BTreeSet<MtreeKeyword>,
+}
+
+impl Default for MtreeOptions {
+    fn default() -> MtreeOptions {
+        MtreeOptions {
+            keywords_: MtreeKeyword::all().into_iter().collect(),
+        }
+    }
+}
+
+impl MtreeOptions {
+    /// Creates options selecting every supported keyword.
+    pub fn new() -> MtreeOptions {
+        MtreeOptions::default()
+    }
+
+    /// Restricts the options to the given keywords.
+    pub fn with_keywords(mut self, keywords: &[MtreeKeyword]) -> MtreeOptions {
+        self.keywords_ = keywords.iter().copied().collect();
+        self
+    }
+
+    /// Returns `true` if the keyword is selected.
+    pub fn has_keyword(&self, keyword: MtreeKeyword) -> bool {
+        self.keywords_.contains(&keyword)
+    }
+}
+
+/// Describes a keyword whose value in the manifest differs from the indexed entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtreeMismatch {
+    path_: PathBuf,
+    keyword_: MtreeKeyword,
+    expected_: String,
+    actual_: String,
+}
+
+impl MtreeMismatch {
+    /// Returns the relative path of the entry.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns the keyword that differs.
+    pub fn get_keyword(&self) -> MtreeKeyword {
+        self.keyword_
+    }
+
+    /// Returns the value found in the manifest.
+    pub fn get_expected(&self) -> &str {
+        &self.expected_
+    }
+
+    /// Returns the value found in the index.
+    pub fn get_actual(&self) -> &str {
+        &self.actual_
+    }
+}
+
+/// The result of verifying an index against an mtree manifest.
+#[derive(Debug, Clone, Default)]
+pub struct MtreeReport {
+    missing_: Vec<PathBuf>,
+    extra_: Vec<PathBuf>,
+    mismatched_: Vec<MtreeMismatch>,
+}
+
+impl MtreeReport {
+    /// Returns the sorted relative paths listed in the manifest but absent from the index.
+    pub fn get_missing(&self) -> &[PathBuf] {
+        &self.missing_
+    }
+
+    /// Returns the sorted relative paths present in the index but absent from the manifest.
+    pub fn get_extra(&self) -> &[PathBuf] {
+        &self.extra_
+    }
+
+    /// Returns the keyword mismatches, sorted by path.
+    pub fn get_mismatched(&self) -> &[MtreeMismatch] {
+        &self.mismatched_
+    }
+
+    /// Returns `true` if the index matches the manifest.
+    pub fn is_ok(&self) -> bool {
+        self.missing_.is_empty() && self.extra_.is_empty() && self.mismatched_.is_empty()
+    }
+}
+
+/// Writes an mtree manifest of the index.
+pub(crate) fn write_mtree<W: Write>(indexer: &DirIndexer, out: &mut W, options: &MtreeOptions) -> io::Result<()> {
+    out.write_all(b"#mtree\n")?;
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        let mut line = if rl_path.as_os_str().is_empty() {
+            String::from(".")
+        } else {
+            format!("./{}", escape(codec::path_to_bytes(&rl_path)))
+        };
+        for keyword in &options.keywords_ {
+            if let Some(value) = actual_value(indexer, node, *keyword) {
+                line.push(' ');
+                line.push_str(keyword.as_str());
+                line.push('=');
+                line.push_str(&value);
+            }
+        }
+        line.push('\n');
+        out.write_all(line.as_bytes())?;
+    }
+    out.flush()
+}
+
+/// Verifies the index against an mtree manifest.
+pub(crate) fn verify_mtree(indexer: &DirIndexer, spec: &str, options: &MtreeOptions) -> Result<MtreeReport, DirIndexerErr> {
+    let entries = parse_spec(spec)?;
+    let nodes = indexer.get_tree().get_rl2node_map();
+    let mut report = MtreeReport::default();
+    let mut ignored: Vec<&PathBuf> = Vec::new();
+
+    for (rl_path, entry) in &entries {
+        if entry.flags.contains("ignore") {
+            ignored.push(rl_path);
+        }
+        let node = match nodes.get(rl_path) {
+            Some(node) => node,
+            None => {
+                if !entry.flags.contains("optional") {
+                    report.missing_.push(rl_path.clone());
+                }
+                continue;
+            }
+        };
+        for (key, expected) in &entry.keywords {
+            let keyword = match MtreeKeyword::from(key) {
+                Some(keyword) if options.has_keyword(keyword) => keyword,
+                _ => continue,
+            };
+            let actual = actual_value(indexer, node, keyword);
+            if !values_match(keyword, expected, actual.as_deref()) {
+                report.mismatched_.push(MtreeMismatch {
+                    path_: rl_path.clone(),
+                    keyword_: keyword,
+                    expected_: expected.clone(),
+                    actual_: actual.unwrap_or_default(),
+                });
+            }
+        }
+    }
+    for rl_path in nodes.keys() {
+        let under_ignored = ignored.iter().any(|dir| rl_path.starts_with(dir) && rl_path != *dir);
+        if !entries.contains_key(rl_path) && !under_ignored {
+            report.extra_.push(rl_path.clone());
+        }
+    }
+    Ok(report)
+}
+
+/// Computes the manifest value of a keyword for an entry, or `None` if it does not apply.
+fn actual_value(indexer: &DirIndexer, node: &DirNode, keyword: MtreeKeyword) -> Option<String> {
+    let meta = node.get_meta();
+    match keyword {
+        MtreeKeyword::Type => Some(type_name(node).to_string()),
+        MtreeKeyword::Mode => Some(format!("{:04o}", meta.get_permissions())),
+        MtreeKeyword::Uid => Some(meta.get_uid().to_string()),
+        MtreeKeyword::Gid => Some(meta.get_gid().to_string()),
+        MtreeKeyword::Size if node.get_kind() == DirEntryKind::File => Some(meta.get_size().to_string()),
+        MtreeKeyword::Time => Some(format!("{}.{:09}", meta.get_mtime(), meta.get_mtime_nsec())),
+        MtreeKeyword::Link => node.get_link_target().map(|t| escape(codec::path_to_bytes(t))),
+        MtreeKeyword::Sha256Digest if node.get_kind() == DirEntryKind::File => {
+            node.hash_contents(indexer.get_root_path(), HashAlgo::Sha256).ok().map(|h| h.to_hex())
+        }
+        _ => None,
+    }
+}
+
+/// Compares a manifest value with the indexed value, normalizing the keyword's syntax.
+fn values_match(keyword: MtreeKeyword, expected: &str, actual: Option<&str>) -> bool {
+    let actual = match actual {
+        Some(actual) => actual,
+        None => return false,
+    };
+    match keyword {
+        MtreeKeyword::Mode => match (u32::from_str_radix(expected, 8), u32::from_str_radix(actual, 8)) {
+            (Ok(e), Ok(a)) => e & 0o7777 == a,
+            _ => false,
+        },
+        MtreeKeyword::Time => parse_time(expected).is_some() && parse_time(expected) == parse_time(actual),
+        MtreeKeyword::Link => unescape(expected) == unescape(actual),
+        MtreeKeyword::Sha256Digest => expected.eq_ignore_ascii_case(actual),
+        _ => expected == actual,
+    }
+}
+
+/// Parses `seconds[.fraction]` into seconds and nanoseconds.
+fn parse_time(value: &str) -> Option<(i64, u32)> {
+    let mut parts = value.splitn(2, '.');
+    let secs = parts.next()?.parse::<i64>().ok()?;
+    let nanos = match parts.next() {
+        Some(fraction) if fraction.len() <= 9 && fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{:0<9}", fraction).parse::<u32>().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some((secs, nanos))
+}
+
+fn type_name(node: &DirNode) -> &'static str {
+    match node.get_kind() {
+        DirEntryKind::File => "file",
+        DirEntryKind::Dir => "dir",
+        DirEntryKind::Symlink => "link",
+        DirEntryKind::Other => match node.get_meta().get_mode() & S_IFMT {
+            S_IFIFO => "fifo",
+            S_IFSOCK => "socket",
+            S_IFCHR => "char",
+            S_IFBLK => "block",
+            _ => "file",
+        },
+    }
+}
+
+/// A parsed manifest entry: its keywords (after applying `/set` defaults) and value-less flags.
+struct SpecEntry {
+    keywords: BTreeMap<String, String>,
+    flags: BTreeSet<String>,
+}
+
+fn parse_spec(spec: &str) -> Result<BTreeMap<PathBuf, SpecEntry>, DirIndexerErr> {
+    let mut entries: BTreeMap<PathBuf, SpecEntry> = BTreeMap::new();
+    let mut defaults: BTreeMap<String, String> = BTreeMap::new();
+    let mut cwd = PathBuf::new();
+    let mut logical = String::new();
+
+    for (line_no, raw_line) in spec.lines().enumerate() {
+        // A line ending in an unescaped backslash continues on the next line
+        let trailing = raw_line.len() - raw_line.trim_end_matches('\\').len();
+        if trailing % 2 == 1 {
+            logical.push_str(&raw_line[..raw_line.len() - 1]);
+            logical.push(' ');
+            continue;
+        }
+        logical.push_str(raw_line);
+        let line = std::mem::take(&mut logical);
+        let fail = |reason: &str| DirIndexerErr::ManifestParseFail(format!("line {}: {}", line_no + 1, reason));
+
+        let mut tokens = line.split_whitespace();
+        let first = match tokens.next() {
+            Some(first) if !first.starts_with('#') => first,
+            _ => continue,
+        };
+        match first {
+            "/set" => {
+                for token in tokens {
+                    let (key, value) = split_keyword(token).ok_or_else(|| fail("expected keyword=value"))?;
+                    defaults.insert(key.to_string(), value.to_string());
+                }
+            }
+            "/unset" => {
+                for key in tokens {
+                    if key == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(key);
+                    }
+                }
+            }
+            ".." => {
+                if !cwd.pop() {
+                    return Err(fail("'..' above the root"));
+                }
+            }
+            _ => {
+                let name = codec::bytes_to_path(&unescape(first)).ok_or_else(|| fail("invalid path"))?;
+                let full_path = first.contains('/');
+                let rl_path = if full_path {
+                    codec::normalize_relative(&name).ok_or_else(|| fail("path escapes the root"))?
+                } else {
+                    cwd.join(codec::normalize_relative(&name).ok_or_else(|| fail("invalid name"))?)
+                };
+                let mut entry = SpecEntry {
+                    keywords: defaults.clone(),
+                    flags: BTreeSet::new(),
+                };
+                for token in tokens {
+                    match split_keyword(token) {
+                        Some((key, value)) => {
+                            entry.keywords.insert(key.to_string(), value.to_string());
+                        }
+                        None => {
+                            entry.flags.insert(token.to_string());
+                        }
+                    }
+                }
+                let is_dir = entry.keywords.get("type").map(String::as_str) == Some("dir");
+                if !full_path && is_dir && first != "." {
+                    cwd = rl_path.clone();
+                }
+                entries.insert(rl_path, entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn split_keyword(token: &str) -> Option<(&str, &str)> {
+    let mut parts = token.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Some((key, value)),
+        _ => None,
+    }
+}
+
+/// Encodes bytes the way mtree expects: whitespace, non-printable bytes, `#`, `=` and `\` become
+/// `\ooo` octal escapes.
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for byte in bytes {
+        if *byte <= b' ' || *byte >= 0x7f || matches!(byte, b'\\' | b'#' | b'=') {
+            out.push_str(&format!("\\{:03o}", byte));
+        } else {
+            out.push(*byte as char);
+        }
+    }
+    out
+}
+
+/// Decodes `\ooo` octal escapes and the usual C-style escapes.
+fn unescape(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 >= bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let octal = &bytes[i + 1..bytes.len().min(i + 4)];
+        if octal.len() == 3 && octal.iter().all(|b| (b'0'..=b'7').contains(b)) {
+            let value = octal.iter().fold(0u32, |acc, b| acc * 8 + (b - b'0') as u32);
+            out.push(value as u8);
+            i += 4;
+            continue;
+        }
+        out.push(match bytes[i + 1] {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b's' => b' ',
+            b'b' => 8,
+            b'f' => 12,
+            b'v' => 11,
+            b'a' => 7,
+            other => other,
+        });
+        i += 2;
+    }
+    out
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::{hash_bytes, HashCache};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    fn sample_indexer(dir: &TempDir) -> DirIndexer {
+        dir.write("a.txt", b"alpha");
+        dir.write("sub/b c#=.txt", b"beta");
+        symlink("a.txt", dir.join("link")).unwrap();
+        DirIndexer::from(dir.get_path().clone()).unwrap()
+    }
+
+    fn manifest(indexer: &DirIndexer, options: &MtreeOptions) -> String {
+        let mut out = Vec::new();
+        indexer.write_mtree(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn keyword_names_round_trip() {
+        for keyword in MtreeKeyword::all() {
+            assert_eq!(MtreeKeyword::from(keyword.as_str()), Some(keyword));
+        }
+        assert_eq!(MtreeKeyword::from("sha256"), Some(MtreeKeyword::Sha256Digest));
+        assert_eq!(MtreeKeyword::from("md5digest"), None);
+    }
+
+    #[test]
+    fn generated_manifests_verify_cleanly() {
+        let dir = TempDir::new("mtree-generate");
+        let indexer = sample_indexer(&dir);
+        let options = MtreeOptions::new();
+        let text = manifest(&indexer, &options);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "#mtree");
+        assert!(lines[1].starts_with(". type=dir mode="));
+        let file_line = lines.iter().find(|line| line.starts_with("./a.txt ")).unwrap();
+        assert!(file_line.contains(" size=5 "));
+        assert!(file_line.ends_with(&format!(" sha256digest={}", hash_bytes(HashAlgo::Sha256, b"alpha").to_hex())));
+        assert!(lines.iter().any(|line| line.starts_with("./link type=link ") && line.contains(" link=a.txt")));
+        assert!(lines.iter().any(|line| line.starts_with("./sub/b\\040c\\043\\075.txt ")));
+        assert_eq!(lines.len(), 1 + indexer.get_tree().get_rl2node_map().len());
+
+        let report = indexer.verify_mtree(&text, &options).unwrap();
+        assert!(report.is_ok(), "{:?}", report.get_mismatched());
+    }
+
+    #[test]
+    fn stored_sha256_hashes_are_not_read_again() {
+        let dir = TempDir::new("mtree-stored-hash");
+        let mut indexer = sample_indexer(&dir);
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        let options = MtreeOptions::new().with_keywords(&[MtreeKeyword::Sha256Digest]);
+        let text = manifest(&indexer, &options);
+        assert!(text.lines().any(|line| line == format!("./a.txt sha256digest={}", hash_bytes(HashAlgo::Sha256, b"alpha").to_hex())));
+        assert!(indexer.verify_mtree(&text, &options).unwrap().is_ok());
+
+        // Hashes of another algorithm are of no use, so the file is read
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Xxh64), 1);
+        assert!(manifest(&indexer, &options).lines().any(|line| line == "./a.txt"));
+    }
+
+    #[test]
+    fn selected_keywords_limit_output_and_checks() {
+        let dir = TempDir::new("mtree-keywords");
+        let indexer = sample_indexer(&dir);
+        let options = MtreeOptions::new().with_keywords(&[MtreeKeyword::Type, MtreeKeyword::Size]);
+        let text = manifest(&indexer, &options);
+        assert!(text.lines().any(|line| line == "./a.txt type=file size=5"));
+        assert!(text.lines().any(|line| line == "./sub type=dir"));
+
+        // Keywords that are not selected are not compared
+        let spec = text.replace("./a.txt type=file size=5", "./a.txt type=file size=5 mode=0000 uid=12345");
+        assert!(indexer.verify_mtree(&spec, &options).unwrap().is_ok());
+        assert!(!indexer.verify_mtree(&spec, &MtreeOptions::new()).unwrap().is_ok());
+    }
+
+    #[test]
+    fn differences_are_reported() {
+        let dir = TempDir::new("mtree-differences");
+        let indexer = sample_indexer(&dir);
+        let options = MtreeOptions::new().with_keywords(&[MtreeKeyword::Type, MtreeKeyword::Size]);
+        let text = manifest(&indexer, &options)
+            .replace("./a.txt type=file size=5", "./a.txt type=file size=6")
+            .replace("./link type=link\n", "")
+            + "./gone type=file size=1\n./maybe type=file optional\n";
+
+        let report = indexer.verify_mtree(&text, &options).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.get_missing(), &[PathBuf::from("gone")]);
+        assert_eq!(report.get_extra(), &[PathBuf::from("link")]);
+        let mismatch = &report.get_mismatched()[0];
+        assert_eq!(report.get_mismatched().len(), 1);
+        assert_eq!(mismatch.get_path(), &PathBuf::from("a.txt"));
+        assert_eq!(mismatch.get_keyword(), MtreeKeyword::Size);
+        assert_eq!(mismatch.get_expected(), "6");
+        assert_eq!(mismatch.get_actual(), "5");
+
+        // Only the directory itself is checked below an ignored entry
+        let spec = ". type=dir\n./a.txt type=file\n./link type=link\n./sub type=dir ignore\n";
+        assert!(indexer.verify_mtree(spec, &options).unwrap().is_ok());
+    }
+
+    #[test]
+    fn classic_manifests_are_understood() {
+        let dir = TempDir::new("mtree-classic");
+        let indexer = sample_indexer(&dir);
+        let spec = "\
+#mtree
+/set type=file
+. type=dir
+a.txt size=5
+link type=link \\
+    link=a.txt
+sub type=dir
+    b\\sc\\043\\075.txt size=4
+/unset all
+..
+";
+        let options = MtreeOptions::new().with_keywords(&[MtreeKeyword::Type, MtreeKeyword::Size, MtreeKeyword::Link]);
+        let report = indexer.verify_mtree(spec, &options).unwrap();
+        assert!(report.is_ok(), "{:?} {:?} {:?}", report.get_missing(), report.get_extra(), report.get_mismatched());
+
+        let wrong_level = spec.replace("/unset all\n..\n", "..\n..\n");
+        match indexer.verify_mtree(&wrong_level, &options) {
+            Err(DirIndexerErr::ManifestParseFail(reason)) => assert_eq!(reason, "line 10: '..' above the root"),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn malformed_manifests_are_rejected() {
+        let dir = TempDir::new("mtree-malformed");
+        let indexer = sample_indexer(&dir);
+        let options = MtreeOptions::new();
+        for spec in ["..\n", "/set =file\n", "/set type\n", "./../escape type=file\n", "/abs/path type=file\n"].iter() {
+            assert!(matches!(indexer.verify_mtree(spec, &options), Err(DirIndexerErr::ManifestParseFail(_))), "accepted {:?}", spec);
+        }
+    }
+
+    #[test]
+    fn escaping_round_trips_every_byte() {
+        let bytes: Vec<u8> = (0..=255u8).collect();
+        let escaped = escape(&bytes);
+        assert!(escaped.bytes().all(|b| b > b' ' && b < 0x7f && b != b'#' && b != b'='));
+        assert_eq!(unescape(&escaped), bytes);
+        assert_eq!(unescape("a\\sb\\tc\\n\\\\"), b"a b\tc\n\\".to_vec());
+        assert_eq!(unescape("trailing\\"), b"trailing\\".to_vec());
+        assert_eq!(unescape("\\12"), b"12".to_vec());
+    }
+
+    #[test]
+    fn values_are_compared_in_normal_form() {
+        assert!(values_match(MtreeKeyword::Mode, "100644", Some("0644")));
+        assert!(values_match(MtreeKeyword::Mode, "644", Some("0644")));
+        assert!(!values_match(MtreeKeyword::Mode, "9", Some("0644")));
+        assert!(values_match(MtreeKeyword::Time, "12.5", Some("12.500000000")));
+        assert!(values_match(MtreeKeyword::Time, "12", Some("12.000000000")));
+        assert!(!values_match(MtreeKeyword::Time, "12.1234567890", Some("12.123456789")));
+        assert!(!values_match(MtreeKeyword::Time, "x", Some("x")));
+        assert!(values_match(MtreeKeyword::Sha256Digest, "ABCDEF", Some("abcdef")));
+        assert!(values_match(MtreeKeyword::Link, "a\\040b", Some("a\\sb")));
+        assert!(!values_match(MtreeKeyword::Size, "1", None));
+        assert_eq!(parse_time("-5.25"), Some((-5, 250_000_000)));
+        assert_eq!(parse_time("5.-1"), None);
+    }
+}
@@ -0,0 +1,460 @@
+use super::{codec, DirChange, DirDiff, DirEntryKind, DirNode, DirTree};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+struct TrieNode {
+    kind_: DirEntryKind,
+    children_: BTreeMap<OsString, TrieNode>,
+}
+
+impl TrieNode {
+    fn new(kind: DirEntryKind) -> TrieNode {
+        TrieNode {
+            kind_: kind,
+            children_: BTreeMap::new(),
+        }
+    }
+
+    fn from_dir_node(node: &DirNode) -> TrieNode {
+        let mut trie_node = TrieNode::new(node.get_kind());
+        for child in node.get_children() {
+            trie_node.children_.insert(child.get_name().to_os_string(), TrieNode::from_dir_node(child));
+        }
+        trie_node
+    }
+
+    /// Returns the number of entries in the subtree, including this one.
+    fn count(&self) -> usize {
+        1 + self.children_.values().map(TrieNode::count).sum::<usize>()
+    }
+
+    /// Appends the subtree below this node, as `rl_path` and its descendants, in sorted order.
+    fn collect(&self, rl_path: &Path, out: &mut Vec<(PathBuf, DirEntryKind)>) {
+        for (name, child) in &self.children_ {
+            let child_path = rl_path.join(name);
+            out.push((child_path.clone(), child.kind_));
+            child.collect(&child_path, out);
+        }
+    }
+}
+
+/// A trie of relative paths, one level per path component, for prefix completion.
+///
+/// Each level keeps its children in a sorted map, so a prefix such as `src/dir_in` is answered by
+/// walking down to `src` and scanning the range of names starting with `dir_in`. The trie copies
+/// only names and kinds from the tree, and can follow later changes with `apply_diff`.
+#[derive(Debug, Clone)]
+pub struct PathTrie {
+    root_: TrieNode,
+    len_: usize,
+}
+
+impl Default for PathTrie {
+    fn default() -> PathTrie {
+        PathTrie {
+            root_: TrieNode::new(DirEntryKind::Dir),
+            len_: 0,
+        }
+    }
+}
+
+impl PathTrie {
+    /// Creates an empty trie.
+    pub fn new() -> PathTrie {
+        PathTrie::default()
+    }
+
+    /// Builds a trie holding every entry of a tree except its root.
+    pub fn from(tree: &DirTree) -> PathTrie {
+        let root = TrieNode::from_dir_node(tree.get_root_node());
+        let len = root.count() - 1;
+        PathTrie { root_: root, len_: len }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.len_
+    }
+
+    /// Returns `true` if the trie holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len_ == 0
+    }
+
+    fn get_node(&self, rl_path: &Path) -> Option<&TrieNode> {
+        let rl_path = codec::normalize_relative(rl_path)?;
+        let mut node = &self.root_;
+        for name in rl_path.iter() {
+            node = node.children_.get(name)?;
+        }
+        Some(node)
+    }
+
+    /// Returns `true` if the trie holds an entry at `rl_path`.
+    pub fn contains(&self, rl_path: &Path) -> bool {
+        self.get_kind(rl_path).is_some()
+    }
+
+    /// Returns the kind of the entry at `rl_path`, or `None` if there is none.
+    pub fn get_kind(&self, rl_path: &Path) -> Option<DirEntryKind> {
+        if rl_path.as_os_str().is_empty() {
+            return None;
+        }
+        self.get_node(rl_path).map(|node| node.kind_)
+    }
+
+    /// Adds an entry, or changes the kind of an existing one. Missing parents are added as
+    /// directories.
+    ///
+    /// # Returns
+    ///
+    /// `false` if `rl_path` is empty, absolute or contains `..`.
+    pub fn insert(&mut self, rl_path: &Path, kind: DirEntryKind) -> bool {
+        self.insert_node(rl_path, TrieNode::new(kind))
+    }
+
+    /// Inserts a subtree at `rl_path`. An existing entry keeps its children and takes the kind of
+    /// the new node; new children are merged in.
+    fn insert_node(&mut self, rl_path: &Path, subtree: TrieNode) -> bool {
+        let rl_path = match codec::normalize_relative(rl_path) {
+            Some(rl_path) if !rl_path.as_os_str().is_empty() => rl_path,
+            _ => return false,
+        };
+        let names: Vec<&OsStr> = rl_path.iter().collect();
+        let mut added = 0;
+        let mut node = &mut self.root_;
+        for name in &names[..names.len() - 1] {
+            node = node.children_.entry(name.to_os_string()).or_insert_with(|| {
+                added += 1;
+                TrieNode::new(DirEntryKind::Dir)
+            });
+        }
+        let last = names[names.len() - 1].to_os_string();
+        match node.children_.get_mut(&last) {
+            Some(existing) => {
+                let before = existing.count();
+                existing.kind_ = subtree.kind_;
+                existing.children_.extend(subtree.children_);
+                self.len_ = self.len_ + added + existing.count() - before;
+            }
+            None => {
+                added += subtree.count();
+                node.children_.insert(last, subtree);
+                self.len_ += added;
+            }
+        }
+        true
+    }
+
+    /// Removes an entry together with everything below it.
+    ///
+    /// # Returns
+    ///
+    /// `true` if there was an entry at `rl_path`.
+    pub fn remove(&mut self, rl_path: &Path) -> bool {
+        self.take(rl_path).is_some()
+    }
+
+    fn take(&mut self, rl_path: &Path) -> Option<TrieNode> {
+        let rl_path = codec::normalize_relative(rl_path)?;
+        let names: Vec<&OsStr> = rl_path.iter().collect();
+        let (last, parents) = names.split_last()?;
+        let mut node = &mut self.root_;
+        for name in parents {
+            node = node.children_.get_mut(*name)?;
+        }
+        let removed = node.children_.remove(*last)?;
+        self.len_ -= removed.count();
+        Some(removed)
+    }
+
+    /// Completes the last component of a path, as a shell does.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Complete components followed by a partial name, such as `src/dir_in`. A
+    ///   prefix ending in `/` lists the whole directory.
+    ///
+    /// # Returns
+    ///
+    /// The entries in that directory whose name starts with the partial name, sorted.
+    pub fn complete(&self, prefix: &Path) -> Vec<(PathBuf, DirEntryKind)> {
+        let mut out = Vec::new();
+        if let Some((dir, partial)) = split_prefix(prefix) {
+            if let Some(node) = self.get_node(&dir) {
+                for (name, child) in matching_children(node, partial) {
+                    out.push((dir.join(name), child.kind_));
+                }
+            }
+        }
+        out
+    }
+
+    /// Lists every entry whose relative path starts with `prefix`, including whole subtrees of
+    /// matching directories.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Complete components followed by a partial name, such as `src/dir_in`. An
+    ///   empty prefix lists everything.
+    ///
+    /// # Returns
+    ///
+    /// The matching entries sorted by relative path.
+    pub fn get_prefixed(&self, prefix: &Path) -> Vec<(PathBuf, DirEntryKind)> {
+        let mut out = Vec::new();
+        if let Some((dir, partial)) = split_prefix(prefix) {
+            if let Some(node) = self.get_node(&dir) {
+                for (name, child) in matching_children(node, partial) {
+                    let child_path = dir.join(name);
+                    out.push((child_path.clone(), child.kind_));
+                    child.collect(&child_path, &mut out);
+                }
+            }
+        }
+        out
+    }
+
+    /// Brings the trie up to date with the changes in a diff, such as the one returned by
+    /// `DirIndexer::refresh`. Renamed directories are moved with their whole subtree.
+    pub fn apply_diff(&mut self, diff: &DirDiff) {
+        // Removed paths and rename sources are detached deepest first: a removal below a renamed
+        // directory happens before the directory moves, and a directory renamed out of a removed
+        // one is detached before its old parent goes
+        let mut detached: Vec<(&PathBuf, Option<(&PathBuf, DirEntryKind)>)> = Vec::new();
+        for (rl_path, change) in diff.get_changes() {
+            match change {
+                DirChange::Removed(_) => detached.push((rl_path, None)),
+                DirChange::Renamed(kind, from) => detached.push((from, Some((rl_path, *kind)))),
+                _ => {}
+            }
+        }
+        detached.sort_by_key(|(from, _)| std::cmp::Reverse(from.components().count()));
+        let mut moved: Vec<(&PathBuf, TrieNode)> = Vec::new();
+        for (from, rename) in detached {
+            let subtree = self.take(from);
+            if let Some((to, kind)) = rename {
+                let mut subtree = subtree.unwrap_or_else(|| TrieNode::new(kind));
+                subtree.kind_ = kind;
+                moved.push((to, subtree));
+            }
+        }
+        moved.sort_by_key(|(to, _)| to.components().count());
+        for (to, subtree) in moved {
+            self.insert_node(to, subtree);
+        }
+
+        for (rl_path, change) in diff.get_changes() {
+            match change {
+                DirChange::Added(kind) => {
+                    self.insert(rl_path, *kind);
+                }
+                DirChange::TypeChanged(_, kind) => {
+                    if *kind != DirEntryKind::Dir {
+                        self.remove(rl_path);
+                    }
+                    self.insert(rl_path, *kind);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Splits a completion prefix into its normalized directory and the partial name that follows.
+/// Returns `None` for a directory that cannot be in the trie, such as one containing `..`.
+fn split_prefix(prefix: &Path) -> Option<(PathBuf, &[u8])> {
+    let bytes = codec::path_to_bytes(prefix);
+    let (dir, partial) = match bytes.iter().rposition(|b| *b == b'/') {
+        Some(slash) => (&bytes[..slash], &bytes[slash + 1..]),
+        None => (&bytes[..0], bytes),
+    };
+    let dir = codec::normalize_relative(&codec::bytes_to_path(dir)?)?;
+    Some((dir, partial))
+}
+
+/// Returns the children of `node` whose name starts with `partial`, in sorted order.
+fn matching_children<'a>(node: &'a TrieNode, partial: &'a [u8]) -> impl Iterator<Item = (&'a OsString, &'a TrieNode)> + 'a {
+    let start = codec::bytes_to_path(partial).map(PathBuf::into_os_string).unwrap_or_default();
+    node.children_
+        .range(start..)
+        .take_while(move |(name, _)| codec::path_to_bytes(Path::new(name.as_os_str())).starts_with(partial))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::{DirDiffOptions, DirIndexer};
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn entries(trie: &PathTrie) -> Vec<(PathBuf, DirEntryKind)> {
+        trie.get_prefixed(Path::new(""))
+    }
+
+    fn names(found: Vec<(PathBuf, DirEntryKind)>) -> Vec<String> {
+        found.into_iter().map(|(p, _)| p.to_string_lossy().into_owned()).collect()
+    }
+
+    fn sample() -> PathTrie {
+        let mut trie = PathTrie::new();
+        for path in ["src/dir_indexer/mod.rs", "src/dir_indexer.rs", "src/dir_tree.rs", "src/main.rs", "README.md"].iter() {
+            trie.insert(Path::new(path), DirEntryKind::File);
+        }
+        trie
+    }
+
+    /// Checks that applying the diff between two scans of `dir` to a trie of the first scan gives
+    /// the trie of the second.
+    fn assert_follows<F: FnOnce()>(dir: &TempDir, change: F) {
+        let old = DirIndexer::from(dir.get_path().clone()).unwrap();
+        change();
+        let new = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let rebuilt = PathTrie::from(new.get_tree());
+        for options in [DirDiffOptions::new(), DirDiffOptions::new().with_renames()].iter() {
+            let mut trie = PathTrie::from(old.get_tree());
+            trie.apply_diff(&old.diff_with(&new, options));
+            assert_eq!(entries(&trie), entries(&rebuilt));
+            assert_eq!(trie.len(), rebuilt.len());
+        }
+    }
+
+    #[test]
+    fn completes_the_last_component() {
+        let trie = sample();
+        assert_eq!(trie.len(), 7);
+        assert_eq!(names(trie.complete(Path::new("src/dir_"))), vec!["src/dir_indexer", "src/dir_indexer.rs", "src/dir_tree.rs"]);
+        assert_eq!(names(trie.complete(Path::new("src/dir_indexer/"))), vec!["src/dir_indexer/mod.rs"]);
+        assert_eq!(names(trie.complete(Path::new(""))), vec!["README.md", "src"]);
+        assert_eq!(names(trie.complete(Path::new("./src/m"))), vec!["src/main.rs"]);
+        assert!(trie.complete(Path::new("src/x")).is_empty());
+        assert!(trie.complete(Path::new("nowhere/")).is_empty());
+        assert!(trie.complete(Path::new("../src/")).is_empty());
+        assert!(trie.complete(Path::new("/src/")).is_empty());
+        assert_eq!(trie.complete(Path::new("src/dir_indexer"))[0].1, DirEntryKind::Dir);
+    }
+
+    #[test]
+    fn lists_whole_prefixed_subtrees() {
+        let trie = sample();
+        assert_eq!(
+            names(trie.get_prefixed(Path::new("src/dir_i"))),
+            vec!["src/dir_indexer", "src/dir_indexer/mod.rs", "src/dir_indexer.rs"]
+        );
+        assert_eq!(entries(&trie).len(), trie.len());
+        assert!(trie.get_prefixed(Path::new("src/z")).is_empty());
+    }
+
+    #[test]
+    fn completes_names_that_are_not_utf8() {
+        let mut trie = PathTrie::new();
+        let name = Path::new(OsStr::from_bytes(b"caf\xe9/menu"));
+        assert!(trie.insert(name, DirEntryKind::File));
+        assert!(trie.insert(Path::new("cafeteria"), DirEntryKind::File));
+        assert_eq!(trie.complete(Path::new("caf")).len(), 2);
+        let prefix = Path::new(OsStr::from_bytes(b"caf\xe9/"));
+        assert_eq!(trie.complete(prefix), vec![(name.to_path_buf(), DirEntryKind::File)]);
+    }
+
+    #[test]
+    fn inserts_and_removes_entries() {
+        let mut trie = sample();
+        assert!(trie.insert(Path::new("a/b/c"), DirEntryKind::Symlink));
+        assert_eq!(trie.len(), 10);
+        assert_eq!(trie.get_kind(Path::new("a/b")), Some(DirEntryKind::Dir));
+        assert_eq!(trie.get_kind(Path::new("./a/b/c")), Some(DirEntryKind::Symlink));
+        assert!(trie.insert(Path::new("a/b/c"), DirEntryKind::File));
+        assert_eq!(trie.len(), 10);
+        assert!(!trie.insert(Path::new(""), DirEntryKind::File));
+        assert!(!trie.insert(Path::new("/etc"), DirEntryKind::File));
+        assert!(!trie.insert(Path::new("a/../b"), DirEntryKind::File));
+        assert!(!trie.contains(Path::new("")));
+        assert!(!trie.contains(Path::new("a/..")));
+
+        assert!(trie.remove(Path::new("src")));
+        assert!(!trie.remove(Path::new("src")));
+        assert!(!trie.remove(Path::new("")));
+        assert_eq!(trie.len(), 4);
+        assert_eq!(names(entries(&trie)), vec!["README.md", "a", "a/b", "a/b/c"]);
+        assert!(PathTrie::new().is_empty());
+    }
+
+    #[test]
+    fn builds_from_a_tree() {
+        let dir = TempDir::new("trie-from");
+        dir.write("a/b.txt", b"");
+        dir.write("c.txt", b"");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let trie = PathTrie::from(indexer.get_tree());
+        assert_eq!(names(entries(&trie)), vec!["a", "a/b.txt", "c.txt"]);
+        assert_eq!(trie.get_kind(Path::new("a")), Some(DirEntryKind::Dir));
+    }
+
+    #[test]
+    fn applying_diffs_matches_a_rebuild() {
+        let dir = TempDir::new("trie-diff");
+        for path in ["a/x.txt", "a/b/y.txt", "c/z.txt", "d.txt", "e/f.txt", "g/h/i.txt", "k.txt"].iter() {
+            dir.write(path, path.as_bytes());
+        }
+        let root = dir.get_path().clone();
+        assert_follows(&dir, || {
+            // A moved directory, a rename nested inside it and a file moved into it
+            fs::rename(root.join("a"), root.join("m")).unwrap();
+            fs::rename(root.join("m/b"), root.join("m/bb")).unwrap();
+            fs::rename(root.join("k.txt"), root.join("m/k.txt")).unwrap();
+            // A directory moved out of one that is then removed
+            fs::rename(root.join("g/h"), root.join("h2")).unwrap();
+            fs::remove_dir_all(root.join("g")).unwrap();
+            fs::remove_dir_all(root.join("c")).unwrap();
+            // Type changes both ways
+            fs::remove_file(root.join("d.txt")).unwrap();
+            dir.write("d.txt/new.txt", b"");
+            fs::remove_dir_all(root.join("e")).unwrap();
+            dir.write("e", b"");
+            dir.write("n/o/p.txt", b"");
+        });
+        assert_follows(&dir, || {
+            // Swap two directories through a temporary name
+            fs::rename(root.join("m"), root.join("tmp")).unwrap();
+            fs::rename(root.join("h2"), root.join("m")).unwrap();
+            fs::rename(root.join("tmp"), root.join("h2")).unwrap();
+        });
+        assert_follows(&dir, || {
+            // Removals inside a moved directory, one of them holding an entry moved out of it
+            fs::rename(root.join("h2"), root.join("q")).unwrap();
+            fs::remove_file(root.join("q/x.txt")).unwrap();
+            fs::rename(root.join("q/bb/y.txt"), root.join("y.txt")).unwrap();
+            fs::remove_dir_all(root.join("q/bb")).unwrap();
+        });
+    }
+
+    #[test]
+    fn applying_random_diffs_matches_a_rebuild() {
+        let dir = TempDir::new("trie-random");
+        let root = dir.get_path().clone();
+        let candidates = ["a", "b", "a/c", "b/c", "a/c/d", "b/d", "e", "a/e"];
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |n: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+        for _ in 0..40 {
+            let ops: Vec<(usize, usize, usize)> = (0..3).map(|_| (next(4), next(candidates.len()), next(candidates.len()))).collect();
+            assert_follows(&dir, || {
+                for (op, a, b) in ops {
+                    let (a, b) = (root.join(candidates[a]), root.join(candidates[b]));
+                    // Operations that do not apply to the current tree simply fail
+                    let _ = match op {
+                        0 => fs::write(&a, b"data"),
+                        1 => fs::create_dir_all(&a),
+                        2 => fs::remove_dir_all(&a).or_else(|_| fs::remove_file(&a)),
+                        _ => fs::rename(&a, &b),
+                    };
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,820 @@
+//! A `find`-like query language over an indexed tree.
+//!
+//! Queries are evaluated against the recorded metadata only and never touch the disk. The string
+//! form accepts these primaries, joined by `-a`/`-and` (or nothing), `-o`/`-or`, negated with
+//! `!`/`-not` and grouped with `(` `)`:
+//!
+//! * `-name GLOB`, `-iname GLOB` - the file name matches the glob.
+//! * `-path GLOB`, `-ipath GLOB` - the relative path matches the glob; use `**` to cross `/`.
+//! * `-regex RE`, `-iregex RE` - the regex matches somewhere in the relative path.
+//! * `-type f,d,l,p,s,c,b` - the entry is one of the listed types.
+//! * `-size [+-]N[bckMGT]` - more than, less than or exactly `N` units; a bare number is bytes.
+//! * `-mtime [+-]N`, `-mmin [+-]N` - age in whole days or minutes, as `find` counts it.
+//! * `-perm MODE`, `-perm -MODE`, `-perm /MODE` - octal permissions: exact, all set, any set.
+//! * `-user NAME`, `-group NAME`, `-uid N`, `-gid N` - the owner; names may also be ids.
+//! * `-mindepth N`, `-maxdepth N`, `-depth [+-]N` - entries directly under the root have depth 1.
+//! * `-empty`, `-true`, `-false`.
+//!
+//! Arguments can be quoted with `'` or `"`, and a backslash escapes the next character.
+
+use super::{DirEntryKind, DirIndexerErr, DirNode};
+use super::codec;
+use super::glob::GlobPattern;
+use super::regex::Regex;
+use super::users::UserTable;
+use std::ops::Not;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// Maximum nesting of parentheses and negations accepted by the parser.
+const MAX_DEPTH: usize = 256;
+
+const NANOS_PER_MINUTE: i128 = 60 * 1_000_000_000;
+const NANOS_PER_DAY: i128 = 24 * 60 * NANOS_PER_MINUTE;
+
+/// The entry types a query can test for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryFileType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl QueryFileType {
+    fn from_letter(letter: &str) -> Option<QueryFileType> {
+        match letter {
+            "f" => Some(QueryFileType::File),
+            "d" => Some(QueryFileType::Dir),
+            "l" => Some(QueryFileType::Symlink),
+            "p" => Some(QueryFileType::Fifo),
+            "s" => Some(QueryFileType::Socket),
+            "c" => Some(QueryFileType::CharDevice),
+            "b" => Some(QueryFileType::BlockDevice),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, node: &DirNode) -> bool {
+        let type_bits = node.get_meta().get_mode() & S_IFMT;
+        match (self, node.get_kind()) {
+            (QueryFileType::File, kind) => kind == DirEntryKind::File,
+            (QueryFileType::Dir, kind) => kind == DirEntryKind::Dir,
+            (QueryFileType::Symlink, kind) => kind == DirEntryKind::Symlink,
+            (_, DirEntryKind::Other) => match self {
+                QueryFileType::Fifo => type_bits == S_IFIFO,
+                QueryFileType::Socket => type_bits == S_IFSOCK,
+                QueryFileType::CharDevice => type_bits == S_IFCHR,
+                _ => type_bits == S_IFBLK,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PermTest {
+    Exact,
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    True,
+    False,
+    Name(GlobPattern),
+    Path(GlobPattern),
+    Regex(Regex),
+    Type(Vec<QueryFileType>),
+    /// Inclusive bounds on the size in bytes.
+    Size(u64, u64),
+    /// Inclusive bounds on the modification time in nanoseconds since the Unix epoch.
+    Mtime(i128, i128),
+    Perm(PermTest, u32),
+    Uid(u32),
+    Gid(u32),
+    /// Inclusive bounds on the depth.
+    Depth(usize, usize),
+    Empty,
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, node: &DirNode) -> bool {
+        let meta = node.get_meta();
+        match self {
+            Expr::True => true,
+            Expr::False => false,
+            Expr::Name(glob) => glob.matches(codec::path_to_bytes(Path::new(node.get_name()))),
+            Expr::Path(glob) => glob.matches(codec::path_to_bytes(node.get_entry())),
+            Expr::Regex(regex) => regex.is_match(codec::path_to_bytes(node.get_entry())),
+            Expr::Type(types) => types.iter().any(|t| t.matches(node)),
+            Expr::Size(min, max) => (*min..=*max).contains(&meta.get_size()),
+            Expr::Mtime(min, max) => (*min..=*max).contains(&meta.get_mtime_ns()),
+            Expr::Perm(test, mode) => {
+                let perms = meta.get_permissions();
+                match test {
+                    PermTest::Exact => perms == *mode,
+                    PermTest::All => perms & mode == *mode,
+                    PermTest::Any => *mode == 0 || perms & mode != 0,
+                }
+            }
+            Expr::Uid(uid) => meta.get_uid() == *uid,
+            Expr::Gid(gid) => meta.get_gid() == *gid,
+            Expr::Depth(min, max) => (*min..=*max).contains(&node.get_entry().components().count()),
+            Expr::Empty => match node.get_kind() {
+                DirEntryKind::File => meta.get_size() == 0,
+                DirEntryKind::Dir => node.get_children().next().is_none(),
+                _ => false,
+            },
+            Expr::Not(inner) => !inner.matches(node),
+            Expr::And(items) => items.iter().all(|item| item.matches(node)),
+            Expr::Or(items) => items.iter().any(|item| item.matches(node)),
+        }
+    }
+}
+
+/// A query selecting entries of an indexed tree, built from a `find`-style string with
+/// `DirQuery::parse` or from the typed constructors.
+///
+/// Constructors produce single tests, which combine with `and`, `or` and `!`, as in
+/// `DirQuery::name("*.rs").and(!DirQuery::empty())`.
+#[derive(Debug, Clone)]
+pub struct DirQuery {
+    expr_: Expr,
+}
+
+impl DirQuery {
+    fn from_expr(expr: Expr) -> DirQuery {
+        DirQuery { expr_: expr }
+    }
+
+    /// Parses a query in `find` syntax. Relative times are measured from now.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query, for example `-name '*.rs' -a ! ( -type d -o -empty )`. An empty
+    ///   query matches every entry.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirQuery`, or `DirIndexerErr::QueryParseFail` describing the
+    /// first problem.
+    pub fn parse(query: &str) -> Result<DirQuery, DirIndexerErr> {
+        DirQuery::parse_at(query, SystemTime::now())
+    }
+
+    /// Parses a query in `find` syntax, measuring `-mtime` and `-mmin` ages from `now`.
+    pub fn parse_at(query: &str, now: SystemTime) -> Result<DirQuery, DirIndexerErr> {
+        let tokens = tokenize(query).map_err(DirIndexerErr::QueryParseFail)?;
+        let mut parser = QueryParser {
+            tokens_: tokens,
+            pos_: 0,
+            depth_: 0,
+            now_ns_: time_to_ns(now),
+            users_: None,
+        };
+        let expr = if parser.tokens_.is_empty() { Expr::True } else { parser.parse_or()? };
+        if let Some(token) = parser.tokens_.get(parser.pos_) {
+            return Err(DirIndexerErr::QueryParseFail(format!("unexpected '{}'", token.text)));
+        }
+        Ok(DirQuery::from_expr(expr))
+    }
+
+    /// Matches every entry.
+    pub fn all() -> DirQuery {
+        DirQuery::from_expr(Expr::True)
+    }
+
+    /// Matches no entry.
+    pub fn none() -> DirQuery {
+        DirQuery::from_expr(Expr::False)
+    }
+
+    /// Matches entries whose file name matches the glob.
+    pub fn name(pattern: &str) -> DirQuery {
+        DirQuery::from_expr(Expr::Name(GlobPattern::from(pattern)))
+    }
+
+    /// Matches entries whose file name matches the glob, ignoring ASCII case.
+    pub fn iname(pattern: &str) -> DirQuery {
+        DirQuery::from_expr(Expr::Name(GlobPattern::from_case_insensitive(pattern)))
+    }
+
+    /// Matches entries whose relative path matches the glob. `*` stops at `/`, `**` does not.
+    pub fn path(pattern: &str) -> DirQuery {
+        DirQuery::from_expr(Expr::Path(GlobPattern::from(pattern)))
+    }
+
+    /// Matches entries whose relative path contains a match of the regular expression.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirQuery`, or `DirIndexerErr::QueryParseFail` if the pattern is
+    /// not a valid regular expression.
+    pub fn regex(pattern: &str) -> Result<DirQuery, DirIndexerErr> {
+        Regex::new(pattern)
+            .map(|regex| DirQuery::from_expr(Expr::Regex(regex)))
+            .map_err(|e| DirIndexerErr::QueryParseFail(format!("invalid regex '{}': {}", pattern, e)))
+    }
+
+    /// Matches entries of the given type.
+    pub fn kind(file_type: QueryFileType) -> DirQuery {
+        DirQuery::from_expr(Expr::Type(vec![file_type]))
+    }
+
+    /// Matches entries of at least `bytes` bytes.
+    pub fn min_size(bytes: u64) -> DirQuery {
+        DirQuery::from_expr(Expr::Size(bytes, u64::MAX))
+    }
+
+    /// Matches entries of at most `bytes` bytes.
+    pub fn max_size(bytes: u64) -> DirQuery {
+        DirQuery::from_expr(Expr::Size(0, bytes))
+    }
+
+    /// Matches entries modified at or after `time`.
+    pub fn modified_after(time: SystemTime) -> DirQuery {
+        DirQuery::from_expr(Expr::Mtime(time_to_ns(time), i128::MAX))
+    }
+
+    /// Matches entries modified before `time`.
+    pub fn modified_before(time: SystemTime) -> DirQuery {
+        DirQuery::from_expr(Expr::Mtime(i128::MIN, time_to_ns(time) - 1))
+    }
+
+    /// Matches entries modified within `age` of now.
+    pub fn modified_within(age: Duration) -> DirQuery {
+        let now = time_to_ns(SystemTime::now());
+        DirQuery::from_expr(Expr::Mtime(now - age.as_nanos() as i128, i128::MAX))
+    }
+
+    /// Matches entries whose permission bits are exactly `mode`.
+    pub fn perm_exact(mode: u32) -> DirQuery {
+        DirQuery::from_expr(Expr::Perm(PermTest::Exact, mode & 0o7777))
+    }
+
+    /// Matches entries with all of the permission bits of `mode` set.
+    pub fn perm_all(mode: u32) -> DirQuery {
+        DirQuery::from_expr(Expr::Perm(PermTest::All, mode & 0o7777))
+    }
+
+    /// Matches entries with any of the permission bits of `mode` set.
+    pub fn perm_any(mode: u32) -> DirQuery {
+        DirQuery::from_expr(Expr::Perm(PermTest::Any, mode & 0o7777))
+    }
+
+    /// Matches entries owned by the user id.
+    pub fn uid(uid: u32) -> DirQuery {
+        DirQuery::from_expr(Expr::Uid(uid))
+    }
+
+    /// Matches entries owned by the group id.
+    pub fn gid(gid: u32) -> DirQuery {
+        DirQuery::from_expr(Expr::Gid(gid))
+    }
+
+    /// Matches entries owned by the named user, looked up in `/etc/passwd`. A numeric name is
+    /// taken as a uid.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirQuery`, or `DirIndexerErr::QueryParseFail` for an unknown user.
+    pub fn owner(name: &str) -> Result<DirQuery, DirIndexerErr> {
+        UserTable::load()
+            .uid_by_name(name)
+            .map(DirQuery::uid)
+            .ok_or_else(|| DirIndexerErr::QueryParseFail(format!("unknown user '{}'", name)))
+    }
+
+    /// Matches entries owned by the named group, looked up in `/etc/group`. A numeric name is
+    /// taken as a gid.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DirQuery`, or `DirIndexerErr::QueryParseFail` for an unknown group.
+    pub fn group(name: &str) -> Result<DirQuery, DirIndexerErr> {
+        UserTable::load()
+            .gid_by_name(name)
+            .map(DirQuery::gid)
+            .ok_or_else(|| DirIndexerErr::QueryParseFail(format!("unknown group '{}'", name)))
+    }
+
+    /// Matches entries at least `depth` levels below the root.
+    pub fn min_depth(depth: usize) -> DirQuery {
+        DirQuery::from_expr(Expr::Depth(depth, usize::MAX))
+    }
+
+    /// Matches entries at most `depth` levels below the root.
+    pub fn max_depth(depth: usize) -> DirQuery {
+        DirQuery::from_expr(Expr::Depth(0, depth))
+    }
+
+    /// Matches empty regular files and directories without children.
+    pub fn empty() -> DirQuery {
+        DirQuery::from_expr(Expr::Empty)
+    }
+
+    /// Matches entries matching both queries.
+    pub fn and(self, other: DirQuery) -> DirQuery {
+        let items = match (self.expr_, other.expr_) {
+            (Expr::And(mut left), Expr::And(right)) => {
+                left.extend(right);
+                left
+            }
+            (Expr::And(mut left), right) => {
+                left.push(right);
+                left
+            }
+            (left, right) => vec![left, right],
+        };
+        DirQuery::from_expr(Expr::And(items))
+    }
+
+    /// Matches entries matching either query.
+    pub fn or(self, other: DirQuery) -> DirQuery {
+        let items = match (self.expr_, other.expr_) {
+            (Expr::Or(mut left), Expr::Or(right)) => {
+                left.extend(right);
+                left
+            }
+            (Expr::Or(mut left), right) => {
+                left.push(right);
+                left
+            }
+            (left, right) => vec![left, right],
+        };
+        DirQuery::from_expr(Expr::Or(items))
+    }
+
+    /// Returns `true` if the node matches the query.
+    pub fn matches(&self, node: &DirNode) -> bool {
+        self.expr_.matches(node)
+    }
+}
+
+impl Not for DirQuery {
+    type Output = DirQuery;
+
+    /// Matches entries not matching the query.
+    fn not(self) -> DirQuery {
+        match self.expr_ {
+            Expr::Not(inner) => DirQuery::from_expr(*inner),
+            expr => DirQuery::from_expr(Expr::Not(Box::new(expr))),
+        }
+    }
+}
+
+fn time_to_ns(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+struct Token {
+    text: String,
+    /// `true` for an unquoted `(`, `)` or `!`, which are operators rather than arguments.
+    operator: bool,
+}
+
+/// Splits a query into words, honouring quotes and backslash escapes. Unquoted parentheses are
+/// always words of their own.
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(Token { text: c.to_string(), operator: true });
+            continue;
+        }
+        let mut text = String::new();
+        let mut quoted = false;
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            chars.next();
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => {
+                        text.push(escaped);
+                        quoted = true;
+                    }
+                    None => return Err("trailing backslash".to_string()),
+                },
+                '\'' | '"' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some(end) if end == c => break,
+                            Some('\\') if c == '"' => match chars.next() {
+                                Some(escaped) => text.push(escaped),
+                                None => return Err("unterminated quote".to_string()),
+                            },
+                            Some(inner) => text.push(inner),
+                            None => return Err("unterminated quote".to_string()),
+                        }
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+        let operator = !quoted && text == "!";
+        tokens.push(Token { text, operator });
+    }
+    Ok(tokens)
+}
+
+struct QueryParser {
+    tokens_: Vec<Token>,
+    pos_: usize,
+    /// How many parentheses and negations enclose the current position.
+    depth_: usize,
+    now_ns_: i128,
+    users_: Option<UserTable>,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens_.get(self.pos_).map(|token| token.text.as_str())
+    }
+
+    fn peek_operator(&self, text: &str) -> bool {
+        self.tokens_.get(self.pos_).map_or(false, |token| token.text == text && (token.operator || text.starts_with('-')))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, DirIndexerErr> {
+        let mut items = vec![self.parse_and()?];
+        while self.peek_operator("-o") || self.peek_operator("-or") {
+            self.pos_ += 1;
+            items.push(self.parse_and()?);
+        }
+        Ok(if items.len() == 1 { items.remove(0) } else { Expr::Or(items) })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, DirIndexerErr> {
+        let mut items = vec![self.parse_unary()?];
+        loop {
+            if self.peek_operator("-a") || self.peek_operator("-and") {
+                self.pos_ += 1;
+            } else if self.peek().is_none() || self.peek_operator(")") || self.peek_operator("-o") || self.peek_operator("-or") {
+                break;
+            }
+            items.push(self.parse_unary()?);
+        }
+        Ok(if items.len() == 1 { items.remove(0) } else { Expr::And(items) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, DirIndexerErr> {
+        let negated = self.peek_operator("!") || self.peek_operator("-not");
+        if !negated && !self.peek_operator("(") {
+            return self.parse_primary();
+        }
+        if self.depth_ >= MAX_DEPTH {
+            return Err(DirIndexerErr::QueryParseFail("expression nested too deeply".to_string()));
+        }
+        self.pos_ += 1;
+        self.depth_ += 1;
+        let result = if negated { self.parse_unary().map(|inner| Expr::Not(Box::new(inner))) } else { self.parse_group() };
+        self.depth_ -= 1;
+        result
+    }
+
+    /// Parses the rest of a parenthesized expression, whose `(` has been consumed.
+    fn parse_group(&mut self) -> Result<Expr, DirIndexerErr> {
+        let inner = self.parse_or()?;
+        if !self.peek_operator(")") {
+            return Err(DirIndexerErr::QueryParseFail("missing ')'".to_string()));
+        }
+        self.pos_ += 1;
+        Ok(inner)
+    }
+
+    fn argument(&mut self, primary: &str) -> Result<String, DirIndexerErr> {
+        match self.tokens_.get(self.pos_) {
+            Some(token) => {
+                self.pos_ += 1;
+                Ok(token.text.clone())
+            }
+            None => Err(DirIndexerErr::QueryParseFail(format!("{} needs an argument", primary))),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, DirIndexerErr> {
+        let primary = match self.tokens_.get(self.pos_) {
+            Some(token) => token.text.clone(),
+            None => return Err(DirIndexerErr::QueryParseFail("expected an expression".to_string())),
+        };
+        self.pos_ += 1;
+        let invalid = |what: &str, arg: &str| DirIndexerErr::QueryParseFail(format!("invalid {} '{}' for {}", what, arg, primary));
+        Ok(match primary.as_str() {
+            "-true" => Expr::True,
+            "-false" => Expr::False,
+            "-empty" => Expr::Empty,
+            "-name" => Expr::Name(GlobPattern::from(&self.argument(&primary)?)),
+            "-iname" => Expr::Name(GlobPattern::from_case_insensitive(&self.argument(&primary)?)),
+            "-path" => Expr::Path(GlobPattern::from(&self.argument(&primary)?)),
+            "-ipath" => Expr::Path(GlobPattern::from_case_insensitive(&self.argument(&primary)?)),
+            "-regex" | "-iregex" => {
+                let pattern = self.argument(&primary)?;
+                let regex = if primary == "-regex" { Regex::new(&pattern) } else { Regex::new_case_insensitive(&pattern) };
+                Expr::Regex(regex.map_err(|e| DirIndexerErr::QueryParseFail(format!("invalid regex '{}': {}", pattern, e)))?)
+            }
+            "-type" => {
+                let arg = self.argument(&primary)?;
+                let types = arg
+                    .split(',')
+                    .map(QueryFileType::from_letter)
+                    .collect::<Option<Vec<QueryFileType>>>()
+                    .ok_or_else(|| invalid("type", &arg))?;
+                Expr::Type(types)
+            }
+            "-size" => {
+                let arg = self.argument(&primary)?;
+                let (sign, rest) = split_sign(&arg);
+                let (digits, unit) = match rest.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+                    Some((i, _)) => rest.split_at(i),
+                    None => (rest, ""),
+                };
+                let scale: u64 = match unit {
+                    "" | "c" => 1,
+                    "b" => 512,
+                    "k" => 1 << 10,
+                    "M" => 1 << 20,
+                    "G" => 1 << 30,
+                    "T" => 1 << 40,
+                    _ => return Err(invalid("size", &arg)),
+                };
+                let bytes = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(scale))
+                    .ok_or_else(|| invalid("size", &arg))?;
+                match sign {
+                    '+' => Expr::Size(bytes.saturating_add(1), u64::MAX),
+                    '-' if bytes == 0 => Expr::False,
+                    '-' => Expr::Size(0, bytes - 1),
+                    _ => Expr::Size(bytes, bytes),
+                }
+            }
+            "-mtime" | "-mmin" => {
+                let arg = self.argument(&primary)?;
+                let unit = if primary == "-mtime" { NANOS_PER_DAY } else { NANOS_PER_MINUTE };
+                let (sign, rest) = split_sign(&arg);
+                let n: i128 = rest.parse::<u32>().map_err(|_| invalid("age", &arg))?.into();
+                // An entry's age in whole units is compared with N, so N spans [N, N + 1) units
+                let now = self.now_ns_;
+                match sign {
+                    '+' => Expr::Mtime(i128::MIN, now - (n + 1) * unit),
+                    '-' => Expr::Mtime(now - n * unit + 1, i128::MAX),
+                    _ => Expr::Mtime(now - (n + 1) * unit + 1, now - n * unit),
+                }
+            }
+            "-perm" => {
+                let arg = self.argument(&primary)?;
+                let (test, digits) = match arg.strip_prefix('-') {
+                    Some(rest) => (PermTest::All, rest),
+                    None => match arg.strip_prefix('/') {
+                        Some(rest) => (PermTest::Any, rest),
+                        None => (PermTest::Exact, arg.as_str()),
+                    },
+                };
+                let mode = u32::from_str_radix(digits, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o7777)
+                    .ok_or_else(|| invalid("mode", &arg))?;
+                Expr::Perm(test, mode)
+            }
+            "-uid" | "-gid" => {
+                let arg = self.argument(&primary)?;
+                let id = arg.parse::<u32>().map_err(|_| invalid("id", &arg))?;
+                if primary == "-uid" { Expr::Uid(id) } else { Expr::Gid(id) }
+            }
+            "-user" | "-group" => {
+                let arg = self.argument(&primary)?;
+                let users = self.users_.get_or_insert_with(UserTable::load);
+                let id = if primary == "-user" { users.uid_by_name(&arg) } else { users.gid_by_name(&arg) };
+                let id = id.ok_or_else(|| invalid(&primary[1..], &arg))?;
+                if primary == "-user" { Expr::Uid(id) } else { Expr::Gid(id) }
+            }
+            "-mindepth" | "-maxdepth" => {
+                let arg = self.argument(&primary)?;
+                let depth = arg.parse::<usize>().map_err(|_| invalid("depth", &arg))?;
+                if primary == "-mindepth" { Expr::Depth(depth, usize::MAX) } else { Expr::Depth(0, depth) }
+            }
+            "-depth" => {
+                let arg = self.argument(&primary)?;
+                let (sign, rest) = split_sign(&arg);
+                let depth = rest.parse::<usize>().map_err(|_| invalid("depth", &arg))?;
+                match sign {
+                    '+' => Expr::Depth(depth.saturating_add(1), usize::MAX),
+                    '-' if depth == 0 => Expr::False,
+                    '-' => Expr::Depth(0, depth - 1),
+                    _ => Expr::Depth(depth, depth),
+                }
+            }
+            _ => return Err(DirIndexerErr::QueryParseFail(format!("unknown primary '{}'", primary))),
+        })
+    }
+}
+
+/// Splits a leading `+` or `-` from a numeric argument; `=` is returned when there is none.
+fn split_sign(arg: &str) -> (char, &str) {
+    match arg.chars().next() {
+        Some(sign) if sign == '+' || sign == '-' => (sign, &arg[1..]),
+        _ => ('=', arg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::DirTree;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn sample_tree() -> DirTree {
+        let lines = [
+            format!("{{\"path\":\"\",\"type\":\"directory\",\"mode\":\"0755\",\"mtime\":{}}}", NOW),
+            format!("{{\"path\":\"src\",\"type\":\"directory\",\"mode\":\"0755\",\"uid\":1000,\"gid\":100,\"mtime\":{}}}", NOW - 3 * 86_400),
+            format!("{{\"path\":\"src/main.rs\",\"type\":\"file\",\"mode\":\"0644\",\"size\":1500,\"uid\":1000,\"gid\":100,\"mtime\":{}}}", NOW - 600),
+            format!("{{\"path\":\"src/Lib.RS\",\"type\":\"file\",\"mode\":\"0600\",\"size\":0,\"uid\":0,\"mtime\":{}}}", NOW - 90),
+            format!("{{\"path\":\"empty\",\"type\":\"directory\",\"mode\":\"0700\",\"mtime\":{}}}", NOW - 40 * 86_400),
+            format!("{{\"path\":\"big.bin\",\"type\":\"file\",\"mode\":\"4755\",\"size\":{},\"mtime\":{}}}", 3 << 20, NOW - 40 * 86_400),
+            format!("{{\"path\":\"link\",\"type\":\"link\",\"target\":\"src\",\"mode\":\"0777\",\"mtime\":{}}}", NOW),
+            format!("{{\"path\":\"pipe\",\"type\":\"fifo\",\"mode\":\"0600\",\"mtime\":{}}}", NOW),
+            format!("{{\"path\":\"with space!\",\"type\":\"file\",\"mode\":\"0644\",\"size\":1,\"mtime\":{}}}", NOW),
+        ];
+        DirTree::from_json_lines(&lines.join("\n")).unwrap()
+    }
+
+    fn select_query(tree: &DirTree, query: &DirQuery) -> Vec<String> {
+        tree.get_rl2node_map()
+            .into_iter()
+            .filter(|(_, node)| query.matches(node))
+            .map(|(rl_path, _)| rl_path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn select(query: &str) -> Vec<String> {
+        let now = UNIX_EPOCH + Duration::from_secs(NOW);
+        select_query(&sample_tree(), &DirQuery::parse_at(query, now).unwrap())
+    }
+
+    fn parse_error(query: &str) -> String {
+        match DirQuery::parse(query) {
+            Err(DirIndexerErr::QueryParseFail(reason)) => reason,
+            other => panic!("unexpected result for {:?}: {:?}", query, other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn names_paths_and_regexes() {
+        assert_eq!(select("-name *.rs"), vec!["src/main.rs"]);
+        assert_eq!(select("-iname *.rs"), vec!["src/Lib.RS", "src/main.rs"]);
+        assert_eq!(select("-path 'src/*'"), vec!["src/Lib.RS", "src/main.rs"]);
+        assert_eq!(select("-ipath '**.RS'"), vec!["src/Lib.RS", "src/main.rs"]);
+        assert_eq!(select("-regex '^s.*n'"), vec!["src/main.rs"]);
+        assert_eq!(select("-iregex 'lib\\.rs$'"), vec!["src/Lib.RS"]);
+        assert_eq!(select("-name 'with space!'"), vec!["with space!"]);
+        assert_eq!(select("-name with\\ space\\!"), vec!["with space!"]);
+    }
+
+    #[test]
+    fn types_sizes_and_permissions() {
+        assert_eq!(select("-type d"), vec!["", "empty", "src"]);
+        assert_eq!(select("-type l,p"), vec!["link", "pipe"]);
+        assert_eq!(select("-type f -size 1500c"), vec!["src/main.rs"]);
+        assert_eq!(select("-type f -size +1k"), vec!["big.bin", "src/main.rs"]);
+        assert_eq!(select("-type f -size -2"), vec!["src/Lib.RS", "with space!"]);
+        assert_eq!(select("-size 3M"), vec!["big.bin"]);
+        assert!(select("-size -0").is_empty());
+        assert_eq!(select("-perm 0600"), vec!["pipe", "src/Lib.RS"]);
+        assert_eq!(select("-perm -4000"), vec!["big.bin"]);
+        assert_eq!(select("-type f -perm /0011"), vec!["big.bin"]);
+        assert_eq!(select("-empty"), vec!["empty", "src/Lib.RS"]);
+    }
+
+    #[test]
+    fn ages_count_whole_units() {
+        assert_eq!(select("-type f -mmin -11"), vec!["src/Lib.RS", "src/main.rs", "with space!"]);
+        assert_eq!(select("-mmin 10"), vec!["src/main.rs"]);
+        assert_eq!(select("-mmin 1"), vec!["src/Lib.RS"]);
+        assert_eq!(select("-mtime 3"), vec!["src"]);
+        assert_eq!(select("-mtime +30"), vec!["big.bin", "empty"]);
+        assert_eq!(select("-mtime +2 -mtime -4"), vec!["src"]);
+    }
+
+    #[test]
+    fn owners_and_depths() {
+        assert_eq!(select("-uid 1000"), vec!["src", "src/main.rs"]);
+        assert_eq!(select("-gid 100 -type f"), vec!["src/main.rs"]);
+        assert_eq!(select("-user 1000 -type d"), vec!["src"]);
+        assert_eq!(select("-mindepth 2"), vec!["src/Lib.RS", "src/main.rs"]);
+        assert_eq!(select("-maxdepth 0"), vec![""]);
+        assert_eq!(select("-depth +1"), vec!["src/Lib.RS", "src/main.rs"]);
+        assert_eq!(select("-depth -1"), vec![""]);
+        assert!(select("-depth -0").is_empty());
+    }
+
+    #[test]
+    fn operators_combine_like_find() {
+        assert_eq!(select("-type f -name 'b*'"), vec!["big.bin"]);
+        assert_eq!(select("-type f -a -name 'b*'"), vec!["big.bin"]);
+        assert_eq!(select("-name link -o -name pipe"), vec!["link", "pipe"]);
+        // -and binds tighter than -or
+        assert_eq!(select("-name link -o -type f -size 0"), vec!["link", "src/Lib.RS"]);
+        assert_eq!(select("( -name link -o -type f ) -size 0"), vec!["link", "src/Lib.RS"]);
+        assert_eq!(select("-type f ! -name '*.rs' ! -name '*.RS'"), vec!["big.bin", "with space!"]);
+        assert_eq!(select("-not -not -name pipe"), vec!["pipe"]);
+        assert_eq!(select("!(-type f -o -type d)"), vec!["link", "pipe"]);
+        assert_eq!(select("-false -o -true -maxdepth 0"), vec![""]);
+        assert_eq!(select("").len(), sample_tree().get_rl2node_map().len());
+        // A quoted `!` or `(` is an argument, not an operator
+        assert!(select("-name '!'").is_empty());
+        assert!(select("-name \"(\"").is_empty());
+    }
+
+    #[test]
+    fn constructors_match_parsed_queries() {
+        let tree = sample_tree();
+        let typed = DirQuery::name("*.rs").or(DirQuery::iname("*.RS")).and(!DirQuery::empty());
+        assert_eq!(select_query(&tree, &typed), select("( -name '*.rs' -o -iname '*.RS' ) ! -empty"));
+        let typed = DirQuery::kind(QueryFileType::Dir).and(DirQuery::max_depth(1)).and(DirQuery::min_depth(1));
+        assert_eq!(select_query(&tree, &typed), vec!["empty", "src"]);
+        let typed = DirQuery::min_size(1).and(DirQuery::max_size(1500)).and(DirQuery::kind(QueryFileType::File));
+        assert_eq!(select_query(&tree, &typed), vec!["src/main.rs", "with space!"]);
+        let typed = DirQuery::perm_all(0o4000).or(DirQuery::perm_exact(0o700)).or(DirQuery::perm_any(0));
+        assert_eq!(select_query(&tree, &typed).len(), tree.get_rl2node_map().len());
+        let typed = DirQuery::uid(0).and(DirQuery::gid(0)).and(DirQuery::path("src/*"));
+        assert_eq!(select_query(&tree, &typed), vec!["src/Lib.RS"]);
+        let typed = DirQuery::regex("\\.bin$").unwrap().or(DirQuery::kind(QueryFileType::Fifo));
+        assert_eq!(select_query(&tree, &typed), vec!["big.bin", "pipe"]);
+        let since = UNIX_EPOCH + Duration::from_secs(NOW - 700);
+        let typed = DirQuery::modified_after(since).and(DirQuery::modified_before(UNIX_EPOCH + Duration::from_secs(NOW - 1)));
+        assert_eq!(select_query(&tree, &typed), vec!["src/Lib.RS", "src/main.rs"]);
+        assert!(select_query(&tree, &!DirQuery::all()).is_empty());
+        assert!(select_query(&tree, &DirQuery::none()).is_empty());
+        assert!(DirQuery::regex("(").is_err());
+    }
+
+    #[test]
+    fn malformed_queries_are_rejected() {
+        assert_eq!(parse_error("-bogus"), "unknown primary '-bogus'");
+        assert_eq!(parse_error("-name"), "-name needs an argument");
+        assert_eq!(parse_error("( -true"), "missing ')'");
+        assert_eq!(parse_error("-true )"), "unexpected ')'");
+        assert_eq!(parse_error("()"), "unknown primary ')'");
+        assert_eq!(parse_error("!"), "expected an expression");
+        assert_eq!(parse_error("-true -o"), "expected an expression");
+        assert_eq!(parse_error("-type x"), "invalid type 'x' for -type");
+        assert_eq!(parse_error("-type f,"), "invalid type 'f,' for -type");
+        assert_eq!(parse_error("-size 1Q"), "invalid size '1Q' for -size");
+        assert_eq!(parse_error("-size 99999999999T"), "invalid size '99999999999T' for -size");
+        assert_eq!(parse_error("-mtime x"), "invalid age 'x' for -mtime");
+        assert_eq!(parse_error("-perm 8"), "invalid mode '8' for -perm");
+        assert_eq!(parse_error("-perm 17777"), "invalid mode '17777' for -perm");
+        assert_eq!(parse_error("-uid -1"), "invalid id '-1' for -uid");
+        assert_eq!(parse_error("-maxdepth x"), "invalid depth 'x' for -maxdepth");
+        assert_eq!(parse_error("-user no-such-user-here"), "invalid user 'no-such-user-here' for -user");
+        assert!(parse_error("-regex 'a{2}{3}'").starts_with("invalid regex 'a{2}{3}': nested repetition"));
+        assert_eq!(parse_error("-name 'open"), "unterminated quote");
+        assert_eq!(parse_error("-name \"a\\"), "unterminated quote");
+        assert_eq!(parse_error("-name a\\"), "trailing backslash");
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_crash() {
+        let nested = format!("{}-true{}", "( ".repeat(MAX_DEPTH), " )".repeat(MAX_DEPTH));
+        assert!(DirQuery::parse(&nested).is_ok());
+        let negated = format!("{}-true", "! ".repeat(MAX_DEPTH));
+        assert!(DirQuery::parse(&negated).is_ok());
+
+        let too_deep = format!("{}-true{}", "( ".repeat(MAX_DEPTH + 1), " )".repeat(MAX_DEPTH + 1));
+        assert_eq!(parse_error(&too_deep), "expression nested too deeply");
+        assert_eq!(parse_error(&"(".repeat(200_000)), "expression nested too deeply");
+        assert_eq!(parse_error(&format!("{}-true", "! ".repeat(200_000))), "expression nested too deeply");
+        assert_eq!(parse_error(&format!("{}-true", "-not ( ".repeat(200))), "expression nested too deeply");
+        // Long flat expressions are not nesting
+        let flat = vec!["-true"; 100_000].join(" -o ");
+        assert!(DirQuery::parse(&flat).is_ok());
+    }
+}
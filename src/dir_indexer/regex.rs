@@ -0,0 +1,912 @@
+//! A small regular expression engine over raw bytes.
+//!
+//! Patterns are parsed into a syntax tree and compiled to a program run by a Pike VM, so matching
+//! takes time linear in the input whatever the pattern. Supported syntax: literals, `.`, bracket
+//! classes with ranges, negation and POSIX names such as `[:alpha:]`, the escapes `\d \w \s`,
+//! their negations and `\b \B`, anchors `^ $`, groups `(...)` and `(?:...)`, alternation `|` and
+//! the quantifiers `* + ? {n} {n,} {n,m}` (a trailing `?` for laziness is accepted and ignored). A
+//! leading `(?i)` makes the pattern ignore case.
+//!
+//! `.` and negated classes consume a whole UTF-8 character. Negated classes match every non-ASCII
+//! character, even ones listed in the class.
+
+/// Maximum nesting of groups accepted by the parser.
+const MAX_DEPTH: usize = 256;
+
+/// Maximum size of a compiled program, bounding the cost of counted repetitions.
+const MAX_PROGRAM: usize = 100_000;
+
+/// Zero-width assertions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Look {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+/// A set of characters: ASCII members as a bitmap plus explicitly listed non-ASCII characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CharClass {
+    pub(crate) ascii: [bool; 128],
+    pub(crate) chars: Vec<char>,
+    pub(crate) any_non_ascii: bool,
+}
+
+impl CharClass {
+    fn empty() -> CharClass {
+        CharClass {
+            ascii: [false; 128],
+            chars: Vec::new(),
+            any_non_ascii: false,
+        }
+    }
+
+    fn add(&mut self, c: char) {
+        if c.is_ascii() {
+            self.ascii[c as usize] = true;
+        } else if !self.chars.contains(&c) {
+            self.chars.push(c);
+        }
+    }
+
+    fn add_range(&mut self, lo: char, hi: char) {
+        for code in lo as u32..=(hi as u32).min(0x7f) {
+            self.ascii[code as usize] = true;
+        }
+        if hi as u32 > 0x7f {
+            // Wide non-ASCII ranges are not enumerated; they match any non-ASCII character
+            let lo = (lo as u32).max(0x80);
+            if hi as u32 - lo > 256 {
+                self.any_non_ascii = true;
+            } else {
+                for code in lo..=hi as u32 {
+                    if let Some(c) = char::from_u32(code) {
+                        self.add(c);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_class(&mut self, other: &CharClass) {
+        for (i, member) in other.ascii.iter().enumerate() {
+            self.ascii[i] |= *member;
+        }
+        for c in &other.chars {
+            self.add(*c);
+        }
+        self.any_non_ascii |= other.any_non_ascii;
+    }
+
+    fn negate(&mut self) {
+        for member in self.ascii.iter_mut() {
+            *member = !*member;
+        }
+        self.chars.clear();
+        self.any_non_ascii = true;
+    }
+
+    fn fold_case(&mut self) {
+        for b in b'a'..=b'z' {
+            let upper = b.to_ascii_uppercase();
+            let either = self.ascii[b as usize] || self.ascii[upper as usize];
+            self.ascii[b as usize] = either;
+            self.ascii[upper as usize] = either;
+        }
+        let chars = self.chars.clone();
+        for c in chars {
+            for folded in c.to_lowercase().chain(c.to_uppercase()) {
+                self.add(folded);
+            }
+        }
+    }
+}
+
+/// The syntax tree of a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    Empty,
+    /// The UTF-8 bytes of one character.
+    Literal(Vec<u8>),
+    Class(CharClass),
+    AnyChar,
+    Look(Look),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32> },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Bytes(Vec<(u8, u8)>),
+    Split(usize, usize),
+    Jmp(usize),
+    Look(Look),
+    Match,
+}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub(crate) struct Regex {
+    ast_: Node,
+    prog_: Vec<Inst>,
+}
+
+impl Regex {
+    /// Compiles a pattern.
+    ///
+    /// # Returns
+    ///
+    /// The compiled `Regex`, or a message describing the syntax error.
+    pub(crate) fn new(pattern: &str) -> Result<Regex, String> {
+        Regex::with_case(pattern, false)
+    }
+
+    /// Compiles a pattern that ignores case.
+    pub(crate) fn new_case_insensitive(pattern: &str) -> Result<Regex, String> {
+        Regex::with_case(pattern, true)
+    }
+
+    fn with_case(pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+        let (pattern, case_insensitive) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (rest, true),
+            None => (pattern, case_insensitive),
+        };
+        let mut parser = Parser {
+            chars_: pattern.chars().collect(),
+            pos_: 0,
+            case_insensitive_: case_insensitive,
+        };
+        let ast = parser.parse_alt(0)?;
+        if parser.pos_ != parser.chars_.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+        let mut compiler = Compiler { prog_: Vec::new() };
+        compiler.emit(&ast)?;
+        compiler.push(Inst::Match)?;
+        Ok(Regex {
+            ast_: ast,
+            prog_: compiler.prog_,
+        })
+    }
+
+    /// Returns the syntax tree the program was compiled from.
+    pub(crate) fn get_ast(&self) -> &Node {
+        &self.ast_
+    }
+
+    /// Returns `true` if the pattern matches anywhere in `text`.
+    pub(crate) fn is_match(&self, text: &[u8]) -> bool {
+        let mut current = ThreadSet::new(self.prog_.len());
+        let mut next = ThreadSet::new(self.prog_.len());
+        let mut stack: Vec<usize> = Vec::new();
+        for pos in 0..=text.len() {
+            // Starting a thread at every position makes the search unanchored
+            if self.add_thread(&mut current, &mut stack, 0, text, pos) {
+                return true;
+            }
+            if pos == text.len() {
+                break;
+            }
+            let byte = text[pos];
+            for i in 0..current.len() {
+                let pc = current.get(i);
+                if let Inst::Bytes(ranges) = &self.prog_[pc] {
+                    if ranges.iter().any(|(lo, hi)| *lo <= byte && byte <= *hi)
+                        && self.add_thread(&mut next, &mut stack, pc + 1, text, pos + 1)
+                    {
+                        return true;
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Follows jumps, splits and assertions from `start`, adding the threads that consume input.
+    ///
+    /// # Returns
+    ///
+    /// `true` as soon as the match instruction is reached.
+    fn add_thread(&self, set: &mut ThreadSet, stack: &mut Vec<usize>, start: usize, text: &[u8], pos: usize) -> bool {
+        stack.push(start);
+        while let Some(pc) = stack.pop() {
+            if !set.insert(pc) {
+                continue;
+            }
+            match &self.prog_[pc] {
+                Inst::Bytes(_) => {}
+                Inst::Split(a, b) => {
+                    stack.push(*b);
+                    stack.push(*a);
+                }
+                Inst::Jmp(target) => stack.push(*target),
+                Inst::Look(look) => {
+                    if look_holds(*look, text, pos) {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::Match => {
+                    stack.clear();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+fn look_holds(look: Look, text: &[u8], pos: usize) -> bool {
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let before = pos > 0 && is_word(text[pos - 1]);
+    let after = pos < text.len() && is_word(text[pos]);
+    match look {
+        Look::Start => pos == 0,
+        Look::End => pos == text.len(),
+        Look::WordBoundary => before != after,
+        Look::NotWordBoundary => before == after,
+    }
+}
+
+/// An insertion-ordered set of program counters with constant-time clearing.
+struct ThreadSet {
+    dense_: Vec<usize>,
+    sparse_: Vec<usize>,
+}
+
+impl ThreadSet {
+    fn new(capacity: usize) -> ThreadSet {
+        ThreadSet {
+            dense_: Vec::with_capacity(capacity),
+            sparse_: vec![0; capacity],
+        }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        let i = self.sparse_[pc];
+        if i < self.dense_.len() && self.dense_[i] == pc {
+            return false;
+        }
+        self.sparse_[pc] = self.dense_.len();
+        self.dense_.push(pc);
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.dense_.len()
+    }
+
+    fn get(&self, i: usize) -> usize {
+        self.dense_[i]
+    }
+
+    fn clear(&mut self) {
+        self.dense_.clear();
+    }
+}
+
+struct Parser {
+    chars_: Vec<char>,
+    pos_: usize,
+    case_insensitive_: bool,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> String {
+        format!("{} at position {}", msg, self.pos_)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars_.get(self.pos_).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos_ += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self, depth: usize) -> Result<Node, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        let mut branches = vec![self.parse_concat(depth)?];
+        while self.eat('|') {
+            branches.push(self.parse_concat(depth)?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap_or(Node::Empty) } else { Node::Alt(branches) })
+    }
+
+    fn parse_concat(&mut self, depth: usize) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom(depth)?;
+            items.push(self.parse_quantifiers(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap_or(Node::Empty),
+            _ => Node::Concat(items),
+        })
+    }
+
+    /// Applies the quantifiers following an atom. Stacked `* + ?` collapse into one repetition,
+    /// as `a+?*` matches the same as `a*`; a counted repetition cannot be stacked at all.
+    fn parse_quantifiers(&mut self, mut node: Node) -> Result<Node, String> {
+        let mut quantified = false;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.parse_counted()? {
+                    Some(bounds) => bounds,
+                    None => return Ok(node),
+                },
+                Some(c) if c == '*' || c == '+' || c == '?' => {
+                    self.pos_ += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(node),
+            };
+            if matches!(node, Node::Look(_) | Node::Empty) {
+                return Err(self.error("nothing to repeat"));
+            }
+            self.eat('?');
+            node = match node {
+                Node::Repeat { node: inner, min: inner_min, max: inner_max } if quantified => {
+                    let simple = |min: u32, max: Option<u32>| min <= 1 && max.map_or(true, |max| max == 1);
+                    if !simple(inner_min, inner_max) || !simple(min, max) {
+                        return Err(self.error("nested repetition"));
+                    }
+                    Node::Repeat {
+                        node: inner,
+                        min: inner_min * min,
+                        max: inner_max.and(max),
+                    }
+                }
+                node => Node::Repeat {
+                    node: Box::new(node),
+                    min,
+                    max,
+                },
+            };
+            quantified = true;
+        }
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`. A brace that does not start a valid count is a literal.
+    fn parse_counted(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let start = self.pos_;
+        self.pos_ += 1;
+        let read_number = |parser: &mut Parser| -> Option<u32> {
+            let begin = parser.pos_;
+            while parser.peek().map_or(false, |c| c.is_ascii_digit()) {
+                parser.pos_ += 1;
+            }
+            parser.chars_[begin..parser.pos_].iter().collect::<String>().parse().ok()
+        };
+        let min = match read_number(self) {
+            Some(min) => min,
+            None => {
+                self.pos_ = start;
+                return Ok(None);
+            }
+        };
+        let max = if self.eat(',') { read_number(self) } else { Some(min) };
+        if !self.eat('}') {
+            self.pos_ = start;
+            return Ok(None);
+        }
+        if max.map_or(false, |max| max < min) {
+            return Err(self.error("invalid repetition bounds"));
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn parse_atom(&mut self, depth: usize) -> Result<Node, String> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("unexpected end of pattern")),
+        };
+        self.pos_ += 1;
+        match c {
+            '(' => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err(self.error("unsupported group flag"));
+                }
+                let inner = self.parse_alt(depth + 1)?;
+                if !self.eat(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                Ok(inner)
+            }
+            '[' => self.parse_class().map(Node::Class),
+            '.' => Ok(Node::AnyChar),
+            '^' => Ok(Node::Look(Look::Start)),
+            '$' => Ok(Node::Look(Look::End)),
+            '*' | '+' | '?' => Err(self.error("nothing to repeat")),
+            '\\' => match self.parse_escape()? {
+                Escape::Char(c) => Ok(self.literal(c)),
+                Escape::Class(class) => Ok(Node::Class(class)),
+                Escape::Look(look) => Ok(Node::Look(look)),
+            },
+            c => Ok(self.literal(c)),
+        }
+    }
+
+    fn literal(&self, c: char) -> Node {
+        let folds = c.to_lowercase().chain(c.to_uppercase()).any(|f| f != c);
+        if self.case_insensitive_ && folds {
+            let mut class = CharClass::empty();
+            class.add(c);
+            class.fold_case();
+            Node::Class(class)
+        } else {
+            let mut buf = [0u8; 4];
+            Node::Literal(c.encode_utf8(&mut buf).as_bytes().to_vec())
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Escape, String> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error("trailing backslash")),
+        };
+        self.pos_ += 1;
+        let class = |f: fn(u8) -> bool, negated: bool| {
+            let mut class = CharClass::empty();
+            for b in 0u8..128 {
+                class.ascii[b as usize] = f(b);
+            }
+            if negated {
+                class.negate();
+            }
+            Escape::Class(class)
+        };
+        Ok(match c {
+            'd' => class(|b| b.is_ascii_digit(), false),
+            'D' => class(|b| b.is_ascii_digit(), true),
+            'w' => class(|b| b.is_ascii_alphanumeric() || b == b'_', false),
+            'W' => class(|b| b.is_ascii_alphanumeric() || b == b'_', true),
+            's' => class(|b| b.is_ascii_whitespace() || b == 0x0b, false),
+            'S' => class(|b| b.is_ascii_whitespace() || b == 0x0b, true),
+            'b' => Escape::Look(Look::WordBoundary),
+            'B' => Escape::Look(Look::NotWordBoundary),
+            'A' => Escape::Look(Look::Start),
+            'z' => Escape::Look(Look::End),
+            'n' => Escape::Char('\n'),
+            't' => Escape::Char('\t'),
+            'r' => Escape::Char('\r'),
+            'f' => Escape::Char('\u{c}'),
+            'v' => Escape::Char('\u{b}'),
+            '0' => Escape::Char('\0'),
+            'x' => {
+                let digits: String = self.chars_.get(self.pos_..self.pos_ + 2).unwrap_or(&[]).iter().collect();
+                let code = u8::from_str_radix(&digits, 16).map_err(|_| self.error("invalid \\x escape"))?;
+                self.pos_ += 2;
+                Escape::Char(code as char)
+            }
+            c if c.is_ascii_alphanumeric() => return Err(self.error("unknown escape")),
+            c => Escape::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<CharClass, String> {
+        let mut class = CharClass::empty();
+        let negated = self.eat('^');
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("missing ']'")),
+            };
+            if c == ']' && !first {
+                self.pos_ += 1;
+                break;
+            }
+            first = false;
+            if c == '[' && self.peek_at(1) == Some(':') {
+                if let Some(named) = self.parse_posix_class() {
+                    class.add_class(&named);
+                    continue;
+                }
+            }
+            self.pos_ += 1;
+            let lo = if c == '\\' {
+                match self.parse_escape()? {
+                    Escape::Char(c) => c,
+                    Escape::Class(named) => {
+                        class.add_class(&named);
+                        continue;
+                    }
+                    Escape::Look(_) => return Err(self.error("assertion inside class")),
+                }
+            } else {
+                c
+            };
+            if self.peek() == Some('-') && self.peek_at(1).map_or(false, |next| next != ']') {
+                self.pos_ += 1;
+                let hi = match self.peek() {
+                    Some('\\') => {
+                        self.pos_ += 1;
+                        match self.parse_escape()? {
+                            Escape::Char(c) => c,
+                            _ => return Err(self.error("invalid class range")),
+                        }
+                    }
+                    Some(c) => {
+                        self.pos_ += 1;
+                        c
+                    }
+                    None => return Err(self.error("missing ']'")),
+                };
+                if hi < lo {
+                    return Err(self.error("invalid class range"));
+                }
+                class.add_range(lo, hi);
+            } else {
+                class.add(lo);
+            }
+        }
+        if self.case_insensitive_ {
+            class.fold_case();
+        }
+        if negated {
+            class.negate();
+        }
+        Ok(class)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars_.get(self.pos_ + offset).copied()
+    }
+
+    /// Parses `[:name:]` inside a bracket class, leaving the position unchanged if it is not one.
+    fn parse_posix_class(&mut self) -> Option<CharClass> {
+        let rest: String = self.chars_[self.pos_..].iter().take(12).collect();
+        // Search past the opening `[:` so that `[:]` is not taken for an empty name
+        let end = rest.get(2..)?.find(":]")? + 2;
+        let name = &rest[2..end];
+        let test: fn(u8) -> bool = match name {
+            "alpha" => |b| b.is_ascii_alphabetic(),
+            "digit" => |b| b.is_ascii_digit(),
+            "alnum" => |b| b.is_ascii_alphanumeric(),
+            "space" => |b| b.is_ascii_whitespace() || b == 0x0b,
+            "upper" => |b| b.is_ascii_uppercase(),
+            "lower" => |b| b.is_ascii_lowercase(),
+            "punct" => |b| b.is_ascii_punctuation(),
+            "xdigit" => |b| b.is_ascii_hexdigit(),
+            "blank" => |b| b == b' ' || b == b'\t',
+            "cntrl" => |b| b.is_ascii_control(),
+            "print" => |b| (0x20..0x7f).contains(&b),
+            "graph" => |b| b.is_ascii_graphic(),
+            _ => return None,
+        };
+        let mut class = CharClass::empty();
+        for b in 0u8..128 {
+            class.ascii[b as usize] = test(b);
+        }
+        self.pos_ += end + 2;
+        Some(class)
+    }
+}
+
+enum Escape {
+    Char(char),
+    Class(CharClass),
+    Look(Look),
+}
+
+struct Compiler {
+    prog_: Vec<Inst>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.prog_.len() >= MAX_PROGRAM {
+            return Err("pattern too large".to_string());
+        }
+        self.prog_.push(inst);
+        Ok(self.prog_.len() - 1)
+    }
+
+    fn emit(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Empty => {}
+            Node::Literal(bytes) => {
+                for b in bytes {
+                    self.push(Inst::Bytes(vec![(*b, *b)]))?;
+                }
+            }
+            Node::Class(class) => self.emit_class(class)?,
+            Node::AnyChar => {
+                let mut class = CharClass::empty();
+                class.negate();
+                class.ascii[b'\n' as usize] = false;
+                self.emit_class(&class)?;
+            }
+            Node::Look(look) => {
+                self.push(Inst::Look(*look))?;
+            }
+            Node::Concat(items) => {
+                for item in items {
+                    self.emit(item)?;
+                }
+            }
+            Node::Alt(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.emit(branch)?;
+                        jumps.push(self.push(Inst::Jmp(0))?);
+                        let next = self.prog_.len();
+                        self.prog_[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.emit(branch)?;
+                    }
+                }
+                let end = self.prog_.len();
+                for jump in jumps {
+                    self.prog_[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.emit(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.emit(node)?;
+                        self.push(Inst::Jmp(split))?;
+                        let end = self.prog_.len();
+                        self.prog_[split] = Inst::Split(split + 1, end);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0))?);
+                            self.emit(node)?;
+                        }
+                        let end = self.prog_.len();
+                        for split in splits {
+                            self.prog_[split] = Inst::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits a class as alternatives: one byte-range instruction for its ASCII members, the byte
+    /// sequences of listed characters, and any well-formed multi-byte character if requested.
+    fn emit_class(&mut self, class: &CharClass) -> Result<(), String> {
+        let mut sequences: Vec<Vec<(u8, u8)>> = Vec::new();
+        let mut ascii_ranges = byte_ranges(&class.ascii);
+        if class.any_non_ascii {
+            // Bytes that cannot start a well-formed sequence are matched on their own
+            ascii_ranges.push((0x80, 0xc1));
+            ascii_ranges.push((0xf5, 0xff));
+            sequences.push(vec![(0xc2, 0xdf), (0x80, 0xbf)]);
+            sequences.push(vec![(0xe0, 0xef), (0x80, 0xbf), (0x80, 0xbf)]);
+            sequences.push(vec![(0xf0, 0xf4), (0x80, 0xbf), (0x80, 0xbf), (0x80, 0xbf)]);
+        } else {
+            for c in &class.chars {
+                let mut buf = [0u8; 4];
+                sequences.push(c.encode_utf8(&mut buf).bytes().map(|b| (b, b)).collect());
+            }
+        }
+        if !ascii_ranges.is_empty() {
+            sequences.insert(0, vec![]);
+        }
+        if sequences.is_empty() {
+            // An empty class never matches
+            self.push(Inst::Bytes(Vec::new()))?;
+            return Ok(());
+        }
+        let mut jumps = Vec::new();
+        let count = sequences.len();
+        for (i, sequence) in sequences.into_iter().enumerate() {
+            let split = if i + 1 < count { Some(self.push(Inst::Split(0, 0))?) } else { None };
+            if sequence.is_empty() {
+                self.push(Inst::Bytes(ascii_ranges.clone()))?;
+            } else {
+                for range in sequence {
+                    self.push(Inst::Bytes(vec![range]))?;
+                }
+            }
+            if let Some(split) = split {
+                jumps.push(self.push(Inst::Jmp(0))?);
+                let next = self.prog_.len();
+                self.prog_[split] = Inst::Split(split + 1, next);
+            }
+        }
+        let end = self.prog_.len();
+        for jump in jumps {
+            self.prog_[jump] = Inst::Jmp(end);
+        }
+        Ok(())
+    }
+}
+
+/// Converts an ASCII membership bitmap to sorted byte ranges.
+fn byte_ranges(members: &[bool; 128]) -> Vec<(u8, u8)> {
+    let mut ranges = Vec::new();
+    let mut start: Option<u8> = None;
+    for (b, member) in members.iter().copied().chain(std::iter::once(false)).enumerate() {
+        match (member, start) {
+            (true, None) => start = Some(b as u8),
+            (false, Some(lo)) => {
+                ranges.push((lo, (b - 1) as u8));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text.as_bytes())
+    }
+
+    fn error(pattern: &str) -> String {
+        Regex::new(pattern).unwrap_err()
+    }
+
+    #[test]
+    fn searches_are_unanchored_unless_asked() {
+        assert!(is_match("b.d", "abcde"));
+        assert!(!is_match("^b", "abc"));
+        assert!(is_match("^a", "abc"));
+        assert!(is_match("c$", "abc"));
+        assert!(!is_match("b$", "abc"));
+        assert!(is_match("\\Aabc\\z", "abc"));
+        assert!(is_match("", ""));
+        assert!(is_match("x|", "abc"));
+    }
+
+    #[test]
+    fn quantifiers_and_alternation() {
+        assert!(is_match("^ab*c$", "ac"));
+        assert!(is_match("^ab+c$", "abbbc"));
+        assert!(!is_match("^ab+c$", "ac"));
+        assert!(is_match("^colou?r$", "color"));
+        assert!(is_match("^a{3}$", "aaa"));
+        assert!(!is_match("^a{3}$", "aaaa"));
+        assert!(is_match("^a{2,}$", "aaaaa"));
+        assert!(is_match("^a{1,2}b$", "aab"));
+        assert!(!is_match("^a{1,2}b$", "aaab"));
+        assert!(is_match("^a{0}b$", "b"));
+        assert!(is_match("^(?:ab|cd)+$", "abcdab"));
+        assert!(is_match("^(foo|bar)\\.rs$", "bar.rs"));
+        assert!(is_match("^a*?$", "aaa"));
+        // A brace that does not start a count is a literal
+        assert!(is_match("^a{$", "a{"));
+        assert!(is_match("^a{x}$", "a{x}"));
+        assert!(is_match("^a{2,x}$", "a{2,x}"));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(is_match("^[a-c]+$", "cab"));
+        assert!(!is_match("^[^a-c]$", "b"));
+        assert!(is_match("^[]a]+$", "]a"));
+        assert!(is_match("^[a-]+$", "-a"));
+        assert!(is_match("^[\\d_]+$", "4_2"));
+        assert!(is_match("^\\w+\\s\\W$", "ab_1 !"));
+        assert!(is_match("^\\D\\S$", "x!"));
+        assert!(is_match("^[[:alpha:][:digit:]]+$", "abc123"));
+        assert!(!is_match("^[[:upper:]]$", "a"));
+        assert!(is_match("^[[:xdigit:][:punct:]]+$", "fF0!"));
+        assert!(is_match("^\\x41\\t\\n$", "A\t\n"));
+        assert!(is_match("^\\.\\*$", ".*"));
+        assert!(is_match("\\bword\\b", "a word here"));
+        assert!(!is_match("\\bword\\b", "swordfish"));
+        assert!(is_match("\\Bor\\B", "word"));
+    }
+
+    #[test]
+    fn bracket_edge_cases_do_not_panic() {
+        // `[:` with no name is just two members of the class
+        assert!(is_match("^[[:]+$", "[:"));
+        assert!(is_match("^[[:]$", ":"));
+        assert!(is_match("[[:bogus:]]", "b]"));
+        assert!(is_match("^[[:é:]]$", "é]"));
+        assert!(Regex::new("[[:").is_err());
+        assert!(Regex::new("[[:alpha:").is_err());
+        assert!(Regex::new("[").is_err());
+    }
+
+    #[test]
+    fn characters_are_whole_utf8_sequences() {
+        assert!(is_match("^.$", "é"));
+        assert!(is_match("^..$", "日本"));
+        assert!(is_match("^[^a]$", "日"));
+        assert!(is_match("^[é]$", "é"));
+        assert!(!is_match("^[é]$", "e"));
+        assert!(Regex::new("^.$").unwrap().is_match(b"\xff"));
+    }
+
+    #[test]
+    fn case_can_be_ignored() {
+        let regex = Regex::new_case_insensitive("^readme\\.[a-z]+$").unwrap();
+        assert!(regex.is_match(b"README.MD"));
+        assert!(is_match("(?i)^abc$", "AbC"));
+        assert!(!is_match("^abc$", "AbC"));
+        assert!(is_match("(?i)^é$", "É"));
+        assert!(is_match("(?i)^[^x]$", "y"));
+        assert!(!is_match("(?i)^[^x]$", "X"));
+    }
+
+    #[test]
+    fn simple_stacked_quantifiers_collapse() {
+        let star = Regex::new("a*").unwrap();
+        for pattern in ["a+?*", "a**", "a?*", "a*+", "a+*"].iter() {
+            assert_eq!(Regex::new(pattern).unwrap().get_ast(), star.get_ast(), "{}", pattern);
+        }
+        assert_eq!(Regex::new("a+?+").unwrap().get_ast(), Regex::new("a+").unwrap().get_ast());
+        assert_eq!(Regex::new("a??").unwrap().get_ast(), Regex::new("a?").unwrap().get_ast());
+        assert!(is_match("^(ab)+*$", ""));
+    }
+
+    #[test]
+    fn counted_repetitions_cannot_be_stacked() {
+        for pattern in ["a{2}{3}", "a{2}*", "a*{2}", "a+{1,3}", "a{1,}?{2}"].iter() {
+            assert!(error(pattern).starts_with("nested repetition"), "{}", pattern);
+        }
+        assert!(Regex::new("(?:a{2}){3}").is_ok());
+        assert!(is_match("^(?:a{2}){3}$", "aaaaaa"));
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        assert_eq!(error("*a"), "nothing to repeat at position 1");
+        assert!(error("^*").starts_with("nothing to repeat"));
+        assert!(error("\\b+").starts_with("nothing to repeat"));
+        assert!(error("(a").starts_with("missing ')'"));
+        assert!(error("a)").starts_with("unmatched ')'"));
+        assert!(error("(?x)").starts_with("unsupported group flag"));
+        assert!(error("\\q").starts_with("unknown escape"));
+        assert!(error("a\\").starts_with("trailing backslash"));
+        assert!(error("\\xZZ").starts_with("invalid \\x escape"));
+        assert!(error("[z-a]").starts_with("invalid class range"));
+        assert!(error("[a-\\d]").starts_with("invalid class range"));
+        assert!(error("[\\b]").starts_with("assertion inside class"));
+        assert!(error("a{3,1}").starts_with("invalid repetition bounds"));
+    }
+
+    #[test]
+    fn hostile_patterns_are_bounded() {
+        let deep = format!("{}a{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert!(error(&deep).starts_with("nesting too deep"));
+        let very_deep = "(".repeat(100_000);
+        assert!(error(&very_deep).starts_with("nesting too deep"));
+        let nested = format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(is_match(&nested, "a"));
+        assert_eq!(error("(?:a{1000}){1000}"), "pattern too large");
+        assert_eq!(error("a{4294967295}"), "pattern too large");
+
+        // Matching stays linear where a backtracking engine would not finish
+        let text = "a".repeat(20_000);
+        assert!(!is_match("^(a|a)*(a*)*b$", &text));
+        assert!(is_match("(a*)*a{10}$", &text));
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+/// Describes an entry that was left out of the index during a scan, such as a directory that
+/// could not be read or a dangling symbolic link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    path_: PathBuf,
+    reason_: String,
+}
+
+impl ScanError {
+    /// Creates a record for the entry at the relative path `path`.
+    pub(crate) fn new(path: PathBuf, reason: String) -> ScanError {
+        ScanError {
+            path_: path,
+            reason_: reason,
+        }
+    }
+
+    /// Returns the path of the entry, relative to the root.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns why the entry was left out, as in `Permission denied (os error 13)`.
+    pub fn get_reason(&self) -> &str {
+        &self.reason_
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::DirIndexer;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+    use std::process::Command;
+
+    #[test]
+    fn records_entries_left_out_of_the_index() {
+        let dir = TempDir::new("scan-errors");
+        dir.write("ok.txt", b"");
+        std::fs::create_dir(dir.join("b")).unwrap();
+        symlink("nowhere", dir.join("b/dangling")).unwrap();
+        symlink("nowhere", dir.join("a-dangling")).unwrap();
+        assert!(Command::new("mkfifo").arg(dir.join("pipe")).status().unwrap().success());
+
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let errors: Vec<(&Path, &str)> = indexer
+            .get_scan_errors()
+            .iter()
+            .map(|error| (error.get_path().as_path(), error.get_reason()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (Path::new("a-dangling"), "dangling symbolic link"),
+                (Path::new("b/dangling"), "dangling symbolic link"),
+                (Path::new("pipe"), "not a regular file or directory"),
+            ]
+        );
+        assert!(indexer.get_tree().get_node(Path::new("pipe")).is_none());
+        assert!(indexer.get_tree().get_node(Path::new("ok.txt")).is_some());
+    }
+}
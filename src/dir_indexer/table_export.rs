@@ -0,0 +1,355 @@
+use super::{DirEntryKind, DirIndexer, DirNode, HashAlgo};
+use super::codec;
+use super::time_format;
+use super::users::UserTable;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Represents a column of a tabular export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DirColumn {
+    /// The path relative to the indexed root.
+    RelativePath,
+    /// The absolute path of the entry.
+    AbsolutePath,
+    /// The entry kind: `file`, `dir`, `symlink` or `other`.
+    Kind,
+    /// The size in bytes.
+    Size,
+    /// The modification time as an ISO 8601 UTC timestamp.
+    Mtime,
+    /// The permission bits in octal, e.g. `0644`.
+    Mode,
+    /// The owner user name, or the numeric uid when it has no name.
+    Owner,
+    /// The content hash of regular files, taken from the node when it was stored with the same
+    /// algorithm; empty for other kinds and unreadable files.
+    Hash,
+}
+
+impl DirColumn {
+    /// Returns the header name of the column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DirColumn::RelativePath => "relative_path",
+            DirColumn::AbsolutePath => "absolute_path",
+            DirColumn::Kind => "kind",
+            DirColumn::Size => "size",
+            DirColumn::Mtime => "mtime",
+            DirColumn::Mode => "mode",
+            DirColumn::Owner => "owner",
+            DirColumn::Hash => "hash",
+        }
+    }
+
+    /// Parses a header name as produced by `as_str`.
+    pub fn from(name: &str) -> Option<DirColumn> {
+        match name {
+            "relative_path" => Some(DirColumn::RelativePath),
+            "absolute_path" => Some(DirColumn::AbsolutePath),
+            "kind" => Some(DirColumn::Kind),
+            "size" => Some(DirColumn::Size),
+            "mtime" => Some(DirColumn::Mtime),
+            "mode" => Some(DirColumn::Mode),
+            "owner" => Some(DirColumn::Owner),
+            "hash" => Some(DirColumn::Hash),
+            _ => None,
+        }
+    }
+}
+
+/// Represents the delimited text formats a table can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    /// Comma-separated values with RFC 4180 quoting and CRLF line endings.
+    Csv,
+    /// Tab-separated values; tabs, newlines and backslashes inside fields are backslash-escaped.
+    Tsv,
+}
+
+/// The `DirTableExporter` struct writes one row per indexed entry with caller-chosen columns.
+///
+/// Rows are sorted by relative path and the root itself is not listed. Paths are written as raw
+/// bytes, so names that are not valid UTF-8 survive unchanged.
+#[derive(Debug, Clone)]
+pub struct DirTableExporter {
+    columns_: Vec<DirColumn>,
+    format_: TableFormat,
+    header_: bool,
+    hash_algo_: HashAlgo,
+}
+
+impl DirTableExporter {
+    /// Creates a CSV exporter with a header row, hashing with XXH64 when the hash column is chosen.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - The columns to write, in order.
+    pub fn new(columns: Vec<DirColumn>) -> DirTableExporter {
+        DirTableExporter {
+            columns_: columns,
+            format_: TableFormat::Csv,
+            header_: true,
+            hash_algo_: HashAlgo::Xxh64,
+        }
+    }
+
+    /// Sets the output format.
+    pub fn with_format(mut self, format: TableFormat) -> DirTableExporter {
+        self.format_ = format;
+        self
+    }
+
+    /// Enables or disables the header row.
+    pub fn with_header(mut self, header: bool) -> DirTableExporter {
+        self.header_ = header;
+        self
+    }
+
+    /// Sets the algorithm used for the hash column.
+    pub fn with_hash_algo(mut self, algo: HashAlgo) -> DirTableExporter {
+        self.hash_algo_ = algo;
+        self
+    }
+
+    /// Writes the table for an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `indexer` - The index to export.
+    /// * `out` - The destination of the table.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the I/O error raised by `out`.
+    pub fn write<W: Write>(&self, indexer: &DirIndexer, out: &mut W) -> io::Result<()> {
+        let users = if self.columns_.contains(&DirColumn::Owner) {
+            UserTable::load()
+        } else {
+            UserTable::default()
+        };
+        if self.header_ {
+            let names: Vec<&[u8]> = self.columns_.iter().map(|c| c.as_str().as_bytes()).collect();
+            self.write_row(out, &names)?;
+        }
+        let root_path = indexer.get_root_path();
+        for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+            if rl_path.as_os_str().is_empty() {
+                continue;
+            }
+            let fields: Vec<Vec<u8>> = self
+                .columns_
+                .iter()
+                .map(|column| self.field(*column, root_path, &rl_path, node, &users))
+                .collect();
+            let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+            self.write_row(out, &fields)?;
+        }
+        out.flush()
+    }
+
+    fn field(&self, column: DirColumn, root_path: &Path, rl_path: &Path, node: &DirNode, users: &UserTable) -> Vec<u8> {
+        let meta = node.get_meta();
+        match column {
+            DirColumn::RelativePath => codec::path_to_bytes(rl_path).to_vec(),
+            DirColumn::AbsolutePath => codec::path_to_bytes(&root_path.join(rl_path)).to_vec(),
+            DirColumn::Kind => node.get_kind().as_str().as_bytes().to_vec(),
+            DirColumn::Size => meta.get_size().to_string().into_bytes(),
+            DirColumn::Mtime => time_format::format_utc(meta.get_mtime()).into_bytes(),
+            DirColumn::Mode => format!("{:04o}", meta.get_permissions()).into_bytes(),
+            DirColumn::Owner => match users.user_name(meta.get_uid()) {
+                Some(name) => name.as_bytes().to_vec(),
+                None => meta.get_uid().to_string().into_bytes(),
+            },
+            DirColumn::Hash => {
+                if node.get_kind() != DirEntryKind::File {
+                    return Vec::new();
+                }
+                match node.hash_contents(root_path, self.hash_algo_) {
+                    Ok(hash) => hash.to_hex().into_bytes(),
+                    Err(_) => Vec::new(),
+                }
+            }
+        }
+    }
+
+    fn write_row<W: Write>(&self, out: &mut W, fields: &[&[u8]]) -> io::Result<()> {
+        let mut line: Vec<u8> = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            match self.format_ {
+                TableFormat::Csv => {
+                    if i > 0 {
+                        line.push(b',');
+                    }
+                    push_csv_field(&mut line, field);
+                }
+                TableFormat::Tsv => {
+                    if i > 0 {
+                        line.push(b'\t');
+                    }
+                    push_tsv_field(&mut line, field);
+                }
+            }
+        }
+        line.extend_from_slice(match self.format_ {
+            TableFormat::Csv => b"\r\n",
+            TableFormat::Tsv => b"\n",
+        });
+        out.write_all(&line)
+    }
+}
+
+/// Appends a CSV field, quoting it when it holds a separator, a quote or a line break.
+fn push_csv_field(line: &mut Vec<u8>, field: &[u8]) {
+    let needs_quotes = field
+        .iter()
+        .any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r'))
+        || field.first() == Some(&b' ')
+        || field.last() == Some(&b' ');
+    if !needs_quotes {
+        line.extend_from_slice(field);
+        return;
+    }
+    line.push(b'"');
+    for byte in field {
+        if *byte == b'"' {
+            line.push(b'"');
+        }
+        line.push(*byte);
+    }
+    line.push(b'"');
+}
+
+/// Appends a TSV field, backslash-escaping characters that would break the row structure.
+fn push_tsv_field(line: &mut Vec<u8>, field: &[u8]) {
+    for byte in field {
+        match byte {
+            b'\t' => line.extend_from_slice(b"\\t"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            b'\r' => line.extend_from_slice(b"\\r"),
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            _ => line.push(*byte),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::{hash_bytes, HashCache};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn export(indexer: &DirIndexer, exporter: &DirTableExporter) -> Vec<u8> {
+        let mut out = Vec::new();
+        exporter.write(indexer, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn column_names_round_trip() {
+        let columns = [
+            DirColumn::RelativePath,
+            DirColumn::AbsolutePath,
+            DirColumn::Kind,
+            DirColumn::Size,
+            DirColumn::Mtime,
+            DirColumn::Mode,
+            DirColumn::Owner,
+            DirColumn::Hash,
+        ];
+        for column in columns.iter() {
+            assert_eq!(DirColumn::from(column.as_str()), Some(*column));
+        }
+        assert_eq!(DirColumn::from("Size"), None);
+        assert_eq!(DirColumn::from(""), None);
+    }
+
+    #[test]
+    fn rows_are_sorted_and_skip_the_root() {
+        let dir = TempDir::new("table-rows");
+        dir.write("b/c.txt", b"hello");
+        dir.write("a.txt", b"");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let exporter = DirTableExporter::new(vec![DirColumn::RelativePath, DirColumn::Kind, DirColumn::Size, DirColumn::Hash]);
+        let text = String::from_utf8(export(&indexer, &exporter)).unwrap();
+        let lines: Vec<&str> = text.split_terminator("\r\n").collect();
+        assert_eq!(lines[0], "relative_path,kind,size,hash");
+        assert_eq!(lines[1], format!("a.txt,file,0,{}", hash_bytes(HashAlgo::Xxh64, b"").to_hex()));
+        assert_eq!(lines[2], "b,dir,".to_string() + &indexer.get_tree().get_node(Path::new("b")).unwrap().get_meta().get_size().to_string() + ",");
+        assert_eq!(lines[3], format!("b/c.txt,file,5,{}", hash_bytes(HashAlgo::Xxh64, b"hello").to_hex()));
+        assert_eq!(lines.len(), 4);
+
+        let exporter = DirTableExporter::new(vec![DirColumn::AbsolutePath, DirColumn::Mode, DirColumn::Mtime]).with_header(false);
+        let text = String::from_utf8(export(&indexer, &exporter)).unwrap();
+        let first = text.lines().next().unwrap();
+        let meta = indexer.get_tree().get_node(Path::new("a.txt")).unwrap().get_meta();
+        let expected = format!(
+            "{},{:04o},{}",
+            dir.join("a.txt").display(),
+            meta.get_permissions(),
+            time_format::format_utc(meta.get_mtime())
+        );
+        assert_eq!(first.trim_end(), expected);
+    }
+
+    #[test]
+    fn stored_hashes_of_the_same_algorithm_are_not_read_again() {
+        let dir = TempDir::new("table-stored-hash");
+        dir.write("a.txt", b"alpha");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        indexer.compute_hashes(&mut HashCache::new(HashAlgo::Sha256), 1);
+        fs::remove_file(dir.join("a.txt")).unwrap();
+
+        let exporter = DirTableExporter::new(vec![DirColumn::RelativePath, DirColumn::Hash]).with_header(false);
+        let sha256 = exporter.clone().with_hash_algo(HashAlgo::Sha256);
+        assert_eq!(export(&indexer, &sha256), format!("a.txt,{}\r\n", hash_bytes(HashAlgo::Sha256, b"alpha").to_hex()).into_bytes());
+        assert_eq!(export(&indexer, &exporter), b"a.txt,\r\n".to_vec());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let cases: [(&[u8], &[u8]); 7] = [
+            (b"plain", b"plain"),
+            (b"", b""),
+            (b"a,b", b"\"a,b\""),
+            (b"say \"hi\"", b"\"say \"\"hi\"\"\""),
+            (b"two\nlines", b"\"two\nlines\""),
+            (b"cr\r", b"\"cr\r\""),
+            (b" padded", b"\" padded\""),
+        ];
+        for (field, expected) in cases.iter() {
+            let mut line = Vec::new();
+            push_csv_field(&mut line, field);
+            assert_eq!(&line[..], *expected);
+        }
+    }
+
+    #[test]
+    fn tsv_fields_are_escaped() {
+        let mut line = Vec::new();
+        push_tsv_field(&mut line, b"a\tb\nc\rd\\e");
+        assert_eq!(&line[..], b"a\\tb\\nc\\rd\\\\e");
+    }
+
+    #[test]
+    fn awkward_names_keep_one_row_each() {
+        let dir = TempDir::new("table-names");
+        dir.write("tab\tand\nnewline, \"quoted\"", b"x");
+        dir.write(Path::new(OsStr::from_bytes(b"raw\xff")), b"y");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+
+        let tsv = DirTableExporter::new(vec![DirColumn::RelativePath, DirColumn::Size])
+            .with_format(TableFormat::Tsv)
+            .with_header(false);
+        let out = export(&indexer, &tsv);
+        let rows: Vec<&[u8]> = out.split(|b| *b == b'\n').filter(|row| !row.is_empty()).collect();
+        assert_eq!(rows, vec![&b"raw\xff\t1"[..], &b"tab\\tand\\nnewline, \"quoted\"\t1"[..]]);
+
+        let csv = DirTableExporter::new(vec![DirColumn::RelativePath]).with_header(false);
+        let out = export(&indexer, &csv);
+        assert_eq!(out, b"raw\xff\r\n\"tab\tand\nnewline, \"\"quoted\"\"\"\r\n".to_vec());
+    }
+}
@@ -0,0 +1,71 @@
+//! Helpers shared by the unit tests of the library and, included by path, of the command-line
+//! interface. Each uses only some of them.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temporary directory, removed with everything in it on drop.
+pub(crate) struct TempDir {
+    path_: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory whose name is unique to this process and call.
+    pub(crate) fn new(name: &str) -> TempDir {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("dir_indexer-{}-{}-{}", name, process::id(), id));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path_: fs::canonicalize(&path).unwrap() }
+    }
+
+    /// Returns the canonical absolute path of the directory.
+    pub(crate) fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns the canonical absolute path of the directory as text, for use as an operand.
+    pub(crate) fn get_arg(&self) -> &str {
+        self.path_.to_str().unwrap()
+    }
+
+    /// Returns the absolute path of `rl_path` inside the directory.
+    pub(crate) fn join<P: AsRef<Path>>(&self, rl_path: P) -> PathBuf {
+        self.path_.join(rl_path)
+    }
+
+    /// Writes a file, creating its parent directories.
+    pub(crate) fn write<P: AsRef<Path>>(&self, rl_path: P, contents: &[u8]) -> PathBuf {
+        let path = self.join(rl_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Sets the modification time of an entry to `secs` seconds after the epoch, so that it is
+    /// old enough to fall outside the racy window.
+    pub(crate) fn set_mtime<P: AsRef<Path>>(&self, rl_path: P, secs: i64) {
+        let status = Command::new("touch")
+            .arg("-m")
+            .arg("-d")
+            .arg(format!("@{}", secs))
+            .arg(self.join(rl_path))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path_);
+    }
+}
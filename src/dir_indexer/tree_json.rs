@@ -0,0 +1,418 @@
+//! JSON export and import of a `DirTree`.
+//!
+//! The nested form follows `tree -J`: an array holding the root directory object, whose entries
+//! list their children under `contents`, followed by a `report` object with the directory and file
+//! counts. The flat form holds one JSON object per line with the relative `path` of the entry.
+//!
+//! Names that are not valid UTF-8 are exported lossily.
+
+use super::{DirEntryKind, DirIndexerErr, DirMeta, DirNode, DirTree};
+use super::json::{self, JsonValue};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+/// Exports a tree in the nested `tree -J` form.
+pub(crate) fn tree_to_json(tree: &DirTree) -> String {
+    let root = tree.get_root_node();
+    let mut out = String::from("[");
+    push_nested(&mut out, root, ".");
+    let (dirs, files) = count_entries(root);
+    let _ = write!(out, ",{{\"type\":\"report\",\"directories\":{},\"files\":{}}}]", dirs, files);
+    out.push('\n');
+    out
+}
+
+/// Exports a tree as newline-delimited records, one per entry, in sorted path order.
+pub(crate) fn tree_to_json_lines(tree: &DirTree) -> String {
+    let mut out = String::new();
+    for (rl_path, node) in tree.get_rl2node_map() {
+        out.push_str("{\"path\":");
+        json::push_string(&mut out, &rl_path.to_string_lossy());
+        push_fields(&mut out, node);
+        out.push_str("}\n");
+    }
+    out
+}
+
+/// Rebuilds a tree from the nested `tree -J` form.
+pub(crate) fn tree_from_json(text: &str) -> Result<DirTree, DirIndexerErr> {
+    let value = json::parse(text).map_err(DirIndexerErr::JsonParseFail)?;
+    let root_value = match &value {
+        JsonValue::Array(items) => items
+            .iter()
+            .find(|item| item.get("type").and_then(|t| t.as_str()) != Some("report"))
+            .ok_or(DirIndexerErr::TreeConstructFailed)?,
+        JsonValue::Object(_) => &value,
+        _ => return Err(DirIndexerErr::TreeConstructFailed),
+    };
+    let root_node = node_from_nested(root_value, PathBuf::new())?;
+    Ok(DirTree::from_root_node(root_node))
+}
+
+/// Rebuilds a tree from newline-delimited records.
+///
+/// Ancestors that have no record of their own are recreated as directories without metadata.
+pub(crate) fn tree_from_json_lines(text: &str) -> Result<DirTree, DirIndexerErr> {
+    let mut entries: BTreeMap<PathBuf, (DirEntryKind, DirMeta, Option<PathBuf>)> = BTreeMap::new();
+    for (line_no, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = json::parse(line).map_err(|e| DirIndexerErr::JsonParseFail(format!("line {}: {}", line_no + 1, e)))?;
+        let path = value.get("path").and_then(|p| p.as_str()).ok_or(DirIndexerErr::TreeConstructFailed)?;
+        let rl_path = valid_relative_path(path).ok_or(DirIndexerErr::TreeConstructFailed)?;
+        let parts = parts_from_value(&value)?;
+        if entries.insert(rl_path, parts).is_some() {
+            return Err(DirIndexerErr::TreeConstructFailed);
+        }
+    }
+
+    let missing: Vec<PathBuf> = entries
+        .keys()
+        .flat_map(|p| p.ancestors().skip(1).map(Path::to_path_buf).collect::<Vec<_>>())
+        .filter(|p| !entries.contains_key(p))
+        .collect();
+    for ancestor in missing {
+        let meta = DirMeta::from_fields(&[0, 0, 0, (S_IFDIR | 0o755) as u64, 0, 0, 0, 0, 1]).unwrap_or_default();
+        entries.insert(ancestor, (DirEntryKind::Dir, meta, None));
+    }
+    if entries.is_empty() {
+        return Err(DirIndexerErr::TreeConstructFailed);
+    }
+
+    // Build from the deepest entries up, so every node owns its finished children
+    let mut ordered: Vec<PathBuf> = entries.keys().cloned().collect();
+    ordered.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    let mut children: HashMap<PathBuf, HashSet<DirNode>> = HashMap::new();
+    let mut root_node = None;
+    for rl_path in ordered {
+        let (kind, meta, link_target) = entries.remove(&rl_path).unwrap_or((DirEntryKind::Dir, DirMeta::default(), None));
+        let node_children = children.remove(&rl_path).unwrap_or_default();
+        let parent = rl_path.parent().map(Path::to_path_buf);
+        let node = DirNode::from_parts(rl_path, kind, meta, link_target, node_children);
+        match parent {
+            Some(parent) => {
+                children.entry(parent).or_default().insert(node);
+            }
+            None => root_node = Some(node),
+        }
+    }
+    root_node.map(DirTree::from_root_node).ok_or(DirIndexerErr::TreeConstructFailed)
+}
+
+fn push_nested(out: &mut String, node: &DirNode, name: &str) {
+    out.push_str("{\"type\":");
+    json::push_string(out, type_name(node));
+    out.push_str(",\"name\":");
+    json::push_string(out, name);
+    push_meta(out, node);
+    let children = node.get_sorted_children();
+    if node.get_kind() == DirEntryKind::Dir || !children.is_empty() {
+        out.push_str(",\"contents\":[");
+        for (i, child) in children.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            push_nested(out, child, &child.get_name().to_string_lossy());
+        }
+        out.push(']');
+    }
+    out.push('}');
+}
+
+fn push_fields(out: &mut String, node: &DirNode) {
+    out.push_str(",\"type\":");
+    json::push_string(out, type_name(node));
+    push_meta(out, node);
+}
+
+fn push_meta(out: &mut String, node: &DirNode) {
+    if let Some(target) = node.get_link_target() {
+        out.push_str(",\"target\":");
+        json::push_string(out, &target.to_string_lossy());
+    }
+    let meta = node.get_meta();
+    let _ = write!(
+        out,
+        ",\"mode\":\"{:04o}\",\"prot\":\"{}\",\"size\":{},\"uid\":{},\"gid\":{},\"inode\":{},\"dev\":{},\"nlink\":{},\"mtime\":{},\"mtime_nsec\":{}",
+        meta.get_permissions(),
+        prot_string(node.get_kind(), meta.get_mode()),
+        meta.get_size(),
+        meta.get_uid(),
+        meta.get_gid(),
+        meta.get_ino(),
+        meta.get_dev(),
+        meta.get_nlink(),
+        meta.get_mtime(),
+        meta.get_mtime_nsec()
+    );
+}
+
+fn node_from_nested(value: &JsonValue, entry: PathBuf) -> Result<DirNode, DirIndexerErr> {
+    let (kind, meta, link_target) = parts_from_value(value)?;
+    let mut children: HashSet<DirNode> = HashSet::new();
+    if let Some(contents) = value.get("contents").and_then(|c| c.as_array()) {
+        for child in contents {
+            let name = child.get("name").and_then(|n| n.as_str()).ok_or(DirIndexerErr::TreeConstructFailed)?;
+            let name = valid_relative_path(name)
+                .filter(|p| p.components().count() == 1)
+                .ok_or(DirIndexerErr::TreeConstructFailed)?;
+            let child_node = node_from_nested(child, entry.join(name))?;
+            if !children.insert(child_node) {
+                return Err(DirIndexerErr::TreeConstructFailed);
+            }
+        }
+    }
+    Ok(DirNode::from_parts(entry, kind, meta, link_target, children))
+}
+
+fn parts_from_value(value: &JsonValue) -> Result<(DirEntryKind, DirMeta, Option<PathBuf>), DirIndexerErr> {
+    let type_str = value.get("type").and_then(|t| t.as_str()).ok_or(DirIndexerErr::TreeConstructFailed)?;
+    let (kind, type_bits) = match type_str {
+        "directory" => (DirEntryKind::Dir, S_IFDIR),
+        "file" => (DirEntryKind::File, S_IFREG),
+        "link" => (DirEntryKind::Symlink, S_IFLNK),
+        "fifo" => (DirEntryKind::Other, S_IFIFO),
+        "socket" => (DirEntryKind::Other, S_IFSOCK),
+        "char" => (DirEntryKind::Other, S_IFCHR),
+        "block" => (DirEntryKind::Other, S_IFBLK),
+        _ => (DirEntryKind::Other, 0),
+    };
+    let field = |key: &str| value.get(key).and_then(|v| v.as_u64());
+    let perms = match value.get("mode").and_then(|m| m.as_str()) {
+        Some(mode) => u32::from_str_radix(mode, 8).map_err(|_| DirIndexerErr::TreeConstructFailed)? & 0o7777,
+        None => 0,
+    };
+    let mtime = value.get("mtime").and_then(|v| v.as_i64()).unwrap_or(0);
+    let fields = [
+        field("size").unwrap_or(0),
+        mtime as u64,
+        field("mtime_nsec").unwrap_or(0),
+        (type_bits | perms) as u64,
+        field("uid").unwrap_or(0),
+        field("gid").unwrap_or(0),
+        field("dev").unwrap_or(0),
+        field("inode").unwrap_or(0),
+        field("nlink").unwrap_or(1),
+    ];
+    let meta = DirMeta::from_fields(&fields).ok_or(DirIndexerErr::TreeConstructFailed)?;
+    let link_target = value.get("target").and_then(|t| t.as_str()).map(PathBuf::from);
+    Ok((kind, meta, link_target))
+}
+
+/// Accepts a relative path made only of normal components; `""` and `"."` denote the root.
+fn valid_relative_path(path: &str) -> Option<PathBuf> {
+    if path.contains('\0') {
+        return None;
+    }
+    let mut rl_path = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => rl_path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(rl_path)
+}
+
+/// Returns the `tree -J` type name of an entry.
+fn type_name(node: &DirNode) -> &'static str {
+    match node.get_kind() {
+        DirEntryKind::Dir => "directory",
+        DirEntryKind::File => "file",
+        DirEntryKind::Symlink => "link",
+        DirEntryKind::Other => match node.get_meta().get_mode() & S_IFMT {
+            S_IFIFO => "fifo",
+            S_IFSOCK => "socket",
+            S_IFCHR => "char",
+            S_IFBLK => "block",
+            _ => "file",
+        },
+    }
+}
+
+/// Formats mode bits the way `ls -l` does, e.g. `drwxr-xr-x`.
+pub(crate) fn prot_string(kind: DirEntryKind, mode: u32) -> String {
+    let type_char = match kind {
+        DirEntryKind::Dir => 'd',
+        DirEntryKind::File => '-',
+        DirEntryKind::Symlink => 'l',
+        DirEntryKind::Other => match mode & S_IFMT {
+            S_IFIFO => 'p',
+            S_IFSOCK => 's',
+            S_IFCHR => 'c',
+            S_IFBLK => 'b',
+            _ => '?',
+        },
+    };
+    let mut prot = String::with_capacity(10);
+    prot.push(type_char);
+    let specials = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+    for (i, (special, set_exec, set_noexec)) in specials.iter().enumerate() {
+        let shift = 6 - 3 * i;
+        let bits = (mode >> shift) & 0o7;
+        prot.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        prot.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        let exec = bits & 0o1 != 0;
+        prot.push(match (mode & special != 0, exec) {
+            (true, true) => *set_exec,
+            (true, false) => *set_noexec,
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    prot
+}
+
+/// Counts directories and other entries below a node, excluding the node itself.
+fn count_entries(node: &DirNode) -> (usize, usize) {
+    let mut counts = (0, 0);
+    for child in node.get_children() {
+        if child.get_kind() == DirEntryKind::Dir {
+            counts.0 += 1;
+        } else {
+            counts.1 += 1;
+        }
+        let (dirs, files) = count_entries(child);
+        counts.0 += dirs;
+        counts.1 += files;
+    }
+    counts
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use std::os::unix::fs::symlink;
+
+    fn sample_tree(dir: &TempDir) -> DirTree {
+        dir.write("a.txt", b"a");
+        dir.write("sub/deeper/b.bin", &[0, 1, 2]);
+        dir.write("sub/with \"quote\"\tand\nnewline", b"q");
+        dir.write("empty/.keep", b"");
+        symlink("a.txt", dir.join("link")).unwrap();
+        DirTree::from(dir.get_path()).unwrap()
+    }
+
+    #[test]
+    fn nested_export_loads_back_unchanged() {
+        let dir = TempDir::new("json-nested");
+        let tree = sample_tree(&dir);
+        let text = tree_to_json(&tree);
+        assert!(text.starts_with("[{\"type\":\"directory\",\"name\":\".\""));
+        assert!(text.ends_with(",{\"type\":\"report\",\"directories\":3,\"files\":5}]\n"));
+
+        let loaded = tree_from_json(&text).unwrap();
+        assert!(loaded.get_root_node() == tree.get_root_node());
+        let link = loaded.get_node(Path::new("link")).unwrap();
+        assert_eq!(link.get_kind(), DirEntryKind::Symlink);
+        assert_eq!(link.get_link_target(), Some(&PathBuf::from("a.txt")));
+    }
+
+    #[test]
+    fn line_export_loads_back_unchanged() {
+        let dir = TempDir::new("json-lines");
+        let tree = sample_tree(&dir);
+        let text = tree_to_json_lines(&tree);
+        assert_eq!(text.lines().count(), tree.get_rl2node_map().len());
+        assert!(text.lines().all(|line| json::parse(line).is_ok()));
+
+        let loaded = tree_from_json_lines(&text).unwrap();
+        assert!(loaded.get_root_node() == tree.get_root_node());
+        // Records may come in any order and blank lines are skipped
+        let shuffled: Vec<&str> = text.lines().rev().flat_map(|line| vec![line, ""]).collect();
+        let loaded = tree_from_json_lines(&shuffled.join("\n")).unwrap();
+        assert!(loaded.get_root_node() == tree.get_root_node());
+    }
+
+    #[test]
+    fn missing_ancestors_become_directories() {
+        let tree = tree_from_json_lines("{\"path\":\"a/b/c\",\"type\":\"file\",\"size\":3,\"mode\":\"0640\"}\n").unwrap();
+        for ancestor in ["", "a", "a/b"].iter() {
+            assert_eq!(tree.get_node(Path::new(ancestor)).unwrap().get_kind(), DirEntryKind::Dir);
+        }
+        let file = tree.get_node(Path::new("a/b/c")).unwrap();
+        assert_eq!(file.get_kind(), DirEntryKind::File);
+        assert_eq!(file.get_meta().get_size(), 3);
+        assert_eq!(file.get_meta().get_permissions(), 0o640);
+        assert_eq!(file.get_meta().get_mode(), S_IFREG | 0o640);
+    }
+
+    #[test]
+    fn negative_mtimes_survive() {
+        let tree = tree_from_json_lines("{\"path\":\"old\",\"type\":\"file\",\"mtime\":-86400,\"mtime_nsec\":5}\n").unwrap();
+        let meta = tree.get_node(Path::new("old")).unwrap().get_meta();
+        assert_eq!(meta.get_mtime(), -86400);
+        assert_eq!(meta.get_mtime_nsec(), 5);
+        assert_eq!(tree_from_json_lines(&tree_to_json_lines(&tree)).unwrap().get_root_node(), tree.get_root_node());
+    }
+
+    #[test]
+    fn unsafe_paths_are_rejected() {
+        let lines = [
+            "{\"path\":\"../escape\",\"type\":\"file\"}",
+            "{\"path\":\"/abs\",\"type\":\"file\"}",
+            "{\"path\":\"a/../b\",\"type\":\"file\"}",
+            "{\"path\":\"nul\\u0000byte\",\"type\":\"file\"}",
+        ];
+        for line in lines.iter() {
+            assert!(matches!(tree_from_json_lines(line), Err(DirIndexerErr::TreeConstructFailed)), "accepted {}", line);
+        }
+        let names = ["..", "a/b", "/", ""];
+        for name in names.iter() {
+            let mut text = String::from("{\"type\":\"directory\",\"name\":\".\",\"contents\":[{\"type\":\"file\",\"name\":");
+            json::push_string(&mut text, name);
+            text.push_str("}]}");
+            assert!(matches!(tree_from_json(&text), Err(DirIndexerErr::TreeConstructFailed)), "accepted {:?}", name);
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        assert!(matches!(tree_from_json("[{\"type\":"), Err(DirIndexerErr::JsonParseFail(_))));
+        assert!(matches!(tree_from_json("42"), Err(DirIndexerErr::TreeConstructFailed)));
+        assert!(matches!(tree_from_json("[]"), Err(DirIndexerErr::TreeConstructFailed)));
+        assert!(matches!(tree_from_json("[{\"type\":\"report\"}]"), Err(DirIndexerErr::TreeConstructFailed)));
+        assert!(matches!(tree_from_json("{\"name\":\".\"}"), Err(DirIndexerErr::TreeConstructFailed)));
+        assert!(matches!(tree_from_json("{\"type\":\"file\",\"mode\":\"9x\"}"), Err(DirIndexerErr::TreeConstructFailed)));
+        let duplicate = "{\"type\":\"directory\",\"contents\":[{\"type\":\"file\",\"name\":\"a\"},{\"type\":\"file\",\"name\":\"a\"}]}";
+        assert!(matches!(tree_from_json(duplicate), Err(DirIndexerErr::TreeConstructFailed)));
+
+        assert!(matches!(tree_from_json_lines(""), Err(DirIndexerErr::TreeConstructFailed)));
+        assert!(matches!(tree_from_json_lines("{\"type\":\"file\"}"), Err(DirIndexerErr::TreeConstructFailed)));
+        let duplicate = "{\"path\":\"a\",\"type\":\"file\"}\n{\"path\":\"./a\",\"type\":\"file\"}\n";
+        assert!(matches!(tree_from_json_lines(duplicate), Err(DirIndexerErr::TreeConstructFailed)));
+        match tree_from_json_lines("{\"path\":\"a\",\"type\":\"file\"}\n{oops}\n") {
+            Err(DirIndexerErr::JsonParseFail(reason)) => assert!(reason.starts_with("line 2: ")),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn protection_strings_match_ls() {
+        assert_eq!(prot_string(DirEntryKind::Dir, S_IFDIR | 0o755), "drwxr-xr-x");
+        assert_eq!(prot_string(DirEntryKind::File, S_IFREG | 0o644), "-rw-r--r--");
+        assert_eq!(prot_string(DirEntryKind::File, S_IFREG | 0o4755), "-rwsr-xr-x");
+        assert_eq!(prot_string(DirEntryKind::File, S_IFREG | 0o2644), "-rw-r-Sr--");
+        assert_eq!(prot_string(DirEntryKind::Dir, S_IFDIR | 0o1777), "drwxrwxrwt");
+        assert_eq!(prot_string(DirEntryKind::Dir, S_IFDIR | 0o1776), "drwxrwxrwT");
+        assert_eq!(prot_string(DirEntryKind::Symlink, S_IFLNK | 0o777), "lrwxrwxrwx");
+        assert_eq!(prot_string(DirEntryKind::Other, S_IFIFO | 0o600), "prw-------");
+        assert_eq!(prot_string(DirEntryKind::Other, S_IFSOCK), "s---------");
+        assert_eq!(prot_string(DirEntryKind::Other, S_IFCHR | 0o666), "crw-rw-rw-");
+        assert_eq!(prot_string(DirEntryKind::Other, S_IFBLK | 0o660), "brw-rw----");
+        assert_eq!(prot_string(DirEntryKind::Other, 0), "?---------");
+    }
+}
@@ -0,0 +1,384 @@
+//! Rendering of a `DirTree` or subtree in the style of the `tree` command.
+//!
+//! The output starts with a line naming the root, then one line per entry drawn with connectors,
+//! and ends with a blank line and a report of the directory and file counts. Directory names end
+//! with `/` and symlinks show their target after ` -> `. Names are written as raw bytes on Unix,
+//! except that control characters are shown as `?`, as `tree` does, so that a name holding a
+//! line feed cannot break the layout.
+
+use super::{DirEntryKind, DirNode, DirUsage};
+use super::codec;
+use super::dir_usage;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The characters used to draw the branches of a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeCharset {
+    /// Box-drawing characters, as in `├── name`.
+    Unicode,
+    /// Plain ASCII for terminals and files without UTF-8, as in `|-- name`.
+    Ascii,
+}
+
+impl TreeCharset {
+    /// Returns the connectors for a middle entry, the last entry, a continued branch and an ended
+    /// branch, in that order.
+    fn connectors(&self) -> [&'static str; 4] {
+        match self {
+            TreeCharset::Unicode => ["├── ", "└── ", "│   ", "    "],
+            TreeCharset::Ascii => ["|-- ", "`-- ", "|   ", "    "],
+        }
+    }
+}
+
+/// The annotation written after the name of each entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeAnnotation {
+    /// No annotation.
+    None,
+    /// The aggregated size of the entry, in the form `1.5K`.
+    Size,
+    /// For directories, the number of entries directly inside them.
+    ChildCount,
+}
+
+/// Options controlling how a tree is rendered.
+#[derive(Debug, Clone)]
+pub struct TreeRenderOptions {
+    max_depth_: Option<usize>,
+    dirs_only_: bool,
+    charset_: TreeCharset,
+    annotation_: TreeAnnotation,
+    collapse_chains_: bool,
+    root_name_: Option<PathBuf>,
+}
+
+impl Default for TreeRenderOptions {
+    fn default() -> TreeRenderOptions {
+        TreeRenderOptions {
+            max_depth_: None,
+            dirs_only_: false,
+            charset_: TreeCharset::Unicode,
+            annotation_: TreeAnnotation::None,
+            collapse_chains_: false,
+            root_name_: None,
+        }
+    }
+}
+
+impl TreeRenderOptions {
+    /// Creates options drawing every entry with box-drawing characters and no annotations.
+    pub fn new() -> TreeRenderOptions {
+        TreeRenderOptions::default()
+    }
+
+    /// Limits the output to entries at most `max_depth` levels below the root.
+    pub fn with_max_depth(mut self, max_depth: usize) -> TreeRenderOptions {
+        self.max_depth_ = Some(max_depth);
+        self
+    }
+
+    /// Leaves out everything but directories.
+    pub fn with_dirs_only(mut self, dirs_only: bool) -> TreeRenderOptions {
+        self.dirs_only_ = dirs_only;
+        self
+    }
+
+    /// Sets the characters used to draw branches.
+    pub fn with_charset(mut self, charset: TreeCharset) -> TreeRenderOptions {
+        self.charset_ = charset;
+        self
+    }
+
+    /// Sets the annotation written after each name.
+    pub fn with_annotation(mut self, annotation: TreeAnnotation) -> TreeRenderOptions {
+        self.annotation_ = annotation;
+        self
+    }
+
+    /// Draws a directory whose only shown entry is another directory on one line with it, as in
+    /// `src/main/java/`, down to the first directory with several entries.
+    pub fn with_collapse_chains(mut self, collapse_chains: bool) -> TreeRenderOptions {
+        self.collapse_chains_ = collapse_chains;
+        self
+    }
+
+    /// Sets the name written on the first line instead of the relative path of the root.
+    pub fn with_root_name(mut self, root_name: &Path) -> TreeRenderOptions {
+        self.root_name_ = Some(root_name.to_path_buf());
+        self
+    }
+
+    /// Returns the depth limit, if any.
+    pub fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth_
+    }
+
+    /// Returns `true` if only directories are shown.
+    pub fn get_dirs_only(&self) -> bool {
+        self.dirs_only_
+    }
+
+    /// Returns the characters used to draw branches.
+    pub fn get_charset(&self) -> TreeCharset {
+        self.charset_
+    }
+
+    /// Returns the annotation written after each name.
+    pub fn get_annotation(&self) -> TreeAnnotation {
+        self.annotation_
+    }
+
+    /// Returns `true` if single-directory chains are drawn on one line.
+    pub fn get_collapse_chains(&self) -> bool {
+        self.collapse_chains_
+    }
+}
+
+struct Renderer<'a> {
+    options_: &'a TreeRenderOptions,
+    usage_: BTreeMap<PathBuf, DirUsage>,
+    dirs_: u64,
+    files_: u64,
+}
+
+/// Renders `node` and the entries below it.
+pub(crate) fn write_tree<W: Write>(node: &DirNode, out: &mut W, options: &TreeRenderOptions) -> io::Result<()> {
+    let mut usage = BTreeMap::new();
+    if options.annotation_ == TreeAnnotation::Size {
+        dir_usage::add_usage(node, &mut usage);
+    }
+    let mut renderer = Renderer {
+        options_: options,
+        usage_: usage,
+        dirs_: 0,
+        files_: 0,
+    };
+
+    match &options.root_name_ {
+        Some(root_name) => write_name(out, root_name)?,
+        None if node.get_entry().as_os_str().is_empty() => out.write_all(b".")?,
+        None => write_name(out, node.get_entry())?,
+    }
+    renderer.write_annotation(out, node)?;
+    out.write_all(b"\n")?;
+    if node.get_kind() == DirEntryKind::Dir {
+        renderer.write_children(out, node, 1, &mut Vec::new())?;
+    }
+
+    if options.dirs_only_ {
+        writeln!(out, "\n{} {}", renderer.dirs_, plural(renderer.dirs_, "directory", "directories"))
+    } else {
+        writeln!(
+            out,
+            "\n{} {}, {} {}",
+            renderer.dirs_,
+            plural(renderer.dirs_, "directory", "directories"),
+            renderer.files_,
+            plural(renderer.files_, "file", "files")
+        )
+    }
+}
+
+impl<'a> Renderer<'a> {
+    /// Writes the entries of `node` that are `depth` levels below the root; `prefix` holds
+    /// whether each enclosing level has more entries after the current one.
+    fn write_children<W: Write>(&mut self, out: &mut W, node: &DirNode, depth: usize, prefix: &mut Vec<bool>) -> io::Result<()> {
+        if self.options_.max_depth_.map_or(false, |max_depth| depth > max_depth) {
+            return Ok(());
+        }
+        let [middle, last, continued, ended] = self.options_.charset_.connectors();
+        let children = self.shown_children(node);
+        for (i, child) in children.iter().enumerate() {
+            let is_last = i + 1 == children.len();
+            for has_more in prefix.iter() {
+                out.write_all(if *has_more { continued } else { ended }.as_bytes())?;
+            }
+            out.write_all(if is_last { last } else { middle }.as_bytes())?;
+            write_name(out, Path::new(child.get_name()))?;
+            self.count(child);
+
+            // Follow the chain while each directory holds exactly one shown directory
+            let mut tail = *child;
+            let mut tail_depth = depth;
+            while self.options_.collapse_chains_ && self.options_.max_depth_.map_or(true, |max_depth| tail_depth < max_depth) {
+                match self.shown_children(tail).as_slice() {
+                    [only] if only.get_kind() == DirEntryKind::Dir => {
+                        out.write_all(b"/")?;
+                        write_name(out, Path::new(only.get_name()))?;
+                        self.count(only);
+                        tail = only;
+                        tail_depth += 1;
+                    }
+                    _ => break,
+                }
+            }
+
+            if tail.get_kind() == DirEntryKind::Dir {
+                out.write_all(b"/")?;
+            }
+            if let Some(target) = tail.get_link_target() {
+                out.write_all(b" -> ")?;
+                write_name(out, target)?;
+            }
+            self.write_annotation(out, tail)?;
+            out.write_all(b"\n")?;
+            if tail.get_kind() == DirEntryKind::Dir {
+                prefix.push(!is_last);
+                self.write_children(out, tail, tail_depth + 1, prefix)?;
+                prefix.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn shown_children<'n>(&self, node: &'n DirNode) -> Vec<&'n DirNode> {
+        node.get_sorted_children()
+            .into_iter()
+            .filter(|child| !self.options_.dirs_only_ || child.get_kind() == DirEntryKind::Dir)
+            .collect()
+    }
+
+    fn count(&mut self, node: &DirNode) {
+        if node.get_kind() == DirEntryKind::Dir {
+            self.dirs_ += 1;
+        } else {
+            self.files_ += 1;
+        }
+    }
+
+    fn write_annotation<W: Write>(&self, out: &mut W, node: &DirNode) -> io::Result<()> {
+        match self.options_.annotation_ {
+            TreeAnnotation::None => Ok(()),
+            TreeAnnotation::Size => {
+                let size = self.usage_.get(node.get_entry()).map_or(0, DirUsage::get_size);
+                write!(out, "  [{}]", dir_usage::human_size(size))
+            }
+            TreeAnnotation::ChildCount if node.get_kind() == DirEntryKind::Dir => {
+                let count = node.get_children().count() as u64;
+                write!(out, "  [{} {}]", count, plural(count, "entry", "entries"))
+            }
+            TreeAnnotation::ChildCount => Ok(()),
+        }
+    }
+}
+
+fn plural(count: u64, one: &'static str, many: &'static str) -> &'static str {
+    if count == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+/// Writes a name or link target, replacing control bytes with `?`.
+fn write_name<W: Write>(out: &mut W, path: &Path) -> io::Result<()> {
+    let shown: Vec<u8> = codec::path_to_bytes(path).iter().map(|&b| if b.is_ascii_control() { b'?' } else { b }).collect();
+    out.write_all(&shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::DirTree;
+
+    fn sample_tree() -> DirTree {
+        let lines = [
+            "{\"path\":\"\",\"type\":\"directory\",\"mode\":\"0755\"}",
+            "{\"path\":\"src/main.rs\",\"type\":\"file\",\"mode\":\"0644\",\"size\":1500}",
+            "{\"path\":\"src/util/mod.rs\",\"type\":\"file\",\"mode\":\"0644\",\"size\":10}",
+            "{\"path\":\"deep/er/est/leaf.txt\",\"type\":\"file\",\"mode\":\"0644\",\"size\":5}",
+            "{\"path\":\"empty\",\"type\":\"directory\",\"mode\":\"0755\"}",
+            "{\"path\":\"link\",\"type\":\"link\",\"target\":\"src\\u001b\",\"mode\":\"0777\"}",
+            "{\"path\":\"bad\\nname\",\"type\":\"file\",\"mode\":\"0644\",\"size\":1}",
+        ];
+        DirTree::from_json_lines(&lines.join("\n")).unwrap()
+    }
+
+    fn rendered(options: &TreeRenderOptions) -> String {
+        let mut out = Vec::new();
+        sample_tree().write_tree(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn draws_every_entry() {
+        let expected = concat!(
+            ".\n",
+            "├── bad?name\n",
+            "├── deep/\n",
+            "│   └── er/\n",
+            "│       └── est/\n",
+            "│           └── leaf.txt\n",
+            "├── empty/\n",
+            "├── link -> src?\n",
+            "└── src/\n",
+            "    ├── main.rs\n",
+            "    └── util/\n",
+            "        └── mod.rs\n",
+            "\n",
+            "6 directories, 5 files\n",
+        );
+        assert_eq!(rendered(&TreeRenderOptions::new()), expected);
+        let ascii = expected.replace("├── ", "|-- ").replace("└── ", "`-- ").replace("│   ", "|   ");
+        assert_eq!(rendered(&TreeRenderOptions::new().with_charset(TreeCharset::Ascii)), ascii);
+    }
+
+    #[test]
+    fn limits_depth_and_kinds() {
+        assert_eq!(
+            rendered(&TreeRenderOptions::new().with_max_depth(1)),
+            ".\n├── bad?name\n├── deep/\n├── empty/\n├── link -> src?\n└── src/\n\n3 directories, 2 files\n"
+        );
+        assert_eq!(rendered(&TreeRenderOptions::new().with_max_depth(0)), ".\n\n0 directories, 0 files\n");
+        assert_eq!(
+            rendered(&TreeRenderOptions::new().with_dirs_only(true).with_max_depth(2)),
+            ".\n├── deep/\n│   └── er/\n├── empty/\n└── src/\n    └── util/\n\n5 directories\n"
+        );
+    }
+
+    #[test]
+    fn collapses_chains_of_single_directories() {
+        let collapsed = rendered(&TreeRenderOptions::new().with_collapse_chains(true));
+        assert!(collapsed.contains("├── deep/er/est/\n│   └── leaf.txt\n"));
+        assert!(collapsed.contains("└── src/\n    ├── main.rs\n    └── util/\n"));
+        assert!(collapsed.ends_with("\n6 directories, 5 files\n"));
+
+        // Chains stop at the depth limit, and with only directories shown `src` becomes a chain
+        let limited = rendered(&TreeRenderOptions::new().with_collapse_chains(true).with_max_depth(2));
+        assert!(limited.contains("├── deep/er/\n├── empty/"));
+        let dirs = rendered(&TreeRenderOptions::new().with_collapse_chains(true).with_dirs_only(true));
+        assert_eq!(dirs, ".\n├── deep/er/est/\n├── empty/\n└── src/util/\n\n6 directories\n");
+    }
+
+    #[test]
+    fn annotates_sizes_and_counts() {
+        let sizes = rendered(&TreeRenderOptions::new().with_annotation(TreeAnnotation::Size));
+        assert!(sizes.starts_with(".  [1.5K]\n├── bad?name  [1]\n├── deep/  [5]\n"));
+        assert!(sizes.contains("└── src/  [1.5K]\n    ├── main.rs  [1.5K]\n    └── util/  [10]\n"));
+        let counts = rendered(&TreeRenderOptions::new().with_annotation(TreeAnnotation::ChildCount));
+        assert!(counts.starts_with(".  [5 entries]\n├── bad?name\n├── deep/  [1 entry]\n"));
+        assert!(counts.contains("├── empty/  [0 entries]\n├── link -> src?\n"));
+    }
+
+    #[test]
+    fn draws_subtrees_under_their_path_or_a_given_name() {
+        let tree = sample_tree();
+        let node = tree.get_node(Path::new("src")).unwrap();
+        let mut out = Vec::new();
+        node.write_tree(&mut out, &TreeRenderOptions::new()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "src\n├── main.rs\n└── util/\n    └── mod.rs\n\n1 directory, 2 files\n");
+
+        let mut out = Vec::new();
+        let options = TreeRenderOptions::new().with_root_name(Path::new("/tmp/x\ty")).with_max_depth(0);
+        tree.write_tree(&mut out, &options).unwrap();
+        assert_eq!(out, b"/tmp/x?y\n\n0 directories, 0 files\n");
+
+        let file = tree.get_node(Path::new("src/main.rs")).unwrap();
+        let mut out = Vec::new();
+        file.write_tree(&mut out, &TreeRenderOptions::new()).unwrap();
+        assert_eq!(out, b"src/main.rs\n\n0 directories, 0 files\n");
+    }
+}
@@ -0,0 +1,1106 @@
+//! Trigram index over file contents, for regular expression search in the style of codesearch.
+//!
+//! Every indexed text file is reduced to the set of three-byte sequences it contains. A pattern is
+//! turned into a boolean query over trigrams that any matching file must satisfy, the query picks
+//! the candidate files from the posting lists, and only the candidates are read and matched line
+//! by line. Files are keyed like the hash cache, so `update` re-reads only files whose device,
+//! inode, size or modification time changed.
+//!
+//! Layout of a saved index, all integers as LEB128 varints unless noted:
+//!
+//! * magic `DIRIDXT\n` (8 bytes) and format version (`u32`, little endian)
+//! * file count
+//! * every file: relative path, device, inode, size, zigzag mtime seconds, mtime nanoseconds,
+//!   flags, trigram count, trigrams as ascending deltas
+//! * XXH64 of everything above (`u64`, little endian)
+
+use super::{codec, DirEntryKind, DirIndexer, DirIndexerErr};
+use super::codec::{ByteReader, ByteWriter};
+use super::hash_cache::{FileKey, RACY_WINDOW};
+use super::index_file;
+use super::regex::{Node, Regex};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of every trigram index file.
+const TRIGRAM_MAGIC: &[u8; 8] = b"DIRIDXT\n";
+
+/// Current version of the trigram index format.
+const TRIGRAM_VERSION: u32 = 1;
+
+/// A file with a NUL byte in its first this many bytes is treated as binary.
+const BINARY_PROBE_LEN: usize = 8 * 1024;
+
+/// Files larger than this are not indexed, and are searched by streaming them line by line.
+pub(crate) const MAX_INDEXED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The largest set of exact strings tracked for a pattern fragment before it is summarized.
+const MAX_EXACT: usize = 16;
+
+/// The largest set of prefixes, suffixes or boundary strings tracked for a pattern fragment.
+const MAX_SET: usize = 64;
+
+/// Set in the flags of a file that was not indexed because it is binary or too large.
+const FLAG_SKIPPED: u8 = 1;
+
+/// Set in the flags of a file that must be re-read on the next update.
+const FLAG_RACY: u8 = 2;
+
+#[derive(Debug, Clone)]
+struct IndexedFile {
+    rl_path: PathBuf,
+    key: FileKey,
+    flags: u8,
+    /// Sorted and free of duplicates; empty for skipped files.
+    trigrams: Vec<u32>,
+}
+
+/// The `TrigramIndex` struct maps the trigrams of every indexed text file to the files holding them.
+#[derive(Debug, Clone, Default)]
+pub struct TrigramIndex {
+    files_: Vec<Option<IndexedFile>>,
+    free_ids_: Vec<u32>,
+    by_path_: HashMap<PathBuf, u32>,
+    postings_: HashMap<u32, Vec<u32>>,
+}
+
+impl TrigramIndex {
+    /// Creates an empty index.
+    pub fn new() -> TrigramIndex {
+        TrigramIndex::default()
+    }
+
+    /// Returns the conventional location of the trigram index of a saved index: the index path
+    /// with `.trigrams` appended.
+    pub fn path_for_index(index_path: &Path) -> PathBuf {
+        let mut trigram_path = index_path.as_os_str().to_os_string();
+        trigram_path.push(".trigrams");
+        PathBuf::from(trigram_path)
+    }
+
+    /// Loads an index written by `save`.
+    ///
+    /// # Arguments
+    ///
+    /// * `trigram_path` - The path of the trigram index file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the loaded index. A missing file yields an empty index. A
+    /// `DirIndexerErr::IndexIoFail`, `IncompatibleIndexVersion` or `CorruptIndex` is returned if
+    /// the file cannot be read or decoded.
+    pub fn load(trigram_path: &Path) -> Result<TrigramIndex, DirIndexerErr> {
+        let bytes = match fs::read(trigram_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(TrigramIndex::new()),
+            Err(_) => return Err(DirIndexerErr::IndexIoFail(trigram_path.to_path_buf())),
+        };
+        let corrupt = || DirIndexerErr::CorruptIndex(trigram_path.to_path_buf());
+        if bytes.len() < TRIGRAM_MAGIC.len() + 12 || &bytes[..TRIGRAM_MAGIC.len()] != TRIGRAM_MAGIC {
+            return Err(corrupt());
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 8);
+        let mut reader = ByteReader::from(&body[TRIGRAM_MAGIC.len()..]);
+        let version = reader.get_u32_le().ok_or_else(corrupt)?;
+        if version != TRIGRAM_VERSION {
+            return Err(DirIndexerErr::IncompatibleIndexVersion(version));
+        }
+        if ByteReader::from(sum).get_u64_le() != Some(index_file::checksum(body)) {
+            return Err(corrupt());
+        }
+        let count = reader.get_len(reader.remaining() / 8).ok_or_else(corrupt)?;
+        let mut index = TrigramIndex::new();
+        for _ in 0..count {
+            let rl_path = codec::bytes_to_path(reader.get_bytes().ok_or_else(corrupt)?)
+                .and_then(|rl_path| codec::normalize_relative(&rl_path))
+                .filter(|rl_path| !rl_path.as_os_str().is_empty())
+                .ok_or_else(corrupt)?;
+            let key = FileKey::read(&mut reader).ok_or_else(corrupt)?;
+            let flags = reader.get_u8().filter(|flags| *flags & !(FLAG_SKIPPED | FLAG_RACY) == 0).ok_or_else(corrupt)?;
+            let trigram_count = reader.get_len(reader.remaining()).ok_or_else(corrupt)?;
+            let mut trigrams = Vec::with_capacity(trigram_count);
+            let mut previous: Option<u32> = None;
+            for _ in 0..trigram_count {
+                let delta = reader.get_varint().ok_or_else(corrupt)?;
+                let trigram = match previous {
+                    Some(previous) if delta > 0 => (previous as u64).checked_add(delta),
+                    None => Some(delta),
+                    Some(_) => None,
+                };
+                let trigram = trigram.filter(|trigram| *trigram <= 0xff_ffff).ok_or_else(corrupt)?;
+                trigrams.push(trigram as u32);
+                previous = Some(trigram as u32);
+            }
+            if index.by_path_.contains_key(&rl_path) {
+                return Err(corrupt());
+            }
+            index.insert_file(IndexedFile { rl_path, key, flags, trigrams });
+        }
+        if reader.remaining() != 0 {
+            return Err(corrupt());
+        }
+        Ok(index)
+    }
+
+    /// Writes the index to `trigram_path` through a temporary file.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or `DirIndexerErr::IndexIoFail` if the file cannot be written.
+    pub fn save(&self, trigram_path: &Path) -> Result<(), DirIndexerErr> {
+        let mut writer = ByteWriter::new();
+        writer.put_raw(TRIGRAM_MAGIC);
+        writer.put_u32_le(TRIGRAM_VERSION);
+        writer.put_varint(self.by_path_.len() as u64);
+        for file in self.files_.iter().flatten() {
+            writer.put_bytes(codec::path_to_bytes(&file.rl_path));
+            file.key.write(&mut writer);
+            writer.put_u8(file.flags);
+            writer.put_varint(file.trigrams.len() as u64);
+            let mut previous = 0;
+            for trigram in &file.trigrams {
+                writer.put_varint((trigram - previous) as u64);
+                previous = *trigram;
+            }
+        }
+        let sum = index_file::checksum(writer.as_slice());
+        writer.put_u64_le(sum);
+        index_file::write_atomically(trigram_path, &writer.into_vec())
+    }
+
+    /// Returns the number of files in the index, including skipped binary and oversized files.
+    pub fn len(&self) -> usize {
+        self.by_path_.len()
+    }
+
+    /// Returns `true` if the index holds no files.
+    pub fn is_empty(&self) -> bool {
+        self.by_path_.is_empty()
+    }
+
+    /// Returns the number of distinct trigrams in the index.
+    pub fn get_trigram_count(&self) -> usize {
+        self.postings_.len()
+    }
+
+    /// Lists the indexed files whose trigrams could match a pattern. Every file the pattern
+    /// matches is in the list, as long as it has not changed since the index was updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - A regular expression in the syntax of `DirQuery::regex`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the candidate paths relative to the root, sorted, or
+    /// `DirIndexerErr::QueryParseFail` if the pattern is invalid.
+    pub fn get_candidates(&self, pattern: &str) -> Result<Vec<PathBuf>, DirIndexerErr> {
+        let regex = Regex::new(pattern).map_err(DirIndexerErr::QueryParseFail)?;
+        let ids = self.candidate_ids(&regex);
+        let mut candidates: Vec<PathBuf> = self
+            .files_
+            .iter()
+            .enumerate()
+            .filter_map(|(id, file)| file.as_ref().map(|file| (id as u32, file)))
+            .filter(|(id, file)| file.flags & FLAG_SKIPPED == 0 && ids.as_ref().map_or(true, |ids| ids.contains(id)))
+            .map(|(_, file)| file.rl_path.clone())
+            .collect();
+        candidates.sort();
+        Ok(candidates)
+    }
+
+    /// Evaluates the trigram query of a pattern. `None` stands for every file.
+    fn candidate_ids(&self, regex: &Regex) -> Option<HashSet<u32>> {
+        self.evaluate(&query_for(regex.get_ast()))
+            .map(|ids| ids.into_iter().collect())
+    }
+
+    /// Returns the sorted ids of the files satisfying a query, or `None` for every file.
+    fn evaluate(&self, query: &TrigramQuery) -> Option<Vec<u32>> {
+        match query {
+            TrigramQuery::All => None,
+            TrigramQuery::Trigram(trigram) => Some(self.postings_.get(trigram).cloned().unwrap_or_default()),
+            TrigramQuery::And(queries) => {
+                let mut result: Option<Vec<u32>> = None;
+                for query in queries {
+                    if let Some(ids) = self.evaluate(query) {
+                        result = Some(match result {
+                            Some(result) => intersect(&result, &ids),
+                            None => ids,
+                        });
+                        if result.as_ref().map_or(false, Vec::is_empty) {
+                            break;
+                        }
+                    }
+                }
+                result
+            }
+            TrigramQuery::Or(queries) => {
+                let mut result: Vec<u32> = Vec::new();
+                for query in queries {
+                    result = union(&result, &self.evaluate(query)?);
+                }
+                Some(result)
+            }
+        }
+    }
+
+    fn insert_file(&mut self, file: IndexedFile) {
+        let id = match self.free_ids_.pop() {
+            Some(id) => id,
+            None => {
+                self.files_.push(None);
+                (self.files_.len() - 1) as u32
+            }
+        };
+        for trigram in &file.trigrams {
+            let posting = self.postings_.entry(*trigram).or_default();
+            // Ids are reused, so a new id is not necessarily the largest
+            if let Err(at) = posting.binary_search(&id) {
+                posting.insert(at, id);
+            }
+        }
+        self.by_path_.insert(file.rl_path.clone(), id);
+        self.files_[id as usize] = Some(file);
+    }
+
+    fn remove_file(&mut self, rl_path: &Path) {
+        let id = match self.by_path_.remove(rl_path) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(file) = self.files_[id as usize].take() {
+            for trigram in &file.trigrams {
+                if let Some(posting) = self.postings_.get_mut(trigram) {
+                    if let Ok(at) = posting.binary_search(&id) {
+                        posting.remove(at);
+                    }
+                    if posting.is_empty() {
+                        self.postings_.remove(trigram);
+                    }
+                }
+            }
+        }
+        self.free_ids_.push(id);
+    }
+
+    fn get_file(&self, rl_path: &Path) -> Option<(u32, &IndexedFile)> {
+        let id = *self.by_path_.get(rl_path)?;
+        self.files_[id as usize].as_ref().map(|file| (id, file))
+    }
+}
+
+/// Counts of how the files of a `DirIndexer::update_trigram_index` run were handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrigramStats {
+    indexed_: usize,
+    reused_: usize,
+    skipped_: usize,
+    removed_: usize,
+    failed_: usize,
+}
+
+impl TrigramStats {
+    /// Returns the number of files that were read and indexed.
+    pub fn get_indexed(&self) -> usize {
+        self.indexed_
+    }
+
+    /// Returns the number of unchanged files whose trigrams were kept.
+    pub fn get_reused(&self) -> usize {
+        self.reused_
+    }
+
+    /// Returns the number of new or changed files left out as binary or too large.
+    pub fn get_skipped(&self) -> usize {
+        self.skipped_
+    }
+
+    /// Returns the number of files dropped because they are no longer in the directory index.
+    pub fn get_removed(&self) -> usize {
+        self.removed_
+    }
+
+    /// Returns the number of files that could not be read.
+    pub fn get_failed(&self) -> usize {
+        self.failed_
+    }
+}
+
+/// A line of a file that matched a content search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMatch {
+    path_: PathBuf,
+    line_number_: usize,
+    line_: Vec<u8>,
+}
+
+impl ContentMatch {
+    /// Returns the path of the file, relative to the root.
+    pub fn get_path(&self) -> &Path {
+        &self.path_
+    }
+
+    /// Returns the line number, starting at 1.
+    pub fn get_line_number(&self) -> usize {
+        self.line_number_
+    }
+
+    /// Returns the bytes of the line, without its line terminator.
+    pub fn get_line(&self) -> &[u8] {
+        &self.line_
+    }
+}
+
+/// Brings the index up to date with the regular files of the directory index. Unchanged files
+/// keep their trigrams; new, changed and racy files are read again, and files no longer in the
+/// directory index are dropped.
+pub(crate) fn update(indexer: &DirIndexer, index: &mut TrigramIndex) -> TrigramStats {
+    let racy_since = indexer.get_scan_time().checked_sub(RACY_WINDOW);
+    let mut stats = TrigramStats::default();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        if node.get_kind() != DirEntryKind::File {
+            continue;
+        }
+        let meta = node.get_meta();
+        let key = FileKey::from(meta);
+        seen.insert(rl_path.clone());
+        if let Some((_, file)) = index.get_file(&rl_path) {
+            if file.key == key && file.flags & FLAG_RACY == 0 {
+                stats.reused_ += 1;
+                continue;
+            }
+        }
+        index.remove_file(&rl_path);
+
+        let racy = match racy_since {
+            Some(since) => index_file::time_from_parts(meta.get_mtime(), meta.get_mtime_nsec() as u64)
+                .map_or(true, |mtime| mtime >= since),
+            None => true,
+        };
+        let mut flags = if racy { FLAG_RACY } else { 0 };
+        let mut trigrams = Vec::new();
+        if key.size > MAX_INDEXED_SIZE {
+            flags |= FLAG_SKIPPED;
+        } else {
+            match fs::read(indexer.get_root_path().join(&rl_path)) {
+                Ok(contents) if is_binary(&contents) => flags |= FLAG_SKIPPED,
+                Ok(contents) => trigrams = trigrams_of(&contents),
+                Err(_) => {
+                    stats.failed_ += 1;
+                    continue;
+                }
+            }
+        }
+        if flags & FLAG_SKIPPED != 0 {
+            stats.skipped_ += 1;
+        } else {
+            stats.indexed_ += 1;
+        }
+        index.insert_file(IndexedFile { rl_path, key, flags, trigrams });
+    }
+
+    let gone: Vec<PathBuf> = index.by_path_.keys().filter(|rl_path| !seen.contains(*rl_path)).cloned().collect();
+    for rl_path in gone {
+        index.remove_file(&rl_path);
+        stats.removed_ += 1;
+    }
+    stats
+}
+
+/// Searches the regular files of the directory index for lines matching a pattern.
+///
+/// Files the trigram index knows to be unchanged are read only if their trigrams allow a match.
+/// Files it does not know, or that changed since it was updated, are always read, and so are files
+/// too large to index, a line at a time, so the result is exact for the text files in the
+/// directory index.
+pub(crate) fn search(indexer: &DirIndexer, index: &TrigramIndex, pattern: &str) -> Result<Vec<ContentMatch>, DirIndexerErr> {
+    let regex = Regex::new(pattern).map_err(DirIndexerErr::QueryParseFail)?;
+    let ids = index.candidate_ids(&regex);
+    let mut matches = Vec::new();
+
+    for (rl_path, node) in indexer.get_tree().get_rl2node_map() {
+        if node.get_kind() != DirEntryKind::File {
+            continue;
+        }
+        let key = FileKey::from(node.get_meta());
+        let indexed = index.get_file(&rl_path).filter(|(_, file)| file.key == key && file.flags & FLAG_RACY == 0);
+        let skip = match indexed {
+            Some((_, file)) if file.flags & FLAG_SKIPPED != 0 => key.size <= MAX_INDEXED_SIZE,
+            Some((id, _)) => ids.as_ref().map_or(false, |ids| !ids.contains(&id)),
+            None => false,
+        };
+        if skip {
+            continue;
+        }
+        if key.size > MAX_INDEXED_SIZE {
+            // A file that cannot be read is left out, as below
+            let _ = stream_search(&indexer.get_root_path().join(&rl_path), &rl_path, &regex, &mut matches);
+            continue;
+        }
+        let contents = match read_text(&indexer.get_root_path().join(&rl_path)) {
+            Some(contents) => contents,
+            None => continue,
+        };
+        for (i, line) in contents.split(|b| *b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if regex.is_match(line) {
+                matches.push(ContentMatch {
+                    path_: rl_path.clone(),
+                    line_number_: i + 1,
+                    line_: line.to_vec(),
+                });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Searches a file a line at a time, for files too large to read whole. Matches are appended to
+/// `matches`; a binary file adds none.
+fn stream_search(path: &Path, rl_path: &Path, regex: &Regex, matches: &mut Vec<ContentMatch>) -> io::Result<()> {
+    let mut file = fs::File::open(path)?;
+    let mut probe = Vec::new();
+    (&mut file).take(BINARY_PROBE_LEN as u64).read_to_end(&mut probe)?;
+    if is_binary(&probe) {
+        return Ok(());
+    }
+    let mut reader = BufReader::new(io::Cursor::new(probe).chain(file));
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        line_number += 1;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if regex.is_match(text) {
+            matches.push(ContentMatch {
+                path_: rl_path.to_path_buf(),
+                line_number_: line_number,
+                line_: text.to_vec(),
+            });
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Reads a file for searching, or returns `None` if it cannot be read or is binary.
+fn read_text(path: &Path) -> Option<Vec<u8>> {
+    let mut contents = Vec::new();
+    fs::File::open(path).ok()?.read_to_end(&mut contents).ok()?;
+    if is_binary(&contents) {
+        return None;
+    }
+    // A trailing newline ends the last line rather than starting an empty one
+    if contents.last() == Some(&b'\n') {
+        contents.pop();
+    }
+    Some(contents)
+}
+
+/// Returns `true` if a NUL byte appears in the first 8 KiB of a file.
+pub(crate) fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(BINARY_PROBE_LEN)].contains(&0)
+}
+
+fn trigram(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32
+}
+
+/// Returns the sorted trigrams of a text. Trigrams spanning a line break are left out, since
+/// patterns are matched one line at a time.
+fn trigrams_of(contents: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = contents
+        .windows(3)
+        .filter(|window| !window.contains(&b'\n'))
+        .map(trigram)
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            out.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    out
+}
+
+fn union(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if j == b.len() || (i < a.len() && a[i] < b[j]) {
+            out.push(a[i]);
+            i += 1;
+        } else if i == a.len() || b[j] < a[i] {
+            out.push(b[j]);
+            j += 1;
+        } else {
+            out.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    out
+}
+
+/// A boolean condition on the trigrams of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TrigramQuery {
+    All,
+    Trigram(u32),
+    And(Vec<TrigramQuery>),
+    Or(Vec<TrigramQuery>),
+}
+
+impl TrigramQuery {
+    fn and(self, other: TrigramQuery) -> TrigramQuery {
+        match (self, other) {
+            (TrigramQuery::All, query) | (query, TrigramQuery::All) => query,
+            (TrigramQuery::And(mut left), TrigramQuery::And(right)) => {
+                left.extend(right);
+                TrigramQuery::And(left)
+            }
+            (TrigramQuery::And(mut left), query) | (query, TrigramQuery::And(mut left)) => {
+                left.push(query);
+                TrigramQuery::And(left)
+            }
+            (left, right) => TrigramQuery::And(vec![left, right]),
+        }
+    }
+
+    fn or(self, other: TrigramQuery) -> TrigramQuery {
+        match (self, other) {
+            (TrigramQuery::All, _) | (_, TrigramQuery::All) => TrigramQuery::All,
+            (TrigramQuery::Or(mut left), TrigramQuery::Or(right)) => {
+                left.extend(right);
+                TrigramQuery::Or(left)
+            }
+            (TrigramQuery::Or(mut left), query) | (query, TrigramQuery::Or(mut left)) => {
+                left.push(query);
+                TrigramQuery::Or(left)
+            }
+            (left, right) => TrigramQuery::Or(vec![left, right]),
+        }
+    }
+
+    /// Requires one of the strings to occur. A string shorter than a trigram can occur anywhere,
+    /// so it makes the query match every file.
+    fn any_of(strings: &BTreeSet<Vec<u8>>) -> TrigramQuery {
+        if strings.is_empty() || strings.iter().any(|s| s.len() < 3) {
+            return TrigramQuery::All;
+        }
+        strings
+            .iter()
+            .map(|s| {
+                let mut trigrams: Vec<u32> = s.windows(3).map(trigram).collect();
+                trigrams.sort_unstable();
+                trigrams.dedup();
+                trigrams
+                    .into_iter()
+                    .map(TrigramQuery::Trigram)
+                    .fold(TrigramQuery::All, TrigramQuery::and)
+            })
+            .reduce(TrigramQuery::or)
+            .unwrap_or(TrigramQuery::All)
+    }
+}
+
+/// What is known about the strings a pattern fragment matches.
+#[derive(Debug, Clone)]
+struct Info {
+    /// Every string the fragment matches, if there are few enough to list.
+    exact: Option<BTreeSet<Vec<u8>>>,
+    /// Strings one of which starts every match; unused while `exact` is known.
+    prefix: BTreeSet<Vec<u8>>,
+    /// Strings one of which ends every match; unused while `exact` is known.
+    suffix: BTreeSet<Vec<u8>>,
+    /// A condition every file holding a match satisfies.
+    query: TrigramQuery,
+}
+
+impl Info {
+    fn exact(strings: BTreeSet<Vec<u8>>) -> Info {
+        Info {
+            exact: Some(strings),
+            prefix: BTreeSet::new(),
+            suffix: BTreeSet::new(),
+            query: TrigramQuery::All,
+        }
+    }
+
+    fn anything() -> Info {
+        let empty: BTreeSet<Vec<u8>> = std::iter::once(Vec::new()).collect();
+        Info {
+            exact: None,
+            prefix: empty.clone(),
+            suffix: empty,
+            query: TrigramQuery::All,
+        }
+    }
+
+    fn prefix_or_exact(&self) -> &BTreeSet<Vec<u8>> {
+        self.exact.as_ref().unwrap_or(&self.prefix)
+    }
+
+    fn suffix_or_exact(&self) -> &BTreeSet<Vec<u8>> {
+        self.exact.as_ref().unwrap_or(&self.suffix)
+    }
+
+    /// Folds the exact strings into the query.
+    fn into_query(self) -> TrigramQuery {
+        match self.exact {
+            Some(exact) => self.query.and(TrigramQuery::any_of(&exact)),
+            None => self.query,
+        }
+    }
+}
+
+/// Returns the condition on trigrams that every file holding a match of the pattern satisfies.
+fn query_for(node: &Node) -> TrigramQuery {
+    analyze(node).into_query()
+}
+
+fn analyze(node: &Node) -> Info {
+    match node {
+        Node::Empty | Node::Look(_) => Info::exact(std::iter::once(Vec::new()).collect()),
+        Node::Literal(bytes) => Info::exact(std::iter::once(bytes.clone()).collect()),
+        Node::Class(class) => {
+            let members = class.ascii.iter().filter(|member| **member).count() + class.chars.len();
+            if class.any_non_ascii || members > 8 {
+                return Info::anything();
+            }
+            let mut strings: BTreeSet<Vec<u8>> = (0u8..128).filter(|b| class.ascii[*b as usize]).map(|b| vec![b]).collect();
+            for c in &class.chars {
+                strings.insert(c.to_string().into_bytes());
+            }
+            Info::exact(strings)
+        }
+        Node::AnyChar => Info::anything(),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .map(analyze)
+            .reduce(concat)
+            .unwrap_or_else(|| analyze(&Node::Empty)),
+        Node::Alt(nodes) => nodes
+            .iter()
+            .map(analyze)
+            .reduce(alternate)
+            .unwrap_or_else(Info::anything),
+        Node::Repeat { node, min, max } => match (min, max) {
+            (0, Some(1)) => alternate(analyze(node), analyze(&Node::Empty)),
+            (0, _) => Info::anything(),
+            (1, Some(1)) => analyze(node),
+            _ => {
+                // The fragment occurs at least once, but its repetitions are not listed
+                let info = analyze(node);
+                let prefix = trim_front(info.prefix_or_exact());
+                let suffix = trim_back(info.suffix_or_exact());
+                Info {
+                    exact: None,
+                    prefix,
+                    suffix,
+                    query: info.into_query(),
+                }
+            }
+        },
+    }
+}
+
+fn concat(x: Info, y: Info) -> Info {
+    if let (Some(x_exact), Some(y_exact)) = (&x.exact, &y.exact) {
+        if x_exact.len() * y_exact.len() <= MAX_EXACT {
+            let mut info = Info::exact(cross(x_exact, y_exact));
+            info.query = x.query.and(y.query);
+            return info;
+        }
+    }
+
+    // The strings spanning the boundary between the two fragments
+    let mut query = x.query.clone().and(y.query.clone());
+    let (x_end, y_start) = (x.suffix_or_exact(), y.prefix_or_exact());
+    if x_end.len() * y_start.len() <= MAX_SET {
+        query = query.and(TrigramQuery::any_of(&cross(x_end, y_start)));
+    } else {
+        for exact in x.exact.iter().chain(y.exact.iter()) {
+            query = query.and(TrigramQuery::any_of(exact));
+        }
+    }
+
+    let prefix = match &x.exact {
+        Some(x_exact) if x_exact.len() * y_start.len() <= MAX_SET => trim_front(&cross(x_exact, y_start)),
+        Some(x_exact) => trim_front(x_exact),
+        None => x.prefix.clone(),
+    };
+    let suffix = match &y.exact {
+        Some(y_exact) if x_end.len() * y_exact.len() <= MAX_SET => trim_back(&cross(x_end, y_exact)),
+        Some(y_exact) => trim_back(y_exact),
+        None => y.suffix.clone(),
+    };
+    Info {
+        exact: None,
+        prefix,
+        suffix,
+        query,
+    }
+}
+
+fn alternate(x: Info, y: Info) -> Info {
+    if let (Some(x_exact), Some(y_exact)) = (&x.exact, &y.exact) {
+        if x_exact.len() + y_exact.len() <= MAX_EXACT {
+            let mut info = Info::exact(x_exact.union(y_exact).cloned().collect());
+            info.query = x.query.or(y.query);
+            return info;
+        }
+    }
+    let prefix = capped(trim_front(x.prefix_or_exact()).union(&trim_front(y.prefix_or_exact())).cloned().collect());
+    let suffix = capped(trim_back(x.suffix_or_exact()).union(&trim_back(y.suffix_or_exact())).cloned().collect());
+    Info {
+        exact: None,
+        prefix,
+        suffix,
+        query: x.into_query().or(y.into_query()),
+    }
+}
+
+fn cross(x: &BTreeSet<Vec<u8>>, y: &BTreeSet<Vec<u8>>) -> BTreeSet<Vec<u8>> {
+    let mut out = BTreeSet::new();
+    for a in x {
+        for b in y {
+            let mut s = a.clone();
+            s.extend_from_slice(b);
+            out.insert(s);
+        }
+    }
+    out
+}
+
+/// Shortens every string to its first two bytes, all a boundary trigram can use.
+fn trim_front(set: &BTreeSet<Vec<u8>>) -> BTreeSet<Vec<u8>> {
+    set.iter().map(|s| s[..s.len().min(2)].to_vec()).collect()
+}
+
+/// Shortens every string to its last two bytes, all a boundary trigram can use.
+fn trim_back(set: &BTreeSet<Vec<u8>>) -> BTreeSet<Vec<u8>> {
+    set.iter().map(|s| s[s.len().saturating_sub(2)..].to_vec()).collect()
+}
+
+/// Replaces a set too large to track with the empty string, which says nothing.
+fn capped(set: BTreeSet<Vec<u8>>) -> BTreeSet<Vec<u8>> {
+    if set.len() > MAX_SET {
+        std::iter::once(Vec::new()).collect()
+    } else {
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+
+    /// A small deterministic generator, so failures can be reproduced.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n
+        }
+
+        fn text(&mut self, pieces: &[&str], max_pieces: usize) -> String {
+            (0..self.next(max_pieces + 1)).map(|_| pieces[self.next(pieces.len())]).collect()
+        }
+    }
+
+    const TEXT_PIECES: [&str; 11] = ["a", "b", "c", "A", "B", " ", "\n", "é", "ab", "abc", "cab"];
+
+    const PATTERN_PIECES: [&str; 22] = [
+        "a", "b", "c", "ab", "bc", "abc", "cab", ".", "[ab]", "[^a]", "(a|bc)", "(abc|b)", "x?", "a*", "b+", "(ab)+",
+        "c{2}", "^", "$", "\\w", "é", "[éb]",
+    ];
+
+    fn random_patterns(rng: &mut Lcg, count: usize) -> Vec<String> {
+        let mut patterns = Vec::new();
+        while patterns.len() < count {
+            let mut pattern = rng.text(&PATTERN_PIECES, 5);
+            match rng.next(4) {
+                0 => pattern = format!("{}|{}", pattern, rng.text(&PATTERN_PIECES, 3)),
+                1 => pattern = format!("(?i){}", pattern),
+                _ => {}
+            }
+            if Regex::new(&pattern).is_ok() {
+                patterns.push(pattern);
+            }
+        }
+        patterns
+    }
+
+    fn holds(query: &TrigramQuery, trigrams: &[u32]) -> bool {
+        match query {
+            TrigramQuery::All => true,
+            TrigramQuery::Trigram(trigram) => trigrams.binary_search(trigram).is_ok(),
+            TrigramQuery::And(queries) => queries.iter().all(|query| holds(query, trigrams)),
+            TrigramQuery::Or(queries) => queries.iter().any(|query| holds(query, trigrams)),
+        }
+    }
+
+    fn any_line_matches(regex: &Regex, contents: &[u8]) -> bool {
+        contents.split(|b| *b == b'\n').any(|line| regex.is_match(line))
+    }
+
+    fn updated(dir: &TempDir) -> (DirIndexer, TrigramIndex, TrigramStats) {
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let mut index = TrigramIndex::new();
+        let stats = indexer.update_trigram_index(&mut index);
+        (indexer, index, stats)
+    }
+
+    fn paths(candidates: Vec<PathBuf>) -> Vec<String> {
+        candidates.into_iter().map(|p| p.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn queries_hold_for_every_matching_text() {
+        let mut rng = Lcg(7);
+        let texts: Vec<String> = (0..400).map(|_| rng.text(&TEXT_PIECES, 24)).collect();
+        for pattern in random_patterns(&mut rng, 400) {
+            let regex = Regex::new(&pattern).unwrap();
+            let query = query_for(regex.get_ast());
+            for text in &texts {
+                if any_line_matches(&regex, text.as_bytes()) {
+                    assert!(holds(&query, &trigrams_of(text.as_bytes())), "{:?} matches {:?}", pattern, text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn candidates_include_every_brute_force_match() {
+        let dir = TempDir::new("trigram-brute");
+        let mut rng = Lcg(11);
+        let texts: Vec<String> = (0..40).map(|_| rng.text(&TEXT_PIECES, 30)).collect();
+        for (i, text) in texts.iter().enumerate() {
+            dir.write(format!("f{:02}", i), text.as_bytes());
+        }
+        let (indexer, index, _) = updated(&dir);
+        for pattern in random_patterns(&mut rng, 200) {
+            let regex = Regex::new(&pattern).unwrap();
+            let candidates = index.get_candidates(&pattern).unwrap();
+            let mut expected = Vec::new();
+            for (i, text) in texts.iter().enumerate() {
+                if any_line_matches(&regex, text.as_bytes()) {
+                    let rl_path = PathBuf::from(format!("f{:02}", i));
+                    assert!(candidates.contains(&rl_path), "{:?} misses {:?}", pattern, rl_path);
+                    expected.push(rl_path);
+                }
+            }
+            let found: Vec<PathBuf> = indexer.grep(&index, &pattern).unwrap().into_iter().map(|m| m.get_path().to_path_buf()).collect();
+            let mut found_files = found.clone();
+            found_files.dedup();
+            assert_eq!(found_files, expected, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn candidates_are_narrowed_by_trigrams() {
+        let dir = TempDir::new("trigram-narrow");
+        dir.write("greeting.txt", b"hello world\n");
+        dir.write("farewell.txt", b"goodbye\n");
+        dir.write("split.txt", b"hel\nlo\n");
+        dir.write("image.bin", b"hello\0world");
+        let (_, index, _) = updated(&dir);
+        assert_eq!(paths(index.get_candidates("hello").unwrap()), vec!["greeting.txt"]);
+        // Trigrams never span lines, and a repetition only pins the trigrams next to it
+        assert_eq!(paths(index.get_candidates("hel+o").unwrap()), vec!["greeting.txt", "split.txt"]);
+        assert_eq!(paths(index.get_candidates("(hello|goodbye)").unwrap()), vec!["farewell.txt", "greeting.txt"]);
+        assert_eq!(paths(index.get_candidates("good(bye)?").unwrap()), vec!["farewell.txt"]);
+        assert_eq!(index.get_candidates("o w").unwrap().len(), 1);
+        // Too short or too vague to use trigrams
+        assert_eq!(index.get_candidates("lo").unwrap().len(), 3);
+        assert_eq!(index.get_candidates(".*").unwrap().len(), 3);
+        assert!(index.get_candidates("zzz").unwrap().is_empty());
+        assert!(matches!(index.get_candidates("(hello"), Err(DirIndexerErr::QueryParseFail(_))));
+    }
+
+    #[test]
+    fn grep_reports_matching_lines() {
+        let dir = TempDir::new("trigram-grep");
+        dir.write("a.txt", b"first\r\nsecond line\nthird line\n");
+        dir.write("b/c.txt", b"no match\nLINE\n");
+        dir.write("d.bin", b"line\0");
+        let (indexer, index, _) = updated(&dir);
+        let found = indexer.grep(&index, "line$").unwrap();
+        let lines: Vec<(&Path, usize, &[u8])> = found.iter().map(|m| (m.get_path(), m.get_line_number(), m.get_line())).collect();
+        assert_eq!(
+            lines,
+            vec![(Path::new("a.txt"), 2, &b"second line"[..]), (Path::new("a.txt"), 3, &b"third line"[..])]
+        );
+        assert_eq!(indexer.grep(&index, "(?i)^line").unwrap().len(), 1);
+        assert_eq!(indexer.grep(&index, "first$").unwrap()[0].get_line(), b"first");
+
+        // Files changed or added since the update are still searched
+        dir.write("a.txt", b"rewritten\n");
+        dir.write("new.txt", b"a new line\n");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let found = indexer.grep(&index, "line$").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get_path(), Path::new("new.txt"));
+        assert_eq!(indexer.grep(&TrigramIndex::new(), "rewritten").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn files_too_large_to_index_are_still_searched() {
+        let dir = TempDir::new("trigram-large");
+        let mut text = b"header\r\nthe needle line\n".to_vec();
+        text.resize(BINARY_PROBE_LEN + 1, b'x');
+        text.extend_from_slice(b"\nanother needle\n");
+        dir.write("large.txt", &text);
+        dir.write("large.bin", b"needle\0");
+        // Grown sparse, so the tail reads back as NUL bytes without taking up disk space
+        for name in ["large.txt", "large.bin"].iter() {
+            fs::OpenOptions::new().append(true).open(dir.join(name)).unwrap().set_len(MAX_INDEXED_SIZE + 1).unwrap();
+        }
+        let (indexer, index, stats) = updated(&dir);
+        assert_eq!(stats.get_skipped(), 2);
+
+        let found = indexer.grep(&index, "needle").unwrap();
+        let lines: Vec<(&Path, usize, &[u8])> = found.iter().map(|m| (m.get_path(), m.get_line_number(), m.get_line())).collect();
+        assert_eq!(
+            lines,
+            vec![(Path::new("large.txt"), 2, &b"the needle line"[..]), (Path::new("large.txt"), 4, &b"another needle"[..])]
+        );
+    }
+
+    #[test]
+    fn updates_reuse_unchanged_files() {
+        let dir = TempDir::new("trigram-update");
+        dir.write("old.txt", b"settled text");
+        dir.write("gone.txt", b"going away");
+        dir.write("data.bin", b"\0\0\0");
+        dir.write("fresh.txt", b"just written");
+        dir.set_mtime("old.txt", 1_000_000_000);
+        dir.set_mtime("gone.txt", 1_000_000_000);
+        dir.set_mtime("data.bin", 1_000_000_000);
+        let (indexer, mut index, stats) = updated(&dir);
+        assert_eq!((stats.get_indexed(), stats.get_skipped(), stats.get_reused()), (3, 1, 0));
+        assert_eq!(index.len(), 4);
+
+        // Only the racy file is read again
+        let stats = indexer.update_trigram_index(&mut index);
+        assert_eq!((stats.get_indexed(), stats.get_reused()), (1, 3));
+
+        fs::remove_file(dir.join("gone.txt")).unwrap();
+        dir.write("old.txt", b"changed text");
+        dir.set_mtime("old.txt", 1_000_000_001);
+        dir.set_mtime("fresh.txt", 1_000_000_000);
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let stats = indexer.update_trigram_index(&mut index);
+        assert_eq!((stats.get_indexed(), stats.get_reused(), stats.get_removed()), (2, 1, 1));
+        assert_eq!(index.len(), 3);
+        assert_eq!(paths(index.get_candidates("changed").unwrap()), vec!["old.txt"]);
+        assert!(index.get_candidates("settled").unwrap().is_empty());
+        assert!(index.get_candidates("going").unwrap().is_empty());
+
+        let stats = indexer.update_trigram_index(&mut index);
+        assert_eq!((stats.get_indexed(), stats.get_reused()), (0, 3));
+
+        // Files that disappear between the scan and the update are counted as failures
+        fs::remove_file(dir.join("fresh.txt")).unwrap();
+        let stats = indexer.update_trigram_index(&mut TrigramIndex::new());
+        assert_eq!(stats.get_failed(), 1);
+    }
+
+    #[test]
+    fn saved_indexes_round_trip() {
+        let dir = TempDir::new("trigram-save");
+        dir.write("tree/a.txt", b"alpha beta\ngamma");
+        dir.write("tree/b.txt", b"beta delta");
+        dir.write("tree/c.bin", b"\0binary");
+        let indexer = DirIndexer::from(dir.join("tree")).unwrap();
+        let mut index = TrigramIndex::new();
+        indexer.update_trigram_index(&mut index);
+        let saved = TrigramIndex::path_for_index(&dir.join("index"));
+        assert_eq!(saved, dir.join("index.trigrams"));
+        index.save(&saved).unwrap();
+
+        let mut loaded = TrigramIndex::load(&saved).unwrap();
+        assert_eq!(loaded.len(), index.len());
+        assert_eq!(loaded.get_trigram_count(), index.get_trigram_count());
+        for pattern in ["beta", "alpha|delta", "gam+a", "."].iter() {
+            assert_eq!(loaded.get_candidates(pattern).unwrap(), index.get_candidates(pattern).unwrap());
+        }
+        let stats = indexer.update_trigram_index(&mut loaded);
+        assert_eq!(stats.get_reused() + stats.get_indexed() + stats.get_skipped(), 3);
+
+        assert!(TrigramIndex::load(&dir.join("missing")).unwrap().is_empty());
+        assert!(matches!(TrigramIndex::load(dir.get_path()), Err(DirIndexerErr::IndexIoFail(_))));
+    }
+
+    #[test]
+    fn corrupt_indexes_are_rejected() {
+        let dir = TempDir::new("trigram-corrupt");
+        dir.write("tree/a.txt", b"some text to index");
+        dir.write("tree/b.txt", b"more text");
+        let indexer = DirIndexer::from(dir.join("tree")).unwrap();
+        let mut index = TrigramIndex::new();
+        indexer.update_trigram_index(&mut index);
+        let saved = dir.join("index.trigrams");
+        index.save(&saved).unwrap();
+        let bytes = fs::read(&saved).unwrap();
+        let broken = dir.join("broken");
+
+        for len in 0..bytes.len() {
+            fs::write(&broken, &bytes[..len]).unwrap();
+            assert!(TrigramIndex::load(&broken).is_err(), "truncated to {}", len);
+        }
+        for at in 0..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[at] ^= 0x10;
+            fs::write(&broken, &flipped).unwrap();
+            assert!(TrigramIndex::load(&broken).is_err(), "flipped at {}", at);
+        }
+
+        let mut newer = bytes.clone();
+        newer[TRIGRAM_MAGIC.len()] = 2;
+        fs::write(&broken, &newer).unwrap();
+        assert!(matches!(TrigramIndex::load(&broken), Err(DirIndexerErr::IncompatibleIndexVersion(2))));
+
+        // A well-formed file whose contents make no sense, with a valid checksum
+        let mut writer = ByteWriter::new();
+        writer.put_raw(TRIGRAM_MAGIC);
+        writer.put_u32_le(TRIGRAM_VERSION);
+        writer.put_varint(1);
+        writer.put_bytes(b"../escape");
+        let sum = index_file::checksum(writer.as_slice());
+        writer.put_u64_le(sum);
+        fs::write(&broken, writer.into_vec()).unwrap();
+        assert!(matches!(TrigramIndex::load(&broken), Err(DirIndexerErr::CorruptIndex(_))));
+    }
+
+    #[test]
+    fn binary_files_are_detected_early() {
+        assert!(is_binary(b"abc\0"));
+        assert!(!is_binary(b"plain text"));
+        let mut late = vec![b'x'; BINARY_PROBE_LEN];
+        late.push(0);
+        assert!(!is_binary(&late));
+        assert_eq!(trigrams_of(b"abcd\nab"), vec![trigram(b"abc"), trigram(b"bcd")]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fs;
+
+/// Maps numeric user and group ids to names, read once from `/etc/passwd` and `/etc/group`.
+///
+/// Ids missing from those files (for example users only known to a directory service) have no
+/// name, and callers fall back to the number.
+#[derive(Debug, Default)]
+pub(crate) struct UserTable {
+    users_: HashMap<u32, String>,
+    groups_: HashMap<u32, String>,
+}
+
+impl UserTable {
+    pub(crate) fn load() -> UserTable {
+        UserTable {
+            users_: read_id_file("/etc/passwd"),
+            groups_: read_id_file("/etc/group"),
+        }
+    }
+
+    pub(crate) fn user_name(&self, uid: u32) -> Option<&str> {
+        self.users_.get(&uid).map(String::as_str)
+    }
+
+    /// Resolves a user name, or a decimal id, to a uid.
+    pub(crate) fn uid_by_name(&self, name: &str) -> Option<u32> {
+        id_by_name(&self.users_, name)
+    }
+
+    /// Resolves a group name, or a decimal id, to a gid.
+    pub(crate) fn gid_by_name(&self, name: &str) -> Option<u32> {
+        id_by_name(&self.groups_, name)
+    }
+}
+
+fn id_by_name(map: &HashMap<u32, String>, name: &str) -> Option<u32> {
+    map.iter()
+        .filter(|(_, entry)| entry.as_str() == name)
+        .map(|(id, _)| *id)
+        .min()
+        .or_else(|| name.parse().ok())
+}
+
+/// Reads `name:password:id:...` lines into an id to name map.
+fn read_id_file(path: &str) -> HashMap<u32, String> {
+    let mut map = HashMap::new();
+    if let Ok(content) = fs::read_to_string(path) {
+        for line in content.lines() {
+            let mut fields = line.split(':');
+            if let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next()) {
+                if let Ok(id) = id.parse::<u32>() {
+                    map.entry(id).or_insert_with(|| name.to_string());
+                }
+            }
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+
+    #[test]
+    fn id_files_map_ids_to_the_first_name() {
+        let dir = TempDir::new("users-file");
+        let path = dir.write("passwd", b"root:x:0:0::/root:/bin/sh\n# comment\nbroken\nalias:x:0:0::/:/bin/sh\nbob:x:1000:1000::/home/bob:/bin/sh\nbad:x:nan:0\n");
+        let users = read_id_file(path.to_str().unwrap());
+        assert_eq!(users.len(), 2);
+        assert_eq!(users.get(&0).map(String::as_str), Some("root"));
+        assert_eq!(users.get(&1000).map(String::as_str), Some("bob"));
+        assert!(read_id_file(dir.join("missing").to_str().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn names_and_numbers_resolve_to_ids() {
+        let mut table = UserTable::default();
+        table.users_.insert(1000, String::from("bob"));
+        table.users_.insert(1001, String::from("bob"));
+        table.groups_.insert(50, String::from("staff"));
+        assert_eq!(table.user_name(1000), Some("bob"));
+        assert_eq!(table.user_name(7), None);
+        assert_eq!(table.uid_by_name("bob"), Some(1000));
+        assert_eq!(table.uid_by_name("42"), Some(42));
+        assert_eq!(table.uid_by_name("nobody-here"), None);
+        assert_eq!(table.gid_by_name("staff"), Some(50));
+        assert_eq!(table.gid_by_name("bob"), None);
+    }
+}
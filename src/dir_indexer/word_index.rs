@@ -0,0 +1,565 @@
+//! Inverted word index over the text files of an index, ranked with BM25.
+//!
+//! Text is split into words at every character that is not alphanumeric, so `dir_indexer` holds
+//! the words `dir` and `indexer`, and words are lowercased. Queries are split the same way; a
+//! file matches when it holds any of the query words, and files holding rare words often rank
+//! highest.
+
+use super::{DirChange, DirDiff, DirEntryKind, DirIndexer, DirNode};
+use super::trigram_index::{is_binary, MAX_INDEXED_SIZE};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Words longer than this many bytes, such as encoded data, are not indexed.
+const MAX_WORD_LEN: usize = 64;
+
+/// Snippet lines longer than this many characters are cut short.
+const MAX_SNIPPET_CHARS: usize = 200;
+
+/// BM25 term frequency saturation.
+const BM25_K1: f64 = 1.2;
+
+/// BM25 document length normalization.
+const BM25_B: f64 = 0.75;
+
+/// Options for `WordIndex::search`.
+#[derive(Debug, Clone)]
+pub struct WordSearchOptions {
+    limit_: usize,
+    snippets_: usize,
+}
+
+impl Default for WordSearchOptions {
+    fn default() -> WordSearchOptions {
+        WordSearchOptions {
+            limit_: 10,
+            snippets_: 3,
+        }
+    }
+}
+
+impl WordSearchOptions {
+    /// Creates options returning the best 10 files with up to 3 snippet lines each.
+    pub fn new() -> WordSearchOptions {
+        WordSearchOptions::default()
+    }
+
+    /// Sets the maximum number of files returned.
+    pub fn with_limit(mut self, limit: usize) -> WordSearchOptions {
+        self.limit_ = limit;
+        self
+    }
+
+    /// Sets the maximum number of snippet lines per file; `0` skips reading the files.
+    pub fn with_snippets(mut self, snippets: usize) -> WordSearchOptions {
+        self.snippets_ = snippets;
+        self
+    }
+
+    /// Returns the maximum number of files returned.
+    pub fn get_limit(&self) -> usize {
+        self.limit_
+    }
+
+    /// Returns the maximum number of snippet lines per file.
+    pub fn get_snippets(&self) -> usize {
+        self.snippets_
+    }
+}
+
+/// A line of a file holding one of the query words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordSnippet {
+    line_number_: usize,
+    line_: String,
+}
+
+impl WordSnippet {
+    /// Returns the line number, starting at 1.
+    pub fn get_line_number(&self) -> usize {
+        self.line_number_
+    }
+
+    /// Returns the line with surrounding whitespace removed, cut short if very long. Bytes that
+    /// are not valid UTF-8 are replaced.
+    pub fn get_line(&self) -> &str {
+        &self.line_
+    }
+}
+
+/// A file ranked by a word search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordMatch {
+    path_: PathBuf,
+    score_: f64,
+    snippets_: Vec<WordSnippet>,
+}
+
+impl WordMatch {
+    /// Returns the relative path.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path_
+    }
+
+    /// Returns the BM25 score; higher is better. Scores are only comparable within one query.
+    pub fn get_score(&self) -> f64 {
+        self.score_
+    }
+
+    /// Returns the first lines of the file holding a query word, in file order.
+    pub fn get_snippets(&self) -> &[WordSnippet] {
+        &self.snippets_
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WordDoc {
+    rl_path: PathBuf,
+    /// The number of words in the file.
+    length: u32,
+    /// The ids of the distinct words in the file.
+    terms: Vec<u32>,
+}
+
+/// The `WordIndex` struct maps every word of the text files of a `DirIndexer` to the files
+/// holding it and how often.
+#[derive(Debug, Clone)]
+pub struct WordIndex {
+    root_path_: PathBuf,
+    docs_: Vec<Option<WordDoc>>,
+    free_ids_: Vec<u32>,
+    by_path_: BTreeMap<PathBuf, u32>,
+    term_ids_: HashMap<String, u32>,
+    /// Per term id, the files holding the term with its count, sorted by file id.
+    postings_: Vec<Vec<(u32, u32)>>,
+    total_length_: u64,
+}
+
+impl WordIndex {
+    /// Reads and indexes every regular text file of an index. Binary files, recognized by a NUL
+    /// byte in their first 8 KiB, files over 64 MiB and unreadable files are left out.
+    pub fn from(indexer: &DirIndexer) -> WordIndex {
+        let mut index = WordIndex {
+            root_path_: indexer.get_root_path().clone(),
+            docs_: Vec::new(),
+            free_ids_: Vec::new(),
+            by_path_: BTreeMap::new(),
+            term_ids_: HashMap::new(),
+            postings_: Vec::new(),
+            total_length_: 0,
+        };
+        index.add_subtree(indexer.get_tree().get_root_node());
+        index
+    }
+
+    /// Returns the number of indexed files.
+    pub fn len(&self) -> usize {
+        self.by_path_.len()
+    }
+
+    /// Returns `true` if no file is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.by_path_.is_empty()
+    }
+
+    /// Returns the number of distinct words.
+    pub fn get_word_count(&self) -> usize {
+        self.postings_.iter().filter(|posting| !posting.is_empty()).count()
+    }
+
+    /// Brings the index up to date with the changes in a diff, such as the one returned by
+    /// `DirIndexer::refresh`. Every changed path is dropped together with the files below it,
+    /// and the files now at or below it in `indexer` are read again.
+    ///
+    /// # Arguments
+    ///
+    /// * `indexer` - The index the diff leads to.
+    /// * `diff` - The changes since the files were last indexed.
+    pub fn apply_diff(&mut self, indexer: &DirIndexer, diff: &DirDiff) {
+        let mut changed: Vec<&PathBuf> = Vec::new();
+        for (rl_path, change) in diff.get_changes() {
+            if let DirChange::Renamed(_, from) = change {
+                changed.push(from);
+            }
+            changed.push(rl_path);
+        }
+        for rl_path in &changed {
+            self.remove_subtree(rl_path);
+        }
+        self.root_path_ = indexer.get_root_path().clone();
+        for rl_path in changed {
+            if let Some(node) = indexer.get_tree().get_node(rl_path) {
+                self.add_subtree(node);
+            }
+        }
+    }
+
+    /// Ranks the files holding any word of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Words separated by spaces or punctuation; case does not matter.
+    /// * `options` - The number of results and of snippet lines per result.
+    ///
+    /// # Returns
+    ///
+    /// The best files with their scores and snippets, best first; ties in path order.
+    pub fn search(&self, query: &str, options: &WordSearchOptions) -> Vec<WordMatch> {
+        let mut words: Vec<String> = Vec::new();
+        for_each_word(query, |word| {
+            if !words.iter().any(|seen| seen == word) {
+                words.push(word.to_string());
+            }
+        });
+        let doc_count = self.by_path_.len() as f64;
+        let avg_length = if self.by_path_.is_empty() {
+            1.0
+        } else {
+            (self.total_length_ as f64 / doc_count).max(1.0)
+        };
+
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+        for word in &words {
+            let posting = match self.term_ids_.get(word) {
+                Some(term) => &self.postings_[*term as usize],
+                None => continue,
+            };
+            let holding = posting.len() as f64;
+            let idf = (1.0 + (doc_count - holding + 0.5) / (holding + 0.5)).ln();
+            for (id, count) in posting {
+                let length = self.docs_[*id as usize].as_ref().map_or(0, |doc| doc.length) as f64;
+                let tf = *count as f64;
+                let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * length / avg_length);
+                *scores.entry(*id).or_insert(0.0) += idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(&WordDoc, f64)> = scores
+            .into_iter()
+            .filter_map(|(id, score)| self.docs_[id as usize].as_ref().map(|doc| (doc, score)))
+            .collect();
+        ranked.sort_by(|(a_doc, a_score), (b_doc, b_score)| {
+            b_score
+                .partial_cmp(a_score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_doc.rl_path.cmp(&b_doc.rl_path))
+        });
+        ranked.truncate(options.limit_);
+        ranked
+            .into_iter()
+            .map(|(doc, score)| WordMatch {
+                path_: doc.rl_path.clone(),
+                score_: score,
+                snippets_: self.snippets(&doc.rl_path, &words, options.snippets_),
+            })
+            .collect()
+    }
+
+    /// Reads a file back and returns its first lines holding one of `words`.
+    fn snippets(&self, rl_path: &Path, words: &[String], max: usize) -> Vec<WordSnippet> {
+        let mut snippets = Vec::new();
+        if max == 0 {
+            return snippets;
+        }
+        let contents = match fs::read(self.root_path_.join(rl_path)) {
+            Ok(contents) => contents,
+            Err(_) => return snippets,
+        };
+        let text = String::from_utf8_lossy(&contents);
+        for (i, line) in text.lines().enumerate() {
+            let mut holds_word = false;
+            for_each_word(line, |word| holds_word |= words.iter().any(|w| w == word));
+            if holds_word {
+                let line = line.trim();
+                snippets.push(WordSnippet {
+                    line_number_: i + 1,
+                    line_: match line.char_indices().nth(MAX_SNIPPET_CHARS) {
+                        Some((cut, _)) => format!("{}...", &line[..cut]),
+                        None => line.to_string(),
+                    },
+                });
+                if snippets.len() == max {
+                    break;
+                }
+            }
+        }
+        snippets
+    }
+
+    /// Indexes every regular file at or below `node`.
+    fn add_subtree(&mut self, node: &DirNode) {
+        match node.get_kind() {
+            DirEntryKind::File => self.add_file(node),
+            DirEntryKind::Dir => {
+                for child in node.get_children() {
+                    self.add_subtree(child);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_file(&mut self, node: &DirNode) {
+        if node.get_meta().get_size() > MAX_INDEXED_SIZE {
+            return;
+        }
+        let rl_path = node.get_entry().to_path_buf();
+        let contents = match fs::read(self.root_path_.join(&rl_path)) {
+            Ok(contents) if !is_binary(&contents) => contents,
+            _ => return,
+        };
+        let mut counts: HashMap<u32, u32> = HashMap::new();
+        let mut length: u32 = 0;
+        for_each_word(&String::from_utf8_lossy(&contents), |word| {
+            let next_id = self.term_ids_.len() as u32;
+            let term = *self.term_ids_.entry(word.to_string()).or_insert(next_id);
+            if term == next_id {
+                self.postings_.push(Vec::new());
+            }
+            *counts.entry(term).or_insert(0) += 1;
+            length = length.saturating_add(1);
+        });
+
+        self.remove_file(&rl_path);
+        let id = match self.free_ids_.pop() {
+            Some(id) => id,
+            None => {
+                self.docs_.push(None);
+                (self.docs_.len() - 1) as u32
+            }
+        };
+        let mut terms: Vec<u32> = Vec::with_capacity(counts.len());
+        for (term, count) in counts {
+            let posting = &mut self.postings_[term as usize];
+            // Ids are reused, so a new id is not necessarily the largest
+            let at = posting.binary_search_by_key(&id, |(doc, _)| *doc).unwrap_or_else(|at| at);
+            posting.insert(at, (id, count));
+            terms.push(term);
+        }
+        self.total_length_ += length as u64;
+        self.by_path_.insert(rl_path.clone(), id);
+        self.docs_[id as usize] = Some(WordDoc { rl_path, length, terms });
+    }
+
+    fn remove_file(&mut self, rl_path: &Path) {
+        let id = match self.by_path_.remove(rl_path) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(doc) = self.docs_[id as usize].take() {
+            for term in doc.terms {
+                let posting = &mut self.postings_[term as usize];
+                if let Ok(at) = posting.binary_search_by_key(&id, |(doc, _)| *doc) {
+                    posting.remove(at);
+                }
+            }
+            self.total_length_ -= doc.length as u64;
+        }
+        self.free_ids_.push(id);
+    }
+
+    /// Drops the file at `rl_path` and every file below it.
+    fn remove_subtree(&mut self, rl_path: &Path) {
+        // Paths order component by component, so everything below `rl_path` directly follows it
+        let below: Vec<PathBuf> = self
+            .by_path_
+            .range(rl_path.to_path_buf()..)
+            .map(|(path, _)| path)
+            .take_while(|path| path.starts_with(rl_path))
+            .cloned()
+            .collect();
+        for path in below {
+            self.remove_file(&path);
+        }
+    }
+}
+
+/// Calls `f` with every lowercased word of `text`.
+fn for_each_word<F: FnMut(&str)>(text: &str, mut f: F) {
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            if word.len() <= MAX_WORD_LEN {
+                f(&word);
+            }
+            word.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir_indexer::test_util::TempDir;
+    use crate::dir_indexer::DirDiffOptions;
+
+    fn words_of(text: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        for_each_word(text, |word| words.push(word.to_string()));
+        words
+    }
+
+    fn indexed(dir: &TempDir) -> (DirIndexer, WordIndex) {
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let index = WordIndex::from(&indexer);
+        (indexer, index)
+    }
+
+    fn ranking(index: &WordIndex, query: &str) -> Vec<String> {
+        index
+            .search(query, &WordSearchOptions::new().with_limit(usize::MAX))
+            .iter()
+            .map(|m| m.get_path().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Every search result, for every word of the index, with its score and snippets.
+    fn everything(index: &WordIndex) -> Vec<(String, Vec<WordMatch>)> {
+        let mut words: Vec<&String> = index.term_ids_.keys().collect();
+        words.sort();
+        let options = WordSearchOptions::new().with_limit(usize::MAX);
+        words
+            .into_iter()
+            .map(|word| (word.clone(), index.search(word, &options)))
+            .filter(|(_, found)| !found.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn splits_text_into_lowercase_words() {
+        assert_eq!(words_of("dir_indexer::DirIndexer, x2y"), vec!["dir", "indexer", "dirindexer", "x2y"]);
+        assert_eq!(words_of("Café ÉCOLE naïve"), vec!["café", "école", "naïve"]);
+        assert_eq!(words_of(&format!("short {} end", "z".repeat(MAX_WORD_LEN + 1))), vec!["short", "end"]);
+        assert_eq!(words_of(&"z".repeat(MAX_WORD_LEN)).len(), 1);
+        assert!(words_of(" -- ").is_empty());
+    }
+
+    #[test]
+    fn ranks_files_with_bm25() {
+        let dir = TempDir::new("words-rank");
+        dir.write("common.txt", b"apple banana");
+        dir.write("rare.txt", b"apple cherry");
+        dir.write("twice.txt", b"apple apple banana banana");
+        dir.write("long.txt", b"banana plus many other words that pad the file");
+        let (_, index) = indexed(&dir);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.get_word_count(), 11);
+
+        // The rare word outweighs the common one, repeated words count more and longer files less
+        assert_eq!(ranking(&index, "cherry banana")[0], "rare.txt");
+        assert_eq!(ranking(&index, "banana"), vec!["twice.txt", "common.txt", "long.txt"]);
+        assert_eq!(ranking(&index, "BANANA banana"), ranking(&index, "banana"));
+        assert!(ranking(&index, "durian").is_empty());
+        assert!(ranking(&index, "").is_empty());
+
+        // apple is in 3 of the 4 files, which average 4.25 words
+        let score = index.search("apple", &WordSearchOptions::new())
+            .into_iter()
+            .find(|m| m.get_path() == Path::new("common.txt"))
+            .unwrap()
+            .get_score();
+        let idf = (1.0f64 + (4.0 - 3.0 + 0.5) / (3.0 + 0.5)).ln();
+        let expected = idf * 2.2 / (1.0 + 1.2 * (0.25 + 0.75 * 2.0 / 4.25));
+        assert!((score - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ties_and_limits() {
+        let dir = TempDir::new("words-ties");
+        for name in ["c.txt", "a.txt", "b.txt"].iter() {
+            dir.write(name, b"same words");
+        }
+        let (_, index) = indexed(&dir);
+        assert_eq!(ranking(&index, "same"), vec!["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(index.search("same", &WordSearchOptions::new().with_limit(2)).len(), 2);
+        assert!(index.search("same", &WordSearchOptions::new().with_limit(0)).is_empty());
+    }
+
+    #[test]
+    fn snippets_show_matching_lines() {
+        let dir = TempDir::new("words-snippets");
+        let long = format!("needle {}", "x ".repeat(150));
+        let contents = format!("first line\n   a needle here  \nnothing\nNEEDLE again\n{}\nneedle five\n", long);
+        dir.write("notes.txt", contents.as_bytes());
+        dir.write("latin1.txt", b"caf\xe9 needle");
+        let (_, index) = indexed(&dir);
+
+        let found = index.search("needle", &WordSearchOptions::new().with_snippets(3));
+        let notes = found.iter().find(|m| m.get_path() == Path::new("notes.txt")).unwrap();
+        let lines: Vec<(usize, &str)> = notes.get_snippets().iter().map(|s| (s.get_line_number(), s.get_line())).collect();
+        assert_eq!(lines[..2], [(2, "a needle here"), (4, "NEEDLE again")]);
+        assert_eq!(lines[2].0, 5);
+        assert_eq!(lines[2].1.chars().count(), MAX_SNIPPET_CHARS + 3);
+        assert!(lines[2].1.ends_with("..."));
+
+        let latin1 = found.iter().find(|m| m.get_path() == Path::new("latin1.txt")).unwrap();
+        assert_eq!(latin1.get_snippets()[0].get_line(), "caf\u{fffd} needle");
+        let found = index.search("needle", &WordSearchOptions::new().with_snippets(0));
+        assert!(found.iter().all(|m| m.get_snippets().is_empty()));
+    }
+
+    #[test]
+    fn leaves_out_binary_and_unreadable_files() {
+        let dir = TempDir::new("words-binary");
+        dir.write("text.txt", b"readable words");
+        dir.write("blob.bin", b"readable\0words");
+        dir.write("gone.txt", b"readable words");
+        let indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        fs::remove_file(dir.join("gone.txt")).unwrap();
+        let index = WordIndex::from(&indexer);
+        assert_eq!(ranking(&index, "readable"), vec!["text.txt"]);
+        assert_eq!(index.get_word_count(), 2);
+    }
+
+    #[test]
+    fn applying_diffs_matches_a_rebuild() {
+        let dir = TempDir::new("words-diff");
+        let root = dir.get_path().clone();
+        for options in [DirDiffOptions::new(), DirDiffOptions::new().with_renames()].iter() {
+            if root.exists() {
+                fs::remove_dir_all(&root).unwrap();
+            }
+            dir.write("keep.txt", b"steady words stay");
+            dir.write("edit.txt", b"old words here");
+            dir.write("drop.txt", b"dropped words");
+            dir.write("docs/a.txt", b"moving documents");
+            dir.write("docs/deep/b.txt", b"deeper moving words");
+            dir.write("swap", b"file becoming directory");
+            let old = DirIndexer::from(root.clone()).unwrap();
+            let mut index = WordIndex::from(&old);
+            fs::rename(root.join("docs"), root.join("moved")).unwrap();
+            fs::remove_file(root.join("swap")).unwrap();
+            dir.write("swap/inner.txt", b"directory now");
+            dir.write("edit.txt", b"new words there and more");
+            fs::remove_file(root.join("drop.txt")).unwrap();
+            dir.write("moved/deep/c.txt", b"added deeper");
+            dir.write("fresh.txt", b"brand new words");
+
+            let new = DirIndexer::from(root.clone()).unwrap();
+            index.apply_diff(&new, &old.diff_with(&new, options));
+            let rebuilt = WordIndex::from(&new);
+            assert_eq!(index.len(), rebuilt.len());
+            assert_eq!(index.get_word_count(), rebuilt.get_word_count());
+            assert_eq!(everything(&index), everything(&rebuilt));
+        }
+    }
+
+    #[test]
+    fn refreshes_keep_the_index_in_step() {
+        let dir = TempDir::new("words-refresh");
+        dir.write("a.txt", b"one two");
+        let mut indexer = DirIndexer::from(dir.get_path().clone()).unwrap();
+        let mut index = WordIndex::from(&indexer);
+        dir.write("a.txt", b"three");
+        dir.write("b/c.txt", b"two three");
+        let diff = indexer.refresh().unwrap();
+        index.apply_diff(&indexer, &diff);
+        assert_eq!(ranking(&index, "one"), Vec::<String>::new());
+        assert_eq!(ranking(&index, "two three"), vec!["b/c.txt", "a.txt"]);
+        assert_eq!(everything(&index), everything(&WordIndex::from(&indexer)));
+    }
+}
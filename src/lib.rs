@@ -1,6 +1,34 @@
 mod dir_indexer;
 
 pub use dir_indexer::DirIndexer;
+pub use dir_indexer::DirTree;
+pub use dir_indexer::DirNode;
+pub use dir_indexer::DirIndexerErr;
+pub use dir_indexer::{MappedEntry, MappedIndex};
+pub use dir_indexer::{DirColumn, DirTableExporter, TableFormat};
+pub use dir_indexer::{MtreeKeyword, MtreeMismatch, MtreeOptions, MtreeReport};
+pub use dir_indexer::{ChecksumReport, ChecksumStatus};
+pub use dir_indexer::{HashCache, HashStats};
+pub use dir_indexer::{DuplicateGroup, DuplicateOptions};
+pub use dir_indexer::{DuplicateDirGroup, MerkleDifference, MerkleOptions, MerkleTree};
+pub use dir_indexer::{DedupeOptions, DedupeReport, DedupeSkipReason};
+pub use dir_indexer::{DirQuery, QueryFileType};
+pub use dir_indexer::{FuzzyIndex, FuzzyMatch, FuzzyOptions};
+pub use dir_indexer::PathTrie;
+pub use dir_indexer::{ContentMatch, TrigramIndex, TrigramStats};
+pub use dir_indexer::{WordIndex, WordMatch, WordSearchOptions, WordSnippet};
+pub use dir_indexer::{TreeAnnotation, TreeCharset, TreeRenderOptions};
+pub use dir_indexer::{DotLabel, DotOptions};
+pub use dir_indexer::HtmlReportOptions;
+#[cfg(unix)]
+pub use dir_indexer::{default_socket_path, DaemonClient, DaemonOptions, IndexDaemon, DEFAULT_DAEMON_TIMEOUT};
+#[cfg(unix)]
+pub use dir_indexer::{IndexEntry, IndexSource, IndexStats};
+pub use dir_indexer::{DirEntryKind, DirMeta};
+pub use dir_indexer::{DirChange, DirDiff, DirDiffOptions, DirDiffSummary};
+pub use dir_indexer::{human_size, DirUsage};
+pub use dir_indexer::ScanError;
+pub use dir_indexer::{hash_bytes, hash_file, hash_reader, ContentHash, ContentHasher, HashAlgo};
 
 pub use dir_indexer::get_absolute_dir_paths_set;
 pub use dir_indexer::get_relative_dir_paths_set;